@@ -0,0 +1,333 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+// ---------------------------------------------------------------------------
+// Tuning
+// ---------------------------------------------------------------------------
+
+/// Block (partition) size in samples.  The canceller adds exactly this much
+/// latency to the mic path (about 5 ms at 48 kHz).
+const BLOCK_SIZE: usize = 256;
+const FFT_SIZE: usize = BLOCK_SIZE * 2;
+const FREQ_SIZE: usize = BLOCK_SIZE + 1;
+
+/// Longest bulk reference delay accepted.  The delay line is sized from
+/// it; `MicChain::set_rate` rebuilds the canceller on the command loop when
+/// the output rate changes.
+pub const MAX_DELAY_MS: u32 = 1000;
+
+/// Length of the echo path the filter can model (speaker -> room -> mic plus
+/// the capture ring latency), on top of the configurable bulk delay.
+const TAIL_MS: usize = 250;
+
+/// NLMS step size (normalised by the reference power).
+const STEP: f32 = 0.6;
+/// Step used while double talk is detected, so the near-end voice does not
+/// drag the filter away from the echo path.
+const DOUBLE_TALK_STEP: f32 = 0.02;
+/// Near-end speech is assumed when the residual jumps this many times above
+/// its usual (single-talk) level.
+const DOUBLE_TALK_RATIO: f32 = 4.0;
+/// Per-block growth of the single-talk residual level while double talk is
+/// detected, so a genuine echo-path change is re-learned after a second or
+/// two instead of freezing the filter forever.
+const RESIDUAL_CREEP: f32 = 1.005;
+/// Smoothing of the per-bin reference power estimate.
+const POWER_SMOOTHING: f32 = 0.8;
+/// Reference blocks quieter than this (mean square) do not adapt the filter.
+const REF_SILENCE: f32 = 1e-7;
+/// Per-bin power regularisation.
+const REGULARIZATION: f32 = 1e-4 * FFT_SIZE as f32;
+
+// ---------------------------------------------------------------------------
+// Echo canceller
+// ---------------------------------------------------------------------------
+
+/// Acoustic echo canceller for the mic pass-through.
+///
+/// The reference is the engine's own rendered sound-effect signal: whatever
+/// the mixer plays is exactly what can leak back into the microphone, so no
+/// loopback capture is needed.  Echo is removed with a partitioned-block
+/// frequency-domain adaptive filter (overlap-save, one gradient constraint
+/// per block as in the MDF algorithm).
+///
+/// The mic signal lags the reference (output latency + acoustic path +
+/// capture latency + ring buffer), so the filter is causal; `delay_ms`
+/// shifts the reference further to spend the filter taps on the room
+/// response rather than on a known fixed latency.
+pub struct EchoCanceller {
+    rate: u32,
+    partitions: usize,
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+
+    /// Filter weights, one spectrum per partition.
+    weights: Vec<Vec<Complex<f32>>>,
+    /// Frequency-domain delay line of past reference blocks; slot
+    /// `(head + p) % partitions` holds the block from `p` blocks ago.
+    fdl: Vec<Vec<Complex<f32>>>,
+    head: usize,
+    /// Next partition to apply the gradient constraint to.
+    constrain_next: usize,
+    power: Vec<f32>,
+    /// Smoothed residual energy per block during single talk.
+    residual_level: f32,
+
+    /// Bulk delay applied to the reference before the filter.
+    ref_delay: VecDeque<f32>,
+    /// Previous reference block (first half of the overlap-save input).
+    ref_prev: Vec<f32>,
+    /// Incoming samples waiting for a full block.
+    ref_in: Vec<f32>,
+    mic_in: Vec<f32>,
+    /// Echo-cancelled output, primed with one block of silence.
+    ready: VecDeque<f32>,
+
+    time_buf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    echo_spectrum: Vec<Complex<f32>>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+    error: Vec<f32>,
+}
+
+impl EchoCanceller {
+    pub fn new(rate: u32, delay_ms: u32) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let ifft = planner.plan_fft_inverse(FFT_SIZE);
+
+        let tail = rate as usize * TAIL_MS / 1000;
+        let partitions = tail.div_ceil(BLOCK_SIZE).max(1);
+        let delay_samples = rate as usize * delay_ms as usize / 1000;
+
+        let mut ready = VecDeque::with_capacity(BLOCK_SIZE * 4);
+        ready.extend(std::iter::repeat_n(0.0, BLOCK_SIZE));
+        let mut ref_delay = VecDeque::with_capacity(delay_samples + BLOCK_SIZE * 4);
+        ref_delay.extend(std::iter::repeat_n(0.0, delay_samples));
+
+        Self {
+            rate,
+            partitions,
+            weights: vec![vec![Complex::default(); FREQ_SIZE]; partitions],
+            fdl: vec![vec![Complex::default(); FREQ_SIZE]; partitions],
+            head: 0,
+            constrain_next: 0,
+            power: vec![0.0; FREQ_SIZE],
+            residual_level: BLOCK_SIZE as f32,
+            ref_delay,
+            ref_prev: vec![0.0; BLOCK_SIZE],
+            ref_in: Vec::with_capacity(BLOCK_SIZE * 4),
+            mic_in: Vec::with_capacity(BLOCK_SIZE * 4),
+            ready,
+            time_buf: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            echo_spectrum: fft.make_output_vec(),
+            scratch_fwd: fft.make_scratch_vec(),
+            scratch_inv: ifft.make_scratch_vec(),
+            error: vec![0.0; BLOCK_SIZE],
+            fft,
+            ifft,
+        }
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Remove the echo of `reference` from `mic` in place.  Both are mono
+    /// blocks of the same length at the canceller's rate.
+    pub fn process(&mut self, mic: &mut [f32], reference: &[f32]) {
+        self.ref_delay.extend(reference.iter().copied());
+        for _ in 0..reference.len() {
+            let r = self.ref_delay.pop_front().unwrap_or(0.0);
+            self.ref_in.push(r);
+        }
+        self.mic_in.extend_from_slice(mic);
+
+        let mut consumed = 0;
+        while self.mic_in.len() - consumed >= BLOCK_SIZE {
+            self.process_block(consumed);
+            consumed += BLOCK_SIZE;
+        }
+        self.mic_in.drain(..consumed);
+        self.ref_in.drain(..consumed);
+
+        for s in mic.iter_mut() {
+            *s = self.ready.pop_front().unwrap_or(0.0);
+        }
+    }
+
+    fn process_block(&mut self, offset: usize) {
+        let p_count = self.partitions;
+        let norm = 1.0 / FFT_SIZE as f32;
+        let ref_block = &self.ref_in[offset..offset + BLOCK_SIZE];
+        let mic_block = &self.mic_in[offset..offset + BLOCK_SIZE];
+
+        // 1. Transform [previous block, current block] of the reference and
+        //    push it onto the frequency-domain delay line.
+        self.time_buf[..BLOCK_SIZE].copy_from_slice(&self.ref_prev);
+        self.time_buf[BLOCK_SIZE..].copy_from_slice(ref_block);
+        self.ref_prev.copy_from_slice(ref_block);
+        let ref_energy = ref_block.iter().map(|s| s * s).sum::<f32>() / BLOCK_SIZE as f32;
+
+        self.head = (self.head + p_count - 1) % p_count;
+        let _ = self.fft.process_with_scratch(
+            &mut self.time_buf,
+            &mut self.fdl[self.head],
+            &mut self.scratch_fwd,
+        );
+
+        // 2. Echo estimate: sum of every partition's weights times its
+        //    delayed reference spectrum; keep the last half (overlap-save).
+        for bin in self.echo_spectrum.iter_mut() {
+            *bin = Complex::default();
+        }
+        for p in 0..p_count {
+            let x = &self.fdl[(self.head + p) % p_count];
+            for ((y, w), x) in self.echo_spectrum.iter_mut().zip(&self.weights[p]).zip(x) {
+                *y += w * x;
+            }
+        }
+        self.spectrum.copy_from_slice(&self.echo_spectrum);
+        self.spectrum[0].im = 0.0;
+        self.spectrum[FREQ_SIZE - 1].im = 0.0;
+        let _ = self
+            .ifft
+            .process_with_scratch(&mut self.spectrum, &mut self.time_buf, &mut self.scratch_inv);
+
+        // 3. Error = mic - echo estimate.
+        let mut mic_energy = 0.0;
+        let mut err_energy = 0.0;
+        for ((err, &d), y) in self
+            .error
+            .iter_mut()
+            .zip(mic_block)
+            .zip(&self.time_buf[BLOCK_SIZE..])
+        {
+            let echo = y * norm;
+            let e = d - echo;
+            *err = e;
+            mic_energy += d * d;
+            err_energy += e * e;
+        }
+
+        // Never make the mic worse than it was: if the filter is diverged
+        // (or still empty), pass the block through untouched.
+        if err_energy <= mic_energy {
+            self.ready.extend(self.error.iter().copied());
+        } else {
+            self.ready.extend(mic_block.iter().copied());
+        }
+        if err_energy > 4.0 * mic_energy && mic_energy > 0.0 {
+            for w in self.weights.iter_mut() {
+                w.fill(Complex::default());
+            }
+            return;
+        }
+
+        if ref_energy < REF_SILENCE {
+            return;
+        }
+
+        // 4. Adapt.  Error spectrum of [zeros, e].
+        self.time_buf[..BLOCK_SIZE].fill(0.0);
+        self.time_buf[BLOCK_SIZE..].copy_from_slice(&self.error);
+        let _ = self
+            .fft
+            .process_with_scratch(&mut self.time_buf, &mut self.spectrum, &mut self.scratch_fwd);
+
+        for (f, pw) in self.power.iter_mut().enumerate() {
+            let total: f32 = self.fdl.iter().map(|x| x[f].norm_sqr()).sum();
+            *pw = POWER_SMOOTHING * *pw + (1.0 - POWER_SMOOTHING) * total;
+        }
+
+        let double_talk = err_energy > DOUBLE_TALK_RATIO * self.residual_level;
+        let step = if double_talk {
+            self.residual_level *= RESIDUAL_CREEP;
+            DOUBLE_TALK_STEP
+        } else {
+            self.residual_level = 0.9 * self.residual_level + 0.1 * err_energy;
+            STEP
+        };
+
+        for p in 0..p_count {
+            let x = &self.fdl[(self.head + p) % p_count];
+            let bins = self.weights[p].iter_mut().zip(x).zip(&self.spectrum).zip(&self.power);
+            for (((w, x), e), pw) in bins {
+                *w += x.conj() * e * (step / (pw + REGULARIZATION));
+            }
+        }
+
+        // 5. Gradient constraint on one partition per block: zero the
+        //    second half of its impulse response so the circular
+        //    convolution stays linear.
+        let c = self.constrain_next;
+        self.constrain_next = (c + 1) % p_count;
+        self.spectrum.copy_from_slice(&self.weights[c]);
+        self.spectrum[0].im = 0.0;
+        self.spectrum[FREQ_SIZE - 1].im = 0.0;
+        let _ = self
+            .ifft
+            .process_with_scratch(&mut self.spectrum, &mut self.time_buf, &mut self.scratch_inv);
+        for s in self.time_buf[..BLOCK_SIZE].iter_mut() {
+            *s *= norm;
+        }
+        self.time_buf[BLOCK_SIZE..].fill(0.0);
+        let _ = self.fft.process_with_scratch(
+            &mut self.time_buf,
+            &mut self.weights[c],
+            &mut self.scratch_fwd,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mic_chain::MicChain;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    /// Runs `mic` and `reference` through a canceller with a bulk delay of
+    /// `delay_ms` and returns how far (in dB) the last second was reduced.
+    fn reduction_db(mic: &[f32], reference: &[f32], delay_ms: u32) -> f32 {
+        let mut aec = EchoCanceller::new(RATE, delay_ms);
+        let mut out = mic.to_vec();
+        for (block, reference) in out.chunks_mut(480).zip(reference.chunks(480)) {
+            aec.process(block, reference);
+        }
+        let last = mic.len() - RATE as usize;
+        10.0 * (corpus::power(&mic[last..]) / corpus::power(&out[last..])).log10()
+    }
+
+    /// The reference, `lag` samples later and quieter, as the mic hears it.
+    fn echo(reference: &[f32], lag: usize) -> Vec<f32> {
+        let mut mic = vec![0.0; lag];
+        mic.extend(reference.iter().map(|s| s * 0.5));
+        mic.truncate(reference.len());
+        mic
+    }
+
+    #[test]
+    fn cancels_a_delayed_echo() {
+        let reference = corpus::noise(RATE as usize * 5, 0.3);
+        // 20 ms of latency, inside the filter's tail.
+        let db = reduction_db(&echo(&reference, 960), &reference, 0);
+        assert!(db > 30.0, "echo only reduced by {db} dB");
+        // 400 ms, past the tail, is caught by the bulk delay.
+        let db = reduction_db(&echo(&reference, 19_200), &reference, 390);
+        assert!(db > 30.0, "echo only reduced by {db} dB with a bulk delay");
+    }
+
+    #[test]
+    fn rejects_delays_over_the_maximum() {
+        let mut chain = MicChain::new();
+        assert!(chain.set_echo_cancellation(true, MAX_DELAY_MS, RATE).is_ok());
+        assert!(chain.set_echo_cancellation(true, MAX_DELAY_MS + 1, RATE).is_err());
+    }
+}
@@ -0,0 +1,191 @@
+/// Samples per gain update.  The level detector and gain smoother run once
+/// per chunk; the gain is ramped linearly across each chunk.
+const CHUNK: usize = 32;
+
+/// Time constant of the RMS level detector.
+const LEVEL_TAU_S: f32 = 0.1;
+/// How fast the gain may drop when the mic gets loud.
+const ATTACK_TAU_S: f32 = 0.05;
+/// How fast the gain may rise when the mic gets quiet.
+const RELEASE_TAU_S: f32 = 1.0;
+/// The AGC never attenuates by more than this.
+const MIN_GAIN_DB: f32 = -20.0;
+/// Output peaks are held below this to stop boosted speech from clipping.
+const PEAK_LIMIT: f32 = 0.97;
+
+/// User-facing AGC settings.
+#[derive(Debug, Clone, Copy)]
+pub struct AgcSettings {
+    /// Speech level to aim for, in dBFS (RMS).
+    pub target_db: f32,
+    /// Upper bound on the applied gain, in dB.
+    pub max_gain_db: f32,
+    /// Input below this level (dBFS RMS) is treated as silence: the gain is
+    /// frozen instead of being raised towards the target.
+    pub noise_floor_db: f32,
+}
+
+/// Automatic gain control for the mono mic signal.
+///
+/// A smoothed RMS detector drives a gain (in dB) towards `target - level`,
+/// dropping quickly on loud input and rising slowly on quiet input.  While
+/// the input sits under the noise floor the gain holds, so pauses in speech
+/// don't get pumped up to the target level.
+pub struct Agc {
+    settings: AgcSettings,
+    /// Mean-square level estimate.
+    envelope: f32,
+    /// Current gain in dB.
+    gain_db: f32,
+    /// Linear gain at the end of the previous chunk (ramp start).
+    last_gain: f32,
+}
+
+impl Agc {
+    pub fn new(settings: AgcSettings) -> Self {
+        Self {
+            settings,
+            envelope: 0.0,
+            gain_db: 0.0,
+            last_gain: 1.0,
+        }
+    }
+
+    pub fn set_settings(&mut self, settings: AgcSettings) {
+        self.settings = settings;
+    }
+
+    /// Gain currently applied, in dB.
+    pub fn gain_db(&self) -> f32 {
+        self.gain_db
+    }
+
+    /// Apply the AGC to a block of mono samples at `rate` in place.
+    pub fn process(&mut self, samples: &mut [f32], rate: u32) {
+        let chunk_s = CHUNK as f32 / rate.max(1) as f32;
+        let level_coeff = 1.0 - (-chunk_s / LEVEL_TAU_S).exp();
+        let attack_coeff = 1.0 - (-chunk_s / ATTACK_TAU_S).exp();
+        let release_coeff = 1.0 - (-chunk_s / RELEASE_TAU_S).exp();
+        let max_gain_db = self.settings.max_gain_db.max(0.0);
+
+        for chunk in samples.chunks_mut(CHUNK) {
+            let mut mean_sq = 0.0;
+            let mut peak: f32 = 0.0;
+            for &s in chunk.iter() {
+                mean_sq += s * s;
+                peak = peak.max(s.abs());
+            }
+            mean_sq /= chunk.len() as f32;
+            self.envelope += (mean_sq - self.envelope) * level_coeff;
+
+            let level_db = 10.0 * (self.envelope + 1e-12).log10();
+            if level_db > self.settings.noise_floor_db {
+                let desired = (self.settings.target_db - level_db).clamp(MIN_GAIN_DB, max_gain_db);
+                let coeff = if desired < self.gain_db {
+                    attack_coeff
+                } else {
+                    release_coeff
+                };
+                self.gain_db += (desired - self.gain_db) * coeff;
+            }
+            self.gain_db = self.gain_db.min(max_gain_db);
+
+            // Never let the boosted chunk clip.
+            let mut gain = db_to_linear(self.gain_db);
+            let limit = if peak > 0.0 { PEAK_LIMIT / peak } else { f32::MAX };
+            if gain > limit {
+                gain = limit;
+                self.gain_db = linear_to_db(gain);
+            }
+
+            let start = self.last_gain;
+            let step = (gain - start) / chunk.len() as f32;
+            for (i, s) in chunk.iter_mut().enumerate() {
+                *s *= (start + step * (i + 1) as f32).min(limit);
+            }
+            self.last_gain = gain;
+        }
+    }
+}
+
+fn db_to_linear(db: f32) -> f32 {
+    10f32.powf(db / 20.0)
+}
+
+fn linear_to_db(gain: f32) -> f32 {
+    20.0 * gain.max(1e-9).log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48_000;
+
+    fn settings() -> AgcSettings {
+        AgcSettings {
+            target_db: -20.0,
+            max_gain_db: 12.0,
+            noise_floor_db: -60.0,
+        }
+    }
+
+    fn level_db(samples: &[f32]) -> f32 {
+        10.0 * corpus::power(samples).log10()
+    }
+
+    #[test]
+    fn a_steady_tone_converges_to_the_target() {
+        // -30 dBFS RMS wants +10 dB, inside the 12 dB ceiling.
+        let mut agc = Agc::new(settings());
+        let mut tone = corpus::tone(RATE as usize * 8, RATE, 440.0, 0.0447);
+        agc.process(&mut tone, RATE);
+        let tail = &tone[tone.len() - RATE as usize / 2..];
+        assert!((level_db(tail) + 20.0).abs() < 1.0, "{}", level_db(tail));
+
+        // -40 dBFS wants +20 dB; the gain stops at `max_gain_db`.
+        let mut agc = Agc::new(settings());
+        let mut tone = corpus::tone(RATE as usize * 8, RATE, 440.0, 0.01414);
+        agc.process(&mut tone, RATE);
+        assert!((agc.gain_db() - 12.0).abs() < 0.1, "{}", agc.gain_db());
+        let tail = &tone[tone.len() - RATE as usize / 2..];
+        assert!((level_db(tail) + 28.0).abs() < 1.0, "{}", level_db(tail));
+    }
+
+    #[test]
+    fn input_under_the_noise_floor_holds_the_gain() {
+        // A fresh AGC fed only hiss stays at unity instead of boosting it.
+        let mut agc = Agc::new(settings());
+        agc.process(&mut corpus::noise(RATE as usize * 4, 1e-4), RATE);
+        assert_eq!(agc.gain_db(), 0.0);
+
+        let mut agc = Agc::new(settings());
+        agc.process(&mut corpus::tone(RATE as usize * 4, RATE, 440.0, 0.0447), RATE);
+        assert!(agc.gain_db() > 5.0, "{}", agc.gain_db());
+
+        // Once the level detector has fallen under the floor, the gain
+        // neither rises towards the ceiling nor falls back to 0 dB.
+        agc.process(&mut corpus::noise(RATE as usize, 1e-4), RATE);
+        let gain = agc.gain_db();
+        agc.process(&mut corpus::noise(RATE as usize * 4, 1e-4), RATE);
+        assert_eq!(agc.gain_db(), gain);
+    }
+
+    #[test]
+    fn loud_bursts_never_pass_the_peak_limit() {
+        let mut agc = Agc::new(AgcSettings {
+            max_gain_db: 30.0,
+            ..settings()
+        });
+        // Quiet speech drives the gain to the ceiling, then a full-scale
+        // burst arrives before the attack can pull it down.
+        let mut signal = corpus::tone(RATE as usize * 6, RATE, 440.0, 0.003);
+        signal.extend(corpus::tone(RATE as usize / 2, RATE, 1000.0, 1.0));
+        signal.extend(corpus::noise(RATE as usize / 2, 1.0));
+        agc.process(&mut signal, RATE);
+        let peak = signal.iter().fold(0f32, |m, s| m.max(s.abs()));
+        assert!(peak <= PEAK_LIMIT + 1e-6, "{peak}");
+        assert!(signal.iter().all(|s| s.is_finite()));
+    }
+}
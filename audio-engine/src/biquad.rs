@@ -0,0 +1,129 @@
+use std::f32::consts::PI;
+
+/// Second-order IIR filter section (transposed direct form II) with
+/// coefficients from the RBJ "Audio EQ Cookbook".
+#[derive(Debug, Clone, Copy)]
+pub struct Biquad {
+    b0: f32,
+    b1: f32,
+    b2: f32,
+    a1: f32,
+    a2: f32,
+    z1: f32,
+    z2: f32,
+}
+
+/// Butterworth Q, used where a filter has no user-facing Q.
+pub const BUTTERWORTH_Q: f32 = std::f32::consts::FRAC_1_SQRT_2;
+
+impl Biquad {
+    /// Build a section from unnormalised coefficients.
+    fn from_coeffs(b0: f32, b1: f32, b2: f32, a0: f32, a1: f32, a2: f32) -> Self {
+        Self {
+            b0: b0 / a0,
+            b1: b1 / a0,
+            b2: b2 / a0,
+            a1: a1 / a0,
+            a2: a2 / a0,
+            z1: 0.0,
+            z2: 0.0,
+        }
+    }
+
+    /// A section that passes the signal unchanged.
+    pub fn identity() -> Self {
+        Self::from_coeffs(1.0, 0.0, 0.0, 1.0, 0.0, 0.0)
+    }
+
+    /// Angular frequency and `alpha` for a corner at `freq` Hz, with the
+    /// frequency kept safely below Nyquist for whatever rate the device runs.
+    fn omega_alpha(rate: u32, freq: f32, q: f32) -> (f32, f32) {
+        let nyquist = rate as f32 / 2.0;
+        let freq = freq.clamp(1.0, nyquist * 0.99);
+        let w0 = 2.0 * PI * freq / rate as f32;
+        let alpha = w0.sin() / (2.0 * q.max(0.01));
+        (w0, alpha)
+    }
+
+    pub fn low_pass(rate: u32, freq: f32, q: f32) -> Self {
+        let (w0, alpha) = Self::omega_alpha(rate, freq, q);
+        let cos = w0.cos();
+        Self::from_coeffs(
+            (1.0 - cos) / 2.0,
+            1.0 - cos,
+            (1.0 - cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    pub fn high_pass(rate: u32, freq: f32, q: f32) -> Self {
+        let (w0, alpha) = Self::omega_alpha(rate, freq, q);
+        let cos = w0.cos();
+        Self::from_coeffs(
+            (1.0 + cos) / 2.0,
+            -(1.0 + cos),
+            (1.0 + cos) / 2.0,
+            1.0 + alpha,
+            -2.0 * cos,
+            1.0 - alpha,
+        )
+    }
+
+    /// Bell boost/cut of `gain_db` centred on `freq`.
+    pub fn peaking(rate: u32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (w0, alpha) = Self::omega_alpha(rate, freq, q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let cos = w0.cos();
+        Self::from_coeffs(
+            1.0 + alpha * a,
+            -2.0 * cos,
+            1.0 - alpha * a,
+            1.0 + alpha / a,
+            -2.0 * cos,
+            1.0 - alpha / a,
+        )
+    }
+
+    /// Boost/cut of `gain_db` below `freq`.
+    pub fn low_shelf(rate: u32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (w0, alpha) = Self::omega_alpha(rate, freq, q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let cos = w0.cos();
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::from_coeffs(
+            a * ((a + 1.0) - (a - 1.0) * cos + k),
+            2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - k),
+            (a + 1.0) + (a - 1.0) * cos + k,
+            -2.0 * ((a - 1.0) + (a + 1.0) * cos),
+            (a + 1.0) + (a - 1.0) * cos - k,
+        )
+    }
+
+    /// Boost/cut of `gain_db` above `freq`.
+    pub fn high_shelf(rate: u32, freq: f32, q: f32, gain_db: f32) -> Self {
+        let (w0, alpha) = Self::omega_alpha(rate, freq, q);
+        let a = 10f32.powf(gain_db / 40.0);
+        let cos = w0.cos();
+        let k = 2.0 * a.sqrt() * alpha;
+        Self::from_coeffs(
+            a * ((a + 1.0) + (a - 1.0) * cos + k),
+            -2.0 * a * ((a - 1.0) + (a + 1.0) * cos),
+            a * ((a + 1.0) + (a - 1.0) * cos - k),
+            (a + 1.0) - (a - 1.0) * cos + k,
+            2.0 * ((a - 1.0) - (a + 1.0) * cos),
+            (a + 1.0) - (a - 1.0) * cos - k,
+        )
+    }
+
+    /// Filter one sample.
+    #[inline]
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = self.b0 * x + self.z1;
+        self.z1 = self.b1 * x - self.a1 * y + self.z2;
+        self.z2 = self.b2 * x - self.a2 * y;
+        y
+    }
+}
@@ -0,0 +1,473 @@
+use std::sync::{Arc, Mutex};
+
+use crate::eq::Equalizer;
+use crate::playback::FilePlayback;
+use crate::protocol::{BusInsert, BusOutput, BusStatus, MonitorSource};
+use crate::queue::{PlayQueue, SharedQueue};
+use crate::reverb::Convolver;
+
+/// Name of the bus that carries the mic pass-through.  It always exists.
+pub const MIC_BUS: &str = "mic";
+
+/// Most gain a bus can apply.
+const MAX_BUS_GAIN: f32 = 2.0;
+
+/// The sound playing on a bus, shared between the output callback and the
+/// decode thread that fills it.
+pub type VoiceSlot = Arc<Mutex<Option<FilePlayback>>>;
+
+/// Add the voice in `slot` into `out`, dropping it once it ends.  A paused
+/// voice holds its position and adds nothing.  Returns `true` while the
+/// slot holds a voice that is still playing.
+pub fn render_slot(
+    slot: &VoiceSlot,
+    out: &mut [f32],
+    channels: usize,
+    paused: bool,
+    activity: &mut VoiceActivity,
+) -> bool {
+    let Ok(mut guard) = slot.try_lock() else {
+        // The decode thread holds the voice; it is still alive.
+        activity.alive = true;
+        return true;
+    };
+    let Some(fp) = guard.as_mut() else {
+        return false;
+    };
+    if paused || fp.mix_into(out, channels) {
+        activity.alive = true;
+        true
+    } else {
+        *guard = None;
+        activity.ended = true;
+        false
+    }
+}
+
+/// An effect instance inserted on a bus.
+pub enum Insert {
+    Eq(Equalizer),
+    Reverb(Box<Convolver>),
+}
+
+impl Insert {
+    /// The settings this insert was built from.
+    fn config(&self) -> BusInsert {
+        match self {
+            Insert::Eq(eq) => BusInsert::Eq {
+                bands: eq.bands().to_vec(),
+            },
+            Insert::Reverb(reverb) => BusInsert::Reverb(reverb.settings().clone()),
+        }
+    }
+
+    fn process(&mut self, data: &mut [f32], channels: usize, rate: u32) {
+        match self {
+            Insert::Eq(eq) => eq.process(data, channels, rate),
+            Insert::Reverb(reverb) => {
+                if reverb.matches(rate, channels) {
+                    reverb.process(data);
+                }
+            }
+        }
+    }
+}
+
+/// One named bus: an optional sound voice (plus the mic, for the mic bus)
+/// run through the inserts, scaled by the gain and sent to its outputs.
+pub struct Bus {
+    pub name: String,
+    pub gain: f32,
+    pub muted: bool,
+    pub outputs: Vec<BusOutput>,
+    pub inserts: Vec<Insert>,
+    pub voice: VoiceSlot,
+    /// Files queued to play back to back on the bus.
+    pub queue: SharedQueue,
+    /// The bus signal for the current block.
+    buffer: Vec<f32>,
+}
+
+impl Bus {
+    fn new(name: &str, outputs: &[BusOutput]) -> Self {
+        Self {
+            name: name.to_string(),
+            gain: 1.0,
+            muted: false,
+            outputs: outputs.to_vec(),
+            inserts: Vec::new(),
+            voice: Arc::new(Mutex::new(None)),
+            queue: Arc::new(Mutex::new(PlayQueue::new())),
+            buffer: Vec::with_capacity(8192),
+        }
+    }
+
+    pub fn set_gain(&mut self, gain: f32) {
+        self.gain = gain.clamp(0.0, MAX_BUS_GAIN);
+    }
+
+    /// The bus's settings and handles to its voice and queue, to be read
+    /// once the graph lock is released.
+    pub fn snapshot(&self) -> BusSnapshot {
+        BusSnapshot {
+            status: BusStatus {
+                name: self.name.clone(),
+                gain: self.gain,
+                muted: self.muted,
+                inserts: self.inserts.iter().map(Insert::config).collect(),
+                outputs: self.outputs.clone(),
+                playing: false,
+                pan: None,
+                queue: Default::default(),
+            },
+            voice: Arc::clone(&self.voice),
+            queue: Arc::clone(&self.queue),
+        }
+    }
+
+    /// Add the bus voice and play queue into `buffer`.
+    fn render_voice(&mut self, channels: usize, paused: bool, activity: &mut VoiceActivity) {
+        render_slot(&self.voice, &mut self.buffer, channels, paused, activity);
+        match self.queue.try_lock() {
+            Ok(mut queue) => queue.render(&mut self.buffer, channels, paused, activity),
+            // Held by the command loop for a moment; the queue resumes
+            // where it was next block.
+            Err(_) => activity.alive = true,
+        }
+    }
+
+    /// Run the inserts and apply gain / mute.  Inserts keep running while
+    /// muted so reverb tails and filter state stay continuous.
+    fn finish(&mut self, channels: usize, rate: u32) {
+        for insert in self.inserts.iter_mut() {
+            insert.process(&mut self.buffer, channels, rate);
+        }
+        let gain = if self.muted { 0.0 } else { self.gain };
+        if (gain - 1.0).abs() > f32::EPSILON {
+            for s in self.buffer.iter_mut() {
+                *s *= gain;
+            }
+        }
+    }
+}
+
+/// A bus copied out of the graph.  The output callback renders every bus
+/// under the graph lock, so the command loop mustn't hold that lock while
+/// it waits on a voice (which a decode thread may hold while it appends).
+pub struct BusSnapshot {
+    status: BusStatus,
+    pub voice: VoiceSlot,
+    pub queue: SharedQueue,
+}
+
+impl BusSnapshot {
+    pub fn status(self) -> BusStatus {
+        let (playing, pan) = match self.voice.try_lock() {
+            Ok(v) => (v.is_some(), v.as_ref().map(|fp| fp.panner.pan())),
+            // Held by its decode thread, so still playing.
+            Err(_) => (true, None),
+        };
+        let queue = self.queue.lock().map(|q| q.status()).unwrap_or_default();
+        BusStatus {
+            playing,
+            pan,
+            queue,
+            ..self.status
+        }
+    }
+}
+
+/// What happened to the bus voices during one block.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct VoiceActivity {
+    /// At least one voice is still playing.
+    pub alive: bool,
+    /// At least one voice finished during the block.
+    pub ended: bool,
+}
+
+/// The set of buses and where each one is routed.
+pub struct BusGraph {
+    buses: Vec<Bus>,
+    /// Sound playback is paused: the voices hold still while the mic and
+    /// the bus inserts (e.g. reverb tails) keep running.
+    pub paused: bool,
+    /// What the monitor output is given: with [`MonitorSource::Mix`] the
+    /// caller adds the finished main mix, so only the buses routed to the
+    /// monitor alone are rendered into it.
+    pub monitor_source: MonitorSource,
+    /// Sum of the sound buses, used as the mic chain's echo reference.
+    reference: Vec<f32>,
+}
+
+impl BusGraph {
+    /// The default graph: the mic and two sound buses feed the main output,
+    /// the sound buses also feed the monitor (when it plays the buses rather
+    /// than the mix), and the `monitor` bus is for sounds that should only be
+    /// heard locally.
+    pub fn new() -> Self {
+        use BusOutput::{Main, Monitor};
+        Self {
+            buses: vec![
+                Bus::new(MIC_BUS, &[Main]),
+                Bus::new("sfx", &[Main, Monitor]),
+                Bus::new("music", &[Main, Monitor]),
+                Bus::new("monitor", &[Monitor]),
+            ],
+            paused: false,
+            monitor_source: MonitorSource::Mix,
+            reference: Vec::with_capacity(8192),
+        }
+    }
+
+    pub fn buses(&self) -> &[Bus] {
+        &self.buses
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Bus> {
+        self.buses.iter().find(|b| b.name == name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Bus> {
+        self.buses.iter_mut().find(|b| b.name == name)
+    }
+
+    /// The named bus, created with the default routing if it doesn't exist.
+    pub fn get_or_insert(&mut self, name: &str) -> &mut Bus {
+        let index = match self.buses.iter().position(|b| b.name == name) {
+            Some(i) => i,
+            None => {
+                self.buses
+                    .push(Bus::new(name, &[BusOutput::Main, BusOutput::Monitor]));
+                self.buses.len() - 1
+            }
+        };
+        &mut self.buses[index]
+    }
+
+    /// Every bus, copied out so it can be read without the graph lock.
+    pub fn snapshot(&self) -> Vec<BusSnapshot> {
+        self.buses.iter().map(Bus::snapshot).collect()
+    }
+
+    /// Remove a bus, returning it so the caller can account for its voice.
+    pub fn remove(&mut self, name: &str) -> Result<Bus, String> {
+        if name == MIC_BUS {
+            return Err("The mic bus can't be removed".to_string());
+        }
+        let index = self
+            .buses
+            .iter()
+            .position(|b| b.name == name)
+            .ok_or_else(|| format!("No such bus: {name}"))?;
+        Ok(self.buses.remove(index))
+    }
+
+    /// Render one block of every bus and add each into the outputs it is
+    /// routed to.  `main` and `monitor` must be zeroed by the caller.  See
+    /// `monitor_source` for which buses reach the monitor.
+    ///
+    /// The sound buses are rendered first so their sum can be handed to
+    /// `mic_stage` (the mic chain and mic volume) as the echo reference,
+    /// together with the captured `mic` block.
+    pub fn render(
+        &mut self,
+        mic: &[f32],
+        main: &mut [f32],
+        mut monitor: Option<&mut [f32]>,
+        channels: usize,
+        rate: u32,
+        mic_stage: impl FnOnce(&mut [f32], &[f32]),
+    ) -> VoiceActivity {
+        let len = main.len();
+        let mut activity = VoiceActivity::default();
+        let paused = self.paused;
+
+        self.reference.clear();
+        self.reference.resize(len, 0.0);
+        for bus in self.buses.iter_mut().filter(|b| b.name != MIC_BUS) {
+            bus.buffer.clear();
+            bus.buffer.resize(len, 0.0);
+            bus.render_voice(channels, paused, &mut activity);
+            bus.finish(channels, rate);
+            for (r, s) in self.reference.iter_mut().zip(bus.buffer.iter()) {
+                *r += s;
+            }
+        }
+
+        if let Some(bus) = self.buses.iter_mut().find(|b| b.name == MIC_BUS) {
+            bus.buffer.clear();
+            bus.buffer.extend_from_slice(mic);
+            bus.buffer.resize(len, 0.0);
+            mic_stage(&mut bus.buffer, &self.reference);
+            bus.render_voice(channels, paused, &mut activity);
+            bus.finish(channels, rate);
+        }
+
+        for bus in self.buses.iter() {
+            let to_main = bus.outputs.contains(&BusOutput::Main);
+            if to_main {
+                for (o, s) in main.iter_mut().zip(bus.buffer.iter()) {
+                    *o += s;
+                }
+            }
+            if let Some(monitor) = monitor.as_deref_mut() {
+                let to_monitor = bus.outputs.contains(&BusOutput::Monitor)
+                    && (self.monitor_source == MonitorSource::Buses || !to_main);
+                if to_monitor {
+                    for (o, s) in monitor.iter_mut().zip(bus.buffer.iter()) {
+                        *o += s;
+                    }
+                }
+            }
+        }
+
+        activity
+    }
+
+    /// Add the last block of the buses routed to the main output into
+    /// `out`: the mic bus if `mic`, otherwise the sound buses.  Call after
+    /// `render`.
+    pub fn mix_main(&self, mic: bool, out: &mut [f32]) {
+        for bus in self
+            .buses
+            .iter()
+            .filter(|b| (b.name == MIC_BUS) == mic && b.outputs.contains(&BusOutput::Main))
+        {
+            for (o, s) in out.iter_mut().zip(bus.buffer.iter()) {
+                *o += s;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+    use crate::pan::Panner;
+    use crate::protocol::ReverbSettings;
+    use crate::sample_cache::CachedSound;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    /// Loudest sample in the file at `path`.
+    fn peak(path: &Path) -> f32 {
+        corpus::decode(path).2.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    /// A voice playing `samples` (stereo, in the output format).
+    fn voice(samples: Vec<f32>) -> Option<FilePlayback> {
+        let sound = CachedSound {
+            samples: Arc::new(samples),
+            src_channels: 2,
+        };
+        Some(FilePlayback::cached(0, 1.0, Panner::new(0.0, false), sound))
+    }
+
+    #[test]
+    fn buses_reach_the_outputs_they_are_routed_to() {
+        let sound = corpus::wav_float("routed.wav", RATE, 2, &[0.5; 4800]);
+        let routes = [
+            (vec![BusOutput::Main], (0.5, 0.0)),
+            (vec![BusOutput::Monitor], (0.0, 0.5)),
+            (vec![BusOutput::Main, BusOutput::Monitor], (0.5, 0.5)),
+        ];
+        for (i, (outputs, want)) in routes.into_iter().enumerate() {
+            let main = corpus::path(&format!("routed-main-{i}.wav"));
+            let monitor = corpus::path(&format!("routed-monitor-{i}.wav"));
+            let mut mixer = corpus::monitored_mixer(&main, &monitor);
+            mixer.start_output().unwrap();
+            mixer.start_monitor().unwrap();
+            // The buses themselves, not the finished mix, on the monitor.
+            mixer.set_monitor_source(MonitorSource::Buses);
+            mixer.set_bus("routed", None, None, None, Some(outputs)).unwrap();
+            mixer
+                .play_file(sound.to_str().unwrap(), "routed", corpus::play_params())
+                .unwrap();
+            mixer.advance_clock(50.0).unwrap();
+            drop(mixer);
+            assert_eq!((peak(&main), peak(&monitor)), want, "route {i}");
+        }
+    }
+
+    #[test]
+    fn gain_scales_the_bus_up_to_its_limit() {
+        let sound = corpus::wav_float("gained.wav", RATE, 2, &[0.25; 4800]);
+        for (gain, want) in [(0.5, 0.125), (5.0, 0.25 * MAX_BUS_GAIN)] {
+            let output = corpus::path(&format!("gained-{gain}.wav"));
+            let mut mixer = corpus::null_mixer(None, &output);
+            mixer.start_output().unwrap();
+            mixer.set_bus("sfx", Some(gain), None, None, None).unwrap();
+            mixer
+                .play_file(sound.to_str().unwrap(), "sfx", corpus::play_params())
+                .unwrap();
+            mixer.advance_clock(50.0).unwrap();
+            drop(mixer);
+            assert_eq!(peak(&output), want, "gain {gain}");
+        }
+    }
+
+    #[test]
+    fn a_muted_bus_keeps_running_its_inserts() {
+        // A click into a long, flat reverb: its tail outlasts the sound.
+        let mut click = vec![0.0; 960];
+        click[..2].fill(0.5);
+        let sound = corpus::wav_float("click.wav", RATE, 2, &click);
+        let ir = corpus::wav_float("flat-ir.wav", RATE, 1, &[0.1; 24000]);
+        let reverb = BusInsert::Reverb(ReverbSettings {
+            ir_path: ir.to_string_lossy().into_owned(),
+            wet: 1.0,
+            dry: 0.0,
+        });
+
+        let output = corpus::path("muted-out.wav");
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+        mixer.set_bus("sfx", None, Some(true), Some(vec![reverb]), None).unwrap();
+        mixer
+            .play_file(sound.to_str().unwrap(), "sfx", corpus::play_params())
+            .unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        mixer.set_bus("sfx", None, Some(false), None, None).unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        drop(mixer);
+
+        let out = corpus::decode(&output).2;
+        let (muted, unmuted) = out.split_at(9600);
+        assert!(muted.iter().all(|&s| s == 0.0), "a muted bus was heard");
+        // The click went into the reverb while the bus was muted, so its
+        // tail follows the unmute.
+        assert!(unmuted.iter().all(|&s| s > 1e-3), "the reverb tail was lost");
+    }
+
+    #[test]
+    fn the_mic_bus_cannot_be_removed() {
+        let mut mixer = corpus::null_mixer(None, &corpus::path("removed-out.wav"));
+        assert!(mixer.remove_bus(MIC_BUS).is_err());
+        assert!(mixer.remove_bus("music").is_ok());
+        assert!(mixer.remove_bus("music").is_err());
+        let names: Vec<String> = mixer.bus_status().into_iter().map(|b| b.name).collect();
+        assert_eq!(names, [MIC_BUS, "sfx", "monitor"]);
+    }
+
+    #[test]
+    fn the_main_mix_splits_into_mic_and_sounds() {
+        let mut graph = BusGraph::new();
+        *graph.get("sfx").unwrap().voice.lock().unwrap() = voice(vec![0.25; 960]);
+        *graph.get("music").unwrap().voice.lock().unwrap() = voice(vec![0.125; 960]);
+        let mic = [0.5; 960];
+        let mut main = [0.0; 960];
+        graph.render(&mic, &mut main, None, 2, RATE, |_, _| {});
+        assert!(main.iter().all(|&s| s == 0.875));
+
+        let mut mic_only = [0.0; 960];
+        graph.mix_main(true, &mut mic_only);
+        assert!(mic_only.iter().all(|&s| s == 0.5));
+        let mut sounds = [0.0; 960];
+        graph.mix_main(false, &mut sounds);
+        assert!(sounds.iter().all(|&s| s == 0.375));
+    }
+}
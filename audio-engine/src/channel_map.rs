@@ -0,0 +1,303 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+/// Speaker positions, in the order used by WAV / WASAPI channel masks.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Speaker {
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    Lfe,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+}
+
+use Speaker::*;
+
+impl Speaker {
+    /// The speaker's bit in a WAV channel mask.
+    fn mask_bit(self) -> u32 {
+        match self {
+            FrontLeft => 0x1,
+            FrontRight => 0x2,
+            FrontCenter => 0x4,
+            Lfe => 0x8,
+            BackLeft => 0x10,
+            BackRight => 0x20,
+            SideLeft => 0x200,
+            SideRight => 0x400,
+        }
+    }
+}
+
+const SPEAKERS: [Speaker; 8] = [
+    FrontLeft,
+    FrontRight,
+    FrontCenter,
+    Lfe,
+    BackLeft,
+    BackRight,
+    SideLeft,
+    SideRight,
+];
+
+/// The speakers of `channels` channels: the ones in the file's channel
+/// `mask` if it gave one (channels come in mask bit order), otherwise the
+/// standard layout for the count.  `None` for layouts with speakers not
+/// handled here.
+fn layout(channels: u16, mask: Option<u32>) -> Option<Vec<Speaker>> {
+    // A one-channel file is mono whichever speaker it names.
+    if let Some(mask) = mask.filter(|_| channels > 1) {
+        let speakers: Vec<Speaker> = SPEAKERS
+            .into_iter()
+            .filter(|s| mask & s.mask_bit() != 0)
+            .collect();
+        let known = speakers.iter().fold(0, |m, s| m | s.mask_bit());
+        return (known == mask && speakers.len() == channels as usize).then_some(speakers);
+    }
+    let standard: &[Speaker] = match channels {
+        1 => &[FrontCenter],
+        2 => &[FrontLeft, FrontRight],
+        4 => &[FrontLeft, FrontRight, BackLeft, BackRight],
+        6 => &[FrontLeft, FrontRight, FrontCenter, Lfe, BackLeft, BackRight],
+        8 => &SPEAKERS,
+        _ => return None,
+    };
+    Some(standard.to_vec())
+}
+
+/// The other surround on the same side: side for back and back for side.
+fn surround_pair(speaker: Speaker) -> Option<Speaker> {
+    match speaker {
+        BackLeft => Some(SideLeft),
+        SideLeft => Some(BackLeft),
+        BackRight => Some(SideRight),
+        SideRight => Some(BackRight),
+        _ => None,
+    }
+}
+
+/// Where a source speaker goes in a destination layout that has a left /
+/// right pair, as `(speaker, gain)` pairs.  Follows the ITU-R BS.775
+/// downmix: centre and surrounds fold into the fronts at -3 dB, and the
+/// LFE is dropped unless the destination has one.  A side and a back
+/// surround that end up in one speaker go in at -3 dB each.
+fn route(speaker: Speaker, src: &[Speaker], dst: &[Speaker]) -> Vec<(Speaker, f32)> {
+    let has = |s: Speaker| dst.contains(&s);
+    let surround = match surround_pair(speaker) {
+        Some(pair) if src.contains(&pair) && has(speaker) != has(pair) => FRAC_1_SQRT_2,
+        _ => 1.0,
+    };
+    if has(speaker) {
+        return vec![(speaker, surround)];
+    }
+    match speaker {
+        FrontCenter => vec![(FrontLeft, FRAC_1_SQRT_2), (FrontRight, FRAC_1_SQRT_2)],
+        Lfe => Vec::new(),
+        BackLeft if has(SideLeft) => vec![(SideLeft, surround)],
+        BackRight if has(SideRight) => vec![(SideRight, surround)],
+        SideLeft if has(BackLeft) => vec![(BackLeft, surround)],
+        SideRight if has(BackRight) => vec![(BackRight, surround)],
+        BackLeft | SideLeft => vec![(FrontLeft, FRAC_1_SQRT_2)],
+        BackRight | SideRight => vec![(FrontRight, FRAC_1_SQRT_2)],
+        // Every layout with more than one channel has the front pair.
+        FrontLeft | FrontRight => Vec::new(),
+    }
+}
+
+/// A `dst × src` mixing matrix that converts interleaved audio between
+/// channel counts.
+#[derive(Debug, Clone)]
+pub struct ChannelMatrix {
+    src: usize,
+    dst: usize,
+    /// `coeffs[out * src + in]`.
+    coeffs: Vec<f32>,
+}
+
+impl ChannelMatrix {
+    fn zeros(src: usize, dst: usize) -> Self {
+        Self {
+            src,
+            dst,
+            coeffs: vec![0.0; src * dst],
+        }
+    }
+
+    fn set(&mut self, out: usize, input: usize, gain: f32) {
+        self.coeffs[out * self.src + input] = gain;
+    }
+
+    /// The standard matrix between two channel counts, picked from their
+    /// speaker layouts: the source's from its channel mask if the file has
+    /// one (so quad and 3.1 differ), otherwise mono, stereo, quad, 5.1 or
+    /// 7.1 by count.
+    ///
+    /// Downmixes follow ITU-R BS.775 and a mono target takes the average
+    /// of the stereo downmix.  Upmixes only fill the speakers the source
+    /// has: stereo content stays in the front pair instead of being copied
+    /// to the surrounds.  A mono source is the exception and is sent to
+    /// the front left / right pair at unity (a centred sound, which the
+    /// voice panner can then move).
+    pub fn standard(src_channels: u16, src_mask: Option<u32>, dst_channels: u16) -> Self {
+        let (src, dst) = (src_channels as usize, dst_channels as usize);
+        let mut m = Self::zeros(src, dst);
+        let src_layout = layout(src_channels, src_mask);
+        let dst_layout = layout(dst_channels, None);
+        // The same count is passed through unless the file says its
+        // speakers differ from the output's.
+        if src == dst && (src_mask.is_none() || src_layout.is_none() || src_layout == dst_layout) {
+            for c in 0..src {
+                m.set(c, c, 1.0);
+            }
+            return m;
+        }
+
+        let (Some(src_layout), Some(dst_layout)) = (src_layout, dst_layout) else {
+            return Self::fallback(src, dst);
+        };
+
+        if src == 1 {
+            for (o, speaker) in dst_layout.iter().enumerate() {
+                if matches!(speaker, FrontLeft | FrontRight) {
+                    m.set(o, 0, 1.0);
+                }
+            }
+            return m;
+        }
+
+        if dst == 1 {
+            let stereo = Self::standard(src_channels, src_mask, 2);
+            for i in 0..src {
+                m.set(0, i, 0.5 * (stereo.coeffs[i] + stereo.coeffs[src + i]));
+            }
+            return m;
+        }
+
+        for (i, &speaker) in src_layout.iter().enumerate() {
+            for (target, gain) in route(speaker, &src_layout, &dst_layout) {
+                if let Some(o) = dst_layout.iter().position(|&s| s == target) {
+                    m.set(o, i, gain);
+                }
+            }
+        }
+        m
+    }
+
+    /// Matrix for channel counts without a known layout: average into a
+    /// mono target, copy a mono source everywhere, otherwise map channels
+    /// by index.
+    fn fallback(src: usize, dst: usize) -> Self {
+        let mut m = Self::zeros(src, dst);
+        if dst == 1 {
+            for i in 0..src {
+                m.set(0, i, 1.0 / src as f32);
+            }
+        } else if src == 1 {
+            for o in 0..dst {
+                m.set(o, 0, 1.0);
+            }
+        } else {
+            for c in 0..src.min(dst) {
+                m.set(c, c, 1.0);
+            }
+        }
+        m
+    }
+
+    /// A user-supplied matrix: one row per output channel, each with one
+    /// gain per source channel.
+    pub fn custom(src_channels: u16, rows: Vec<Vec<f32>>) -> Result<Self, String> {
+        let src = src_channels as usize;
+        if src == 0 || rows.is_empty() {
+            return Err("Channel matrix must have at least one row and column".to_string());
+        }
+        if let Some(row) = rows.iter().find(|r| r.len() != src) {
+            return Err(format!(
+                "Channel matrix rows must have {src} entries (got {})",
+                row.len()
+            ));
+        }
+        Ok(Self {
+            src,
+            dst: rows.len(),
+            coeffs: rows.into_iter().flatten().collect(),
+        })
+    }
+
+    pub fn dst_channels(&self) -> u16 {
+        self.dst as u16
+    }
+
+    /// Rows of the matrix (one per output channel).
+    pub fn rows(&self) -> Vec<Vec<f32>> {
+        self.coeffs.chunks(self.src).map(|r| r.to_vec()).collect()
+    }
+
+    pub fn is_identity(&self) -> bool {
+        self.src == self.dst
+            && self.coeffs.iter().enumerate().all(|(i, &g)| {
+                let expected = if i / self.src == i % self.src { 1.0 } else { 0.0 };
+                g == expected
+            })
+    }
+
+    /// Convert one frame (`src` samples) into `dst` (`dst` samples) without
+    /// allocating.
+    #[inline]
+    pub fn convert_frame(&self, src: &[f32], dst: &mut [f32]) {
+        for (o, row) in dst.iter_mut().zip(self.coeffs.chunks(self.src)) {
+            *o = row.iter().zip(src).map(|(g, s)| g * s).sum();
+        }
+    }
+
+    /// Convert interleaved `samples` (`src` channels) to `dst` channels.
+    pub fn convert(&self, samples: &[f32]) -> Vec<f32> {
+        if self.is_identity() {
+            return samples.to_vec();
+        }
+        let frames = samples.len() / self.src;
+        let mut out = Vec::with_capacity(frames * self.dst);
+        for frame in samples.chunks_exact(self.src) {
+            for row in self.coeffs.chunks(self.src) {
+                out.push(row.iter().zip(frame).map(|(g, s)| g * s).sum());
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const H: f32 = FRAC_1_SQRT_2;
+
+    #[test]
+    fn the_channel_mask_picks_the_layout() {
+        // Quad by count: the backs fold into the fronts.
+        let quad = ChannelMatrix::standard(4, None, 2);
+        assert_eq!(quad.rows(), [[1.0, 0.0, H, 0.0], [0.0, 1.0, 0.0, H]]);
+        // 3.1 from its mask: the centre is split and the LFE dropped.
+        let three_one = ChannelMatrix::standard(4, Some(0xF), 2);
+        assert_eq!(three_one.rows(), [[1.0, 0.0, H, 0.0], [0.0, 1.0, H, 0.0]]);
+        // Quad played on a quad device passes through; 3.1 doesn't.
+        assert!(ChannelMatrix::standard(4, Some(0x33), 4).is_identity());
+        assert!(!ChannelMatrix::standard(4, Some(0xF), 4).is_identity());
+        // Speakers not handled here fall back to mapping by index.
+        let wide = ChannelMatrix::standard(4, Some(0xC3), 2);
+        assert_eq!(wide.rows(), [[1.0, 0.0, 0.0, 0.0], [0.0, 1.0, 0.0, 0.0]]);
+    }
+
+    #[test]
+    fn side_and_back_share_a_surround_at_minus_3_db() {
+        let m = ChannelMatrix::standard(8, None, 6).rows();
+        // Back left (input 4) and side left (input 6) into back left.
+        assert_eq!(m[4], [0.0, 0.0, 0.0, 0.0, H, 0.0, H, 0.0]);
+        assert_eq!(m[5], [0.0, 0.0, 0.0, 0.0, 0.0, H, 0.0, H]);
+        // 5.1 with side surrounds moves them to the backs as they are.
+        let m = ChannelMatrix::standard(6, Some(0x60F), 6).rows();
+        assert_eq!(m[4], [0.0, 0.0, 0.0, 0.0, 1.0, 0.0]);
+    }
+}
@@ -0,0 +1,596 @@
+use std::fs::File;
+use std::io::{ErrorKind, Read};
+use std::path::Path;
+use std::sync::OnceLock;
+use std::time::Duration;
+
+use symphonia::core::audio::SampleBuffer;
+use symphonia::core::codecs::{
+    CodecParameters, CodecRegistry, Decoder, DecoderOptions, CODEC_TYPE_NULL, CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::Error;
+use symphonia::core::formats::{FormatOptions, FormatReader, SeekMode, SeekTo};
+use symphonia::core::io::MediaSourceStream;
+use symphonia::core::meta::{MetadataOptions, StandardTagKey};
+use symphonia::core::probe::{Hint, ProbeResult};
+
+use crate::opus::OpusDecoder;
+use crate::protocol::MediaInfo;
+
+/// Symphonia's codecs, plus Opus.
+fn codecs() -> &'static CodecRegistry {
+    static CODECS: OnceLock<CodecRegistry> = OnceLock::new();
+    CODECS.get_or_init(|| {
+        let mut registry = CodecRegistry::new();
+        symphonia::default::register_enabled_codecs(&mut registry);
+        registry.register_all::<OpusDecoder>();
+        registry
+    })
+}
+
+/// An audio file opened for decoding to interleaved `f32` at its own rate
+/// and channel count.
+///
+/// Integer formats are scaled from their full bit depth (16, 24 or 32
+/// bits) and float formats pass through unchanged, so nothing is lost on
+/// the way to the mixer.  Supports WAV (integer and float), FLAC, ALAC and
+/// AAC (in MP4/M4A), Vorbis and Opus (in Ogg or Matroska/WebM) and MP3.
+pub struct AudioDecoder {
+    format: Box<dyn FormatReader>,
+    decoder: Box<dyn Decoder>,
+    track_id: u32,
+    params: CodecParameters,
+    rate: u32,
+    channels: u16,
+    /// Speakers of the channels, as a WAV-style channel mask.
+    mask: u32,
+    /// The mask is Symphonia's guess from the channel count (a WAV file
+    /// without one), not the file's.
+    mask_guessed: bool,
+    /// Reused for converting each decoded packet to interleaved `f32`.
+    buffer: Option<SampleBuffer<f32>>,
+    /// Decoded samples not handed out yet.
+    pending: Vec<f32>,
+    /// Frames to drop after a seek that landed before its target.
+    skip_frames: u64,
+}
+
+/// Open `path` and identify its container.
+fn open_format(path: &str) -> Result<ProbeResult, String> {
+    let file = File::open(path).map_err(|e| format!("Cannot open file: {e}"))?;
+    let stream = MediaSourceStream::new(Box::new(file), Default::default());
+    let mut hint = Hint::new();
+    if let Some(ext) = Path::new(path).extension().and_then(|e| e.to_str()) {
+        hint.with_extension(ext);
+    }
+    // Gapless trims encoder delay and padding, so queued tracks join
+    // without a gap.
+    let options = FormatOptions {
+        enable_gapless: true,
+        ..Default::default()
+    };
+    symphonia::default::get_probe()
+        .format(&hint, stream, &options, &MetadataOptions::default())
+        .map_err(|e| format!("Cannot decode audio file: {e}"))
+}
+
+/// Whether `path` is a WAV file whose format chunk has no channel mask
+/// (anything but `WAVE_FORMAT_EXTENSIBLE`).
+fn wav_without_channel_mask(path: &str) -> bool {
+    let Ok(mut file) = File::open(path) else {
+        return false;
+    };
+    let mut header = [0u8; 12];
+    if file.read_exact(&mut header).is_err() || &header[..4] != b"RIFF" || &header[8..] != b"WAVE"
+    {
+        return false;
+    }
+    // The format chunk normally comes first, but may follow a few others.
+    for _ in 0..16 {
+        let mut chunk = [0u8; 10];
+        if file.read_exact(&mut chunk[..8]).is_err() {
+            return false;
+        }
+        let len = u32::from_le_bytes([chunk[4], chunk[5], chunk[6], chunk[7]]) as u64;
+        if &chunk[..4] == b"fmt " {
+            return file.read_exact(&mut chunk[8..]).is_ok()
+                && u16::from_le_bytes([chunk[8], chunk[9]]) != 0xFFFE;
+        }
+        if std::io::copy(&mut (&mut file).take(len + len % 2), &mut std::io::sink()).is_err() {
+            return false;
+        }
+    }
+    false
+}
+
+/// Read a file's format, length and tags without playing it.  Only the
+/// first packet is decoded; if the container doesn't give the length, the
+/// packets are scanned for it.
+pub fn probe(path: &str) -> Result<MediaInfo, String> {
+    let mut probed = open_format(path)?;
+    // Tags in the container itself win over ones in front of it (ID3v2).
+    let tags = probed
+        .format
+        .metadata()
+        .skip_to_latest()
+        .cloned()
+        .or_else(|| probed.metadata.get()?.skip_to_latest().cloned());
+    let tag = |key: StandardTagKey| {
+        let tags = tags.as_ref()?.tags();
+        let tag = tags.iter().find(|t| t.std_key == Some(key))?;
+        Some(tag.value.to_string())
+    };
+
+    let mut decoder = AudioDecoder::from_format(probed.format)?;
+    let duration = match decoder.duration() {
+        Some(duration) => duration,
+        None => decoder.scan_duration(),
+    };
+    let params = &decoder.params;
+    Ok(MediaInfo {
+        duration_ms: duration.as_secs_f64() * 1000.0,
+        sample_rate: decoder.rate,
+        channels: decoder.channels,
+        codec: codecs()
+            .get_codec(params.codec)
+            .map_or("unknown", |c| c.short_name)
+            .to_string(),
+        bit_depth: params.bits_per_sample,
+        title: tag(StandardTagKey::TrackTitle),
+        artist: tag(StandardTagKey::Artist),
+        album: tag(StandardTagKey::Album),
+        has_album_art: tags.as_ref().is_some_and(|t| !t.visuals().is_empty()),
+    })
+}
+
+impl AudioDecoder {
+    pub fn open(path: &str) -> Result<Self, String> {
+        let mut this = Self::from_format(open_format(path)?.format)?;
+        this.mask_guessed = wav_without_channel_mask(path);
+        Ok(this)
+    }
+
+    fn from_format(format: Box<dyn FormatReader>) -> Result<Self, String> {
+        let track = format
+            .tracks()
+            .iter()
+            .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+            .ok_or_else(|| "Cannot decode audio file: no audio track".to_string())?;
+        let track_id = track.id;
+        let params = track.codec_params.clone();
+        let decoder = codecs()
+            .make(&params, &DecoderOptions::default())
+            .map_err(|e| format!("Cannot decode audio file: {e}"))?;
+
+        let mut this = Self {
+            format,
+            decoder,
+            track_id,
+            rate: params.sample_rate.unwrap_or(0),
+            channels: params.channels.map_or(0, |c| c.count() as u16),
+            mask: params.channels.map_or(0, |c| c.bits()),
+            mask_guessed: false,
+            params,
+            buffer: None,
+            pending: Vec::new(),
+            skip_frames: 0,
+        };
+        // Some containers only give the format in the first packet, so
+        // decode it now and keep its samples for the first read.
+        let mut first = Vec::new();
+        while first.is_empty() {
+            if !this.decode_packet(&mut first)? {
+                break;
+            }
+        }
+        this.pending = first;
+        if this.rate == 0 || this.channels == 0 {
+            return Err("Cannot decode audio file: unknown sample format".to_string());
+        }
+        Ok(this)
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Which speakers the channels are for, as a WAV-style channel mask,
+    /// if the file says.
+    pub fn channel_mask(&self) -> Option<u32> {
+        (!self.mask_guessed && self.mask.count_ones() == self.channels as u32).then_some(self.mask)
+    }
+
+    /// Length of the file, if the container says.
+    pub fn duration(&self) -> Option<Duration> {
+        let mut length = self.params.n_frames?;
+        // Symphonia's Ogg reader counts the Opus pre-skip, which the
+        // decoder drops.
+        if self.params.codec == CODEC_TYPE_OPUS {
+            length = length.saturating_sub(self.params.delay.unwrap_or(0) as u64);
+        }
+        // Counted in the track's time base, which is the sample period for
+        // most formats but not for Matroska.
+        let seconds = match self.params.time_base {
+            Some(base) => {
+                let time = base.calc_time(length);
+                time.seconds as f64 + time.frac
+            }
+            None => length as f64 / self.rate.max(1) as f64,
+        };
+        Some(Duration::from_secs_f64(seconds))
+    }
+
+    /// Length of the rest of the file, found by reading through its packets
+    /// without decoding them.
+    fn scan_duration(&mut self) -> Duration {
+        let mut end = 0;
+        while let Ok(packet) = self.format.next_packet() {
+            if packet.track_id() == self.track_id {
+                end = end.max(packet.ts() + packet.dur());
+            }
+        }
+        self.params.n_frames = Some(end.saturating_sub(self.params.start_ts));
+        self.duration().unwrap_or_default()
+    }
+
+    /// Append decoded samples to `out` until it holds at least `min_len`
+    /// (always whole frames).  Returns `false` once the file has ended;
+    /// a stream that breaks off part-way ends there.
+    pub fn read(&mut self, out: &mut Vec<f32>, min_len: usize) -> bool {
+        out.append(&mut self.pending);
+        while out.len() < min_len {
+            match self.decode_packet(out) {
+                Ok(true) => {}
+                Ok(false) => return false,
+                Err(e) => {
+                    eprintln!("[decode] {e}");
+                    return false;
+                }
+            }
+        }
+        true
+    }
+
+    /// Decode the whole file, stopping after `max_len` samples.
+    pub fn read_all(&mut self, max_len: usize) -> Vec<f32> {
+        let mut out = Vec::new();
+        while out.len() < max_len && self.read(&mut out, max_len) {}
+        out.truncate(max_len - max_len % self.channels.max(1) as usize);
+        out
+    }
+
+    /// Continue decoding from `to`.
+    pub fn seek(&mut self, to: Duration) -> Result<(), String> {
+        self.pending.clear();
+        let target = SeekTo::Time {
+            time: to.into(),
+            track_id: Some(self.track_id),
+        };
+        let seeked = self
+            .format
+            .seek(SeekMode::Accurate, target)
+            .map_err(|e| format!("Cannot seek: {e}"))?;
+        self.decoder.reset();
+        // The demuxer lands on a packet boundary at or before the target.
+        let early = seeked.required_ts.saturating_sub(seeked.actual_ts);
+        self.skip_frames = match self.params.time_base {
+            Some(base) => {
+                let time = base.calc_time(early);
+                ((time.seconds as f64 + time.frac) * self.rate as f64).round() as u64
+            }
+            None => early,
+        };
+        Ok(())
+    }
+
+    /// Decode the next packet of the track into `out`.  Returns `false` at
+    /// the end of the file.
+    fn decode_packet(&mut self, out: &mut Vec<f32>) -> Result<bool, String> {
+        loop {
+            let packet = match self.format.next_packet() {
+                Ok(packet) => packet,
+                Err(Error::IoError(e)) if e.kind() == ErrorKind::UnexpectedEof => return Ok(false),
+                Err(Error::ResetRequired) => {
+                    // A new stream is chained on (Ogg); it may be a new
+                    // track with new codec settings.
+                    if let Some(track) = self
+                        .format
+                        .tracks()
+                        .iter()
+                        .find(|t| t.codec_params.codec != CODEC_TYPE_NULL)
+                    {
+                        self.track_id = track.id;
+                        self.params = track.codec_params.clone();
+                    }
+                    self.decoder = codecs()
+                        .make(&self.params, &DecoderOptions::default())
+                        .map_err(|e| e.to_string())?;
+                    continue;
+                }
+                Err(e) => return Err(e.to_string()),
+            };
+            if packet.track_id() != self.track_id {
+                continue;
+            }
+            let decoded = match self.decoder.decode(&packet) {
+                Ok(decoded) => decoded,
+                // A corrupt packet: drop it and carry on with the next.
+                Err(Error::DecodeError(_)) => continue,
+                Err(e) => return Err(e.to_string()),
+            };
+            let spec = *decoded.spec();
+            let frames = decoded.frames();
+            if frames == 0 {
+                continue;
+            }
+            self.rate = spec.rate;
+            self.channels = spec.channels.count() as u16;
+            self.mask = spec.channels.bits();
+
+            let fits = self
+                .buffer
+                .as_ref()
+                .is_some_and(|b| b.capacity() >= decoded.capacity() * spec.channels.count());
+            if !fits {
+                self.buffer = Some(SampleBuffer::new(decoded.capacity() as u64, spec));
+            }
+            let Some(buffer) = self.buffer.as_mut() else {
+                continue;
+            };
+            buffer.copy_interleaved_ref(decoded);
+
+            let skip = (self.skip_frames as usize).min(frames);
+            self.skip_frames -= skip as u64;
+            out.extend_from_slice(&buffer.samples()[skip * self.channels as usize..]);
+            return Ok(true);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+    use std::path::PathBuf;
+
+    const RATE: u32 = 44100;
+    const FRAMES: usize = 10_000;
+
+    /// Decodes `path` and checks it matches `expected` sample for sample.
+    fn check_exact(path: &Path, channels: u16, expected: &[f32]) {
+        let (rate, ch, samples) = corpus::decode(path);
+        assert_eq!((rate, ch), (RATE, channels));
+        assert_eq!(samples.len(), expected.len());
+        if let Some(i) = (0..samples.len()).find(|&i| samples[i] != expected[i]) {
+            panic!("sample {i}: got {}, want {}", samples[i], expected[i]);
+        }
+    }
+
+    /// Decodes a silent lossy file, checking its format and length.
+    fn check_silent(path: &Path, rate: u32, channels: u16, frames: usize) {
+        let (r, ch, samples) = corpus::decode(path);
+        assert_eq!((r, ch), (rate, channels));
+        assert_eq!(samples.len(), frames * channels as usize);
+        assert!(samples.iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn wav_16_bit() {
+        let signal = corpus::signal(FRAMES, 2, 16);
+        let path = corpus::wav_int("16.wav", RATE, 2, 16, &signal);
+        check_exact(&path, 2, &corpus::expected(&signal, 16));
+    }
+
+    #[test]
+    fn wav_24_bit_keeps_low_bits() {
+        let signal = corpus::signal(FRAMES, 2, 24);
+        assert!(signal.iter().any(|s| s & 0xFF != 0));
+        let path = corpus::wav_int("24.wav", RATE, 2, 24, &signal);
+        check_exact(&path, 2, &corpus::expected(&signal, 24));
+    }
+
+    #[test]
+    fn wav_32_bit() {
+        let signal = corpus::signal(FRAMES, 1, 32);
+        let path = corpus::wav_int("32.wav", RATE, 1, 32, &signal);
+        check_exact(&path, 1, &corpus::expected(&signal, 32));
+    }
+
+    #[test]
+    fn wav_float_passes_through() {
+        let signal = corpus::float_signal(FRAMES, 2);
+        let path = corpus::wav_float("float.wav", RATE, 2, &signal);
+        check_exact(&path, 2, &signal);
+    }
+
+    #[test]
+    fn reads_the_channel_mask_only_where_the_file_has_one() {
+        let signal = corpus::float_signal(FRAMES, 4);
+        let open = |path: PathBuf| AudioDecoder::open(path.to_str().unwrap()).unwrap();
+        // 3.1: front left / right, centre, LFE.
+        let path = corpus::wav_float_with_mask("3.1.wav", RATE, 4, 0xF, &signal);
+        check_exact(&path, 4, &signal);
+        assert_eq!(open(path).channel_mask(), Some(0xF));
+        let path = corpus::wav_float("4ch.wav", RATE, 4, &signal);
+        assert_eq!(open(path).channel_mask(), None);
+        // FLAC defines four channels as quad.
+        let signal = corpus::signal(FRAMES, 4, 16);
+        let path = corpus::flac("4ch.flac", RATE, 4, 16, &signal);
+        assert_eq!(open(path).channel_mask(), Some(0x33));
+    }
+
+    #[test]
+    fn flac_16_and_24_bit() {
+        for bits in [16, 24] {
+            let signal = corpus::signal(FRAMES, 2, bits as u32);
+            let path = corpus::flac(&format!("{bits}.flac"), RATE, 2, bits, &signal);
+            check_exact(&path, 2, &corpus::expected(&signal, bits as u32));
+        }
+    }
+
+    #[test]
+    fn alac_16_and_24_bit() {
+        for (bits, channels) in [(16, 2), (24, 2), (24, 1)] {
+            let signal = corpus::signal(FRAMES, channels as usize, bits as u32);
+            let name = format!("{bits}-{channels}.m4a");
+            let path = corpus::alac(&name, RATE, channels, bits, &signal);
+            check_exact(&path, channels, &corpus::expected(&signal, bits as u32));
+        }
+    }
+
+    #[test]
+    fn aac_in_m4a() {
+        let path = corpus::aac_silence("aac.m4a", 48000, 20);
+        let (rate, channels, samples) = corpus::decode(&path);
+        assert_eq!((rate, channels), (48000, 1));
+        // Gapless trims the encoder delay, if the container gives one.
+        assert!(samples.len() <= 20 * 1024 && samples.len() >= 18 * 1024);
+        assert!(samples.iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn mp3() {
+        let path = corpus::mp3_silence("silence.mp3", 40);
+        let (rate, channels, samples) = corpus::decode(&path);
+        assert_eq!((rate, channels), (44100, 1));
+        assert!(samples.len() <= 40 * 1152 && samples.len() >= 38 * 1152);
+        assert!(samples.iter().all(|s| s.abs() < 1e-6));
+    }
+
+    #[test]
+    fn vorbis_in_ogg() {
+        let path = corpus::vorbis_silence("silence.ogg", 48000, 2, 120);
+        check_silent(&path, 48000, 2, 119 * 128);
+    }
+
+    /// Decodes a 48 kHz Opus file and checks each channel follows its
+    /// source, allowing for the codec's loss.
+    fn check_opus(path: &Path, signal: &[f32], channels: usize, frames: usize) {
+        let (rate, ch, samples) = corpus::decode(path);
+        assert_eq!((rate, ch as usize), (48000, channels));
+        assert_eq!(samples.len(), frames * channels);
+        for c in 0..channels {
+            let (mut power, mut noise) = (0.0, 0.0);
+            for (&got, &want) in samples.iter().zip(signal).skip(c).step_by(channels) {
+                power += want * want;
+                noise += (got - want) * (got - want);
+            }
+            let snr = 10.0 * (power / noise).log10();
+            assert!(snr > 15.0, "channel {c}: {snr:.1} dB");
+        }
+    }
+
+    /// Stereo with a different tone in each channel.
+    fn opus_signal(frames: usize) -> Vec<f32> {
+        (0..frames)
+            .flat_map(|i| {
+                let t = i as f32 / 48000.0;
+                let tau = std::f32::consts::TAU;
+                [(tau * 440.0 * t).sin() * 0.5, (tau * 660.0 * t).sin() * 0.3]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn opus_in_ogg() {
+        let signal = opus_signal(FRAMES * 3);
+        let path = corpus::ogg_opus("stereo.opus", 2, &signal, &[]);
+        check_opus(&path, &signal, 2, FRAMES * 3);
+
+        let mono: Vec<f32> = signal.iter().step_by(2).copied().collect();
+        let path = corpus::ogg_opus("mono.opus", 1, &mono, &[]);
+        check_opus(&path, &mono, 1, FRAMES * 3);
+    }
+
+    #[test]
+    fn opus_seek() {
+        let signal = opus_signal(FRAMES * 3);
+        let path = corpus::ogg_opus("seek.opus", 2, &signal, &[]);
+        let mut decoder = AudioDecoder::open(path.to_str().unwrap()).unwrap();
+        decoder.seek(Duration::from_millis(300)).unwrap();
+        let samples = decoder.read_all(usize::MAX);
+        let frame = 48000 * 3 / 10;
+        assert_eq!(samples.len(), (FRAMES * 3 - frame) * 2);
+        // Past the first packet, once the decoder has settled.
+        let settled = 960 * 2;
+        let (mut power, mut noise) = (0.0, 0.0);
+        for (&got, &want) in samples[settled..]
+            .iter()
+            .zip(&signal[frame * 2 + settled..])
+        {
+            power += want * want;
+            noise += (got - want) * (got - want);
+        }
+        assert!(10.0 * (power / noise).log10() > 15.0);
+    }
+
+    #[test]
+    fn opus_in_webm() {
+        let signal = opus_signal(FRAMES * 3);
+        let path = corpus::webm_opus("stereo.webm", 2, &signal);
+        let decoder = AudioDecoder::open(path.to_str().unwrap()).unwrap();
+        let packets = (FRAMES * 3).div_ceil(960);
+        // Matroska counts the duration in milliseconds, not frames.
+        let length = decoder.duration().unwrap().as_secs_f64();
+        assert!((length - packets as f64 * 0.02).abs() < 1e-6);
+        // No end trim: whole packets less libopus's 312-frame pre-skip.
+        check_opus(&path, &signal, 2, packets * 960 - 312);
+    }
+
+    #[test]
+    fn probe_reads_format_and_tags() {
+        let signal = corpus::signal(FRAMES, 2, 24);
+        let path = corpus::flac("probe.flac", RATE, 2, 24, &signal);
+        let media = probe(path.to_str().unwrap()).unwrap();
+        assert_eq!((media.sample_rate, media.channels), (RATE, 2));
+        assert_eq!((media.codec.as_str(), media.bit_depth), ("flac", Some(24)));
+        let want = FRAMES as f64 * 1000.0 / RATE as f64;
+        assert!((media.duration_ms - want).abs() < 0.01);
+        assert_eq!(media.title, None);
+
+        let signal = opus_signal(FRAMES * 3);
+        let tags = ["TITLE=Air horn", "ARTIST=Someone", "ALBUM=Memes"];
+        let path = corpus::ogg_opus("probe.opus", 2, &signal, &tags);
+        let media = probe(path.to_str().unwrap()).unwrap();
+        assert_eq!((media.sample_rate, media.channels), (48000, 2));
+        assert_eq!((media.codec.as_str(), media.bit_depth), ("opus", None));
+        assert!((media.duration_ms - FRAMES as f64 * 3.0 / 48.0).abs() < 0.01);
+        assert_eq!(media.title.as_deref(), Some("Air horn"));
+        assert_eq!(media.artist.as_deref(), Some("Someone"));
+        assert_eq!(media.album.as_deref(), Some("Memes"));
+        assert!(!media.has_album_art);
+    }
+
+    #[test]
+    fn probe_scans_for_a_missing_length() {
+        // No Xing or LAME header, so only the frames give the length.
+        let path = corpus::mp3_silence("probe.mp3", 40);
+        let media = probe(path.to_str().unwrap()).unwrap();
+        assert_eq!(media.codec, "mp3");
+        let (_, _, samples) = corpus::decode(&path);
+        let want = samples.len() as f64 * 1000.0 / 44100.0;
+        assert!(
+            (media.duration_ms - want).abs() < 0.01,
+            "{}",
+            media.duration_ms
+        );
+    }
+
+    #[test]
+    fn seek_is_sample_exact() {
+        let signal = corpus::signal(FRAMES, 2, 24);
+        let expected = corpus::expected(&signal, 24);
+        let path = corpus::flac("seek.flac", RATE, 2, 24, &signal);
+        let mut decoder = AudioDecoder::open(path.to_str().unwrap()).unwrap();
+        let frame = 6000;
+        decoder
+            .seek(Duration::from_secs_f64(frame as f64 / RATE as f64))
+            .unwrap();
+        let samples = decoder.read_all(usize::MAX);
+        assert_eq!(samples, expected[frame * 2..]);
+    }
+}
@@ -0,0 +1,125 @@
+use std::collections::VecDeque;
+
+use nnnoiseless::DenoiseState;
+
+use crate::mixer::StreamResampler;
+
+/// RNNoise always runs at 48 kHz; other device rates are resampled on the
+/// way in and out.
+pub const INTERNAL_RATE: u32 = 48_000;
+
+/// RNNoise hop size: 10 ms at 48 kHz.
+const FRAME_SIZE: usize = DenoiseState::FRAME_SIZE;
+
+/// RNNoise works on samples in the 16-bit range.
+const PCM_SCALE: f32 = 32768.0;
+
+/// Streaming RNNoise noise suppressor for the mono mic signal.
+///
+/// Incoming blocks of arbitrary size are resampled to 48 kHz, cut into
+/// 480-sample frames, run through the RNNoise network (the `nnnoiseless`
+/// port, with its trained weights), resampled back and queued.  The queue
+/// is primed with one frame of silence so a full frame is always ready
+/// before the caller needs it; the added latency is 10 ms.
+///
+/// Everything is sized for one device rate in `reset`, which allocates and
+/// so belongs on the command loop, not the output callback.
+pub struct Denoiser {
+    state: Box<DenoiseState<'static>>,
+    rate: u32,
+    to_internal: StreamResampler,
+    from_internal: StreamResampler,
+    /// 48 kHz input waiting for a full frame.
+    pending: Vec<f32>,
+    /// Processed samples at the device rate, ready for output.
+    ready: VecDeque<f32>,
+    input: Vec<f32>,
+    frame: Vec<f32>,
+    resampled: Vec<f32>,
+}
+
+impl Denoiser {
+    pub fn new() -> Self {
+        let mut d = Self {
+            state: DenoiseState::new(),
+            rate: INTERNAL_RATE,
+            to_internal: StreamResampler::new(INTERNAL_RATE, INTERNAL_RATE),
+            from_internal: StreamResampler::new(INTERNAL_RATE, INTERNAL_RATE),
+            pending: Vec::with_capacity(FRAME_SIZE * 4),
+            ready: VecDeque::with_capacity(FRAME_SIZE * 8),
+            input: vec![0.0; FRAME_SIZE],
+            frame: vec![0.0; FRAME_SIZE],
+            resampled: Vec::with_capacity(FRAME_SIZE * 4),
+        };
+        d.reset(INTERNAL_RATE);
+        d
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Drop all buffered audio and network state and adapt to `rate`.
+    pub fn reset(&mut self, rate: u32) {
+        self.state = DenoiseState::new();
+        self.rate = rate;
+        self.to_internal = StreamResampler::new(rate, INTERNAL_RATE);
+        self.from_internal = StreamResampler::new(INTERNAL_RATE, rate);
+        self.pending.clear();
+        self.ready.clear();
+
+        // Prime with one frame (plus a little slack for resampler rounding).
+        let prime = FRAME_SIZE * rate as usize / INTERNAL_RATE as usize + 32;
+        self.ready.extend(std::iter::repeat_n(0.0, prime));
+    }
+
+    /// Denoise a block of mono samples in place.  The block must be at the
+    /// rate the denoiser was last reset for.
+    pub fn process(&mut self, samples: &mut [f32]) {
+        self.to_internal.process(samples, &mut self.pending);
+
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= FRAME_SIZE {
+            for (i, s) in self.input.iter_mut().zip(&self.pending[consumed..]) {
+                *i = s * PCM_SCALE;
+            }
+            self.state.process_frame(&mut self.frame, &self.input);
+            for s in self.frame.iter_mut() {
+                *s /= PCM_SCALE;
+            }
+            consumed += FRAME_SIZE;
+
+            self.resampled.clear();
+            self.from_internal.process(&self.frame, &mut self.resampled);
+            self.ready.extend(self.resampled.iter().copied());
+        }
+        self.pending.drain(..consumed);
+
+        for s in samples.iter_mut() {
+            *s = self.ready.pop_front().unwrap_or(0.0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    #[test]
+    fn suppresses_steady_noise_at_any_rate() {
+        for rate in [48_000, 44_100] {
+            let mut denoiser = Denoiser::new();
+            denoiser.reset(rate);
+            let input = corpus::noise(rate as usize * 4, 0.03);
+            let mut output = input.clone();
+            for block in output.chunks_mut(441) {
+                denoiser.process(block);
+            }
+            // Judge the last second, once the network has settled.
+            let last = input.len() - rate as usize;
+            let ratio = corpus::power(&output[last..]) / corpus::power(&input[last..]);
+            assert!(ratio < 0.1, "{rate} Hz: kept {ratio} of the noise energy");
+        }
+    }
+}
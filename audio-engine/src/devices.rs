@@ -1,53 +1,184 @@
-use cpal::traits::{DeviceTrait, HostTrait};
-
-/// Return the names of all available audio input (capture) devices.
-pub fn list_input_devices() -> Vec<String> {
-    let host = cpal::default_host();
-    host.input_devices()
-        .map(|devices| {
-            devices
-                .filter_map(|d| d.name().ok())
-                .collect()
-        })
-        .unwrap_or_default()
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+
+use crate::null_audio::{NullDevice, NullHost, NullStream};
+
+/// Sample rate and channel count of a stream.  Samples are always f32.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct StreamFormat {
+    pub sample_rate: u32,
+    pub channels: u16,
 }
 
-/// Return the names of all available audio output (render) devices.
-pub fn list_output_devices() -> Vec<String> {
-    let host = cpal::default_host();
-    host.output_devices()
-        .map(|devices| {
-            devices
-                .filter_map(|d| d.name().ok())
-                .collect()
-        })
-        .unwrap_or_default()
+/// Where the engine's audio comes from and goes to: the system's devices
+/// through cpal, or the virtual devices of the null backend.
+pub enum Host {
+    Cpal(cpal::Host),
+    Null(NullHost),
 }
 
-/// Find an input device whose name exactly matches `name`.
-pub fn find_input_device(name: &str) -> Option<cpal::Device> {
-    let host = cpal::default_host();
-    host.input_devices().ok()?.find(|d| {
-        d.name().map(|n| n == name).unwrap_or(false)
-    })
+impl Host {
+    /// The system's default audio host.
+    pub fn system() -> Self {
+        Host::Cpal(cpal::default_host())
+    }
+
+    /// Return the names of all available audio input (capture) devices.
+    pub fn list_input_devices(&self) -> Vec<String> {
+        match self {
+            Host::Cpal(host) => host
+                .input_devices()
+                .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+                .unwrap_or_default(),
+            Host::Null(host) => host.input_devices(),
+        }
+    }
+
+    /// Return the names of all available audio output (render) devices.
+    pub fn list_output_devices(&self) -> Vec<String> {
+        match self {
+            Host::Cpal(host) => host
+                .output_devices()
+                .map(|devices| devices.filter_map(|d| d.name().ok()).collect())
+                .unwrap_or_default(),
+            Host::Null(host) => host.output_devices(),
+        }
+    }
+
+    /// Find an input device whose name exactly matches `name`.
+    pub fn find_input_device(&self, name: &str) -> Option<Device> {
+        match self {
+            Host::Cpal(host) => host
+                .input_devices()
+                .ok()?
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .map(Device::Cpal),
+            Host::Null(host) => host.find_input_device(name).map(Device::Null),
+        }
+    }
+
+    /// Find an output device whose name exactly matches `name`.
+    pub fn find_output_device(&self, name: &str) -> Option<Device> {
+        match self {
+            Host::Cpal(host) => host
+                .output_devices()
+                .ok()?
+                .find(|d| d.name().map(|n| n == name).unwrap_or(false))
+                .map(Device::Cpal),
+            Host::Null(host) => host.find_output_device(name).map(Device::Null),
+        }
+    }
+
+    /// Return the default input device, if any.
+    pub fn default_input_device(&self) -> Option<Device> {
+        match self {
+            Host::Cpal(host) => host.default_input_device().map(Device::Cpal),
+            Host::Null(host) => host.default_input_device().map(Device::Null),
+        }
+    }
+
+    /// Return the default output device, if any.
+    pub fn default_output_device(&self) -> Option<Device> {
+        match self {
+            Host::Cpal(host) => host.default_output_device().map(Device::Cpal),
+            Host::Null(host) => host.default_output_device().map(Device::Null),
+        }
+    }
+}
+
+/// An input or output device of a `Host`.
+pub enum Device {
+    Cpal(cpal::Device),
+    Null(NullDevice),
+}
+
+impl Device {
+    /// The format the device prefers; streams are opened in it.
+    pub fn default_format(&self, is_input: bool) -> Result<StreamFormat, String> {
+        let device = match self {
+            Device::Cpal(device) => device,
+            Device::Null(device) => return Ok(device.format()),
+        };
+        let supported = if is_input {
+            device
+                .default_input_config()
+                .map_err(|e| format!("No supported input stream config: {e}"))?
+        } else {
+            device
+                .default_output_config()
+                .map_err(|e| format!("No supported output stream config: {e}"))?
+        };
+        Ok(StreamFormat {
+            sample_rate: supported.sample_rate().0,
+            channels: supported.channels(),
+        })
+    }
+
+    /// Open a capture stream; `data` gets each block of interleaved
+    /// samples.  It starts on `Stream::play`.
+    pub fn build_input_stream(
+        &self,
+        format: StreamFormat,
+        mut data: impl FnMut(&[f32]) + Send + 'static,
+        error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<Stream, String> {
+        match self {
+            Device::Cpal(device) => device
+                .build_input_stream(
+                    &stream_config(format),
+                    move |samples: &[f32], _: &cpal::InputCallbackInfo| data(samples),
+                    error,
+                    None,
+                )
+                .map(Stream::Cpal)
+                .map_err(|e| e.to_string()),
+            Device::Null(device) => device.build_input_stream(data).map(Stream::Null),
+        }
+    }
+
+    /// Open a render stream; `data` fills each block with interleaved
+    /// samples.  It starts on `Stream::play`.
+    pub fn build_output_stream(
+        &self,
+        format: StreamFormat,
+        mut data: impl FnMut(&mut [f32]) + Send + 'static,
+        error: impl FnMut(cpal::StreamError) + Send + 'static,
+    ) -> Result<Stream, String> {
+        match self {
+            Device::Cpal(device) => device
+                .build_output_stream(
+                    &stream_config(format),
+                    move |samples: &mut [f32], _: &cpal::OutputCallbackInfo| data(samples),
+                    error,
+                    None,
+                )
+                .map(Stream::Cpal)
+                .map_err(|e| e.to_string()),
+            Device::Null(device) => device.build_output_stream(data).map(Stream::Null),
+        }
+    }
 }
 
-/// Find an output device whose name exactly matches `name`.
-pub fn find_output_device(name: &str) -> Option<cpal::Device> {
-    let host = cpal::default_host();
-    host.output_devices().ok()?.find(|d| {
-        d.name().map(|n| n == name).unwrap_or(false)
-    })
+/// An open stream.  Dropping it closes it.
+pub enum Stream {
+    Cpal(cpal::Stream),
+    Null(NullStream),
 }
 
-/// Return the default input device, if any.
-pub fn default_input_device() -> Option<cpal::Device> {
-    let host = cpal::default_host();
-    host.default_input_device()
+impl Stream {
+    pub fn play(&self) -> Result<(), String> {
+        match self {
+            Stream::Cpal(stream) => stream.play().map_err(|e| e.to_string()),
+            Stream::Null(stream) => stream.play(),
+        }
+    }
 }
 
-/// Return the default output device, if any.
-pub fn default_output_device() -> Option<cpal::Device> {
-    let host = cpal::default_host();
-    host.default_output_device()
+fn stream_config(format: StreamFormat) -> cpal::StreamConfig {
+    // We always request f32 samples to keep the mixing simple; cpal converts
+    // for devices whose native format differs.
+    cpal::StreamConfig {
+        channels: format.channels,
+        sample_rate: cpal::SampleRate(format.sample_rate),
+        buffer_size: cpal::BufferSize::Default,
+    }
 }
@@ -0,0 +1,510 @@
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use crate::protocol::AudioFileFormat;
+
+/// Frames per FLAC frame.
+const FLAC_BLOCK: usize = 4096;
+/// Highest Rice parameter the 4-bit residual coding method allows (15
+/// means an escape).
+const MAX_RICE: u32 = 14;
+/// Highest residual partition order tried.
+const MAX_PARTITION_ORDER: u32 = 6;
+/// Most channels either format is written with.
+pub const MAX_CHANNELS: u16 = 8;
+/// Highest rate a FLAC frame header can hold (in tens of Hz).
+pub const MAX_SAMPLE_RATE: u32 = 655_350;
+
+/// Writes interleaved float audio to a WAV or FLAC file as it arrives,
+/// filling in the lengths in the header when finished.
+///
+/// WAV holds 16 or 24-bit integers or 32-bit floats; FLAC 16 or 24-bit
+/// integers, compressed with its fixed predictors.
+pub struct AudioWriter {
+    out: BufWriter<File>,
+    format: AudioFileFormat,
+    rate: u32,
+    channels: u16,
+    bits: u16,
+    frames: u64,
+    /// FLAC: samples waiting to fill a frame.
+    pending: Vec<i32>,
+    /// FLAC: frames written so far.
+    flac_frames: u64,
+}
+
+impl AudioWriter {
+    pub fn create(
+        path: &Path,
+        format: AudioFileFormat,
+        rate: u32,
+        channels: u16,
+        bits: u16,
+    ) -> Result<Self, String> {
+        let supported = match format {
+            AudioFileFormat::Wav => matches!(bits, 16 | 24 | 32),
+            AudioFileFormat::Flac => matches!(bits, 16 | 24),
+        };
+        if !supported {
+            return Err(format!("Unsupported bit depth for {format:?}: {bits}"));
+        }
+        if !(1..=MAX_CHANNELS).contains(&channels) || !(1..=MAX_SAMPLE_RATE).contains(&rate) {
+            return Err(format!(
+                "Unsupported format: {rate} Hz, {channels} channels"
+            ));
+        }
+        let file =
+            File::create(path).map_err(|e| format!("Cannot create {}: {e}", path.display()))?;
+        let mut writer = Self {
+            out: BufWriter::new(file),
+            format,
+            rate,
+            channels,
+            bits,
+            frames: 0,
+            pending: Vec::new(),
+            flac_frames: 0,
+        };
+        // Written again with the lengths once they are known.
+        writer.write_header().map_err(|e| writer.error(e))?;
+        Ok(writer)
+    }
+
+    /// Append interleaved samples (whole frames).
+    pub fn write(&mut self, samples: &[f32]) -> Result<(), String> {
+        self.frames += (samples.len() / self.channels as usize) as u64;
+        let result = match self.format {
+            AudioFileFormat::Wav => self.write_wav(samples),
+            AudioFileFormat::Flac => {
+                let bits = self.bits;
+                self.pending
+                    .extend(samples.iter().map(|&s| quantize(s, bits)));
+                let block = FLAC_BLOCK * self.channels as usize;
+                let mut written = 0;
+                let mut result = Ok(());
+                while result.is_ok() && self.pending.len() - written >= block {
+                    let frame = self.pending[written..written + block].to_vec();
+                    result = self.write_flac_frame(&frame);
+                    written += block;
+                }
+                self.pending.drain(..written);
+                result
+            }
+        };
+        result.map_err(|e| self.error(e))
+    }
+
+    /// Flush what is left and fill in the header.
+    pub fn finish(mut self) -> Result<(), String> {
+        let result = self.finish_inner();
+        result.map_err(|e| self.error(e))
+    }
+
+    fn finish_inner(&mut self) -> std::io::Result<()> {
+        if !self.pending.is_empty() {
+            let frame = std::mem::take(&mut self.pending);
+            self.write_flac_frame(&frame)?;
+        }
+        if self.format == AudioFileFormat::Wav && self.wav_data_bytes() % 2 == 1 {
+            // RIFF chunks are padded to an even length.
+            self.out.write_all(&[0])?;
+        }
+        self.out.seek(SeekFrom::Start(0))?;
+        self.write_header()?;
+        self.out.flush()?;
+        self.out.get_ref().sync_all()
+    }
+
+    fn error(&self, e: std::io::Error) -> String {
+        format!("Cannot write audio file: {e}")
+    }
+
+    fn write_header(&mut self) -> std::io::Result<()> {
+        match self.format {
+            AudioFileFormat::Wav => self.write_wav_header(),
+            AudioFileFormat::Flac => {
+                self.out.write_all(b"fLaC")?;
+                // The only metadata block: STREAMINFO, 34 bytes.
+                self.out.write_all(&[0x80, 0, 0, 34])?;
+                let info = self.streaminfo();
+                self.out.write_all(&info)
+            }
+        }
+    }
+
+    // -- WAV ------------------------------------------------------------------
+
+    fn wav_data_bytes(&self) -> u64 {
+        self.frames * self.channels as u64 * self.bits as u64 / 8
+    }
+
+    fn write_wav_header(&mut self) -> std::io::Result<()> {
+        let block = self.channels as u32 * self.bits as u32 / 8;
+        // Longer files (over 4 GB) claim the most a WAV can hold.
+        let data = self.wav_data_bytes().min(u32::MAX as u64 - 36) as u32;
+        let tag: u16 = if self.bits == 32 { 3 } else { 1 };
+        let out = &mut self.out;
+        out.write_all(b"RIFF")?;
+        out.write_all(&(36 + data + data % 2).to_le_bytes())?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_all(&16u32.to_le_bytes())?;
+        out.write_all(&tag.to_le_bytes())?;
+        out.write_all(&self.channels.to_le_bytes())?;
+        out.write_all(&self.rate.to_le_bytes())?;
+        out.write_all(&(self.rate * block).to_le_bytes())?;
+        out.write_all(&(block as u16).to_le_bytes())?;
+        out.write_all(&self.bits.to_le_bytes())?;
+        out.write_all(b"data")?;
+        out.write_all(&data.to_le_bytes())
+    }
+
+    fn write_wav(&mut self, samples: &[f32]) -> std::io::Result<()> {
+        for &sample in samples {
+            match self.bits {
+                32 => self.out.write_all(&sample.to_le_bytes())?,
+                bits => {
+                    let bytes = quantize(sample, bits).to_le_bytes();
+                    self.out.write_all(&bytes[..bits as usize / 8])?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // -- FLAC -----------------------------------------------------------------
+
+    fn streaminfo(&self) -> Vec<u8> {
+        let mut info = BitWriter::default();
+        info.put(16, FLAC_BLOCK as u64);
+        info.put(16, FLAC_BLOCK as u64);
+        // Frame sizes unknown.
+        info.put(24, 0);
+        info.put(24, 0);
+        info.put(20, self.rate as u64);
+        info.put(3, self.channels as u64 - 1);
+        info.put(5, self.bits as u64 - 1);
+        info.put(36, self.frames);
+        // No MD5 signature.
+        info.put(64, 0);
+        info.put(64, 0);
+        info.finish()
+    }
+
+    fn write_flac_frame(&mut self, samples: &[i32]) -> std::io::Result<()> {
+        let channels = self.channels as usize;
+        let len = samples.len() / channels;
+        if len == 0 {
+            return Ok(());
+        }
+        let mut frame = BitWriter::default();
+        frame.put(16, 0xFFF8);
+        // Block size in 16 bits after the header; rate from STREAMINFO.
+        frame.put(4, 0b0111);
+        frame.put(4, 0);
+        frame.put(4, channels as u64 - 1);
+        frame.put(3, if self.bits == 24 { 0b110 } else { 0b100 });
+        frame.put(1, 0);
+        put_utf8(&mut frame, self.flac_frames);
+        frame.put(16, len as u64 - 1);
+        let crc = crc8(&frame.bytes);
+        frame.put(8, crc as u64);
+
+        let mut channel = Vec::with_capacity(len);
+        for c in 0..channels {
+            channel.clear();
+            channel.extend(samples.iter().skip(c).step_by(channels).map(|&s| s as i64));
+            write_subframe(&mut frame, &channel, self.bits as u32);
+        }
+        let mut bytes = frame.finish();
+        let crc = crc16(&bytes);
+        bytes.extend_from_slice(&crc.to_be_bytes());
+        self.out.write_all(&bytes)?;
+        self.flac_frames += 1;
+        Ok(())
+    }
+}
+
+/// Round `sample` to a `bits`-bit integer, clipping.
+fn quantize(sample: f32, bits: u16) -> i32 {
+    let scale = (1i64 << (bits - 1)) as f64;
+    (sample as f64 * scale).round().clamp(-scale, scale - 1.0) as i32
+}
+
+/// One channel of a FLAC frame: constant when it is (silence), else the
+/// cheapest fixed predictor, else verbatim.
+fn write_subframe(out: &mut BitWriter, samples: &[i64], bits: u32) {
+    if samples.iter().all(|&s| s == samples[0]) {
+        out.put(8, 0);
+        out.put(bits, samples[0] as u64 & mask(bits));
+        return;
+    }
+
+    let mut best: Option<(u64, usize, Vec<i64>)> = None;
+    for order in 0..=4.min(samples.len() - 1) {
+        let residual = fixed_residual(samples, order);
+        let (cost, _) = residual_coding(&residual, order, samples.len());
+        let cost = cost + (order as u64) * bits as u64;
+        if best.as_ref().is_none_or(|(c, ..)| cost < *c) {
+            best = Some((cost, order, residual));
+        }
+    }
+    let (cost, order, residual) = best.unwrap();
+    if cost >= samples.len() as u64 * bits as u64 {
+        out.put(8, 0b0000_0010);
+        for &s in samples {
+            out.put(bits, s as u64 & mask(bits));
+        }
+        return;
+    }
+
+    out.put(8, (0b00_1000 | order as u64) << 1);
+    for &s in &samples[..order] {
+        out.put(bits, s as u64 & mask(bits));
+    }
+    let (_, partition_order) = residual_coding(&residual, order, samples.len());
+    out.put(2, 0);
+    out.put(4, partition_order as u64);
+    let partitions = 1usize << partition_order;
+    let size = samples.len() >> partition_order;
+    let mut at = 0;
+    for p in 0..partitions {
+        let n = if p == 0 { size - order } else { size };
+        let part = &residual[at..at + n];
+        at += n;
+        let k = best_rice(part).1;
+        out.put(4, k as u64);
+        for &r in part {
+            let u = zigzag(r);
+            let q = u >> k;
+            // Unary quotient, then the low `k` bits.
+            for _ in 0..q / 32 {
+                out.put(32, 0);
+            }
+            out.put((q % 32) as u32 + 1, 1);
+            if k > 0 {
+                out.put(k, u & ((1 << k) - 1));
+            }
+        }
+    }
+}
+
+/// Residual of the fixed predictor of `order`, from sample `order` on.
+fn fixed_residual(samples: &[i64], order: usize) -> Vec<i64> {
+    (order..samples.len())
+        .map(|i| {
+            let s = |j: usize| samples[i - j];
+            match order {
+                0 => s(0),
+                1 => s(0) - s(1),
+                2 => s(0) - 2 * s(1) + s(2),
+                3 => s(0) - 3 * s(1) + 3 * s(2) - s(3),
+                _ => s(0) - 4 * s(1) + 6 * s(2) - 4 * s(3) + s(4),
+            }
+        })
+        .collect()
+}
+
+/// Bits to Rice-code `residual` with the best partition order, and that
+/// order.
+fn residual_coding(residual: &[i64], order: usize, len: usize) -> (u64, u32) {
+    let mut best = (u64::MAX, 0);
+    for partition_order in 0..=MAX_PARTITION_ORDER {
+        let partitions = 1usize << partition_order;
+        let size = len >> partition_order;
+        // Every partition must hold whole samples, and the first one more
+        // than the warm-up.
+        if !len.is_multiple_of(partitions) || size <= order {
+            break;
+        }
+        let mut bits = 6;
+        let mut at = 0;
+        for p in 0..partitions {
+            let n = if p == 0 { size - order } else { size };
+            bits += 4 + best_rice(&residual[at..at + n]).0;
+            at += n;
+        }
+        if bits < best.0 {
+            best = (bits, partition_order);
+        }
+    }
+    best
+}
+
+/// Bits to Rice-code `part` with its best parameter, and that parameter.
+fn best_rice(part: &[i64]) -> (u64, u32) {
+    (0..=MAX_RICE)
+        .map(|k| {
+            let bits: u64 = part.iter().map(|&r| (zigzag(r) >> k) + 1 + k as u64).sum();
+            (bits, k)
+        })
+        .min()
+        .unwrap_or((0, 0))
+}
+
+fn zigzag(r: i64) -> u64 {
+    ((r << 1) ^ (r >> 63)) as u64
+}
+
+fn mask(bits: u32) -> u64 {
+    (1 << bits) - 1
+}
+
+/// A FLAC frame number, UTF-8 coded.
+fn put_utf8(out: &mut BitWriter, n: u64) {
+    if n < 0x80 {
+        out.put(8, n);
+        return;
+    }
+    let continuation = (1..=6).find(|&c| n < 1 << (6 + 5 * c)).unwrap_or(6);
+    let lead_bits = 6 - continuation as u32;
+    let lead = (0xFF00u64 >> (continuation + 1)) & 0xFF;
+    out.put(8, lead | ((n >> (6 * continuation)) & mask(lead_bits)));
+    for i in (0..continuation).rev() {
+        out.put(8, 0x80 | (n >> (6 * i)) & 0x3F);
+    }
+}
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// Packs values most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    /// Append the low `bits` (at most 32) of `value`.
+    fn put(&mut self, bits: u32, value: u64) {
+        if bits > 32 {
+            self.put(bits - 32, value >> 32);
+            self.put(32, value & 0xFFFF_FFFF);
+            return;
+        }
+        self.acc = (self.acc << bits) | (value & mask(bits));
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.acc >> self.len) as u8);
+        }
+    }
+
+    /// The bytes, with the last one padded with zeros.
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push((self.acc << (8 - self.len)) as u8);
+        }
+        self.bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 44100;
+    /// Not a whole number of FLAC blocks.
+    const FRAMES: usize = 10_000;
+
+    fn write(
+        name: &str,
+        format: AudioFileFormat,
+        channels: u16,
+        bits: u16,
+        samples: &[f32],
+    ) -> String {
+        let path = corpus::path(name);
+        let mut writer = AudioWriter::create(&path, format, RATE, channels, bits).unwrap();
+        // In uneven pieces, as a recording arrives.
+        for piece in samples.chunks(777 * channels as usize) {
+            writer.write(piece).unwrap();
+        }
+        writer.finish().unwrap();
+        path.to_str().unwrap().to_string()
+    }
+
+    fn check_exact(path: &str, channels: u16, expected: &[f32]) {
+        let (rate, ch, samples) = corpus::decode(Path::new(path));
+        assert_eq!((rate, ch), (RATE, channels));
+        assert_eq!(samples.len(), expected.len());
+        if let Some(i) = (0..samples.len()).find(|&i| samples[i] != expected[i]) {
+            panic!(
+                "{path} sample {i}: got {}, want {}",
+                samples[i], expected[i]
+            );
+        }
+    }
+
+    #[test]
+    fn writes_lossless_integer_files() {
+        for bits in [16u16, 24] {
+            for channels in [1u16, 2] {
+                let mut signal = corpus::signal(FRAMES, channels as usize, bits as u32);
+                // A silent stretch, for FLAC's constant subframes.
+                signal[..4096 * channels as usize].fill(0);
+                let samples = corpus::expected(&signal, bits as u32);
+                for (format, ext) in [
+                    (AudioFileFormat::Wav, "wav"),
+                    (AudioFileFormat::Flac, "flac"),
+                ] {
+                    let name = format!("encode-{bits}-{channels}.{ext}");
+                    check_exact(
+                        &write(&name, format, channels, bits, &samples),
+                        channels,
+                        &samples,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn writes_float_wav_and_clips_integers() {
+        let samples = corpus::float_signal(FRAMES, 2);
+        check_exact(
+            &write("encode-float.wav", AudioFileFormat::Wav, 2, 32, &samples),
+            2,
+            &samples,
+        );
+
+        let loud = [1.5, -1.5, 0.5, -0.5];
+        let path = write("encode-clip.flac", AudioFileFormat::Flac, 1, 16, &loud);
+        check_exact(&path, 1, &[32767.0 / 32768.0, -1.0, 0.5, -0.5]);
+    }
+
+    #[test]
+    fn refuses_unsupported_formats() {
+        let path = corpus::path("encode-bad.flac");
+        assert!(AudioWriter::create(&path, AudioFileFormat::Flac, RATE, 2, 32).is_err());
+        assert!(AudioWriter::create(&path, AudioFileFormat::Wav, RATE, 0, 16).is_err());
+    }
+}
@@ -0,0 +1,154 @@
+use crate::biquad::Biquad;
+use crate::protocol::{EqBand, EqBandType};
+
+/// Upper bound on bands per equalizer, to keep the callback cost bounded.
+pub const MAX_EQ_BANDS: usize = 16;
+/// Band gains are clamped to this many dB either way.
+pub const MAX_EQ_GAIN_DB: f32 = 24.0;
+
+/// N-band parametric equalizer: a cascade of biquads, one per band and
+/// channel.
+///
+/// The band list is the source of truth; filter coefficients are derived
+/// from it for whatever rate and channel count the stream runs at, and
+/// rebuilt if either changes (e.g. after switching output device).
+pub struct Equalizer {
+    bands: Vec<EqBand>,
+    rate: u32,
+    channels: usize,
+    /// `filters[band * channels + channel]`.
+    filters: Vec<Biquad>,
+}
+
+impl Equalizer {
+    pub fn new() -> Self {
+        Self {
+            bands: Vec::new(),
+            rate: 0,
+            channels: 0,
+            filters: Vec::new(),
+        }
+    }
+
+    pub fn bands(&self) -> &[EqBand] {
+        &self.bands
+    }
+
+    /// Replace the band list for a stream at `rate`.  An empty list
+    /// bypasses the equalizer.  Gains are clamped to `MAX_EQ_GAIN_DB`.
+    pub fn set_bands(&mut self, mut bands: Vec<EqBand>, rate: u32) -> Result<(), String> {
+        if bands.len() > MAX_EQ_BANDS {
+            return Err(format!("At most {MAX_EQ_BANDS} EQ bands are supported"));
+        }
+        let valid = |b: &EqBand| {
+            b.freq_hz.is_finite() && b.freq_hz > 0.0 && b.q.is_finite() && b.q > 0.0
+        };
+        if let Some(b) = bands.iter().find(|b| !valid(b)) {
+            return Err(format!(
+                "Invalid EQ band: frequency and Q must be positive (got {} Hz, Q {})",
+                b.freq_hz, b.q
+            ));
+        }
+        if let Some(b) = bands.iter().find(|b| !below_nyquist(b, rate)) {
+            return Err(format!(
+                "Invalid EQ band: {} Hz is not below half the {rate} Hz sample rate",
+                b.freq_hz
+            ));
+        }
+        if bands.iter().any(|b| !b.gain_db.is_finite()) {
+            return Err("Invalid EQ band: gain_db must be a number".to_string());
+        }
+        for band in &mut bands {
+            band.gain_db = band.gain_db.clamp(-MAX_EQ_GAIN_DB, MAX_EQ_GAIN_DB);
+        }
+        self.bands = bands;
+        // Force a rebuild on the next block.
+        self.rate = 0;
+        Ok(())
+    }
+
+    fn rebuild(&mut self, channels: usize, rate: u32) {
+        self.rate = rate;
+        self.channels = channels;
+        self.filters.clear();
+        for band in &self.bands {
+            let filter = design(band, rate);
+            self.filters.extend(std::iter::repeat_n(filter, channels));
+        }
+    }
+
+    /// Equalize an interleaved block in place.
+    pub fn process(&mut self, data: &mut [f32], channels: usize, rate: u32) {
+        if self.bands.is_empty() || channels == 0 {
+            return;
+        }
+        if rate != self.rate || channels != self.channels {
+            self.rebuild(channels, rate);
+        }
+        for frame in data.chunks_mut(channels) {
+            for (c, s) in frame.iter_mut().enumerate() {
+                let mut x = *s;
+                for band in 0..self.bands.len() {
+                    x = self.filters[band * channels + c].process(x);
+                }
+                *s = x;
+            }
+        }
+    }
+}
+
+/// Whether `band` can be designed at `rate`.
+fn below_nyquist(band: &EqBand, rate: u32) -> bool {
+    band.freq_hz < rate as f32 / 2.0
+}
+
+/// Compute the biquad for one band at `rate`.  A band at or above Nyquist
+/// (the stream's rate dropped since it was set) is left out: it passes the
+/// signal unchanged.
+fn design(band: &EqBand, rate: u32) -> Biquad {
+    if !below_nyquist(band, rate) {
+        return Biquad::identity();
+    }
+    match band.kind {
+        EqBandType::Peaking => Biquad::peaking(rate, band.freq_hz, band.q, band.gain_db),
+        EqBandType::LowShelf => Biquad::low_shelf(rate, band.freq_hz, band.q, band.gain_db),
+        EqBandType::HighShelf => Biquad::high_shelf(rate, band.freq_hz, band.q, band.gain_db),
+        EqBandType::HighPass => Biquad::high_pass(rate, band.freq_hz, band.q),
+        EqBandType::LowPass => Biquad::low_pass(rate, band.freq_hz, band.q),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn band(freq_hz: f32, gain_db: f32) -> EqBand {
+        EqBand {
+            kind: EqBandType::Peaking,
+            freq_hz,
+            gain_db,
+            q: 1.0,
+        }
+    }
+
+    #[test]
+    fn rejects_bands_it_cannot_design() {
+        let mut eq = Equalizer::new();
+        assert!(eq.set_bands(vec![band(f32::NAN, 0.0)], 48000).is_err());
+        assert!(eq.set_bands(vec![band(1000.0, f32::INFINITY)], 48000).is_err());
+        assert!(eq.set_bands(vec![band(24000.0, 6.0)], 48000).is_err());
+
+        // Extreme gains are clamped, and the output stays finite.
+        eq.set_bands(vec![band(1000.0, 2000.0)], 48000).unwrap();
+        assert_eq!(eq.bands()[0].gain_db, MAX_EQ_GAIN_DB);
+        let mut data: Vec<f32> = (0..4800).map(|i| (i as f32 * 0.13).sin()).collect();
+        eq.process(&mut data, 1, 48000);
+        assert!(data.iter().all(|s| s.is_finite()));
+
+        // A band left above Nyquist by a rate change passes audio through.
+        let mut low = vec![0.5; 64];
+        eq.set_bands(vec![band(15000.0, 12.0)], 48000).unwrap();
+        eq.process(&mut low, 1, 22050);
+        assert!(low.iter().all(|&s| s == 0.5));
+    }
+}
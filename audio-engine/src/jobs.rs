@@ -0,0 +1,107 @@
+//! Commands that read whole files (probing, measuring, rendering) run on a
+//! worker thread, so a long file doesn't hold up the command loop.
+//!
+//! Such a command is answered straight away with `Started { job }`, and its
+//! result follows as `Finished { job, result }` once the worker has run it.
+//! Jobs run one at a time, in the order they were sent.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{self, Sender};
+use std::thread::{self, JoinHandle};
+
+use crate::protocol::Response;
+
+/// Name of the worker thread.  The panic hook leaves reporting its panics
+/// to the worker, which answers for the job that panicked.
+pub const THREAD_NAME: &str = "jobs";
+
+type Job = Box<dyn FnOnce() -> Response + Send>;
+
+pub struct Jobs {
+    queue: Option<Sender<(u64, Job)>>,
+    worker: Option<JoinHandle<()>>,
+    next_id: u64,
+}
+
+impl Jobs {
+    /// Start the worker.  It hands each finished job's response to `reply`.
+    pub fn new(reply: impl Fn(Response) + Send + 'static) -> Self {
+        let (queue, jobs) = mpsc::channel::<(u64, Job)>();
+        let worker = thread::Builder::new()
+            .name(THREAD_NAME.to_string())
+            .spawn(move || {
+                for (job, run) in jobs {
+                    let result = panic::catch_unwind(AssertUnwindSafe(run)).unwrap_or_else(|payload| {
+                        let message = payload
+                            .downcast_ref::<&str>()
+                            .map(|s| s.to_string())
+                            .or_else(|| payload.downcast_ref::<String>().cloned())
+                            .unwrap_or_else(|| "unknown panic".to_string());
+                        Response::error(message)
+                    });
+                    reply(Response::Finished {
+                        job,
+                        result: Box::new(result),
+                    });
+                }
+            })
+            .ok();
+        Self {
+            queue: worker.is_some().then_some(queue),
+            worker,
+            next_id: 0,
+        }
+    }
+
+    /// Queue `run` and return the response that acknowledges it.
+    pub fn start(&mut self, run: impl FnOnce() -> Response + Send + 'static) -> Response {
+        self.next_id += 1;
+        let job = self.next_id;
+        let sent = self
+            .queue
+            .as_ref()
+            .is_some_and(|queue| queue.send((job, Box::new(run))).is_ok());
+        if sent {
+            Response::Started { job }
+        } else {
+            Response::error("The job worker has stopped")
+        }
+    }
+}
+
+impl Drop for Jobs {
+    /// Let the queued jobs finish, so no render is left half-written.
+    fn drop(&mut self) {
+        self.queue = None;
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn answers_each_job_in_order() {
+        let (tx, rx) = mpsc::channel();
+        let mut jobs = Jobs::new(move |resp| tx.send(resp).unwrap());
+        assert!(matches!(jobs.start(|| Response::Ok), Response::Started { job: 1 }));
+        assert!(matches!(
+            jobs.start(|| panic!("bad file")),
+            Response::Started { job: 2 }
+        ));
+        drop(jobs);
+
+        let results: Vec<Response> = rx.iter().collect();
+        assert!(matches!(
+            results.as_slice(),
+            [
+                Response::Finished { job: 1, result: first },
+                Response::Finished { job: 2, result: second },
+            ] if matches!(**first, Response::Ok)
+                && matches!(&**second, Response::Error { message } if message == "bad file")
+        ));
+    }
+}
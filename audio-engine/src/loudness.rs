@@ -0,0 +1,153 @@
+use std::f32::consts::FRAC_1_SQRT_2;
+
+use crate::biquad::Biquad;
+
+/// Gating block length and step (ITU-R BS.1770: 400 ms, 75% overlap).
+const BLOCK_SECONDS: f64 = 0.4;
+const STEP_SECONDS: f64 = 0.1;
+/// Blocks quieter than this never count.
+const ABSOLUTE_GATE_LUFS: f64 = -70.0;
+/// Nor do blocks this far below the loudness of the rest.
+const RELATIVE_GATE_LU: f64 = 10.0;
+
+/// Measures the integrated loudness of interleaved audio in LUFS, as
+/// ITU-R BS.1770 does, a chunk at a time as the audio arrives.
+///
+/// The K-weighting filters are the cookbook shelf and high-pass with
+/// parameters that match the standard's 48 kHz coefficients to within a
+/// few hundredths of a dB, and carry over to other rates.  Every channel is weighted equally
+/// (the standard gives surrounds +1.5 dB).
+pub struct LoudnessMeter {
+    channels: usize,
+    /// Frames per step.
+    step: usize,
+    /// K-weighting (shelf, high-pass) of each channel.
+    filters: Vec<(Biquad, Biquad)>,
+    /// Mean square of the K-weighted signal over each step, summed across
+    /// channels; the last is still filling.
+    steps: Vec<f64>,
+    /// Frames in the last step.
+    filled: usize,
+    frames: usize,
+}
+
+impl LoudnessMeter {
+    pub fn new(rate: u32, channels: u16) -> Self {
+        let channels = channels.max(1) as usize;
+        Self {
+            channels,
+            step: ((STEP_SECONDS * rate as f64) as usize).max(1),
+            filters: (0..channels)
+                .map(|_| {
+                    (
+                        Biquad::high_shelf(rate, 1500.0, FRAC_1_SQRT_2, 4.0),
+                        Biquad::high_pass(rate, 38.0, 0.5),
+                    )
+                })
+                .collect(),
+            steps: Vec::new(),
+            filled: 0,
+            frames: 0,
+        }
+    }
+
+    /// Measure the next interleaved samples.
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks_exact(self.channels) {
+            if self.filled == 0 {
+                self.steps.push(0.0);
+            }
+            let power = self.steps.last_mut().unwrap();
+            for (&sample, (shelf, high_pass)) in frame.iter().zip(&mut self.filters) {
+                let y = high_pass.process(shelf.process(sample)) as f64;
+                *power += y * y;
+            }
+            self.filled = (self.filled + 1) % self.step;
+        }
+        self.frames += samples.len() / self.channels;
+    }
+
+    /// Loudness of everything measured so far, or `None` if it is silent
+    /// throughout.
+    pub fn integrated(&self) -> Option<f32> {
+        if self.frames == 0 {
+            return None;
+        }
+        let (steps, step) = (&self.steps, self.step);
+
+        // Overlapping blocks of four steps; a clip shorter than one block is
+        // measured whole.
+        let per_block = (BLOCK_SECONDS / STEP_SECONDS) as usize;
+        let blocks: Vec<f64> = if steps.len() < per_block {
+            vec![steps.iter().sum::<f64>() / self.frames as f64]
+        } else {
+            steps
+                .windows(per_block)
+                .map(|w| w.iter().sum::<f64>() / (per_block * step) as f64)
+                .collect()
+        };
+
+        let lufs = |power: f64| -0.691 + 10.0 * power.log10();
+        let gated_mean = |gate: f64| {
+            let loud: Vec<f64> = blocks.iter().copied().filter(|&p| lufs(p) > gate).collect();
+            (!loud.is_empty()).then(|| loud.iter().sum::<f64>() / loud.len() as f64)
+        };
+        let relative = lufs(gated_mean(ABSOLUTE_GATE_LUFS)?) - RELATIVE_GATE_LU;
+        let power = gated_mean(relative.max(ABSOLUTE_GATE_LUFS))?;
+        Some(lufs(power) as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn integrated(samples: &[f32], rate: u32, channels: u16) -> Option<f32> {
+        let mut meter = LoudnessMeter::new(rate, channels);
+        meter.push(samples);
+        meter.integrated()
+    }
+
+    fn sine(rate: u32, channels: usize, freq: f32, amplitude: f32, seconds: f32) -> Vec<f32> {
+        let frames = (seconds * rate as f32) as usize;
+        (0..frames * channels)
+            .map(|i| {
+                let t = (i / channels) as f32 / rate as f32;
+                amplitude * (std::f32::consts::TAU * freq * t).sin()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn measures_the_reference_tone() {
+        // A full-scale 997 Hz sine in one channel reads -3.01 LUFS.
+        for rate in [44100, 48000] {
+            let lufs = integrated(&sine(rate, 1, 997.0, 1.0, 5.0), rate, 1).unwrap();
+            assert!((lufs + 3.01).abs() < 0.1, "{rate} Hz: {lufs}");
+        }
+        // Two channels sum, and -20 dB reads 20 LU lower.
+        let lufs = integrated(&sine(48000, 2, 997.0, 0.1, 5.0), 48000, 2).unwrap();
+        assert!((lufs + 20.0).abs() < 0.1, "{lufs}");
+    }
+
+    #[test]
+    fn gates_silence() {
+        assert_eq!(integrated(&[0.0; 48000], 48000, 1), None);
+        // Quiet passages don't drag the measurement down.
+        let mut tone = sine(48000, 1, 997.0, 1.0, 2.0);
+        tone.extend(std::iter::repeat_n(0.0, 48000 * 4));
+        let lufs = integrated(&tone, 48000, 1).unwrap();
+        assert!((lufs + 3.01).abs() < 0.5, "{lufs}");
+    }
+
+    #[test]
+    fn measures_in_pieces() {
+        let tone = sine(44100, 2, 440.0, 0.3, 3.0);
+        let mut meter = LoudnessMeter::new(44100, 2);
+        for piece in tone.chunks(2 * 1001) {
+            meter.push(piece);
+        }
+        let whole = integrated(&tone, 44100, 2).unwrap();
+        assert!((meter.integrated().unwrap() - whole).abs() < 1e-4);
+    }
+}
@@ -1,19 +1,56 @@
+mod aec;
+mod agc;
+mod biquad;
+mod bus;
+mod channel_map;
+mod decode;
+mod denoise;
 mod devices;
+mod encode;
+mod eq;
+mod jobs;
+mod loudness;
+mod mic_chain;
 mod mixer;
+mod null_audio;
+mod opus;
+mod pan;
+mod pcm_cache;
+mod playback;
 mod protocol;
 mod ptt;
+mod queue;
+mod record;
+mod render;
+mod reverb;
+mod sample_cache;
+mod schedule;
+mod silence;
+mod sound_group;
+#[cfg(test)]
+mod test_corpus;
+mod voice_fx;
+mod waveform;
 
 use std::io::{self, BufRead, Write};
 use std::panic;
 
+use devices::Host;
+use jobs::Jobs;
+use null_audio::{NullConfig, NullHost};
 use protocol::{Command, Response};
 
 fn main() {
     // Install a custom panic hook that writes an Error response to stdout
     // instead of printing the default panic message to stderr.  This ensures
     // the Node.js parent process always gets a JSON line it can parse.
+    // Panics in file jobs are answered for by the job worker instead.
     let default_hook = panic::take_hook();
     panic::set_hook(Box::new(move |info| {
+        if std::thread::current().name() == Some(jobs::THREAD_NAME) {
+            default_hook(info);
+            return;
+        }
         let message = if let Some(s) = info.payload().downcast_ref::<&str>() {
             s.to_string()
         } else if let Some(s) = info.payload().downcast_ref::<String>() {
@@ -31,18 +68,32 @@ fn main() {
     }));
 
     // ---- initialise mixer & PTT ----------------------------------------
-    let mut mixer = mixer::MixerState::new();
+    // `--null*` options swap the sound card for files (see `NullConfig`).
+    let host = match NullConfig::from_args(std::env::args().skip(1)).and_then(|config| {
+        config.map_or(Ok(Host::system()), |c| NullHost::new(c).map(Host::Null))
+    }) {
+        Ok(host) => host,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let mut mixer = mixer::MixerState::new(host);
     let ptt = ptt::PttState::new();
+    // The worker writes its results between the command loop's responses.
+    let mut jobs = Jobs::new(|resp| {
+        let _ = write_response(&mut io::stdout().lock(), resp);
+    });
 
     // ---- main command loop --------------------------------------------
     let stdin = io::stdin();
-    let mut stdout = io::stdout().lock();
+    let stdout = io::stdout();
 
     for line in stdin.lock().lines() {
         let line = match line {
             Ok(l) => l,
             Err(e) => {
-                let _ = write_response(&mut stdout, Response::error(format!("stdin read error: {e}")));
+                let _ = write_response(&mut stdout.lock(), Response::error(format!("stdin read error: {e}")));
                 continue;
             }
         };
@@ -57,26 +108,32 @@ fn main() {
             Ok(c) => c,
             Err(e) => {
                 let _ = write_response(
-                    &mut stdout,
+                    &mut stdout.lock(),
                     Response::error(format!("Invalid JSON command: {e}")),
                 );
                 continue;
             }
         };
 
-        let response = handle_command(&mut mixer, &ptt, cmd);
+        // Hold stdout until the response is written, so the job worker
+        // can't answer a job before the `Started` that announces it.
+        let mut out = stdout.lock();
+        let response = handle_command(&mut mixer, &ptt, &mut jobs, cmd);
 
         // A `None` return means the Shutdown command was received.
         match response {
             Some(resp) => {
-                if write_response(&mut stdout, resp).is_err() {
+                if write_response(&mut out, resp).is_err() {
                     break;
                 }
             }
             None => {
-                // Acknowledge shutdown, then exit.
-                let _ = write_response(&mut stdout, Response::Ok);
-                break;
+                // Finish the queued file jobs, acknowledge shutdown, then
+                // exit.
+                drop(out);
+                drop(jobs);
+                let _ = write_response(&mut stdout.lock(), Response::Ok);
+                return;
             }
         }
     }
@@ -84,11 +141,16 @@ fn main() {
 
 /// Dispatch a parsed command to the appropriate mixer / device function.
 /// Returns `None` when the engine should shut down.
-fn handle_command(mixer: &mut mixer::MixerState, ptt: &ptt::PttState, cmd: Command) -> Option<Response> {
+fn handle_command(
+    mixer: &mut mixer::MixerState,
+    ptt: &ptt::PttState,
+    jobs: &mut Jobs,
+    cmd: Command,
+) -> Option<Response> {
     match cmd {
         Command::ListDevices => {
-            let input = devices::list_input_devices();
-            let output = devices::list_output_devices();
+            let input = mixer.host.list_input_devices();
+            let output = mixer.host.list_output_devices();
             Some(Response::Devices { input, output })
         }
 
@@ -108,7 +170,195 @@ fn handle_command(mixer: &mut mixer::MixerState, ptt: &ptt::PttState, cmd: Comma
             }
         }
 
-        Command::Play { file_path, volume } => match mixer.play_file(&file_path, volume) {
+        Command::SetMonitorDevice { device_name } => {
+            mixer.monitor_device_name = device_name;
+            match mixer.start_monitor() {
+                Ok(()) => Some(Response::Ok),
+                Err(e) => {
+                    mixer.monitor_device_name = None;
+                    Some(Response::error(e))
+                }
+            }
+        }
+
+        Command::SetMonitorVolume { volume } => {
+            mixer.set_monitor_volume(volume);
+            Some(Response::Ok)
+        }
+
+        Command::SetMonitorSource { source } => {
+            mixer.set_monitor_source(source);
+            Some(Response::Ok)
+        }
+
+        Command::AdvanceClock { ms } => match mixer.advance_clock(ms) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::Play {
+            file_path,
+            bus,
+            volume,
+            pan,
+            reverb,
+            delay_ms,
+            at_ms,
+        } => {
+            let start_ms = match (delay_ms, at_ms) {
+                (Some(_), Some(_)) => {
+                    return Some(Response::error("Give either delay_ms or at_ms, not both"))
+                }
+                (Some(delay), None) => Some(mixer.clock_ms() + delay),
+                (None, at) => at,
+            };
+            let params = playback::PlayParams {
+                volume,
+                pan,
+                reverb,
+                gain_db: 0.0,
+                pitch_semitones: 0.0,
+                start_ms,
+            };
+            match mixer.play_file(&file_path, &bus, params) {
+                // Scheduled: the PTT watcher presses the key when it starts.
+                Ok(Some(id)) => Some(Response::Scheduled { id }),
+                Ok(None) => {
+                    if ptt.is_enabled() {
+                        ptt.press_key();
+                    }
+                    Some(Response::Ok)
+                }
+                Err(e) => Some(Response::error(e)),
+            }
+        }
+
+        Command::PlayRandom {
+            group,
+            files,
+            mode,
+            bus,
+            volume,
+            variation,
+        } => match mixer.play_random(&group, &files, mode, &bus, volume, variation) {
+            Ok(file_path) => {
+                if ptt.is_enabled() {
+                    ptt.press_key();
+                }
+                Some(Response::Played { file_path })
+            }
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::Preload { file_path } => match mixer.preload(&file_path) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::Unload { file_path } => match mixer.unload(&file_path) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetSampleCacheBudget { megabytes } => {
+            match mixer.set_sample_cache_budget(megabytes) {
+                Ok(()) => Some(Response::Ok),
+                Err(e) => Some(Response::error(e)),
+            }
+        }
+
+        Command::SetPcmCacheDir { dir } => match mixer.set_pcm_cache_dir(dir) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetPcmCacheBudget { megabytes } => match mixer.set_pcm_cache_budget(megabytes) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::ClearPcmCache => match mixer.clear_pcm_cache() {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        // These read whole files, so they run on the job worker.
+        Command::Probe { file_path } => Some(jobs.start(move || {
+            match decode::probe(&file_path) {
+                Ok(media) => Response::Probe { media },
+                Err(e) => Response::error(e),
+            }
+        })),
+
+        Command::GetWaveform { file_path, buckets } => {
+            let tools = mixer.file_tools();
+            Some(jobs.start(move || match tools.waveform(&file_path, buckets) {
+                Ok(waveform) => Response::Waveform { waveform },
+                Err(e) => Response::error(e),
+            }))
+        }
+
+        Command::DetectSilence {
+            file_path,
+            threshold_db,
+            min_silence_ms,
+        } => {
+            let tools = mixer.file_tools();
+            Some(jobs.start(move || {
+                match tools.detect_silence(&file_path, threshold_db, min_silence_ms) {
+                    Ok(silence) => Response::Silence { silence },
+                    Err(e) => Response::error(e),
+                }
+            }))
+        }
+
+        Command::Render {
+            file_path,
+            output_path,
+            edits,
+            format,
+            bit_depth,
+        } => {
+            let tools = mixer.file_tools();
+            Some(jobs.start(move || {
+                match tools.render(&file_path, &output_path, &edits, format, bit_depth) {
+                    Ok(render) => Response::Rendered { render },
+                    Err(e) => Response::error(e),
+                }
+            }))
+        }
+
+        Command::StartRecording {
+            path,
+            source,
+            format,
+            bit_depth,
+        } => match mixer.start_recording(&path, source, format, bit_depth) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::StopRecording => match mixer.stop_recording() {
+            Ok(recording) => Some(Response::Recorded { recording }),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::Seek { bus, position_ms } => match mixer.seek(&bus, position_ms) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::CancelScheduled { id } => match mixer.cancel_scheduled(id) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::Enqueue {
+            file_path,
+            bus,
+            volume,
+            gap_ms,
+        } => match mixer.enqueue(&file_path, &bus, volume, gap_ms) {
             Ok(()) => {
                 if ptt.is_enabled() {
                     ptt.press_key();
@@ -118,6 +368,16 @@ fn handle_command(mixer: &mut mixer::MixerState, ptt: &ptt::PttState, cmd: Comma
             Err(e) => Some(Response::error(e)),
         },
 
+        Command::Skip { bus } => match mixer.skip(&bus) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::ClearQueue { bus } => match mixer.clear_queue(&bus) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
         Command::Stop => {
             mixer.stop();
             ptt.release_key();
@@ -134,26 +394,161 @@ fn handle_command(mixer: &mut mixer::MixerState, ptt: &ptt::PttState, cmd: Comma
             Some(Response::Ok)
         }
 
+        Command::SetMicMuted { muted } => {
+            mixer.set_mic_muted(muted);
+            Some(Response::Ok)
+        }
+
+        Command::SetPushToMute { active } => {
+            mixer.set_push_to_mute(active);
+            Some(Response::Ok)
+        }
+
         Command::SetVolume { volume } => {
             mixer.set_volume(volume);
             Some(Response::Ok)
         }
 
+        Command::SetPan { pan, bus } => match mixer.set_pan(&bus, pan) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetBalance { balance } => {
+            mixer.set_balance(balance);
+            Some(Response::Ok)
+        }
+
         Command::SetMicVolume { volume } => {
             mixer.set_mic_volume(volume);
             Some(Response::Ok)
         }
 
+        Command::SetNoiseSuppression { enabled } => {
+            mixer.set_noise_suppression(enabled);
+            Some(Response::Ok)
+        }
+
+        Command::SetEchoCancellation { enabled, delay_ms } => {
+            match mixer.set_echo_cancellation(enabled, delay_ms) {
+                Ok(()) => Some(Response::Ok),
+                Err(e) => Some(Response::error(e)),
+            }
+        }
+
+        Command::SetMicAgc {
+            enabled,
+            target_db,
+            max_gain_db,
+            noise_floor_db,
+        } => {
+            mixer.set_mic_agc(enabled.then_some(agc::AgcSettings {
+                target_db,
+                max_gain_db,
+                noise_floor_db,
+            }));
+            Some(Response::Ok)
+        }
+
+        Command::SetMicEffect { effect } => {
+            mixer.set_mic_effect(effect);
+            Some(Response::Ok)
+        }
+
+        Command::SetEq { target, bands } => match mixer.set_eq(target, bands) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetChannelMatrix {
+            source_channels,
+            matrix,
+        } => match mixer.set_channel_matrix(source_channels, matrix) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetMasterReverb { reverb } => match mixer.set_master_reverb(reverb) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::SetBus {
+            name,
+            gain,
+            muted,
+            inserts,
+            outputs,
+        } => match mixer.set_bus(&name, gain, muted, inserts, outputs) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
+        Command::RemoveBus { name } => match mixer.remove_bus(&name) {
+            Ok(()) => Some(Response::Ok),
+            Err(e) => Some(Response::error(e)),
+        },
+
         Command::GetStatus => {
             let vol = mixer.volume.lock().map(|v| *v).unwrap_or(1.0);
+            let monitor_vol = mixer.monitor_volume.lock().map(|v| *v).unwrap_or(1.0);
+            let balance = mixer.balance.lock().map(|b| *b).unwrap_or(0.0);
             let mic_vol = mixer.mic_volume.lock().map(|v| *v).unwrap_or(1.0);
+            let (noise_suppression, echo_cancellation, mic_agc_gain_db, mic_effect, mic_eq) = mixer
+                .mic_chain
+                .lock()
+                .map(|c| {
+                    (
+                        c.noise_suppression,
+                        c.echo_cancellation(),
+                        c.agc().map(|a| a.gain_db()),
+                        c.effect(),
+                        c.eq.bands().to_vec(),
+                    )
+                })
+                .unwrap_or((false, false, None, protocol::MicEffect::None, Vec::new()));
+            let master_eq = mixer
+                .master_eq
+                .lock()
+                .map(|eq| eq.bands().to_vec())
+                .unwrap_or_default();
+            let master_reverb = mixer
+                .master_reverb
+                .lock()
+                .ok()
+                .and_then(|r| r.as_ref().map(|r| r.settings().clone()));
             Some(Response::Status {
                 playing: mixer.is_playing(),
                 paused: mixer.paused.load(std::sync::atomic::Ordering::Acquire),
+                mic_muted: mixer.mic_muted.load(std::sync::atomic::Ordering::Acquire),
+                push_to_mute: mixer.push_to_mute.load(std::sync::atomic::Ordering::Acquire),
+                clock_ms: mixer.clock_ms(),
+                scheduled: mixer.scheduled(),
+                sample_cache: mixer.sample_cache_status(),
+                pcm_cache: mixer.pcm_cache_status(),
                 volume: vol,
+                balance,
                 mic_volume: mic_vol,
+                noise_suppression,
+                echo_cancellation,
+                mic_agc: mic_agc_gain_db.is_some(),
+                mic_agc_gain_db,
+                mic_effect,
+                mic_eq,
+                master_eq,
+                master_reverb,
+                channel_matrices: mixer
+                    .channel_matrices
+                    .iter()
+                    .map(|(&ch, m)| (ch, m.rows()))
+                    .collect(),
                 input_device: mixer.input_device_name.clone(),
                 output_device: mixer.output_device_name.clone(),
+                monitor_device: mixer.monitor_device_name.clone(),
+                monitor_volume: monitor_vol,
+                monitor_source: mixer.monitor_source(),
+                buses: mixer.bus_status(),
+                recording: mixer.recording(),
             })
         }
 
@@ -176,7 +571,7 @@ fn handle_command(mixer: &mut mixer::MixerState, ptt: &ptt::PttState, cmd: Comma
 
 /// Serialize a response as a single JSON line on stdout.
 fn write_response(out: &mut impl Write, resp: Response) -> io::Result<()> {
-    let json = serde_json::to_string(&resp).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+    let json = serde_json::to_string(&resp).map_err(io::Error::other)?;
     writeln!(out, "{json}")?;
     out.flush()
 }
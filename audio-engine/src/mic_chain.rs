@@ -0,0 +1,182 @@
+use crate::aec::{EchoCanceller, MAX_DELAY_MS};
+use crate::agc::{Agc, AgcSettings};
+use crate::denoise::Denoiser;
+use crate::eq::Equalizer;
+use crate::protocol::MicEffect;
+use crate::voice_fx::VoiceEffect;
+
+/// Processing applied to the microphone pass-through in the output callback,
+/// after the samples come out of the capture ring buffer and before
+/// `mic_volume` is applied.
+///
+/// Stages are sized for the output rate when they are set up, and rebuilt
+/// by `set_rate` (on the command loop) when a new output stream starts; a
+/// stage built for another rate is skipped rather than rebuilt in the
+/// callback.
+///
+/// The stages work on a mono fold-down of the mic signal (every channel of
+/// the ring carries the same capture anyway once channel-converted) and
+/// write the result back to all output channels.  When no stage is enabled
+/// the samples are left untouched.
+pub struct MicChain {
+    /// Acoustic echo cancellation against the engine's own sound output.
+    /// Built on demand because its size depends on the device rate.
+    echo: Option<EchoCanceller>,
+    /// Bulk reference delay requested for the echo canceller.
+    echo_delay_ms: u32,
+    /// Parametric EQ, ahead of the denoiser and AGC so a low-cut keeps
+    /// rumble out of their level estimates.
+    pub eq: Equalizer,
+    /// RNNoise noise suppression.
+    pub noise_suppression: bool,
+    denoiser: Denoiser,
+    /// Automatic gain control, after the clean-up stages so it levels the
+    /// voice rather than the noise.
+    agc: Option<Agc>,
+    /// Voice changer, fed by the levelled signal so its character (e.g. the
+    /// radio distortion) doesn't depend on how loud the mic is.
+    effect: Option<VoiceEffect>,
+    /// Scratch buffers for the mono fold-downs.
+    mono: Vec<f32>,
+    reference: Vec<f32>,
+}
+
+impl MicChain {
+    pub fn new() -> Self {
+        Self {
+            echo: None,
+            echo_delay_ms: 0,
+            eq: Equalizer::new(),
+            noise_suppression: false,
+            denoiser: Denoiser::new(),
+            agc: None,
+            effect: None,
+            mono: Vec::with_capacity(4096),
+            reference: Vec::with_capacity(4096),
+        }
+    }
+
+    /// Enable or disable echo cancellation.  `delay_ms` is a bulk delay
+    /// applied to the reference to account for known output latency, at
+    /// most `MAX_DELAY_MS`.
+    pub fn set_echo_cancellation(
+        &mut self,
+        enabled: bool,
+        delay_ms: u32,
+        rate: u32,
+    ) -> Result<(), String> {
+        if delay_ms > MAX_DELAY_MS {
+            return Err(format!("delay_ms must be at most {MAX_DELAY_MS}"));
+        }
+        self.echo_delay_ms = delay_ms;
+        self.echo = enabled.then(|| EchoCanceller::new(rate, delay_ms));
+        Ok(())
+    }
+
+    /// Rebuild the rate-dependent stages for a new output `rate`.
+    pub fn set_rate(&mut self, rate: u32) {
+        if self.echo.as_ref().is_some_and(|e| e.rate() != rate) {
+            self.echo = Some(EchoCanceller::new(rate, self.echo_delay_ms));
+        }
+        if self.denoiser.rate() != rate {
+            self.denoiser.reset(rate);
+        }
+        if let Some(effect) = self.effect.as_ref().filter(|e| e.rate() != rate) {
+            self.effect = VoiceEffect::new(effect.config(), rate);
+        }
+    }
+
+    pub fn echo_cancellation(&self) -> bool {
+        self.echo.is_some()
+    }
+
+    /// Enable or disable noise suppression.  Re-enabling starts from a clean
+    /// state so stale audio from before the toggle is never replayed.
+    pub fn set_noise_suppression(&mut self, enabled: bool, rate: u32) {
+        if enabled && !self.noise_suppression {
+            self.denoiser.reset(rate);
+        }
+        self.noise_suppression = enabled;
+    }
+
+    /// Enable AGC with the given settings, or disable it with `None`.
+    /// Changing settings keeps the current gain so there is no jump.
+    pub fn set_agc(&mut self, settings: Option<AgcSettings>) {
+        self.agc = match (settings, self.agc.take()) {
+            (Some(settings), Some(mut agc)) => {
+                agc.set_settings(settings);
+                Some(agc)
+            }
+            (Some(settings), None) => Some(Agc::new(settings)),
+            (None, _) => None,
+        };
+    }
+
+    pub fn agc(&self) -> Option<&Agc> {
+        self.agc.as_ref()
+    }
+
+    /// Select the voice effect (`MicEffect::None` removes it).
+    pub fn set_effect(&mut self, effect: MicEffect, rate: u32) {
+        self.effect = VoiceEffect::new(effect, rate);
+    }
+
+    pub fn effect(&self) -> MicEffect {
+        self.effect
+            .as_ref()
+            .map(|e| e.config())
+            .unwrap_or(MicEffect::None)
+    }
+
+    fn is_active(&self) -> bool {
+        self.echo.is_some()
+            || !self.eq.bands().is_empty()
+            || self.noise_suppression
+            || self.agc.is_some()
+            || self.effect.is_some()
+    }
+
+    /// Run the enabled stages over an interleaved block at `rate`.
+    /// `reference` is the sound-effect signal rendered for the same block
+    /// (same layout as `data`), used as the echo canceller's reference.
+    pub fn process(&mut self, data: &mut [f32], reference: &[f32], channels: usize, rate: u32) {
+        if !self.is_active() || channels == 0 {
+            return;
+        }
+
+        fold_to_mono(data, channels, &mut self.mono);
+
+        if let Some(echo) = self.echo.as_mut().filter(|e| e.rate() == rate) {
+            fold_to_mono(reference, channels, &mut self.reference);
+            echo.process(&mut self.mono, &self.reference);
+        }
+
+        self.eq.process(&mut self.mono, 1, rate);
+
+        if self.noise_suppression && self.denoiser.rate() == rate {
+            self.denoiser.process(&mut self.mono);
+        }
+
+        if let Some(agc) = self.agc.as_mut() {
+            agc.process(&mut self.mono, rate);
+        }
+
+        if let Some(effect) = self.effect.as_mut().filter(|e| e.rate() == rate) {
+            effect.process(&mut self.mono, rate);
+        }
+
+        for (frame, &s) in data.chunks_mut(channels).zip(self.mono.iter()) {
+            for out in frame.iter_mut() {
+                *out = s;
+            }
+        }
+    }
+}
+
+/// Average interleaved frames down to one channel.
+fn fold_to_mono(data: &[f32], channels: usize, out: &mut Vec<f32>) {
+    out.clear();
+    for frame in data.chunks(channels) {
+        out.push(frame.iter().sum::<f32>() / channels as f32);
+    }
+}
@@ -1,15 +1,38 @@
-use std::fs::File;
-use std::io::BufReader;
-use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
 use std::sync::{Arc, Mutex};
 use std::thread;
-
-use cpal::traits::{DeviceTrait, StreamTrait};
-use cpal::{Device, SampleFormat, Stream, StreamConfig};
-use rodio::Decoder;
-use rodio::Source;
-
-use crate::devices;
+use std::time::{Duration, Instant};
+
+use crate::agc::AgcSettings;
+use crate::bus::{BusGraph, BusSnapshot, Insert, VoiceSlot};
+use crate::channel_map::ChannelMatrix;
+use crate::devices::{Host, Stream};
+use crate::eq::Equalizer;
+use crate::mic_chain::MicChain;
+use crate::pan::{self, Panner};
+use crate::pcm_cache::{self, MappedPcm, PcmCache, SharedPcmCache, DEFAULT_DISK_BUDGET_BYTES};
+use crate::playback::{FileDecode, FilePlayback, PlayParams, Repitch, Samples, VoiceFill};
+use crate::protocol::{
+    AudioFileFormat, BusInsert, BusOutput, BusStatus, EqBand, EqTarget, MicEffect, MonitorSource,
+    PcmCacheStatus, RandomMode, RecordingInfo, RecordingSource, RenderEdits, RenderInfo,
+    ReverbSettings, SampleCacheStatus, ScheduledPlay, SilenceReport, Variation, Waveform,
+    WeightedFile,
+};
+use crate::queue::{PlayQueue, SharedQueue};
+use crate::record::{Recorder, SharedTap};
+use crate::render;
+use crate::reverb::{Convolver, ImpulseResponse};
+use crate::sample_cache::{CachedSound, SampleCache, SharedSampleCache, DEFAULT_BUDGET_BYTES};
+use crate::schedule::{Scheduler, SharedScheduler};
+use crate::silence;
+use crate::sound_group::{self, Rng, SoundGroup};
+use crate::waveform::{self, Peaks};
+
+/// How long `AdvanceClock` waits for a voice's decode thread before
+/// rendering without it.
+const DECODE_WAIT: Duration = Duration::from_secs(10);
 
 // ---------------------------------------------------------------------------
 // Ring buffer used to ferry samples between threads
@@ -19,7 +42,7 @@ use crate::devices;
 /// samples.  Both the capture callback and the output callback run on
 /// real-time audio threads so we avoid allocations and use atomics for the
 /// cursors.
-struct RingBuffer {
+pub struct RingBuffer {
     buf: Vec<f32>,
     /// Write cursor (producer: capture thread).
     write: std::sync::atomic::AtomicUsize,
@@ -28,7 +51,7 @@ struct RingBuffer {
 }
 
 impl RingBuffer {
-    fn new(capacity: usize) -> Self {
+    pub fn new(capacity: usize) -> Self {
         Self {
             buf: vec![0.0; capacity],
             write: std::sync::atomic::AtomicUsize::new(0),
@@ -37,7 +60,7 @@ impl RingBuffer {
     }
 
     /// Number of samples available for reading.
-    fn available(&self) -> usize {
+    pub fn available(&self) -> usize {
         let w = self.write.load(Ordering::Acquire);
         let r = self.read.load(Ordering::Acquire);
         if w >= r {
@@ -48,7 +71,7 @@ impl RingBuffer {
     }
 
     /// Push samples into the ring buffer.  Drops oldest samples on overflow.
-    fn push(&self, samples: &[f32]) {
+    pub fn push(&self, samples: &[f32]) {
         let cap = self.buf.len();
         let mut w = self.write.load(Ordering::Acquire);
         // SAFETY: we are the only writer so &mut access to buf[w] is safe.
@@ -60,8 +83,20 @@ impl RingBuffer {
         self.write.store(w, Ordering::Release);
     }
 
+    /// Number of samples that can be pushed without overwriting unread data.
+    pub fn free(&self) -> usize {
+        self.buf.len() - 1 - self.available()
+    }
+
+    /// Discard up to `n` unread samples.
+    pub fn skip(&self, n: usize) {
+        let n = n.min(self.available());
+        let r = self.read.load(Ordering::Acquire);
+        self.read.store((r + n) % self.buf.len(), Ordering::Release);
+    }
+
     /// Pop up to `out.len()` samples.  Returns the number actually read.
-    fn pop(&self, out: &mut [f32]) -> usize {
+    pub fn pop(&self, out: &mut [f32]) -> usize {
         let avail = self.available();
         let n = out.len().min(avail);
         let cap = self.buf.len();
@@ -75,51 +110,13 @@ impl RingBuffer {
     }
 }
 
-// ---------------------------------------------------------------------------
-// File playback source that can be read from the output callback
-// ---------------------------------------------------------------------------
-
-/// Streaming playback buffer.  A background thread decodes samples and appends
-/// them here while the output callback reads them in real time.  This lets
-/// playback start as soon as the first decoded chunk is ready instead of waiting
-/// for the entire file to be decoded.
-struct FilePlayback {
-    /// Decoded samples are appended here by the decode thread.
-    samples: Vec<f32>,
-    /// Current read position (advanced by the output callback).
-    position: usize,
-    /// Per-file volume multiplier (0.0 .. 1.0).
-    volume: f32,
-    /// Set to `true` once the decode thread has finished (all samples appended).
-    decode_complete: bool,
-}
-
-impl FilePlayback {
-    /// Read up to `out.len()` samples, mixed (added) into `out`.  Returns
-    /// `true` while there are (or will be) more samples to play.
-    fn mix_into(&mut self, out: &mut [f32]) -> bool {
-        let available = self.samples.len();
-        for sample in out.iter_mut() {
-            if self.position >= available {
-                // If decoding is still in progress, we ran out of buffered
-                // samples temporarily — output silence but keep playing.
-                return !self.decode_complete;
-            }
-            *sample += self.samples[self.position] * self.volume;
-            self.position += 1;
-        }
-        // Still playing if we haven't reached the end, or decode is ongoing.
-        self.position < available || !self.decode_complete
-    }
-}
-
 // ---------------------------------------------------------------------------
 // Audio resampling helpers
 // ---------------------------------------------------------------------------
 
 /// Resample a buffer of interleaved samples using linear interpolation.
 /// `src_rate` and `dst_rate` are in Hz; `channels` is the channel count.
-fn resample(samples: &[f32], src_rate: u32, dst_rate: u32, channels: u16) -> Vec<f32> {
+pub fn resample(samples: &[f32], src_rate: u32, dst_rate: u32, channels: u16) -> Vec<f32> {
     if src_rate == dst_rate || samples.is_empty() || channels == 0 {
         return samples.to_vec();
     }
@@ -149,34 +146,194 @@ fn resample(samples: &[f32], src_rate: u32, dst_rate: u32, channels: u16) -> Vec
     out
 }
 
-/// Convert between channel counts for interleaved samples.
-fn convert_channels(samples: &[f32], src_ch: u16, dst_ch: u16) -> Vec<f32> {
-    if src_ch == dst_ch || samples.is_empty() {
-        return samples.to_vec();
+/// Streaming linear-interpolation resampler for a single (mono) channel.
+///
+/// Unlike [`resample`], which treats every buffer as a standalone clip, this
+/// carries the fractional read position and the last input sample across
+/// calls so consecutive callback-sized blocks join without clicks.
+pub struct StreamResampler {
+    src_rate: u32,
+    dst_rate: u32,
+    /// Read position of the next output sample, in source samples, where
+    /// `0.0` is `prev` and `1.0` is the first sample of the next input block.
+    pos: f64,
+    /// Last input sample of the previous block.
+    prev: f32,
+}
+
+impl StreamResampler {
+    pub fn new(src_rate: u32, dst_rate: u32) -> Self {
+        Self {
+            src_rate,
+            dst_rate,
+            pos: 0.0,
+            prev: 0.0,
+        }
+    }
+
+    /// Resample `input`, appending the result to `out`.
+    pub fn process(&mut self, input: &[f32], out: &mut Vec<f32>) {
+        if self.src_rate == self.dst_rate || self.src_rate == 0 || self.dst_rate == 0 {
+            out.extend_from_slice(input);
+            return;
+        }
+        if input.is_empty() {
+            return;
+        }
+
+        let step = self.src_rate as f64 / self.dst_rate as f64;
+        let n = input.len() as f64;
+        let prev = self.prev;
+        let at = |k: usize| if k == 0 { prev } else { input[k - 1] };
+
+        while self.pos < n {
+            let idx = self.pos as usize;
+            let frac = (self.pos - idx as f64) as f32;
+            let s0 = at(idx);
+            let s1 = at(idx + 1);
+            out.push(s0 + (s1 - s0) * frac);
+            self.pos += step;
+        }
+
+        self.pos -= n;
+        self.prev = input[input.len() - 1];
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Monitor feed: drift-compensating reader for the second output device
+// ---------------------------------------------------------------------------
+
+/// Latency the monitor feed aims to keep buffered, in milliseconds.
+const MONITOR_LATENCY_MS: u32 = 40;
+/// Largest speed correction applied to absorb clock drift (±0.5 %).
+const MONITOR_MAX_CORRECTION: f64 = 0.005;
+/// Correction per unit of relative fill error.
+const MONITOR_CORRECTION_GAIN: f64 = 0.005;
+
+/// Reads the main output's mix from the monitor ring and resamples it to
+/// the monitor device's rate and channel count.
+///
+/// The two devices run on independent clocks, so the nominal rate ratio
+/// is nudged (by at most `MONITOR_MAX_CORRECTION`) to hold the ring at
+/// `MONITOR_LATENCY_MS`.  After an underrun the feed goes silent until the
+/// ring has refilled to the target; a backlog far beyond it is dropped.
+struct MonitorFeed {
+    src_rate: u32,
+    src_ch: usize,
+    matrix: ChannelMatrix,
+    /// Interleaved source frames popped from the ring but not yet consumed.
+    input: Vec<f32>,
+    /// Fractional read position into `input`, in frames.
+    pos: f64,
+    /// Smoothed buffer fill, in source frames.
+    fill: f64,
+    /// Waiting for the ring to refill after an underrun.
+    buffering: bool,
+    /// One interpolated source frame.
+    frame: Vec<f32>,
+}
+
+impl MonitorFeed {
+    fn new(dst_ch: usize) -> Self {
+        Self {
+            src_rate: 0,
+            src_ch: 0,
+            matrix: ChannelMatrix::standard(dst_ch as u16, None, dst_ch as u16),
+            input: Vec::with_capacity(16_384),
+            pos: 0.0,
+            fill: 0.0,
+            buffering: true,
+            frame: Vec::with_capacity(8),
+        }
     }
 
-    let src = src_ch as usize;
-    let dst = dst_ch as usize;
-    let frames = samples.len() / src;
-    let mut out = Vec::with_capacity(frames * dst);
+    /// Fill `out` (interleaved, `dst_ch` channels at `dst_rate`) from `ring`,
+    /// which carries `src_ch`-channel audio at `src_rate`.
+    fn process(
+        &mut self,
+        ring: &RingBuffer,
+        out: &mut [f32],
+        src_rate: u32,
+        src_ch: usize,
+        dst_rate: u32,
+        dst_ch: usize,
+    ) {
+        out.fill(0.0);
+        if src_ch == 0 || dst_ch == 0 || src_rate == 0 || dst_rate == 0 {
+            return;
+        }
+        if src_rate != self.src_rate || src_ch != self.src_ch {
+            // The main output changed format; anything queued is stale.
+            self.src_rate = src_rate;
+            self.src_ch = src_ch;
+            self.matrix = ChannelMatrix::standard(src_ch as u16, None, dst_ch as u16);
+            self.frame.resize(src_ch, 0.0);
+            self.input.clear();
+            self.pos = 0.0;
+            self.buffering = true;
+            ring.skip(ring.available());
+        }
+
+        let target = (src_rate * MONITOR_LATENCY_MS / 1000) as f64;
+        let input_frames = self.input.len() / src_ch;
+        let mut queued = ring.available() / src_ch;
+        let buffered = queued as f64 + input_frames as f64 - self.pos;
 
-    for f in 0..frames {
-        let base = f * src;
-        if dst > src {
-            // Upmix: copy existing channels, duplicate last channel for the rest
-            for c in 0..dst {
-                out.push(samples[base + c.min(src - 1)]);
+        if self.buffering {
+            if buffered < target {
+                return;
             }
-        } else {
-            // Downmix: average all source channels into each destination channel
-            let avg: f32 = samples[base..base + src].iter().sum::<f32>() / src as f32;
-            for _ in 0..dst {
-                out.push(avg);
+            self.buffering = false;
+            self.fill = buffered;
+        }
+        if buffered > target * 4.0 {
+            let excess = (buffered - target) as usize;
+            let dropped = excess.min(queued);
+            ring.skip(dropped * src_ch);
+            queued -= dropped;
+            self.fill = target;
+        }
+
+        let buffered = queued as f64 + input_frames as f64 - self.pos;
+        self.fill += (buffered - self.fill) * 0.01;
+        let correction = ((self.fill - target) / target * MONITOR_CORRECTION_GAIN)
+            .clamp(-MONITOR_MAX_CORRECTION, MONITOR_MAX_CORRECTION);
+        let step = src_rate as f64 / dst_rate as f64 * (1.0 + correction);
+
+        // Pull enough source frames for this block (plus one for the
+        // interpolation) from the ring.
+        let frames_out = out.len() / dst_ch;
+        let need = (self.pos + frames_out as f64 * step) as usize + 2;
+        if need > input_frames {
+            let take = (need - input_frames).min(queued) * src_ch;
+            let start = self.input.len();
+            self.input.resize(start + take, 0.0);
+            let got = ring.pop(&mut self.input[start..]);
+            self.input.truncate(start + got);
+        }
+
+        let input_frames = self.input.len() / src_ch;
+        for out_frame in out.chunks_mut(dst_ch) {
+            let idx = self.pos as usize;
+            if idx + 1 >= input_frames {
+                self.buffering = true;
+                break;
             }
+            let frac = (self.pos - idx as f64) as f32;
+            let a = &self.input[idx * src_ch..(idx + 1) * src_ch];
+            let b = &self.input[(idx + 1) * src_ch..(idx + 2) * src_ch];
+            for ((f, &s0), &s1) in self.frame.iter_mut().zip(a).zip(b) {
+                *f = s0 + (s1 - s0) * frac;
+            }
+            self.matrix.convert_frame(&self.frame, out_frame);
+            self.pos += step;
         }
-    }
 
-    out
+        let consumed = (self.pos as usize).min(input_frames);
+        self.input.drain(..consumed * src_ch);
+        self.pos -= consumed as f64;
+    }
 }
 
 // ---------------------------------------------------------------------------
@@ -187,26 +344,86 @@ pub struct MixerState {
     // --- device names --------------------------------------------------
     pub input_device_name: Option<String>,
     pub output_device_name: Option<String>,
+    /// Local monitor device (e.g. the user's speakers), if one is open.
+    pub monitor_device_name: Option<String>,
 
     // --- transport flags -----------------------------------------------
     pub playing: Arc<AtomicBool>,
+    /// Sound playback is paused (voices hold their position).
     pub paused: Arc<AtomicBool>,
+    /// The mic pass-through is muted.
+    pub mic_muted: Arc<AtomicBool>,
+    /// The push-to-mute key is held.
+    pub push_to_mute: Arc<AtomicBool>,
 
     // --- volume --------------------------------------------------------
     /// Master volume shared with the output callback.
     pub volume: Arc<Mutex<f32>>,
+    /// Master left/right balance (-1.0 .. 1.0).
+    pub balance: Arc<Mutex<f32>>,
     /// Microphone pass-through volume (0.0 .. 1.0).
     pub mic_volume: Arc<Mutex<f32>>,
+    /// Monitor device volume (0.0 .. 1.0).
+    pub monitor_volume: Arc<Mutex<f32>>,
+
+    // --- mic processing ------------------------------------------------
+    /// Effects applied to the mic pass-through before `mic_volume`.
+    pub mic_chain: Arc<Mutex<MicChain>>,
+    /// Parametric EQ on the final mix, before the master volume.
+    pub master_eq: Arc<Mutex<Equalizer>>,
+
+    // --- reverb --------------------------------------------------------
+    /// Convolution reverb on the final mix, after the master EQ.
+    pub master_reverb: Arc<Mutex<Option<Convolver>>>,
+    /// Impulse responses already decoded and transformed, by path.
+    impulse_responses: HashMap<String, Arc<ImpulseResponse>>,
+
+    // --- channel conversion --------------------------------------------
+    /// User-supplied up/downmix matrices, by source channel count.
+    pub channel_matrices: HashMap<u16, ChannelMatrix>,
 
     // --- streams (kept alive so WASAPI doesn't close them) -------------
+    /// The devices streams are opened on.
+    pub host: Host,
     capture_stream: Option<Stream>,
     output_stream: Option<Stream>,
+    monitor_stream: Option<Stream>,
+
+    // --- monitor feed (main output -> monitor device) ------------------
+    monitor_ring: Arc<RingBuffer>,
+    /// Set while a monitor stream is open, so the output callback only
+    /// feeds the ring when someone is reading it.
+    monitor_active: Arc<AtomicBool>,
+    /// The monitor plays the buses routed to it, pre-master, instead of
+    /// the main mix.
+    monitor_buses: Arc<AtomicBool>,
 
     // --- ring buffer carrying mic samples from capture -> output -------
     ring: Arc<RingBuffer>,
 
-    // --- currently playing file ----------------------------------------
-    file_playback: Arc<Mutex<Option<FilePlayback>>>,
+    // --- recording --------------------------------------------------------
+    /// Where the output callback queues the recorded source.
+    recording: SharedTap,
+    /// The recording in progress and its writer thread.
+    recorder: Option<Recorder>,
+
+    // --- buses and the sounds playing on them ---------------------------
+    /// Named buses and their routing to the main and monitor outputs.
+    pub buses: Arc<Mutex<BusGraph>>,
+    /// Id handed to the next voice.
+    next_voice_id: u64,
+    /// Plays waiting for their start time.
+    pub scheduler: SharedScheduler,
+    /// Decoded sounds in the output format, for playing without decoding.
+    pub sample_cache: SharedSampleCache,
+    /// Decoded sounds persisted on disk, for playing without decoding
+    /// after a restart.
+    pub pcm_cache: SharedPcmCache,
+    /// Picking state for `PlayRandom`, by group name.
+    sound_groups: HashMap<String, SoundGroup>,
+    rng: Rng,
+    /// Frames rendered by the main output since it was opened.
+    clock: Arc<AtomicU64>,
 
     // --- output stream format (for resampling) -------------------------
     output_sample_rate: Arc<AtomicU32>,
@@ -218,21 +435,51 @@ pub struct MixerState {
 }
 
 impl MixerState {
-    /// Create a new, idle mixer.  No streams are opened yet.
-    pub fn new() -> Self {
+    /// Create a new, idle mixer on `host`.  No streams are opened yet.
+    pub fn new(host: Host) -> Self {
         // 48000 samples/sec * 2 channels * 0.5 sec = 48 000 -- generous headroom
         let ring_capacity = 48_000;
         Self {
             input_device_name: None,
             output_device_name: None,
+            monitor_device_name: None,
             playing: Arc::new(AtomicBool::new(false)),
             paused: Arc::new(AtomicBool::new(false)),
+            mic_muted: Arc::new(AtomicBool::new(false)),
+            push_to_mute: Arc::new(AtomicBool::new(false)),
             volume: Arc::new(Mutex::new(1.0)),
+            balance: Arc::new(Mutex::new(0.0)),
             mic_volume: Arc::new(Mutex::new(1.0)),
+            monitor_volume: Arc::new(Mutex::new(1.0)),
+            mic_chain: Arc::new(Mutex::new(MicChain::new())),
+            master_eq: Arc::new(Mutex::new(Equalizer::new())),
+            master_reverb: Arc::new(Mutex::new(None)),
+            impulse_responses: HashMap::new(),
+            channel_matrices: HashMap::new(),
+            host,
             capture_stream: None,
             output_stream: None,
+            monitor_stream: None,
+            // 48000 samples/sec * 8 channels * 0.5 sec
+            monitor_ring: Arc::new(RingBuffer::new(192_000)),
+            monitor_active: Arc::new(AtomicBool::new(false)),
+            monitor_buses: Arc::new(AtomicBool::new(false)),
             ring: Arc::new(RingBuffer::new(ring_capacity)),
-            file_playback: Arc::new(Mutex::new(None)),
+            recording: Arc::new(Mutex::new(None)),
+            recorder: None,
+            buses: Arc::new(Mutex::new(BusGraph::new())),
+            next_voice_id: 0,
+            scheduler: Arc::new(Mutex::new(Scheduler::new())),
+            sample_cache: {
+                // Matches the default output format below.
+                let mut cache = SampleCache::new(DEFAULT_BUDGET_BYTES);
+                cache.set_format(48000, 2);
+                Arc::new(Mutex::new(cache))
+            },
+            pcm_cache: Arc::new(Mutex::new(PcmCache::new(DEFAULT_DISK_BUDGET_BYTES))),
+            sound_groups: HashMap::new(),
+            rng: Rng::new(),
+            clock: Arc::new(AtomicU64::new(0)),
             output_sample_rate: Arc::new(AtomicU32::new(48000)),
             output_channels: Arc::new(AtomicU32::new(2)),
             input_sample_rate: Arc::new(AtomicU32::new(48000)),
@@ -248,38 +495,43 @@ impl MixerState {
         self.capture_stream = None;
 
         let device = match &self.input_device_name {
-            Some(name) => devices::find_input_device(name)
+            Some(name) => self
+                .host
+                .find_input_device(name)
                 .ok_or_else(|| format!("Input device not found: {name}"))?,
-            None => devices::default_input_device()
+            None => self
+                .host
+                .default_input_device()
                 .ok_or_else(|| "No default input device available".to_string())?,
         };
 
-        let config = default_stream_config_for(&device, true)?;
-        let in_rate = config.sample_rate.0;
-        let in_ch = config.channels as u32;
+        let format = device.default_format(true)?;
+        let in_rate = format.sample_rate;
+        let in_ch = format.channels as u32;
         self.input_sample_rate.store(in_rate, Ordering::Release);
         self.input_channels.store(in_ch, Ordering::Release);
 
         let ring = Arc::clone(&self.ring);
-        let paused = Arc::clone(&self.paused);
         let out_rate = Arc::clone(&self.output_sample_rate);
         let out_ch = Arc::clone(&self.output_channels);
+        // Rebuilt whenever the output device's channel count changes.
+        let mut matrix =
+            ChannelMatrix::standard(in_ch as u16, None, out_ch.load(Ordering::Acquire) as u16);
 
         let stream = device
             .build_input_stream(
-                &config.into(),
-                move |data: &[f32], _: &cpal::InputCallbackInfo| {
-                    if paused.load(Ordering::Relaxed) {
-                        return;
-                    }
-
+                format,
+                move |data: &[f32]| {
                     let dst_rate = out_rate.load(Ordering::Relaxed);
                     let dst_ch = out_ch.load(Ordering::Relaxed) as u16;
 
                     // Resample and channel-convert mic input to match output device.
-                    if in_rate != dst_rate || in_ch as u16 != dst_ch {
+                    if matrix.dst_channels() != dst_ch {
+                        matrix = ChannelMatrix::standard(in_ch as u16, None, dst_ch);
+                    }
+                    if in_rate != dst_rate || !matrix.is_identity() {
                         let resampled = resample(data, in_rate, dst_rate, in_ch as u16);
-                        let converted = convert_channels(&resampled, in_ch as u16, dst_ch);
+                        let converted = matrix.convert(&resampled);
                         ring.push(&converted);
                     } else {
                         ring.push(data);
@@ -288,7 +540,6 @@ impl MixerState {
                 |err| {
                     eprintln!("[capture error] {err}");
                 },
-                None,
             )
             .map_err(|e| format!("Failed to build input stream: {e}"))?;
 
@@ -302,69 +553,180 @@ impl MixerState {
         self.output_stream = None;
 
         let device = match &self.output_device_name {
-            Some(name) => devices::find_output_device(name)
+            Some(name) => self
+                .host
+                .find_output_device(name)
                 .ok_or_else(|| format!("Output device not found: {name}"))?,
-            None => devices::default_output_device()
+            None => self
+                .host
+                .default_output_device()
                 .ok_or_else(|| "No default output device available".to_string())?,
         };
 
-        let config = default_stream_config_for(&device, false)?;
-        self.output_sample_rate
-            .store(config.sample_rate.0, Ordering::Release);
-        self.output_channels
-            .store(config.channels as u32, Ordering::Release);
+        let format = device.default_format(false)?;
+        let out_rate = format.sample_rate;
+        let out_ch = format.channels as usize;
+        self.output_sample_rate.store(out_rate, Ordering::Release);
+        self.output_channels.store(out_ch as u32, Ordering::Release);
+
+        // The clock restarts with the stream, and scheduled plays were
+        // decoded for the old format, so drop them.  Cached sounds are only
+        // kept if the format is unchanged.
+        self.clock.store(0, Ordering::Release);
+        if let Ok(mut scheduler) = self.scheduler.lock() {
+            scheduler.clear();
+        }
+        if let Ok(mut cache) = self.sample_cache.lock() {
+            cache.set_format(out_rate, out_ch as u16);
+        }
+        // The mic chain runs at the output rate; rebuild it here rather
+        // than in the callback.
+        if let Ok(mut chain) = self.mic_chain.lock() {
+            chain.set_rate(out_rate);
+        }
 
         let ring = Arc::clone(&self.ring);
-        let file_playback = Arc::clone(&self.file_playback);
+        let buses = Arc::clone(&self.buses);
         let volume = Arc::clone(&self.volume);
+        let balance = Arc::clone(&self.balance);
         let mic_volume = Arc::clone(&self.mic_volume);
+        let mic_chain = Arc::clone(&self.mic_chain);
+        let master_eq = Arc::clone(&self.master_eq);
+        let master_reverb = Arc::clone(&self.master_reverb);
         let playing = Arc::clone(&self.playing);
         let paused = Arc::clone(&self.paused);
+        let mic_muted = Arc::clone(&self.mic_muted);
+        let push_to_mute = Arc::clone(&self.push_to_mute);
+        let monitor_ring = Arc::clone(&self.monitor_ring);
+        let monitor_active = Arc::clone(&self.monitor_active);
+        let monitor_buses = Arc::clone(&self.monitor_buses);
+        let scheduler = Arc::clone(&self.scheduler);
+        let clock = Arc::clone(&self.clock);
+        let recording = Arc::clone(&self.recording);
+        // Scratch buffers for the captured mic, the monitor feed and the
+        // recorded buses, reused across callbacks.
+        let mut mic: Vec<f32> = Vec::with_capacity(8192);
+        let mut monitor: Vec<f32> = Vec::with_capacity(8192);
+        let mut recorded: Vec<f32> = Vec::with_capacity(8192);
+        // The echo reference when the bus graph is busy: no sounds.
+        let mut silence: Vec<f32> = Vec::with_capacity(8192);
 
         let stream = device
             .build_output_stream(
-                &config.into(),
-                move |data: &mut [f32], _: &cpal::OutputCallbackInfo| {
+                format,
+                move |data: &mut [f32]| {
                     // Zero out the buffer first.
                     for s in data.iter_mut() {
                         *s = 0.0;
                     }
 
-                    if paused.load(Ordering::Relaxed) {
-                        return;
+                    // 1. Pull mic samples from the ring buffer.
+                    mic.clear();
+                    mic.resize(data.len(), 0.0);
+                    ring.pop(&mut mic);
+
+                    // The recording, if any.  The command loop only takes
+                    // the lock to start or stop one, and holding it for the
+                    // block means a stopped tap is never fed again.
+                    let tap_guard = recording.try_lock();
+                    let tap = tap_guard.as_ref().ok().and_then(|t| t.as_deref());
+                    let record_buses = tap.is_some_and(|t| t.source != RecordingSource::Master);
+                    recorded.clear();
+                    if record_buses {
+                        recorded.resize(data.len(), 0.0);
                     }
 
-                    // 1. Pull mic samples from the ring buffer and write them
-                    //    directly into the output buffer.
-                    ring.pop(data);
-
-                    // 1b. Apply mic volume to the pass-through samples.
-                    if let Ok(mv) = mic_volume.try_lock() {
-                        let v = *mv;
-                        if (v - 1.0).abs() > f32::EPSILON {
-                            for s in data.iter_mut() {
-                                *s *= v;
+                    // 2. Render every bus (sounds, the mic through its
+                    //    processing chain, inserts, gain) and mix each into
+                    //    the outputs it is routed to.
+                    let feed_monitor = monitor_active.load(Ordering::Relaxed);
+                    let monitor_source = if monitor_buses.load(Ordering::Relaxed) {
+                        MonitorSource::Buses
+                    } else {
+                        MonitorSource::Mix
+                    };
+                    monitor.clear();
+                    if feed_monitor {
+                        monitor.resize(data.len(), 0.0);
+                    }
+                    let mic_silenced =
+                        mic_muted.load(Ordering::Relaxed) || push_to_mute.load(Ordering::Relaxed);
+                    let now = clock.load(Ordering::Relaxed);
+                    let frames = (data.len() / out_ch.max(1)) as u64;
+                    // Mic processing chain (echo cancellation, noise
+                    // suppression etc.), then mic volume.  The chain runs
+                    // while muted so its adaptive state is current when the
+                    // mic comes back.
+                    let mic_stage = |mic: &mut [f32], reference: &[f32]| {
+                        if let Ok(mut chain) = mic_chain.try_lock() {
+                            chain.process(mic, reference, out_ch, out_rate);
+                        }
+                        if mic_silenced {
+                            mic.fill(0.0);
+                        } else if let Ok(mv) = mic_volume.try_lock() {
+                            let v = *mv;
+                            if (v - 1.0).abs() > f32::EPSILON {
+                                for s in mic.iter_mut() {
+                                    *s *= v;
+                                }
+                            }
+                        }
+                    };
+                    if let Ok(mut graph) = buses.try_lock() {
+                        // Start scheduled plays due in this block.
+                        if let Ok(mut scheduler) = scheduler.try_lock() {
+                            if scheduler.start_due(&mut graph, now, frames) {
+                                playing.store(true, Ordering::Release);
                             }
                         }
+                        graph.paused = paused.load(Ordering::Relaxed);
+                        graph.monitor_source = monitor_source;
+                        let activity = graph.render(
+                            &mic,
+                            data,
+                            feed_monitor.then_some(&mut monitor[..]),
+                            out_ch,
+                            out_rate,
+                            mic_stage,
+                        );
+                        if let Some(tap) = tap.filter(|_| record_buses) {
+                            graph.mix_main(tap.source == RecordingSource::Mic, &mut recorded);
+                        }
+                        if activity.ended && !activity.alive {
+                            // play_file() sets `playing` after installing its
+                            // voice, so a play racing with this store is only
+                            // reported idle until the next block.
+                            playing.store(false, Ordering::Release);
+                        }
+                    } else {
+                        // The command loop holds the graph for a moment.  The
+                        // sounds skip the block, but the mic it was popped for
+                        // goes straight to the main output rather than being
+                        // lost.
+                        silence.clear();
+                        silence.resize(data.len(), 0.0);
+                        mic_stage(&mut mic, &silence);
+                        for (o, s) in data.iter_mut().zip(mic.iter()) {
+                            *o += s;
+                        }
+                        if tap.is_some_and(|t| t.source == RecordingSource::Mic) {
+                            recorded.copy_from_slice(&mic);
+                        }
                     }
 
-                    // 2. If a file is playing, mix (add) its samples on top.
-                    if playing.load(Ordering::Relaxed) {
-                        if let Ok(mut guard) = file_playback.try_lock() {
-                            if let Some(ref mut fp) = *guard {
-                                let still_going = fp.mix_into(data);
-                                if !still_going {
-                                    *guard = None;
-                                    // Safe to clear here: play_file() also sets
-                                    // playing inside the file_playback lock, so
-                                    // the two stores are mutually exclusive.
-                                    playing.store(false, Ordering::Release);
-                                }
+                    // 3. Master EQ and reverb on the combined mix.
+                    if let Ok(mut eq) = master_eq.try_lock() {
+                        eq.process(data, out_ch, out_rate);
+                    }
+                    if let Ok(mut guard) = master_reverb.try_lock() {
+                        if let Some(reverb) = guard.as_mut() {
+                            if reverb.matches(out_rate, out_ch) {
+                                reverb.process(data);
                             }
                         }
                     }
 
-                    // 3. Apply master volume.
+                    // 3a. Apply master volume.
                     if let Ok(vol) = volume.try_lock() {
                         let v = *vol;
                         if (v - 1.0).abs() > f32::EPSILON {
@@ -374,15 +736,48 @@ impl MixerState {
                         }
                     }
 
+                    // 3b. Apply master balance to the front left / right pair.
+                    if let Ok(b) = balance.try_lock() {
+                        if *b != 0.0 && out_ch >= 2 {
+                            let (gl, gr) = pan::balance_gains(*b);
+                            for frame in data.chunks_mut(out_ch) {
+                                frame[0] *= gl;
+                                frame[1] *= gr;
+                            }
+                        }
+                    }
+
                     // 4. Clamp to [-1, 1] to avoid clipping distortion.
                     for s in data.iter_mut() {
                         *s = s.clamp(-1.0, 1.0);
                     }
+
+                    // 4a. Feed the monitor device, adding the finished mix
+                    //     unless it plays the buses.  If it has stalled the
+                    //     block is dropped rather than overwriting what it
+                    //     hasn't read yet.
+                    if feed_monitor {
+                        if monitor_source == MonitorSource::Mix {
+                            for (m, s) in monitor.iter_mut().zip(data.iter()) {
+                                *m += s;
+                            }
+                        }
+                        if monitor_ring.free() >= monitor.len() {
+                            monitor_ring.push(&monitor);
+                        }
+                    }
+
+                    // 5. Queue the recorded source for the writer thread.
+                    if let Some(tap) = tap {
+                        let block = if record_buses { &recorded[..] } else { &data[..] };
+                        tap.record(block, out_rate, out_ch);
+                    }
+
+                    clock.store(now + frames, Ordering::Release);
                 },
                 |err| {
                     eprintln!("[output error] {err}");
                 },
-                None,
             )
             .map_err(|e| format!("Failed to build output stream: {e}"))?;
 
@@ -391,6 +786,66 @@ impl MixerState {
         Ok(())
     }
 
+    /// Open the local monitor output named by `monitor_device_name`, or
+    /// close it if that is `None`.  The monitor plays the mix or the buses
+    /// (see [`MonitorSource`]) on a second device, resampled from the main
+    /// output with drift compensation.
+    pub fn start_monitor(&mut self) -> Result<(), String> {
+        self.monitor_active.store(false, Ordering::Release);
+        self.monitor_stream = None;
+        // The old reader is gone; start the next stream from an empty ring
+        // so it isn't fed stale audio.
+        self.monitor_ring.skip(self.monitor_ring.available());
+
+        let Some(name) = &self.monitor_device_name else {
+            return Ok(());
+        };
+        let device = self
+            .host
+            .find_output_device(name)
+            .ok_or_else(|| format!("Monitor device not found: {name}"))?;
+
+        let format = device.default_format(false)?;
+        let mon_rate = format.sample_rate;
+        let mon_ch = format.channels as usize;
+
+        let ring = Arc::clone(&self.monitor_ring);
+        let src_rate = Arc::clone(&self.output_sample_rate);
+        let src_ch = Arc::clone(&self.output_channels);
+        let volume = Arc::clone(&self.monitor_volume);
+        let mut feed = MonitorFeed::new(mon_ch);
+
+        let stream = device
+            .build_output_stream(
+                format,
+                move |data: &mut [f32]| {
+                    feed.process(
+                        &ring,
+                        data,
+                        src_rate.load(Ordering::Relaxed),
+                        src_ch.load(Ordering::Relaxed) as usize,
+                        mon_rate,
+                        mon_ch,
+                    );
+                    if let Ok(vol) = volume.try_lock() {
+                        let v = *vol;
+                        for s in data.iter_mut() {
+                            *s = (*s * v).clamp(-1.0, 1.0);
+                        }
+                    }
+                },
+                |err| {
+                    eprintln!("[monitor error] {err}");
+                },
+            )
+            .map_err(|e| format!("Failed to build monitor stream: {e}"))?;
+
+        stream.play().map_err(|e| format!("Failed to start monitor: {e}"))?;
+        self.monitor_stream = Some(stream);
+        self.monitor_active.store(true, Ordering::Release);
+        Ok(())
+    }
+
     // ---- file playback ------------------------------------------------
 
     /// Decode an audio file and start mixing it into the output stream.
@@ -399,109 +854,514 @@ impl MixerState {
     /// immediately — the output callback starts reading samples as soon as
     /// the first chunk has been decoded.  The decode thread resamples and
     /// channel-converts the audio to match the output device format.
-    pub fn play_file(&mut self, path: &str, file_volume: f32) -> Result<(), String> {
-        let file = File::open(path).map_err(|e| format!("Cannot open file: {e}"))?;
-        let reader = BufReader::new(file);
-        let decoder =
-            Decoder::new(reader).map_err(|e| format!("Cannot decode audio file: {e}"))?;
-
-        // Capture the source file's format before we move the decoder.
-        let src_rate = decoder.sample_rate();
-        let src_channels = decoder.channels();
-
-        // Snapshot the output device format.
-        let dst_rate = self.output_sample_rate.load(Ordering::Acquire);
-        let dst_channels = self.output_channels.load(Ordering::Acquire) as u16;
-
-        let needs_resample = src_rate != dst_rate;
-        let needs_channel_convert = src_channels != dst_channels;
-
-        // Pre-allocate for ~10 seconds at the output rate to reduce reallocations.
-        let playback = FilePlayback {
-            samples: Vec::with_capacity(dst_rate as usize * dst_channels as usize * 10),
-            position: 0,
-            volume: file_volume.clamp(0.0, 1.0),
-            decode_complete: false,
-        };
+    pub fn play_file(
+        &mut self,
+        path: &str,
+        bus: &str,
+        params: PlayParams,
+    ) -> Result<Option<u64>, String> {
+        let slot = self.voice_slot(bus)?;
+        let reverb = params.reverb.map(|settings| self.convolver(settings)).transpose()?;
+        let (mut playback, decode) =
+            self.open_voice(path, params.volume, params.pan, params.pitch_semitones)?;
+        let id = playback.id;
+        playback.volume *= 10f32.powf(params.gain_db / 20.0);
+        playback.tail_remaining = reverb.as_ref().map(|r| r.tail_frames()).unwrap_or(0);
+        playback.reverb = reverb;
+
+        if let Some(ms) = params.start_ms {
+            // Decode into a voice of its own; the output callback swaps it
+            // in as the bus voice on its start frame.
+            let rate = self.output_sample_rate.load(Ordering::Acquire);
+            let start_frame = (ms.max(0.0) * rate as f64 / 1000.0).round() as u64;
+            let voice: VoiceSlot = Arc::new(Mutex::new(Some(playback)));
+            self.scheduler
+                .lock()
+                .map_err(|e| e.to_string())?
+                .add(id, bus, path, start_frame, Arc::clone(&voice));
+            if let Some(decode) = decode {
+                decode.spawn(voice, id);
+            }
+            return Ok(Some(id));
+        }
 
         {
-            let mut guard = self.file_playback.lock().map_err(|e| e.to_string())?;
+            let mut guard = slot.lock().map_err(|e| e.to_string())?;
             *guard = Some(playback);
             self.playing.store(true, Ordering::Release);
         }
 
-        // Spawn a background thread to decode samples in chunks, resample to
-        // match the output device, and push them into the shared FilePlayback
-        // buffer.
-        let file_playback = Arc::clone(&self.file_playback);
-        thread::spawn(move || {
-            const CHUNK_SIZE: usize = 4096;
-            let mut chunk = Vec::with_capacity(CHUNK_SIZE);
-
-            for sample in decoder {
-                chunk.push(sample as f32 / i16::MAX as f32);
-                if chunk.len() >= CHUNK_SIZE {
-                    let processed = process_chunk(
-                        &chunk,
-                        src_rate,
-                        dst_rate,
-                        src_channels,
-                        dst_channels,
-                        needs_resample,
-                        needs_channel_convert,
-                    );
-                    if let Ok(mut guard) = file_playback.lock() {
-                        if let Some(ref mut fp) = *guard {
-                            fp.samples.extend_from_slice(&processed);
-                        } else {
-                            return;
-                        }
-                    }
-                    chunk.clear();
-                }
+        // Decode in the background so playback starts with the first chunk.
+        if let Some(decode) = decode {
+            decode.spawn(slot, id);
+        }
+
+        Ok(None)
+    }
+
+    /// A new voice for `path`, straight from the sample cache or the
+    /// on-disk cache if it is in either (resampled from there if pitched),
+    /// otherwise with the decode that will fill it.  Unpitched decodes are
+    /// added to both caches when they complete, except for long files,
+    /// which are streamed.
+    fn open_voice(
+        &mut self,
+        path: &str,
+        volume: f32,
+        pan: f32,
+        pitch_semitones: f32,
+    ) -> Result<(FilePlayback, Option<VoiceFill>), String> {
+        let id = self.next_voice_id;
+        self.next_voice_id += 1;
+
+        let cached = self.sample_cache.lock().ok().and_then(|mut c| c.get(path));
+        if let Some(sound) = cached {
+            let panner = Panner::new(pan, sound.src_channels == 1);
+            if pitch_semitones == 0.0 {
+                return Ok((FilePlayback::cached(id, volume, panner, sound), None));
             }
+            let samples = Samples::Shared(sound.samples);
+            return Ok(self.repitched(id, volume, panner, samples, pitch_semitones));
+        }
 
-            // Flush remaining samples and mark decode complete.
-            if let Ok(mut guard) = file_playback.lock() {
-                if let Some(ref mut fp) = *guard {
-                    if !chunk.is_empty() {
-                        let processed = process_chunk(
-                            &chunk,
-                            src_rate,
-                            dst_rate,
-                            src_channels,
-                            dst_channels,
-                            needs_resample,
-                            needs_channel_convert,
-                        );
-                        fp.samples.extend_from_slice(&processed);
-                    }
-                    fp.decode_complete = true;
+        let mut decode = FileDecode::open(
+            path,
+            self.output_sample_rate.load(Ordering::Acquire),
+            self.output_channels.load(Ordering::Acquire) as u16,
+            &self.channel_matrices,
+        )?;
+        let unpitched = pitch_semitones == 0.0;
+        if !decode.streams() {
+            if let Some(mapped) = self.pcm_lookup(&mut decode, path, unpitched) {
+                let panner = Panner::new(pan, mapped.src_channels == 1);
+                if unpitched {
+                    return Ok((FilePlayback::mapped(id, volume, panner, mapped), None));
                 }
+                let samples = Samples::Mapped(mapped);
+                return Ok(self.repitched(id, volume, panner, samples, pitch_semitones));
+            }
+            if unpitched {
+                decode.cache_into(&self.sample_cache, path);
+            }
+        }
+        decode.set_pitch(pitch_semitones);
+        // Mono sources arrive duplicated into every channel.
+        let panner = Panner::new(pan, decode.src_channels() == 1);
+        let playback = if decode.streams() {
+            FilePlayback::streaming(id, volume, panner, decode.capacity_hint())
+        } else {
+            FilePlayback::new(id, volume, panner, decode.capacity_hint())
+        };
+        Ok((playback, Some(VoiceFill::Decode(Box::new(decode)))))
+    }
+
+    /// A voice for `samples`, a cached sound in the output format, played
+    /// `semitones` higher (or lower).
+    fn repitched(
+        &self,
+        id: u64,
+        volume: f32,
+        panner: Panner,
+        samples: Samples,
+        semitones: f32,
+    ) -> (FilePlayback, Option<VoiceFill>) {
+        let repitch = Repitch::new(
+            samples,
+            self.output_sample_rate.load(Ordering::Acquire),
+            self.output_channels.load(Ordering::Acquire) as u16,
+            semitones,
+        );
+        let playback = FilePlayback::new(id, volume, panner, repitch.capacity_hint());
+        (playback, Some(VoiceFill::Repitch(repitch)))
+    }
+
+    /// Decode `path` in the background and keep it in the sample cache
+    /// until it is unloaded, so it plays without decoding.
+    pub fn preload(&mut self, path: &str) -> Result<(), String> {
+        let mut decode = FileDecode::open(
+            path,
+            self.output_sample_rate.load(Ordering::Acquire),
+            self.output_channels.load(Ordering::Acquire) as u16,
+            &self.channel_matrices,
+        )?;
+        if decode.streams() {
+            return Err(format!("Too long to preload: {path}"));
+        }
+        let Some(mapped) = self.pcm_lookup(&mut decode, path, true) else {
+            decode.spawn_preload(Arc::clone(&self.sample_cache), path.to_string());
+            return Ok(());
+        };
+        // Already decoded on disk; just read it into memory.
+        let cache = Arc::clone(&self.sample_cache);
+        let rate = decode.dst_rate();
+        let channels = decode.matrix().dst_channels();
+        let path = path.to_string();
+        thread::spawn(move || {
+            let sound = CachedSound {
+                samples: Arc::new(mapped.samples().to_vec()),
+                src_channels: mapped.src_channels,
+            };
+            let inserted = cache
+                .lock()
+                .is_ok_and(|mut cache| cache.insert(&path, sound, rate, channels, true));
+            if !inserted {
+                eprintln!("[preload] {path} was not cached: over budget, or the output format changed");
             }
         });
+        Ok(())
+    }
+
+    /// Look `path`, as `decode` would convert it, up in the on-disk cache.
+    /// On a miss, have `decode` write it there once it completes if
+    /// `persist` (it isn't pitched).
+    fn pcm_lookup(
+        &self,
+        decode: &mut FileDecode,
+        path: &str,
+        persist: bool,
+    ) -> Option<Arc<MappedPcm>> {
+        let rate = decode.dst_rate();
+        let channels = decode.matrix().dst_channels();
+        let mut cache = self.pcm_cache.lock().ok()?;
+        let key = cache.key(path, rate, channels, decode.matrix())?;
+        let mapped = cache.get(&key, rate, channels);
+        if mapped.is_none() && persist {
+            decode.persist_into(&self.pcm_cache, &key);
+        }
+        mapped
+    }
+
+    /// Use `dir` for the on-disk cache, or turn it off with `None`.
+    pub fn set_pcm_cache_dir(&self, dir: Option<String>) -> Result<(), String> {
+        let mut cache = self.pcm_cache.lock().map_err(|e| e.to_string())?;
+        cache.set_dir(dir.map(PathBuf::from))
+    }
+
+    /// Set the on-disk cache's budget, deleting entries to fit.
+    pub fn set_pcm_cache_budget(&self, megabytes: f32) -> Result<(), String> {
+        let bytes = (megabytes.max(0.0) as f64 * 1024.0 * 1024.0) as u64;
+        let mut cache = self.pcm_cache.lock().map_err(|e| e.to_string())?;
+        cache.set_budget(bytes);
+        Ok(())
+    }
+
+    pub fn clear_pcm_cache(&self) -> Result<(), String> {
+        let mut cache = self.pcm_cache.lock().map_err(|e| e.to_string())?;
+        cache.clear()
+    }
+
+    pub fn pcm_cache_status(&self) -> PcmCacheStatus {
+        self.pcm_cache
+            .lock()
+            .map(|c| c.status())
+            .unwrap_or_default()
+    }
+
+    /// What the file commands need of the mixer, for running them on the
+    /// job worker.
+    pub fn file_tools(&self) -> FileTools {
+        FileTools {
+            pcm_cache: Arc::clone(&self.pcm_cache),
+            channel_matrices: self.channel_matrices.clone(),
+        }
+    }
+
+    /// Start recording `source` to `path` in the main output's format.
+    pub fn start_recording(
+        &mut self,
+        path: &str,
+        source: RecordingSource,
+        format: Option<AudioFileFormat>,
+        bit_depth: u16,
+    ) -> Result<(), String> {
+        if self.recorder.is_some() {
+            return Err("A recording is already running".to_string());
+        }
+        if self.output_stream.is_none() {
+            return Err("The main output isn't open".to_string());
+        }
+        let recorder = Recorder::start(
+            path,
+            source,
+            format,
+            bit_depth,
+            self.output_sample_rate.load(Ordering::Acquire),
+            self.output_channels.load(Ordering::Acquire) as u16,
+        )?;
+        *self.recording.lock().map_err(|e| e.to_string())? = Some(recorder.tap());
+        self.recorder = Some(recorder);
+        Ok(())
+    }
+
+    /// Finish the recording in progress.
+    pub fn stop_recording(&mut self) -> Result<RecordingInfo, String> {
+        let recorder = self
+            .recorder
+            .take()
+            .ok_or_else(|| "No recording is running".to_string())?;
+        // Out of the callback's reach first, so the writer gets all of it.
+        if let Ok(mut tap) = self.recording.lock() {
+            *tap = None;
+        }
+        recorder.stop()
+    }
+
+    /// The recording in progress, if any.
+    pub fn recording(&self) -> Option<RecordingInfo> {
+        self.recorder.as_ref().map(Recorder::info)
+    }
+
+    /// Drop `path` from the sample cache.
+    pub fn unload(&self, path: &str) -> Result<(), String> {
+        let mut cache = self.sample_cache.lock().map_err(|e| e.to_string())?;
+        if cache.remove(path) {
+            Ok(())
+        } else {
+            Err(format!("Not loaded: {path}"))
+        }
+    }
+
+    /// Set the sample cache's memory budget, evicting to fit.
+    pub fn set_sample_cache_budget(&self, megabytes: f32) -> Result<(), String> {
+        let bytes = (megabytes.max(0.0) as f64 * 1024.0 * 1024.0) as usize;
+        let mut cache = self.sample_cache.lock().map_err(|e| e.to_string())?;
+        cache.set_budget(bytes);
+        Ok(())
+    }
+
+    pub fn sample_cache_status(&self) -> SampleCacheStatus {
+        self.sample_cache
+            .lock()
+            .map(|c| c.status())
+            .unwrap_or_default()
+    }
+
+    /// Pick a file from `files` using the state of `group` and play it on
+    /// `bus` with a random pitch / gain within `variation`.  Returns the
+    /// file played.
+    pub fn play_random(
+        &mut self,
+        group: &str,
+        files: &[WeightedFile],
+        mode: RandomMode,
+        bus: &str,
+        volume: f32,
+        variation: Variation,
+    ) -> Result<String, String> {
+        if files.is_empty() {
+            return Err("PlayRandom needs at least one file".to_string());
+        }
+        sound_group::validate(&variation)?;
+        let state = match self.sound_groups.get_mut(group) {
+            Some(state) if state.matches(files, mode) => state,
+            _ => self
+                .sound_groups
+                .entry(group.to_string())
+                .insert_entry(SoundGroup::new(files, mode))
+                .into_mut(),
+        };
+        let file = &files[state.pick(files, &mut self.rng)];
+
+        let params = PlayParams {
+            volume,
+            pan: 0.0,
+            reverb: None,
+            gain_db: self.rng.spread(variation.gain_db.abs()),
+            pitch_semitones: self.rng.spread(variation.pitch_semitones.abs()),
+            start_ms: None,
+        };
+        self.play_file(&file.file_path, bus, params)?;
+        Ok(file.file_path.clone())
+    }
+
+    /// Cancel a scheduled play that hasn't started yet.
+    pub fn cancel_scheduled(&self, id: u64) -> Result<(), String> {
+        let mut scheduler = self.scheduler.lock().map_err(|e| e.to_string())?;
+        if scheduler.cancel(id) {
+            Ok(())
+        } else {
+            Err(format!("No scheduled play with id {id}"))
+        }
+    }
+
+    /// Current engine clock in milliseconds.
+    pub fn clock_ms(&self) -> f64 {
+        let rate = self.output_sample_rate.load(Ordering::Acquire).max(1);
+        self.clock.load(Ordering::Acquire) as f64 * 1000.0 / rate as f64
+    }
+
+    /// Render `ms` of audio on the null backend's manual clock.  Before
+    /// each block it waits for the decode threads to get ahead of it, so
+    /// what is rendered doesn't depend on how fast they ran.
+    pub fn advance_clock(&self, ms: f64) -> Result<(), String> {
+        let Host::Null(host) = &self.host else {
+            return Err("AdvanceClock needs the null audio backend".to_string());
+        };
+        if !(0.0..).contains(&ms) {
+            return Err("ms must not be negative".to_string());
+        }
+        let frames = (ms * host.sample_rate() as f64 / 1000.0).round() as u64;
+        host.advance(frames, |block| self.wait_for_decodes(block))
+    }
+
+    /// Wait until every voice that could sound has `frames` frames decoded
+    /// ahead (or has finished decoding), for up to `DECODE_WAIT`.
+    fn wait_for_decodes(&self, frames: usize) {
+        let samples = frames * self.output_channels.load(Ordering::Acquire) as usize;
+        // Every voice that can sound in the next few blocks: the buses' own
+        // and their queues'.
+        let mut voices = Vec::new();
+        for bus in self.bus_snapshot() {
+            voices.push(bus.voice);
+            if let Ok(queue) = bus.queue.lock() {
+                voices.extend(queue.voices().cloned());
+            }
+        }
+        if let Ok(scheduler) = self.scheduler.lock() {
+            voices.extend(scheduler.voices().cloned());
+        }
+        let deadline = Instant::now() + DECODE_WAIT;
+        for voice in voices {
+            while !voice
+                .lock()
+                .map_or(true, |v| v.as_ref().is_none_or(|fp| fp.buffered(samples)))
+            {
+                if Instant::now() >= deadline {
+                    eprintln!("[clock] gave up waiting for a decode");
+                    return;
+                }
+                thread::sleep(Duration::from_millis(1));
+            }
+        }
+    }
+
+    /// Scheduled plays, earliest first.
+    pub fn scheduled(&self) -> Vec<ScheduledPlay> {
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        self.scheduler
+            .lock()
+            .map(|s| s.status(rate))
+            .unwrap_or_default()
+    }
+
+    /// Append a file to the play queue of `bus`.  The file is opened (and
+    /// checked) now and decoded shortly before it is due.
+    pub fn enqueue(
+        &mut self,
+        path: &str,
+        bus: &str,
+        file_volume: f32,
+        gap_ms: f32,
+    ) -> Result<(), String> {
+        let queue = self.queue(bus)?;
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        let gap_frames = (gap_ms as f64 * rate as f64 / 1000.0).round() as i64;
+        let (playback, decode) = self.open_voice(path, file_volume, 0.0, 0.0)?;
+        let id = playback.id;
+        let voice: VoiceSlot = Arc::new(Mutex::new(Some(playback)));
+
+        {
+            let mut q = queue.lock().map_err(|e| e.to_string())?;
+            q.push(id, path, gap_frames, voice, decode);
+            self.playing.store(true, Ordering::Release);
+        }
+        PlayQueue::start_feeder(&queue);
+        Ok(())
+    }
+
+    /// Skip to the next entry in the play queue of `bus`.
+    pub fn skip(&self, bus: &str) -> Result<(), String> {
+        let queue = self.queue(bus)?;
+        let mut q = queue.lock().map_err(|e| e.to_string())?;
+        q.skip();
+        Ok(())
+    }
 
+    /// Drop the entries waiting in the play queue of `bus`.
+    pub fn clear_queue(&self, bus: &str) -> Result<(), String> {
+        let queue = self.queue(bus)?;
+        let mut q = queue.lock().map_err(|e| e.to_string())?;
+        q.clear_pending();
         Ok(())
     }
 
-    /// Stop file playback immediately.
+    /// Move the sound playing on `bus` (its voice, or else the current
+    /// entry of its queue) to `position_ms`.
+    pub fn seek(&self, bus: &str, position_ms: f64) -> Result<(), String> {
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        let channels = self.output_channels.load(Ordering::Acquire) as usize;
+        let frame = (position_ms.max(0.0) * rate as f64 / 1000.0) as usize;
+        let (voice, queue) = {
+            let graph = self.buses.lock().map_err(|e| e.to_string())?;
+            let b = graph.get(bus).ok_or_else(|| format!("No such bus: {bus}"))?;
+            (Arc::clone(&b.voice), Arc::clone(&b.queue))
+        };
+        if let Some(fp) = voice.lock().map_err(|e| e.to_string())?.as_mut() {
+            fp.seek(frame, channels);
+            return Ok(());
+        }
+        let mut q = queue.lock().map_err(|e| e.to_string())?;
+        if q.seek(frame, channels) {
+            Ok(())
+        } else {
+            Err(format!("Nothing is playing on bus {bus}"))
+        }
+    }
+
+    /// The play queue of the named bus.
+    fn queue(&self, bus: &str) -> Result<SharedQueue, String> {
+        let graph = self.buses.lock().map_err(|e| e.to_string())?;
+        graph
+            .get(bus)
+            .map(|b| Arc::clone(&b.queue))
+            .ok_or_else(|| format!("No such bus: {bus}"))
+    }
+
+    /// The voice slot of the named bus.
+    fn voice_slot(&self, bus: &str) -> Result<VoiceSlot, String> {
+        let graph = self.buses.lock().map_err(|e| e.to_string())?;
+        graph
+            .get(bus)
+            .map(|b| Arc::clone(&b.voice))
+            .ok_or_else(|| format!("No such bus: {bus}"))
+    }
+
+    /// Stop file playback on every bus immediately.
     pub fn stop(&mut self) {
         self.playing.store(false, Ordering::Release);
-        if let Ok(mut guard) = self.file_playback.lock() {
-            *guard = None;
+        if let Ok(mut scheduler) = self.scheduler.lock() {
+            scheduler.clear();
+        }
+        for bus in self.bus_snapshot() {
+            if let Ok(mut guard) = bus.voice.lock() {
+                *guard = None;
+            }
+            if let Ok(mut queue) = bus.queue.lock() {
+                queue.clear();
+            }
         }
     }
 
-    /// Pause both mic pass-through and file playback.
+    /// Pause sound playback.  The mic pass-through is unaffected.
     pub fn pause(&self) {
         self.paused.store(true, Ordering::Release);
     }
 
-    /// Resume after a pause.
+    /// Resume sound playback after a pause.
     pub fn resume(&self) {
         self.paused.store(false, Ordering::Release);
     }
 
+    /// Mute or unmute the mic pass-through.
+    pub fn set_mic_muted(&self, muted: bool) {
+        self.mic_muted.store(muted, Ordering::Release);
+    }
+
+    /// Hold or release push-to-mute.
+    pub fn set_push_to_mute(&self, active: bool) {
+        self.push_to_mute.store(active, Ordering::Release);
+    }
+
     /// Set master output volume (0.0 .. 1.0).
     pub fn set_volume(&self, vol: f32) {
         if let Ok(mut v) = self.volume.lock() {
@@ -509,6 +1369,46 @@ impl MixerState {
         }
     }
 
+    /// Set master left/right balance (-1.0 .. 1.0).
+    pub fn set_balance(&self, balance: f32) {
+        if let Ok(mut b) = self.balance.lock() {
+            *b = balance.clamp(-1.0, 1.0);
+        }
+    }
+
+    /// Move the sound playing on `bus` (-1.0 left .. 1.0 right).  Does
+    /// nothing when the bus is idle.
+    pub fn set_pan(&self, bus: &str, pan: f32) -> Result<(), String> {
+        let slot = self.voice_slot(bus)?;
+        if let Ok(mut guard) = slot.lock() {
+            if let Some(fp) = guard.as_mut() {
+                fp.panner.set_pan(pan);
+            }
+        }
+        Ok(())
+    }
+
+    /// Set monitor device volume (0.0 .. 1.0).
+    pub fn set_monitor_volume(&self, vol: f32) {
+        if let Ok(mut v) = self.monitor_volume.lock() {
+            *v = vol.clamp(0.0, 1.0);
+        }
+    }
+
+    /// Choose what the monitor device plays.
+    pub fn set_monitor_source(&self, source: MonitorSource) {
+        self.monitor_buses
+            .store(source == MonitorSource::Buses, Ordering::Release);
+    }
+
+    pub fn monitor_source(&self) -> MonitorSource {
+        if self.monitor_buses.load(Ordering::Acquire) {
+            MonitorSource::Buses
+        } else {
+            MonitorSource::Mix
+        }
+    }
+
     /// Set microphone pass-through volume (0.0 .. 1.5).
     pub fn set_mic_volume(&self, vol: f32) {
         if let Ok(mut v) = self.mic_volume.lock() {
@@ -516,6 +1416,198 @@ impl MixerState {
         }
     }
 
+    /// Toggle RNNoise noise suppression on the mic pass-through.
+    pub fn set_noise_suppression(&self, enabled: bool) {
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        if let Ok(mut chain) = self.mic_chain.lock() {
+            chain.set_noise_suppression(enabled, rate);
+        }
+    }
+
+    /// Toggle acoustic echo cancellation on the mic pass-through, using the
+    /// engine's own sound output as the reference.
+    pub fn set_echo_cancellation(&self, enabled: bool, delay_ms: u32) -> Result<(), String> {
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        let mut chain = self.mic_chain.lock().map_err(|e| e.to_string())?;
+        chain.set_echo_cancellation(enabled, delay_ms, rate)
+    }
+
+    /// Enable automatic gain control on the mic pass-through with the given
+    /// settings, or disable it with `None`.  Runs before `mic_volume`.
+    pub fn set_mic_agc(&self, settings: Option<AgcSettings>) {
+        if let Ok(mut chain) = self.mic_chain.lock() {
+            chain.set_agc(settings);
+        }
+    }
+
+    /// Select the voice effect applied to the mic pass-through.
+    pub fn set_mic_effect(&self, effect: MicEffect) {
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        if let Ok(mut chain) = self.mic_chain.lock() {
+            chain.set_effect(effect, rate);
+        }
+    }
+
+    /// Replace the EQ bands on the mic path or the master bus.
+    pub fn set_eq(&self, target: EqTarget, bands: Vec<EqBand>) -> Result<(), String> {
+        // Both run in the output callback, at its rate.
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        match target {
+            EqTarget::Mic => self
+                .mic_chain
+                .lock()
+                .map_err(|e| e.to_string())?
+                .eq
+                .set_bands(bands, rate),
+            EqTarget::Master => self
+                .master_eq
+                .lock()
+                .map_err(|e| e.to_string())?
+                .set_bands(bands, rate),
+        }
+    }
+
+    /// Install a custom matrix for sources with `source_channels` channels
+    /// (one row per output channel), or restore the standard one with
+    /// `None`.  Applies to sounds played after the change; cached sounds
+    /// with that many channels are dropped, and preloaded ones decoded
+    /// again.
+    pub fn set_channel_matrix(
+        &mut self,
+        source_channels: u16,
+        rows: Option<Vec<Vec<f32>>>,
+    ) -> Result<(), String> {
+        match rows {
+            Some(rows) => {
+                let out_ch = self.output_channels.load(Ordering::Acquire) as usize;
+                if rows.len() != out_ch {
+                    return Err(format!(
+                        "Channel matrix must have {out_ch} rows, one per output channel (got {})",
+                        rows.len()
+                    ));
+                }
+                let matrix = ChannelMatrix::custom(source_channels, rows)?;
+                self.channel_matrices.insert(source_channels, matrix);
+            }
+            None => {
+                self.channel_matrices.remove(&source_channels);
+            }
+        }
+        // Those sounds were converted with the old matrix.
+        let pinned = match self.sample_cache.lock() {
+            Ok(mut cache) => cache.remove_source_channels(source_channels),
+            Err(_) => Vec::new(),
+        };
+        for path in pinned {
+            if let Err(e) = self.preload(&path) {
+                eprintln!("[preload] {path} could not be decoded again: {e}");
+            }
+        }
+        Ok(())
+    }
+
+    /// Set the convolution reverb on the final mix, or remove it with `None`.
+    pub fn set_master_reverb(&mut self, settings: Option<ReverbSettings>) -> Result<(), String> {
+        let convolver = settings.map(|s| self.convolver(s)).transpose()?;
+        *self.master_reverb.lock().map_err(|e| e.to_string())? = convolver;
+        Ok(())
+    }
+
+    /// Create or reconfigure a bus; `None` fields are left unchanged.
+    pub fn set_bus(
+        &mut self,
+        name: &str,
+        gain: Option<f32>,
+        muted: Option<bool>,
+        inserts: Option<Vec<BusInsert>>,
+        outputs: Option<Vec<BusOutput>>,
+    ) -> Result<(), String> {
+        if name.is_empty() {
+            return Err("Bus name must not be empty".to_string());
+        }
+        // Build the inserts (which may load impulse responses) before
+        // taking the graph lock the output callback needs.
+        let inserts = inserts
+            .map(|list| {
+                list.into_iter()
+                    .map(|insert| self.build_insert(insert))
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .transpose()?;
+
+        let mut graph = self.buses.lock().map_err(|e| e.to_string())?;
+        let bus = graph.get_or_insert(name);
+        if let Some(gain) = gain {
+            bus.set_gain(gain);
+        }
+        if let Some(muted) = muted {
+            bus.muted = muted;
+        }
+        if let Some(inserts) = inserts {
+            bus.inserts = inserts;
+        }
+        if let Some(outputs) = outputs {
+            bus.outputs = outputs;
+        }
+        Ok(())
+    }
+
+    /// Remove a bus, stopping anything playing on it.
+    pub fn remove_bus(&mut self, name: &str) -> Result<(), String> {
+        let bus = self.buses.lock().map_err(|e| e.to_string())?.remove(name)?;
+        if let Ok(mut guard) = bus.voice.lock() {
+            *guard = None;
+        }
+        Ok(())
+    }
+
+    /// Status of every bus.
+    pub fn bus_status(&self) -> Vec<BusStatus> {
+        self.bus_snapshot()
+            .into_iter()
+            .map(BusSnapshot::status)
+            .collect()
+    }
+
+    /// Every bus, copied out of the graph and the lock released.
+    fn bus_snapshot(&self) -> Vec<BusSnapshot> {
+        self.buses
+            .lock()
+            .map(|graph| graph.snapshot())
+            .unwrap_or_default()
+    }
+
+    fn build_insert(&mut self, insert: BusInsert) -> Result<Insert, String> {
+        match insert {
+            BusInsert::Eq { bands } => {
+                let mut eq = Equalizer::new();
+                eq.set_bands(bands, self.output_sample_rate.load(Ordering::Acquire))?;
+                Ok(Insert::Eq(eq))
+            }
+            BusInsert::Reverb(settings) => Ok(Insert::Reverb(Box::new(self.convolver(settings)?))),
+        }
+    }
+
+    /// Build a convolver for `settings` in the current output format,
+    /// loading the impulse response unless it is already cached.
+    fn convolver(&mut self, settings: ReverbSettings) -> Result<Convolver, String> {
+        if !settings.wet.is_finite() || !settings.dry.is_finite() {
+            return Err("Reverb wet and dry levels must be numbers".to_string());
+        }
+        let rate = self.output_sample_rate.load(Ordering::Acquire);
+        let channels = self.output_channels.load(Ordering::Acquire) as u16;
+        let ir = match self.impulse_responses.get(&settings.ir_path) {
+            Some(ir) if ir.matches(rate, channels as usize) => Arc::clone(ir),
+            _ => {
+                let ir = Arc::new(ImpulseResponse::load(&settings.ir_path, rate, channels)?);
+                self.impulse_responses
+                    .insert(settings.ir_path.clone(), Arc::clone(&ir));
+                ir
+            }
+        };
+        Ok(Convolver::new(ir, settings))
+    }
+
     /// Return `true` if a sound file is currently being played.
     pub fn is_playing(&self) -> bool {
         // Check whether the playback source still has data.  The atomic flag
@@ -523,8 +1615,15 @@ impl MixerState {
         if !self.playing.load(Ordering::Acquire) {
             return false;
         }
-        if let Ok(guard) = self.file_playback.try_lock() {
-            if guard.is_none() {
+        if let Ok(graph) = self.buses.try_lock() {
+            let idle = graph
+                .buses()
+                .iter()
+                .all(|b| {
+                    b.voice.try_lock().map(|v| v.is_none()).unwrap_or(false)
+                        && b.queue.try_lock().map(|q| q.is_idle()).unwrap_or(false)
+                });
+            if idle {
                 self.playing.store(false, Ordering::Release);
                 return false;
             }
@@ -534,55 +1633,84 @@ impl MixerState {
 }
 
 // ---------------------------------------------------------------------------
-// Helper: resample + channel-convert a chunk of decoded audio
+// File commands, run off the command loop
 // ---------------------------------------------------------------------------
 
-fn process_chunk(
-    chunk: &[f32],
-    src_rate: u32,
-    dst_rate: u32,
-    src_channels: u16,
-    dst_channels: u16,
-    needs_resample: bool,
-    needs_channel_convert: bool,
-) -> Vec<f32> {
-    match (needs_resample, needs_channel_convert) {
-        (false, false) => chunk.to_vec(),
-        (true, false) => resample(chunk, src_rate, dst_rate, src_channels),
-        (false, true) => convert_channels(chunk, src_channels, dst_channels),
-        (true, true) => {
-            // Resample first (at source channel count), then convert channels.
-            let resampled = resample(chunk, src_rate, dst_rate, src_channels);
-            convert_channels(&resampled, src_channels, dst_channels)
+/// The part of the mixer state the file commands (waveforms, silence
+/// detection, renders) use, detached from the mixer so they can run on the
+/// job worker.
+pub struct FileTools {
+    pcm_cache: SharedPcmCache,
+    /// The user's matrices at the time the command was sent.
+    channel_matrices: HashMap<u16, ChannelMatrix>,
+}
+
+impl FileTools {
+    /// Peaks of `path` in `buckets` slices.
+    pub fn waveform(&self, path: &str, buckets: usize) -> Result<Waveform, String> {
+        if !(1..=waveform::MAX_BUCKETS).contains(&buckets) {
+            return Err(format!(
+                "buckets must be between 1 and {}",
+                waveform::MAX_BUCKETS
+            ));
         }
+        Ok(self.peaks(path)?.waveform(buckets))
     }
-}
 
-// ---------------------------------------------------------------------------
-// Helper: pick a good default stream config for a device
-// ---------------------------------------------------------------------------
+    /// Quiet stretches of `path` and where to crop it.
+    pub fn detect_silence(
+        &self,
+        path: &str,
+        threshold_db: f32,
+        min_silence_ms: f64,
+    ) -> Result<SilenceReport, String> {
+        if !(-120.0..=0.0).contains(&threshold_db) {
+            return Err("threshold_db must be between -120 and 0".to_string());
+        }
+        if !(0.0..).contains(&min_silence_ms) {
+            return Err("min_silence_ms must not be negative".to_string());
+        }
+        Ok(silence::detect(
+            &self.peaks(path)?,
+            threshold_db,
+            min_silence_ms,
+        ))
+    }
+
+    /// Write `path` with `edits` applied to `output_path`, converting
+    /// channels as playback would.
+    pub fn render(
+        &self,
+        path: &str,
+        output_path: &str,
+        edits: &RenderEdits,
+        format: Option<AudioFileFormat>,
+        bit_depth: u16,
+    ) -> Result<RenderInfo, String> {
+        render::render(
+            path,
+            output_path,
+            edits,
+            format,
+            bit_depth,
+            &self.channel_matrices,
+        )
+    }
 
-fn default_stream_config_for(
-    device: &Device,
-    is_input: bool,
-) -> Result<StreamConfig, String> {
-    let supported = if is_input {
-        device
-            .default_input_config()
-            .map_err(|e| format!("No supported input stream config: {e}"))?
-    } else {
-        device
-            .default_output_config()
-            .map_err(|e| format!("No supported output stream config: {e}"))?
-    };
-
-    // We always request f32 samples to keep the mixing simple.
-    if supported.sample_format() != SampleFormat::F32 {
-        }
-
-    Ok(StreamConfig {
-        channels: supported.channels(),
-        sample_rate: supported.sample_rate(),
-        buffer_size: cpal::BufferSize::Default,
-    })
+    /// Peaks of `path`, from the on-disk cache when it has them.
+    fn peaks(&self, path: &str) -> Result<Peaks, String> {
+        let key = self.pcm_cache.lock().ok().and_then(|mut c| c.peaks_key(path));
+        let cached = key.as_ref().and_then(|key| {
+            let data = self.pcm_cache.lock().ok()?.read(key)?;
+            Peaks::from_bytes(&data)
+        });
+        if let Some(peaks) = cached {
+            return Ok(peaks);
+        }
+        let peaks = Peaks::measure(path)?;
+        if let Some(key) = &key {
+            pcm_cache::store_bytes(&self.pcm_cache, key, &peaks.to_bytes());
+        }
+        Ok(peaks)
+    }
 }
@@ -0,0 +1,570 @@
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::decode::AudioDecoder;
+use crate::devices::StreamFormat;
+use crate::encode::AudioWriter;
+use crate::protocol::AudioFileFormat;
+
+/// Names of the virtual devices.
+pub const INPUT_DEVICE: &str = "Null Input";
+pub const OUTPUT_DEVICE: &str = "Null Output";
+pub const MONITOR_DEVICE: &str = "Null Monitor";
+
+/// Settings for the null backend, from the `--null*` command line options.
+#[derive(Debug, Clone)]
+pub struct NullConfig {
+    /// Audio file played as the mic; silence once it ends, or throughout
+    /// if there is none.  (`--null-input`)
+    pub input: Option<PathBuf>,
+    /// WAV files the main and monitor outputs are written to, as 32-bit
+    /// float.  (`--null-output`, `--null-monitor`)
+    pub output: Option<PathBuf>,
+    pub monitor: Option<PathBuf>,
+    /// Format of the outputs.  (`--null-rate`, `--null-channels`)
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Frames per callback.  (`--null-block`)
+    pub block_frames: usize,
+    /// Render in real time on a thread of its own, instead of only on
+    /// `AdvanceClock`.  (`--null-realtime`)
+    pub realtime: bool,
+}
+
+impl Default for NullConfig {
+    fn default() -> Self {
+        Self {
+            input: None,
+            output: None,
+            monitor: None,
+            sample_rate: 48000,
+            channels: 2,
+            block_frames: 480,
+            realtime: false,
+        }
+    }
+}
+
+impl NullConfig {
+    /// Read the `--null*` options out of the command line; other arguments
+    /// are left alone.  `None` if there are none, so the system's devices
+    /// should be used.
+    pub fn from_args(args: impl IntoIterator<Item = String>) -> Result<Option<Self>, String> {
+        let mut config = Self::default();
+        let mut enabled = false;
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            if !arg.starts_with("--null") {
+                continue;
+            }
+            enabled = true;
+            let mut value = || args.next().ok_or_else(|| format!("{arg} needs a value"));
+            match arg.as_str() {
+                "--null" => {}
+                "--null-realtime" => config.realtime = true,
+                "--null-input" => config.input = Some(value()?.into()),
+                "--null-output" => config.output = Some(value()?.into()),
+                "--null-monitor" => config.monitor = Some(value()?.into()),
+                "--null-rate" => config.sample_rate = number(&arg, &value()?)?,
+                "--null-channels" => config.channels = number(&arg, &value()?)?,
+                "--null-block" => config.block_frames = number(&arg, &value()?)?,
+                _ => return Err(format!("Unknown option: {arg}")),
+            }
+        }
+        if !(8000..=384_000).contains(&config.sample_rate) {
+            return Err("--null-rate must be between 8000 and 384000".to_string());
+        }
+        if !(1..=8).contains(&config.channels) {
+            return Err("--null-channels must be between 1 and 8".to_string());
+        }
+        if !(1..=65_536).contains(&config.block_frames) {
+            return Err("--null-block must be between 1 and 65536".to_string());
+        }
+        Ok(enabled.then_some(config))
+    }
+}
+
+fn number<T: FromStr>(option: &str, value: &str) -> Result<T, String> {
+    value
+        .parse()
+        .map_err(|_| format!("{option} needs a number, not {value}"))
+}
+
+/// Virtual devices for running the engine without a sound card: one input
+/// reading an audio file and two outputs writing WAV files, all driven by
+/// one clock.  On each tick the input's callback runs first, then the
+/// outputs' in the order they were opened, so a block of mic input reaches
+/// the mix in the same block and the result is the same on every run.
+pub struct NullHost {
+    config: NullConfig,
+    input_format: StreamFormat,
+    streams: Arc<Mutex<Streams>>,
+}
+
+impl NullHost {
+    /// Set up the devices.  With `realtime` a thread starts ticking the
+    /// clock once per block, paced like a sound card; otherwise it only
+    /// moves on `advance`.
+    pub fn new(config: NullConfig) -> Result<Self, String> {
+        let input_format = match &config.input {
+            Some(path) => {
+                let decoder = AudioDecoder::open(&path.to_string_lossy())?;
+                StreamFormat {
+                    sample_rate: decoder.sample_rate(),
+                    channels: decoder.channels(),
+                }
+            }
+            None => StreamFormat {
+                sample_rate: config.sample_rate,
+                channels: 1,
+            },
+        };
+        let streams = Arc::new(Mutex::new(Streams::default()));
+        if config.realtime {
+            let streams = Arc::downgrade(&streams);
+            let (block, rate) = (config.block_frames, config.sample_rate);
+            thread::spawn(move || tick_realtime(streams, block, rate));
+        }
+        Ok(Self {
+            config,
+            input_format,
+            streams,
+        })
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.config.sample_rate
+    }
+
+    /// Render `frames` frames on the manual clock, in blocks, calling
+    /// `before_block` with each block's length before it is rendered.
+    pub fn advance(&self, frames: u64, mut before_block: impl FnMut(usize)) -> Result<(), String> {
+        if self.config.realtime {
+            return Err("The null audio clock is running in real time".to_string());
+        }
+        let mut left = frames;
+        while left > 0 {
+            let block = left.min(self.config.block_frames as u64) as usize;
+            before_block(block);
+            self.streams
+                .lock()
+                .map_err(|e| e.to_string())?
+                .tick(block, self.config.sample_rate);
+            left -= block as u64;
+        }
+        Ok(())
+    }
+
+    pub fn input_devices(&self) -> Vec<String> {
+        vec![INPUT_DEVICE.to_string()]
+    }
+
+    pub fn output_devices(&self) -> Vec<String> {
+        vec![OUTPUT_DEVICE.to_string(), MONITOR_DEVICE.to_string()]
+    }
+
+    pub fn find_input_device(&self, name: &str) -> Option<NullDevice> {
+        (name == INPUT_DEVICE).then(|| self.device(INPUT_DEVICE))
+    }
+
+    pub fn find_output_device(&self, name: &str) -> Option<NullDevice> {
+        [OUTPUT_DEVICE, MONITOR_DEVICE]
+            .into_iter()
+            .find(|&n| n == name)
+            .map(|n| self.device(n))
+    }
+
+    pub fn default_input_device(&self) -> Option<NullDevice> {
+        Some(self.device(INPUT_DEVICE))
+    }
+
+    pub fn default_output_device(&self) -> Option<NullDevice> {
+        Some(self.device(OUTPUT_DEVICE))
+    }
+
+    fn device(&self, name: &'static str) -> NullDevice {
+        let output_format = StreamFormat {
+            sample_rate: self.config.sample_rate,
+            channels: self.config.channels,
+        };
+        let (format, path) = match name {
+            INPUT_DEVICE => (self.input_format, self.config.input.clone()),
+            OUTPUT_DEVICE => (output_format, self.config.output.clone()),
+            _ => (output_format, self.config.monitor.clone()),
+        };
+        NullDevice {
+            name,
+            format,
+            path,
+            streams: Arc::clone(&self.streams),
+        }
+    }
+}
+
+fn tick_realtime(streams: Weak<Mutex<Streams>>, block: usize, rate: u32) {
+    let period = Duration::from_secs_f64(block as f64 / rate as f64);
+    let mut next = Instant::now();
+    while let Some(streams) = streams.upgrade() {
+        if let Ok(mut streams) = streams.lock() {
+            streams.tick(block, rate);
+        }
+        drop(streams);
+        next += period;
+        thread::sleep(next.saturating_duration_since(Instant::now()));
+    }
+}
+
+/// One of the `NullHost`'s devices.
+pub struct NullDevice {
+    name: &'static str,
+    format: StreamFormat,
+    /// The file read (input) or written (outputs).
+    path: Option<PathBuf>,
+    streams: Arc<Mutex<Streams>>,
+}
+
+impl NullDevice {
+    pub fn format(&self) -> StreamFormat {
+        self.format
+    }
+
+    /// Start feeding `data` the input file, from its beginning.  A stream
+    /// opened earlier stops being fed.
+    pub fn build_input_stream(
+        &self,
+        data: impl FnMut(&[f32]) + Send + 'static,
+    ) -> Result<NullStream, String> {
+        let samples = match &self.path {
+            Some(path) => AudioDecoder::open(&path.to_string_lossy())?.read_all(usize::MAX),
+            None => Vec::new(),
+        };
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        let id = streams.next_id();
+        let start_frame = streams.frames;
+        streams.input = Some(Input {
+            id,
+            data: Box::new(data),
+            samples,
+            position: 0,
+            format: self.format,
+            start_frame,
+            block: Vec::new(),
+        });
+        Ok(NullStream {
+            id,
+            streams: Arc::downgrade(&self.streams),
+        })
+    }
+
+    /// Start asking `data` for blocks, written to the device's file (which
+    /// is started over).
+    pub fn build_output_stream(
+        &self,
+        data: impl FnMut(&mut [f32]) + Send + 'static,
+    ) -> Result<NullStream, String> {
+        let writer = match &self.path {
+            Some(path) => Some(AudioWriter::create(
+                path,
+                AudioFileFormat::Wav,
+                self.format.sample_rate,
+                self.format.channels,
+                32,
+            )?),
+            None => None,
+        };
+        let mut streams = self.streams.lock().map_err(|e| e.to_string())?;
+        let id = streams.next_id();
+        streams.outputs.push(Output {
+            id,
+            device: self.name,
+            data: Box::new(data),
+            channels: self.format.channels as usize,
+            writer,
+            block: Vec::new(),
+        });
+        Ok(NullStream {
+            id,
+            streams: Arc::downgrade(&self.streams),
+        })
+    }
+}
+
+/// A stream on a `NullDevice`.  Dropping it closes it, finishing its file.
+pub struct NullStream {
+    id: u64,
+    streams: Weak<Mutex<Streams>>,
+}
+
+impl NullStream {
+    /// Null streams run from the moment they are built.
+    pub fn play(&self) -> Result<(), String> {
+        Ok(())
+    }
+}
+
+impl Drop for NullStream {
+    fn drop(&mut self) {
+        let Some(streams) = self.streams.upgrade() else {
+            return;
+        };
+        let Ok(mut streams) = streams.lock() else {
+            return;
+        };
+        if streams.input.as_ref().is_some_and(|i| i.id == self.id) {
+            streams.input = None;
+        }
+        streams.outputs.retain(|o| o.id != self.id);
+    }
+}
+
+type InputCallback = Box<dyn FnMut(&[f32]) + Send>;
+type OutputCallback = Box<dyn FnMut(&mut [f32]) + Send>;
+
+#[derive(Default)]
+struct Streams {
+    input: Option<Input>,
+    outputs: Vec<Output>,
+    /// Frames rendered since the host was set up.
+    frames: u64,
+    last_id: u64,
+}
+
+struct Input {
+    id: u64,
+    data: InputCallback,
+    /// The whole input file, interleaved.
+    samples: Vec<f32>,
+    position: usize,
+    format: StreamFormat,
+    /// Clock frame the stream was opened on.
+    start_frame: u64,
+    block: Vec<f32>,
+}
+
+struct Output {
+    id: u64,
+    device: &'static str,
+    data: OutputCallback,
+    channels: usize,
+    writer: Option<AudioWriter>,
+    block: Vec<f32>,
+}
+
+impl Drop for Output {
+    /// Whether the stream or the host goes first, the file is finished.
+    fn drop(&mut self) {
+        if let Some(writer) = self.writer.take() {
+            if let Err(e) = writer.finish() {
+                eprintln!("[null {}] {e}", self.device);
+            }
+        }
+    }
+}
+
+impl Streams {
+    fn next_id(&mut self) -> u64 {
+        self.last_id += 1;
+        self.last_id
+    }
+
+    /// Run one block of `frames` frames at `rate` through every stream.
+    fn tick(&mut self, frames: usize, rate: u32) {
+        let end = self.frames + frames as u64;
+        if let Some(input) = &mut self.input {
+            // As many input frames as the input's rate has produced by the
+            // end of the block.
+            let channels = input.format.channels as usize;
+            let due = (end - input.start_frame) * input.format.sample_rate as u64 / rate as u64;
+            let len = due as usize * channels - input.position;
+            let end = input.samples.len().min(input.position + len);
+            input.block.clear();
+            input
+                .block
+                .extend_from_slice(input.samples.get(input.position..end).unwrap_or(&[]));
+            input.block.resize(len, 0.0);
+            input.position += len;
+            (input.data)(&input.block);
+        }
+        for output in &mut self.outputs {
+            output.block.clear();
+            output.block.resize(frames * output.channels, 0.0);
+            (output.data)(&mut output.block);
+            if let Some(writer) = &mut output.writer {
+                if let Err(e) = writer.write(&output.block) {
+                    eprintln!("[null {}] {e}", output.device);
+                    output.writer = None;
+                }
+            }
+        }
+        self.frames = end;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::devices::Host;
+    use crate::mixer::MixerState;
+    use crate::protocol::MonitorSource;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    fn args(list: &[&str]) -> Result<Option<NullConfig>, String> {
+        NullConfig::from_args(list.iter().map(|s| s.to_string()))
+    }
+
+    #[test]
+    fn parses_the_command_line() {
+        assert!(args(&["--verbose"]).unwrap().is_none());
+        let config = args(&["--null-output", "out.wav", "--null-rate", "44100"])
+            .unwrap()
+            .unwrap();
+        assert_eq!(config.output, Some(PathBuf::from("out.wav")));
+        assert_eq!((config.sample_rate, config.channels), (44100, 2));
+        assert!(!config.realtime);
+        assert!(args(&["--null-rate"]).is_err());
+        assert!(args(&["--null-rate", "fast"]).is_err());
+        assert!(args(&["--null-channels", "0"]).is_err());
+        assert!(args(&["--null-bogus"]).is_err());
+    }
+
+    /// Plays `sound` on the sfx bus with `mic` as the mic, for `ms`, and
+    /// returns what the main output wrote.
+    fn mix(name: &str, mic: &std::path::Path, sound: &std::path::Path, ms: f64) -> Vec<f32> {
+        let output = corpus::path(name);
+        let mut mixer = corpus::null_mixer(Some(mic), &output);
+        mixer.start_output().unwrap();
+        mixer.start_capture().unwrap();
+        mixer
+            .play_file(sound.to_str().unwrap(), "sfx", corpus::play_params())
+            .unwrap();
+        mixer.advance_clock(ms).unwrap();
+        assert_eq!(mixer.clock_ms(), ms);
+        // Closing the streams finishes the file.
+        drop(mixer);
+
+        let (rate, channels, samples) = corpus::decode(&output);
+        assert_eq!((rate, channels), (RATE, 2));
+        samples
+    }
+
+    #[test]
+    fn mixes_the_mic_and_sounds_into_a_file() {
+        let mic_signal: Vec<f32> = (0..RATE as usize / 10)
+            .map(|i| (i as f32 * 0.01).sin() * 0.25)
+            .collect();
+        let mic = corpus::wav_float("null-mic.wav", RATE, 1, &mic_signal);
+        let sound_signal = corpus::float_signal(RATE as usize, 2);
+        let sound = corpus::wav_float("null-sound.wav", RATE, 2, &sound_signal);
+
+        // Longer than the mic file, which then goes silent.
+        let out = mix("null-out.wav", &mic, &sound, 250.0);
+        assert_eq!(out.len(), 12000 * 2);
+        for (i, &got) in out.iter().enumerate() {
+            let want = mic_signal.get(i / 2).copied().unwrap_or(0.0) + sound_signal[i];
+            assert!(
+                (got - want).abs() < 1e-6,
+                "sample {i}: got {got}, want {want}"
+            );
+        }
+
+        // The same again, to the sample.
+        assert_eq!(mix("null-again.wav", &mic, &sound, 250.0), out);
+    }
+
+    /// Plays `sound` on `bus` at half the master volume for 250 ms and
+    /// returns the loudest sample the monitor wrote.
+    fn monitor_peak(name: &str, sound: &std::path::Path, bus: &str, source: MonitorSource) -> f32 {
+        let monitor = corpus::path(name);
+        let mut mixer = corpus::monitored_mixer(&corpus::path(&format!("main-{name}")), &monitor);
+        mixer.start_output().unwrap();
+        mixer.start_monitor().unwrap();
+        mixer.set_monitor_source(source);
+        mixer.set_volume(0.5);
+        mixer
+            .play_file(sound.to_str().unwrap(), bus, corpus::play_params())
+            .unwrap();
+        mixer.advance_clock(250.0).unwrap();
+        drop(mixer);
+
+        let (_, _, samples) = corpus::decode(&monitor);
+        samples.iter().fold(0.0, |peak, s| peak.max(s.abs()))
+    }
+
+    #[test]
+    fn the_monitor_plays_the_mix_or_the_buses() {
+        let signal: Vec<f32> = (0..RATE as usize)
+            .flat_map(|i| [(i as f32 * 0.05).sin() * 0.8; 2])
+            .collect();
+        let sound = corpus::wav_float("monitor-sound.wav", RATE, 2, &signal);
+
+        // The mix is heard after the master volume, as the mic gets it.
+        let mix = monitor_peak("monitor-mix.wav", &sound, "sfx", MonitorSource::Mix);
+        assert!((mix - 0.4).abs() < 0.01, "mix peak {mix}");
+        let buses = monitor_peak("monitor-buses.wav", &sound, "sfx", MonitorSource::Buses);
+        assert!((buses - 0.8).abs() < 0.01, "buses peak {buses}");
+        // A bus routed only to the monitor is heard either way.
+        let local = monitor_peak("monitor-local.wav", &sound, "monitor", MonitorSource::Mix);
+        assert!((local - 0.8).abs() < 0.01, "local peak {local}");
+    }
+
+    #[test]
+    fn pause_spares_the_mic_and_mute_spares_the_sounds() {
+        let mic = corpus::wav_float("mute-mic.wav", RATE, 1, &vec![0.25; RATE as usize]);
+        let sound = corpus::wav_float("mute-sound.wav", RATE, 2, &vec![0.5; RATE as usize * 2]);
+        let output = corpus::path("mute-out.wav");
+        let mut mixer = corpus::null_mixer(Some(&mic), &output);
+        mixer.start_output().unwrap();
+        mixer.start_capture().unwrap();
+        mixer
+            .play_file(sound.to_str().unwrap(), "sfx", corpus::play_params())
+            .unwrap();
+
+        // 100 ms of each state, in order, and the level each should give.
+        let steps = [0.75, 0.25, 0.5, 0.5, 0.75];
+        for i in 0..steps.len() {
+            match i {
+                1 => mixer.pause(),
+                2 => {
+                    mixer.resume();
+                    mixer.set_mic_muted(true);
+                }
+                3 => {
+                    mixer.set_mic_muted(false);
+                    mixer.set_push_to_mute(true);
+                }
+                4 => mixer.set_push_to_mute(false),
+                _ => {}
+            }
+            mixer.advance_clock(100.0).unwrap();
+        }
+        drop(mixer);
+
+        let (_, _, out) = corpus::decode(&output);
+        let step = RATE as usize / 10 * 2;
+        assert_eq!(out.len(), step * steps.len());
+        for (i, (block, want)) in out.chunks(step).zip(steps).enumerate() {
+            assert!(
+                block.iter().all(|s| (s - want).abs() < 1e-6),
+                "step {i}: want {want}, got {:?}",
+                &block[..4]
+            );
+        }
+    }
+
+    #[test]
+    fn the_manual_clock_refuses_real_devices_and_realtime() {
+        let mixer = MixerState::new(Host::system());
+        assert!(mixer.advance_clock(10.0).is_err());
+        let config = NullConfig {
+            realtime: true,
+            ..Default::default()
+        };
+        let mixer = MixerState::new(Host::Null(NullHost::new(config).unwrap()));
+        assert!(mixer.advance_clock(10.0).is_err());
+    }
+}
@@ -0,0 +1,235 @@
+use audiopus_sys::{
+    opus_multistream_decode_float, opus_multistream_decoder_create, opus_multistream_decoder_ctl,
+    opus_multistream_decoder_destroy, OpusMSDecoder, OPUS_RESET_STATE,
+};
+use symphonia::core::audio::{
+    AsAudioBufferRef, AudioBuffer, AudioBufferRef, Channels, Signal, SignalSpec,
+};
+use symphonia::core::codecs::{
+    CodecDescriptor, CodecParameters, Decoder, DecoderOptions, FinalizeResult, CODEC_TYPE_OPUS,
+};
+use symphonia::core::errors::{decode_error, unsupported_error, Result};
+use symphonia::core::formats::Packet;
+use symphonia::core::support_codec;
+
+/// Opus always decodes at 48 kHz.
+const RATE: u32 = 48_000;
+
+/// The longest Opus packet: 120 ms.
+const MAX_FRAMES: usize = 5760;
+
+/// Owns a libopus multistream decoder.
+struct MsDecoder(*mut OpusMSDecoder);
+
+// SAFETY: the decoder state is only touched through `&mut`, from one thread
+// at a time.
+unsafe impl Send for MsDecoder {}
+unsafe impl Sync for MsDecoder {}
+
+impl Drop for MsDecoder {
+    fn drop(&mut self) {
+        unsafe { opus_multistream_decoder_destroy(self.0) };
+    }
+}
+
+/// The parts of the `OpusHead` header the decoder needs.
+struct OpusHead {
+    channels: usize,
+    pre_skip: usize,
+    /// Output gain in Q7.8 dB.
+    gain: i16,
+    streams: u8,
+    coupled: u8,
+    /// Decoded channel for each output channel.
+    mapping: Vec<u8>,
+}
+
+impl OpusHead {
+    /// Parse the header from a track's extra data (Ogg and Matroska both
+    /// carry it whole).  Without one, assume a plain mono or stereo stream.
+    fn parse(params: &CodecParameters) -> Result<Self> {
+        let Some(data) = params.extra_data.as_deref() else {
+            let channels = params.channels.map_or(2, |c| c.count()).min(2);
+            return Ok(Self::family_0(channels, 0, 0));
+        };
+        if data.len() < 19 || &data[..8] != b"OpusHead" {
+            return decode_error("opus: invalid header");
+        }
+        let channels = data[9] as usize;
+        let pre_skip = u16::from_le_bytes([data[10], data[11]]) as usize;
+        let gain = i16::from_le_bytes([data[16], data[17]]);
+        match data[18] {
+            0 if (1..=2).contains(&channels) => Ok(Self::family_0(channels, pre_skip, gain)),
+            1 if (1..=8).contains(&channels) && data.len() >= 21 + channels => Ok(Self {
+                channels,
+                pre_skip,
+                gain,
+                streams: data[19],
+                coupled: data[20],
+                mapping: data[21..21 + channels].to_vec(),
+            }),
+            _ => unsupported_error("opus: unsupported channel mapping"),
+        }
+    }
+
+    /// One stream, coupled if stereo.
+    fn family_0(channels: usize, pre_skip: usize, gain: i16) -> Self {
+        Self {
+            channels,
+            pre_skip,
+            gain,
+            streams: 1,
+            coupled: (channels == 2) as u8,
+            mapping: (0..channels as u8).collect(),
+        }
+    }
+}
+
+/// Opus channel order (the Vorbis order) for each channel count.
+fn vorbis_order(channels: usize) -> &'static [Channels] {
+    const FL: Channels = Channels::FRONT_LEFT;
+    const FR: Channels = Channels::FRONT_RIGHT;
+    const FC: Channels = Channels::FRONT_CENTRE;
+    const LFE: Channels = Channels::LFE1;
+    const RL: Channels = Channels::REAR_LEFT;
+    const RR: Channels = Channels::REAR_RIGHT;
+    const RC: Channels = Channels::REAR_CENTRE;
+    const SL: Channels = Channels::SIDE_LEFT;
+    const SR: Channels = Channels::SIDE_RIGHT;
+    match channels {
+        1 => &[FL],
+        2 => &[FL, FR],
+        3 => &[FL, FC, FR],
+        4 => &[FL, FR, RL, RR],
+        5 => &[FL, FC, FR, RL, RR],
+        6 => &[FL, FC, FR, RL, RR, LFE],
+        7 => &[FL, FC, FR, SL, SR, RC, LFE],
+        _ => &[FL, FC, FR, SL, SR, RL, RR, LFE],
+    }
+}
+
+/// A symphonia decoder for Opus, backed by libopus.
+///
+/// Handles mono, stereo and surround (mapping family 1) streams, drops the
+/// header's pre-skip and applies its output gain.  Symphonia's Ogg and
+/// Matroska demuxers feed it.
+pub struct OpusDecoder {
+    params: CodecParameters,
+    decoder: MsDecoder,
+    /// Output plane for each decoded channel.
+    planes: Vec<usize>,
+    gain: f32,
+    pre_skip: usize,
+    /// Frames still to drop before the output starts.
+    skip: usize,
+    /// Interleaved output from libopus.
+    pcm: Vec<f32>,
+    buf: AudioBuffer<f32>,
+}
+
+impl Decoder for OpusDecoder {
+    fn try_new(params: &CodecParameters, _: &DecoderOptions) -> Result<Self> {
+        if params.codec != CODEC_TYPE_OPUS {
+            return unsupported_error("opus: invalid codec type");
+        }
+        let head = OpusHead::parse(params)?;
+
+        let mut error = 0;
+        let decoder = unsafe {
+            opus_multistream_decoder_create(
+                RATE as i32,
+                head.channels as i32,
+                head.streams as i32,
+                head.coupled as i32,
+                head.mapping.as_ptr(),
+                &mut error,
+            )
+        };
+        if decoder.is_null() || error != 0 {
+            return decode_error("opus: invalid stream layout");
+        }
+
+        let order = vorbis_order(head.channels);
+        let layout = order.iter().fold(Channels::empty(), |all, &c| all | c);
+        let planes = order
+            .iter()
+            .map(|c| (layout.bits() & (c.bits() - 1)).count_ones() as usize)
+            .collect();
+
+        let mut params = params.clone();
+        params.with_sample_rate(RATE).with_channels(layout);
+        Ok(Self {
+            params,
+            decoder: MsDecoder(decoder),
+            planes,
+            gain: 10f32.powf(head.gain as f32 / (20.0 * 256.0)),
+            pre_skip: head.pre_skip,
+            skip: head.pre_skip,
+            pcm: vec![0.0; MAX_FRAMES * head.channels],
+            buf: AudioBuffer::new(MAX_FRAMES as u64, SignalSpec::new(RATE, layout)),
+        })
+    }
+
+    fn supported_codecs() -> &'static [CodecDescriptor] {
+        &[support_codec!(CODEC_TYPE_OPUS, "opus", "Opus")]
+    }
+
+    fn reset(&mut self) {
+        unsafe { opus_multistream_decoder_ctl(self.decoder.0, OPUS_RESET_STATE) };
+        // The decoder needs the pre-skip to settle again.  Ogg timestamps
+        // count it, so this also puts a seek back on the right frame.
+        self.skip = self.pre_skip;
+    }
+
+    fn codec_params(&self) -> &CodecParameters {
+        &self.params
+    }
+
+    fn decode(&mut self, packet: &Packet) -> Result<AudioBufferRef<'_>> {
+        self.buf.clear();
+        let data = packet.buf();
+        // An empty packet would ask libopus to conceal a lost one.
+        if data.is_empty() {
+            return Ok(self.buf.as_audio_buffer_ref());
+        }
+        let frames = unsafe {
+            opus_multistream_decode_float(
+                self.decoder.0,
+                data.as_ptr(),
+                data.len() as i32,
+                self.pcm.as_mut_ptr(),
+                MAX_FRAMES as i32,
+                0,
+            )
+        };
+        if frames < 0 {
+            return decode_error("opus: corrupt packet");
+        }
+        let frames = frames as usize;
+
+        self.buf.render_reserved(Some(frames));
+        let channels = self.planes.len();
+        for (i, &plane) in self.planes.iter().enumerate() {
+            let decoded = self.pcm.iter().skip(i).step_by(channels);
+            for (out, &sample) in self.buf.chan_mut(plane).iter_mut().zip(decoded) {
+                *out = sample * self.gain;
+            }
+        }
+
+        let skip = self.skip.min(frames);
+        self.skip -= skip;
+        self.buf.trim(
+            skip + packet.trim_start() as usize,
+            packet.trim_end() as usize,
+        );
+        Ok(self.buf.as_audio_buffer_ref())
+    }
+
+    fn finalize(&mut self) -> FinalizeResult {
+        Default::default()
+    }
+
+    fn last_decoded(&self) -> AudioBufferRef<'_> {
+        self.buf.as_audio_buffer_ref()
+    }
+}
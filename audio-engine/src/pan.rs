@@ -0,0 +1,156 @@
+use std::f32::consts::{FRAC_PI_2, FRAC_PI_4, SQRT_2};
+
+/// Equal-power stereo panner for one voice.
+///
+/// Only the first two channels (front left / right) are touched; with a
+/// mono output there is nothing to pan.  A mono source (duplicated into
+/// both channels by the decoder) is placed with a sin/cos law scaled so
+/// the centre position is unchanged; a stereo source is folded towards
+/// one side like the Web Audio `StereoPannerNode`.
+#[derive(Debug, Clone, Copy)]
+pub struct Panner {
+    pan: f32,
+    mono_source: bool,
+    /// 2×2 mixing matrix derived from `pan`:
+    /// `l' = m[0] * l + m[1] * r`, `r' = m[2] * l + m[3] * r`.
+    matrix: [f32; 4],
+}
+
+impl Panner {
+    pub fn new(pan: f32, mono_source: bool) -> Self {
+        let mut panner = Self {
+            pan: 0.0,
+            mono_source,
+            matrix: [1.0, 0.0, 0.0, 1.0],
+        };
+        panner.set_pan(pan);
+        panner
+    }
+
+    pub fn pan(&self) -> f32 {
+        self.pan
+    }
+
+    pub fn set_pan(&mut self, pan: f32) {
+        self.pan = pan.clamp(-1.0, 1.0);
+        self.matrix = if self.mono_source {
+            let theta = (self.pan + 1.0) * FRAC_PI_4;
+            [theta.cos() * SQRT_2, 0.0, 0.0, theta.sin() * SQRT_2]
+        } else if self.pan <= 0.0 {
+            let x = (self.pan + 1.0) * FRAC_PI_2;
+            [1.0, x.cos(), 0.0, x.sin()]
+        } else {
+            let x = self.pan * FRAC_PI_2;
+            [x.cos(), 0.0, x.sin(), 1.0]
+        };
+    }
+
+    /// Whether `mix_frame` would leave the signal unchanged.
+    pub fn is_centered(&self) -> bool {
+        self.pan == 0.0
+    }
+
+    /// Pan one interleaved frame from `src`, scale it by `gain` and add
+    /// it to `dst` (same channel count).
+    #[inline]
+    pub fn mix_frame(&self, src: &[f32], dst: &mut [f32], gain: f32) {
+        if src.len() < 2 {
+            for (d, s) in dst.iter_mut().zip(src) {
+                *d += s * gain;
+            }
+            return;
+        }
+        let (l, r) = (src[0], src[1]);
+        let m = &self.matrix;
+        for (c, (d, &s)) in dst.iter_mut().zip(src).enumerate() {
+            let v = match c {
+                0 => m[0] * l + m[1] * r,
+                1 => m[2] * l + m[3] * r,
+                _ => s,
+            };
+            *d += v * gain;
+        }
+    }
+}
+
+/// Left / right gains for a master balance in -1.0 (left) .. 1.0 (right).
+/// The far side is faded out along an equal-power curve; the near side
+/// stays at unity.
+pub fn balance_gains(balance: f32) -> (f32, f32) {
+    let b = balance.clamp(-1.0, 1.0);
+    if b >= 0.0 {
+        ((b * FRAC_PI_2).cos(), 1.0)
+    } else {
+        (1.0, (-b * FRAC_PI_2).cos())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pan_frame(panner: &Panner, src: &[f32]) -> Vec<f32> {
+        let mut dst = vec![0.0; src.len()];
+        panner.mix_frame(src, &mut dst, 1.0);
+        dst
+    }
+
+    fn assert_close(got: &[f32], want: &[f32]) {
+        for (g, w) in got.iter().zip(want) {
+            assert!((g - w).abs() < 1e-6, "{got:?} vs {want:?}");
+        }
+    }
+
+    #[test]
+    fn centred_pan_is_identity() {
+        for mono_source in [true, false] {
+            let panner = Panner::new(0.0, mono_source);
+            assert!(panner.is_centered());
+            let frame = if mono_source { [0.4, 0.4, 0.2] } else { [0.4, -0.7, 0.2] };
+            assert_close(&pan_frame(&panner, &frame), &frame);
+        }
+        // A mono output has nothing to pan.
+        assert_eq!(pan_frame(&Panner::new(-1.0, true), &[0.4]), [0.4]);
+    }
+
+    #[test]
+    fn mono_pan_keeps_power_constant() {
+        let mut panner = Panner::new(0.0, true);
+        for i in 0..=20 {
+            panner.set_pan(i as f32 / 10.0 - 1.0);
+            let out = pan_frame(&panner, &[1.0, 1.0]);
+            // The √2 scaling keeps the centre at unity, so the total is 2.
+            assert!((out[0] * out[0] + out[1] * out[1] - 2.0).abs() < 1e-5, "{out:?}");
+        }
+        panner.set_pan(-1.0);
+        assert_close(&pan_frame(&panner, &[1.0, 1.0]), &[SQRT_2, 0.0]);
+        panner.set_pan(5.0);
+        assert_eq!(panner.pan(), 1.0);
+        assert_close(&pan_frame(&panner, &[1.0, 1.0]), &[0.0, SQRT_2]);
+    }
+
+    #[test]
+    fn stereo_pan_folds_to_one_side() {
+        // Like `StereoPannerNode`: hard left sums both channels on the left.
+        let frame = [0.3, 0.5, 0.2];
+        assert_close(&pan_frame(&Panner::new(-1.0, false), &frame), &[0.8, 0.0, 0.2]);
+        assert_close(&pan_frame(&Panner::new(1.0, false), &frame), &[0.0, 0.8, 0.2]);
+
+        // Half left keeps the left channel and moves some of the right.
+        let x = 0.5 * FRAC_PI_2;
+        let out = pan_frame(&Panner::new(-0.5, false), &frame);
+        assert_close(&out, &[0.3 + 0.5 * x.cos(), 0.5 * x.sin(), 0.2]);
+    }
+
+    #[test]
+    fn balance_keeps_the_near_side_at_unity() {
+        assert_eq!(balance_gains(0.0), (1.0, 1.0));
+        let (l, r) = balance_gains(0.5);
+        assert_eq!(r, 1.0);
+        assert!((l - FRAC_PI_4.cos()).abs() < 1e-6);
+        let (l, r) = balance_gains(-1.0);
+        assert_eq!(l, 1.0);
+        assert!(r.abs() < 1e-6);
+        assert_eq!(balance_gains(-3.0), balance_gains(-1.0));
+    }
+}
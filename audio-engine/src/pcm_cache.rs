@@ -0,0 +1,608 @@
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::SystemTime;
+
+use memmap2::Mmap;
+
+use crate::channel_map::ChannelMatrix;
+use crate::protocol::PcmCacheStatus;
+
+/// Default disk budget: about 3 hours of 48 kHz stereo.
+pub const DEFAULT_DISK_BUDGET_BYTES: u64 = 4 * 1024 * 1024 * 1024;
+
+/// The cache shared between the command loop and decode threads.
+pub type SharedPcmCache = Arc<Mutex<PcmCache>>;
+
+/// Written in native byte order, so a cache copied to a machine with the
+/// other byte order reads as invalid rather than as noise.
+const MAGIC: u32 = 0x5241_5043;
+const VERSION: u32 = 1;
+/// Magic, version, rate, channels, source channels, sample count and
+/// padding.  A multiple of 4, so the samples stay aligned in the map.
+const HEADER_BYTES: usize = 32;
+const EXTENSION: &str = "pcm";
+/// Waveform peaks, which share the directory and the budget.
+const PEAKS_EXTENSION: &str = "peaks";
+
+/// Numbers the temporary files entries are written to.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// 64-bit FNV-1a.
+struct Fnv(u64);
+
+impl Fnv {
+    fn new() -> Self {
+        Self(0xcbf2_9ce4_8422_2325)
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &b in bytes {
+            self.0 ^= b as u64;
+            self.0 = self.0.wrapping_mul(0x0100_0000_01b3);
+        }
+    }
+}
+
+/// Hash of the contents of the file at `path`.
+fn content_hash(path: &str) -> std::io::Result<u64> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut hash = Fnv::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            return Ok(hash.0);
+        }
+        hash.write(&buf[..n]);
+    }
+}
+
+/// Decoded audio mapped from a cache file.
+pub struct MappedPcm {
+    map: Mmap,
+    len: usize,
+    /// Channel count of the source file (the samples are in the output's).
+    pub src_channels: u16,
+}
+
+impl MappedPcm {
+    /// Map the cache file at `path` if it holds `rate` / `channels` audio.
+    fn open(path: &Path, rate: u32, channels: u16) -> Option<Self> {
+        let file = File::open(path).ok()?;
+        // SAFETY: cache files are only written under a temporary name and
+        // renamed into place, and are never modified afterwards.
+        let map = unsafe { Mmap::map(&file) }.ok()?;
+        let header = map.get(..HEADER_BYTES)?;
+        let word = |i: usize| u32::from_ne_bytes(header[i..i + 4].try_into().unwrap());
+        let len = u64::from_ne_bytes(header[16..24].try_into().unwrap()) as usize;
+        let valid = word(0) == MAGIC
+            && word(4) == VERSION
+            && word(8) == rate
+            && u16::from_ne_bytes([header[12], header[13]]) == channels
+            && map.len() == HEADER_BYTES + len * std::mem::size_of::<f32>();
+        let src_channels = u16::from_ne_bytes([header[14], header[15]]);
+        valid.then_some(Self {
+            map,
+            len,
+            src_channels,
+        })
+    }
+
+    pub fn samples(&self) -> &[f32] {
+        // SAFETY: the map is page aligned and the header a multiple of 4
+        // bytes, and `open` checked that `len` samples follow it.
+        unsafe {
+            std::slice::from_raw_parts(self.map[HEADER_BYTES..].as_ptr() as *const f32, self.len)
+        }
+    }
+
+    /// Read the mapping through on a background thread, so the output
+    /// callback finds the pages in memory instead of faulting them in
+    /// from disk.
+    fn prefault(self: &Arc<Self>) {
+        let mapped = Arc::clone(self);
+        thread::spawn(move || {
+            const PAGE: usize = 4096;
+            let mut sum = 0u8;
+            for offset in (0..mapped.map.len()).step_by(PAGE) {
+                sum = sum.wrapping_add(mapped.map[offset]);
+            }
+            std::hint::black_box(sum);
+        });
+    }
+}
+
+struct Entry {
+    bytes: u64,
+    last_used: SystemTime,
+}
+
+/// Content hash of a file, valid while its size and modification time
+/// stay the same.
+struct KnownHash {
+    len: u64,
+    modified: Option<SystemTime>,
+    hash: u64,
+}
+
+/// Decoded sounds in the output format, persisted in a directory so they
+/// play without decoding after a restart.
+///
+/// Entries are named after the hash of the source file's contents and the
+/// format they were converted to (rate, channels and channel matrix), so a
+/// renamed file still hits and an edited one misses.  They are evicted
+/// least recently used first when they outgrow the disk budget.
+///
+/// The waveform peaks of a file are kept alongside, named after its hash
+/// alone.
+pub struct PcmCache {
+    /// `None` while the cache is disabled.
+    dir: Option<PathBuf>,
+    budget: u64,
+    used: u64,
+    /// Cache files by name.
+    entries: HashMap<String, Entry>,
+    /// Content hashes by source path, so a file is only read through once.
+    hashes: HashMap<String, KnownHash>,
+}
+
+impl PcmCache {
+    pub fn new(budget: u64) -> Self {
+        Self {
+            dir: None,
+            budget,
+            used: 0,
+            entries: HashMap::new(),
+            hashes: HashMap::new(),
+        }
+    }
+
+    /// Use `dir` for the cache (created if missing), or disable the cache
+    /// with `None`.  Entries already in the directory are picked up.
+    pub fn set_dir(&mut self, dir: Option<PathBuf>) -> Result<(), String> {
+        self.entries.clear();
+        self.used = 0;
+        self.dir = None;
+        let Some(dir) = dir else {
+            return Ok(());
+        };
+        fs::create_dir_all(&dir).map_err(|e| format!("Cannot create cache directory: {e}"))?;
+        let listing =
+            fs::read_dir(&dir).map_err(|e| format!("Cannot read cache directory: {e}"))?;
+        for item in listing.flatten() {
+            let path = item.path();
+            let Ok(meta) = item.metadata() else {
+                continue;
+            };
+            let name = item.file_name().to_string_lossy().into_owned();
+            if path.extension().is_some_and(|e| e == "tmp") {
+                // Left over from a write that was cut short.
+                let _ = fs::remove_file(&path);
+            } else if path
+                .extension()
+                .is_some_and(|e| e == EXTENSION || e == PEAKS_EXTENSION)
+                && meta.is_file()
+            {
+                self.used += meta.len();
+                self.entries.insert(
+                    name,
+                    Entry {
+                        bytes: meta.len(),
+                        last_used: meta.modified().unwrap_or(SystemTime::UNIX_EPOCH),
+                    },
+                );
+            }
+        }
+        self.dir = Some(dir);
+        self.evict(0);
+        Ok(())
+    }
+
+    pub fn set_budget(&mut self, budget: u64) {
+        self.budget = budget;
+        self.evict(0);
+    }
+
+    /// Delete every entry.  Entries that are playing can't be deleted on
+    /// every platform; those are kept and reported.
+    pub fn clear(&mut self) -> Result<(), String> {
+        let Some(dir) = self.dir.clone() else {
+            return Ok(());
+        };
+        let names: Vec<String> = self.entries.keys().cloned().collect();
+        let kept = names.iter().filter(|name| !self.remove(&dir, name)).count();
+        match kept {
+            0 => Ok(()),
+            n => Err(format!("{n} cache entries are in use and were kept")),
+        }
+    }
+
+    /// Content hash of the file at `path`, or `None` if the cache is
+    /// disabled or the file can't be read.
+    fn hash(&mut self, path: &str) -> Option<u64> {
+        self.dir.as_ref()?;
+        let meta = fs::metadata(path).ok()?;
+        let modified = meta.modified().ok();
+        match self.hashes.get(path) {
+            Some(known) if known.len == meta.len() && known.modified == modified => {
+                Some(known.hash)
+            }
+            _ => {
+                let hash = content_hash(path).ok()?;
+                self.hashes.insert(
+                    path.to_string(),
+                    KnownHash {
+                        len: meta.len(),
+                        modified,
+                        hash,
+                    },
+                );
+                Some(hash)
+            }
+        }
+    }
+
+    /// The cache entry name for `path` converted to `rate` / `channels`
+    /// with `matrix`, or `None` if the cache is disabled or the file can't
+    /// be read.
+    pub fn key(
+        &mut self,
+        path: &str,
+        rate: u32,
+        channels: u16,
+        matrix: &ChannelMatrix,
+    ) -> Option<String> {
+        let hash = self.hash(path)?;
+        let mut layout = Fnv::new();
+        for row in matrix.rows() {
+            for gain in row {
+                layout.write(&gain.to_bits().to_ne_bytes());
+            }
+        }
+        Some(format!(
+            "{hash:016x}-{rate}-{channels}-{:016x}.{EXTENSION}",
+            layout.0
+        ))
+    }
+
+    /// The entry name for the waveform peaks of `path`, or `None` if the
+    /// cache is disabled or the file can't be read.
+    pub fn peaks_key(&mut self, path: &str) -> Option<String> {
+        let hash = self.hash(path)?;
+        Some(format!("{hash:016x}.{PEAKS_EXTENSION}"))
+    }
+
+    /// Map the entry `key` if it is in the cache and holds `rate` /
+    /// `channels` audio.
+    pub fn get(&mut self, key: &str, rate: u32, channels: u16) -> Option<Arc<MappedPcm>> {
+        let dir = self.dir.clone()?;
+        self.entries.get(key)?;
+        let path = dir.join(key);
+        let Some(mapped) = MappedPcm::open(&path, rate, channels) else {
+            // Truncated, or deleted behind our back.
+            self.remove(&dir, key);
+            return None;
+        };
+        self.touch(&path, key);
+        let mapped = Arc::new(mapped);
+        mapped.prefault();
+        Some(mapped)
+    }
+
+    /// The contents of the entry `key`, if it is in the cache.
+    pub fn read(&mut self, key: &str) -> Option<Vec<u8>> {
+        let dir = self.dir.clone()?;
+        self.entries.get(key)?;
+        let path = dir.join(key);
+        let Ok(data) = fs::read(&path) else {
+            self.remove(&dir, key);
+            return None;
+        };
+        self.touch(&path, key);
+        Some(data)
+    }
+
+    /// Mark the entry `key`, at `path`, as just used.
+    fn touch(&mut self, path: &Path, key: &str) {
+        let now = SystemTime::now();
+        // The modification time carries the LRU order across restarts.
+        if let Ok(file) = File::options().write(true).open(path) {
+            let _ = file.set_modified(now);
+        }
+        if let Some(entry) = self.entries.get_mut(key) {
+            entry.last_used = now;
+        }
+    }
+
+    /// Where to write the entry `key`, unless the cache is disabled or
+    /// already has it.
+    fn target(&self, key: &str) -> Option<PathBuf> {
+        if self.entries.contains_key(key) {
+            return None;
+        }
+        self.dir.as_ref().map(|dir| dir.join(key))
+    }
+
+    /// Add the entry `key`, `bytes` long, evicting older ones to make room.
+    fn commit(&mut self, key: &str, bytes: u64) {
+        if let Some(old) = self.entries.remove(key) {
+            // Another decode of the same file got there first.
+            self.used -= old.bytes;
+        }
+        self.evict(bytes);
+        self.used += bytes;
+        self.entries.insert(
+            key.to_string(),
+            Entry {
+                bytes,
+                last_used: SystemTime::now(),
+            },
+        );
+    }
+
+    /// Delete the entry `name`.  Returns whether it is gone.
+    fn remove(&mut self, dir: &Path, name: &str) -> bool {
+        match fs::remove_file(dir.join(name)) {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => false,
+            _ => {
+                if let Some(entry) = self.entries.remove(name) {
+                    self.used -= entry.bytes;
+                }
+                true
+            }
+        }
+    }
+
+    /// Delete entries, oldest first, until `extra` more bytes fit in the
+    /// budget.  Entries that can't be deleted (mapped, on Windows) are
+    /// skipped.
+    fn evict(&mut self, extra: u64) {
+        let Some(dir) = self.dir.clone() else {
+            return;
+        };
+        let mut by_age: Vec<(SystemTime, String)> = self
+            .entries
+            .iter()
+            .map(|(name, e)| (e.last_used, name.clone()))
+            .collect();
+        by_age.sort();
+        for (_, name) in by_age {
+            if self.used + extra <= self.budget {
+                break;
+            }
+            self.remove(&dir, &name);
+        }
+    }
+
+    pub fn status(&self) -> PcmCacheStatus {
+        PcmCacheStatus {
+            dir: self.dir.as_ref().map(|d| d.to_string_lossy().into_owned()),
+            entries: self.entries.len(),
+            used_bytes: self.used,
+            budget_bytes: self.budget,
+        }
+    }
+}
+
+/// Write `samples` (`rate` / `channels` audio decoded from a file with
+/// `src_channels` channels) to `cache` as entry `key`.  Call from a
+/// background thread: the file is written without holding the lock.
+pub fn store(
+    cache: &SharedPcmCache,
+    key: &str,
+    samples: &[f32],
+    rate: u32,
+    channels: u16,
+    src_channels: u16,
+) {
+    let bytes = (HEADER_BYTES + std::mem::size_of_val(samples)) as u64;
+    store_with(cache, key, bytes, |tmp| {
+        write_entry(tmp, samples, rate, channels, src_channels)
+    });
+}
+
+/// Write `data` to `cache` as entry `key`.
+pub fn store_bytes(cache: &SharedPcmCache, key: &str, data: &[u8]) {
+    store_with(cache, key, data.len() as u64, |tmp| {
+        let mut file = File::create(tmp)?;
+        file.write_all(data)?;
+        file.sync_all()
+    });
+}
+
+/// Add the entry `key`, `bytes` long, to `cache`, with `write` filling in
+/// its file under a temporary name.
+fn store_with(
+    cache: &SharedPcmCache,
+    key: &str,
+    bytes: u64,
+    write: impl FnOnce(&Path) -> std::io::Result<()>,
+) {
+    let Some(path) = cache.lock().ok().and_then(|c| c.target(key)) else {
+        return;
+    };
+    if cache.lock().is_ok_and(|c| bytes > c.budget) {
+        return;
+    }
+    // Unique, in case two decodes of the same file finish together.
+    let tmp = path.with_extension(format!("{}.tmp", NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+    let written = write(&tmp).and_then(|()| fs::rename(&tmp, &path));
+    if let Err(e) = written {
+        let _ = fs::remove_file(&tmp);
+        eprintln!("[pcm-cache] Cannot write {}: {e}", path.display());
+        return;
+    }
+    if let Ok(mut cache) = cache.lock() {
+        cache.commit(key, bytes);
+    }
+}
+
+fn write_entry(
+    path: &Path,
+    samples: &[f32],
+    rate: u32,
+    channels: u16,
+    src_channels: u16,
+) -> std::io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    out.write_all(&MAGIC.to_ne_bytes())?;
+    out.write_all(&VERSION.to_ne_bytes())?;
+    out.write_all(&rate.to_ne_bytes())?;
+    out.write_all(&channels.to_ne_bytes())?;
+    out.write_all(&src_channels.to_ne_bytes())?;
+    out.write_all(&(samples.len() as u64).to_ne_bytes())?;
+    out.write_all(&[0u8; HEADER_BYTES - 24])?;
+    for sample in samples {
+        out.write_all(&sample.to_ne_bytes())?;
+    }
+    out.into_inner().map_err(|e| e.into_error())?.sync_all()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    /// An enabled cache in a fresh directory named `name`.
+    fn cache_in(name: &str, budget: u64) -> SharedPcmCache {
+        let dir = corpus::path(name);
+        let _ = fs::remove_dir_all(&dir);
+        let mut cache = PcmCache::new(budget);
+        cache.set_dir(Some(dir)).unwrap();
+        Arc::new(Mutex::new(cache))
+    }
+
+    /// Bytes taken by an entry of `samples` samples.
+    fn entry_bytes(samples: usize) -> u64 {
+        (HEADER_BYTES + samples * 4) as u64
+    }
+
+    #[test]
+    fn stores_and_maps_entries() {
+        let cache = cache_in("pcm-roundtrip", DEFAULT_DISK_BUDGET_BYTES);
+        let samples = corpus::float_signal(1000, 2);
+        store(&cache, "a.pcm", &samples, 48000, 2, 1);
+        let mut cache = cache.lock().unwrap();
+        let mapped = cache.get("a.pcm", 48000, 2).unwrap();
+        assert_eq!(mapped.samples(), &samples[..]);
+        assert_eq!(mapped.src_channels, 1);
+        assert!(cache.get("missing.pcm", 48000, 2).is_none());
+
+        // A restart picks the entry up again and drops half-written ones.
+        let dir = PathBuf::from(cache.status().dir.unwrap());
+        fs::write(dir.join("b.pcm.7.tmp"), [0u8; 8]).unwrap();
+        let mut reopened = PcmCache::new(DEFAULT_DISK_BUDGET_BYTES);
+        reopened.set_dir(Some(dir.clone())).unwrap();
+        assert_eq!(reopened.status().entries, 1);
+        assert_eq!(reopened.status().used_bytes, entry_bytes(samples.len()));
+        assert!(!dir.join("b.pcm.7.tmp").exists());
+        assert_eq!(reopened.get("a.pcm", 48000, 2).unwrap().samples(), &samples[..]);
+
+        // An entry that doesn't hold what its name says is dropped.
+        assert!(reopened.get("a.pcm", 44100, 2).is_none());
+        assert_eq!(reopened.status().entries, 0);
+        assert!(!dir.join("a.pcm").exists());
+    }
+
+    #[test]
+    fn keys_follow_the_contents_and_the_format() {
+        let path = corpus::wav_float("pcm-key.wav", 48000, 2, &corpus::float_signal(100, 2));
+        let copy = corpus::path("pcm-key-copy.wav");
+        fs::copy(&path, &copy).unwrap();
+        let (path, copy) = (path.to_str().unwrap(), copy.to_str().unwrap());
+        let stereo = ChannelMatrix::standard(2, None, 2);
+        let cache = cache_in("pcm-keys", DEFAULT_DISK_BUDGET_BYTES);
+        let mut cache = cache.lock().unwrap();
+
+        let key = cache.key(path, 48000, 2, &stereo).unwrap();
+        assert_eq!(cache.key(copy, 48000, 2, &stereo).unwrap(), key);
+        assert_ne!(cache.key(path, 44100, 2, &stereo).unwrap(), key);
+        let mono = ChannelMatrix::standard(2, None, 1);
+        assert_ne!(cache.key(path, 48000, 1, &mono).unwrap(), key);
+
+        corpus::wav_float("pcm-key.wav", 48000, 2, &[0.25; 200]);
+        assert_ne!(cache.key(path, 48000, 2, &stereo).unwrap(), key);
+        let missing = corpus::path("missing.wav");
+        assert!(cache.key(missing.to_str().unwrap(), 48000, 2, &stereo).is_none());
+
+        cache.set_dir(None).unwrap();
+        assert!(cache.key(copy, 48000, 2, &stereo).is_none());
+    }
+
+    #[test]
+    fn evicts_least_recently_used_and_clears() {
+        let samples = vec![0.0; 100];
+        let bytes = entry_bytes(samples.len());
+        let cache = cache_in("pcm-evict", 3 * bytes);
+        store(&cache, "a.pcm", &samples, 48000, 2, 2);
+        store(&cache, "b.pcm", &samples, 48000, 2, 2);
+        store(&cache, "c.pcm", &samples, 48000, 2, 2);
+        // Using `a` leaves `b` the oldest.
+        cache.lock().unwrap().get("a.pcm", 48000, 2).unwrap();
+        store(&cache, "d.pcm", &samples, 48000, 2, 2);
+        {
+            let mut cache = cache.lock().unwrap();
+            assert!(cache.get("b.pcm", 48000, 2).is_none());
+            assert_eq!(cache.status().entries, 3);
+            assert_eq!(cache.status().used_bytes, 3 * bytes);
+        }
+        // An entry bigger than the whole budget isn't written at all.
+        store(&cache, "big.pcm", &vec![0.0; 400], 48000, 2, 2);
+        assert!(cache.lock().unwrap().get("big.pcm", 48000, 2).is_none());
+
+        let mut cache = cache.lock().unwrap();
+        cache.set_budget(bytes);
+        assert_eq!(cache.status().entries, 1);
+        cache.clear().unwrap();
+        assert_eq!((cache.status().entries, cache.status().used_bytes), (0, 0));
+        let dir = cache.status().dir.unwrap();
+        assert_eq!(fs::read_dir(dir).unwrap().count(), 0);
+    }
+
+    #[test]
+    fn a_restarted_engine_plays_from_the_cache() {
+        let signal = corpus::float_signal(4800, 2);
+        let path = corpus::wav_float("pcm-played.wav", 48000, 2, &signal);
+        let path = path.to_str().unwrap();
+        let dir = corpus::path("pcm-engine");
+        let _ = fs::remove_dir_all(&dir);
+        let dir = dir.to_str().unwrap().to_string();
+
+        let first = corpus::path("pcm-first-out.wav");
+        let mut mixer = corpus::null_mixer(None, &first);
+        mixer.set_pcm_cache_dir(Some(dir.clone())).unwrap();
+        mixer.start_output().unwrap();
+        mixer.play_file(path, "sfx", corpus::play_params()).unwrap();
+        mixer.advance_clock(200.0).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while mixer.pcm_cache_status().entries == 0 {
+            assert!(Instant::now() < deadline, "the decode was never persisted");
+            thread::sleep(Duration::from_millis(5));
+        }
+        drop(mixer);
+
+        let second = corpus::path("pcm-second-out.wav");
+        let mut mixer = corpus::null_mixer(None, &second);
+        mixer.set_pcm_cache_dir(Some(dir)).unwrap();
+        mixer.start_output().unwrap();
+        let cache = Arc::clone(&mixer.pcm_cache);
+        let key = cache
+            .lock()
+            .unwrap()
+            .key(path, 48000, 2, &ChannelMatrix::standard(2, None, 2))
+            .unwrap();
+        assert_eq!(cache.lock().unwrap().get(&key, 48000, 2).unwrap().samples(), &signal[..]);
+        mixer.play_file(path, "sfx", corpus::play_params()).unwrap();
+        mixer.advance_clock(200.0).unwrap();
+        drop(mixer);
+        assert_eq!(corpus::decode(&first).2, corpus::decode(&second).2);
+        assert_eq!(corpus::decode(&second).2[..signal.len()], signal[..]);
+    }
+}
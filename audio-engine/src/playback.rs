@@ -0,0 +1,797 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::VoiceSlot;
+use crate::channel_map::ChannelMatrix;
+use crate::decode::AudioDecoder;
+use crate::mixer::resample;
+use crate::pan::Panner;
+use crate::pcm_cache::{self, MappedPcm, SharedPcmCache};
+use crate::protocol::ReverbSettings;
+use crate::reverb::Convolver;
+use crate::sample_cache::{CachedSound, SharedSampleCache};
+
+/// Files at least this long (or of unknown length) are streamed through a
+/// bounded window instead of being decoded whole.
+const STREAM_MIN_DURATION: Duration = Duration::from_secs(60);
+
+/// How far a streamed file is decoded ahead of the read position.
+const STREAM_WINDOW_SECONDS: usize = 10;
+
+/// How often a streaming decode thread waits for room in its window (or
+/// for a seek, once the file is fully decoded).
+const STREAM_POLL: Duration = Duration::from_millis(10);
+
+/// Source frames decoded per chunk.
+pub const CHUNK_FRAMES: usize = 2048;
+
+/// Pitch shifts beyond this many semitones either way are refused.
+pub const MAX_PITCH_SEMITONES: f32 = 24.0;
+
+/// How to play a file: everything in a play request besides the file and
+/// the bus.
+#[derive(Debug, Clone)]
+pub struct PlayParams {
+    pub volume: f32,
+    pub pan: f32,
+    pub reverb: Option<ReverbSettings>,
+    /// Gain on top of `volume`, in dB.  Unlike `volume` it can boost.
+    pub gain_db: f32,
+    /// Varispeed pitch shift (changes the duration too).
+    pub pitch_semitones: f32,
+    /// Start time on the engine clock; `None` plays straight away.
+    pub start_ms: Option<f64>,
+}
+
+// ---------------------------------------------------------------------------
+// File playback source that can be read from the output callback
+// ---------------------------------------------------------------------------
+
+/// The decoded audio of a voice.
+pub enum Samples {
+    /// Being appended to by the voice's decode thread.
+    Decoding(Vec<f32>),
+    /// Fully decoded and shared with the sample cache.
+    Shared(Arc<Vec<f32>>),
+    /// Mapped from the on-disk cache.
+    Mapped(Arc<MappedPcm>),
+    /// A window of a long file, filled by a streaming decode thread.
+    Streaming(StreamWindow),
+}
+
+impl Samples {
+    fn extend(&mut self, more: &[f32]) {
+        if let Samples::Decoding(samples) = self {
+            samples.extend_from_slice(more);
+        }
+    }
+
+    /// Decoded samples so far: the index one past the last one.
+    pub fn len(&self) -> usize {
+        match self {
+            Samples::Decoding(samples) => samples.len(),
+            Samples::Shared(samples) => samples.len(),
+            Samples::Mapped(mapped) => mapped.samples().len(),
+            Samples::Streaming(window) => window.end,
+        }
+    }
+
+    /// The sample at `index`, which must be below `len()` (and still in
+    /// the window, when streaming).
+    fn get(&self, index: usize) -> f32 {
+        match self {
+            Samples::Decoding(samples) => samples[index],
+            Samples::Shared(samples) => samples[index],
+            Samples::Mapped(mapped) => mapped.samples()[index],
+            Samples::Streaming(window) => window.ring[index % window.ring.len()],
+        }
+    }
+
+    /// Every sample, for the kinds that hold the whole sound.
+    fn whole(&self) -> &[f32] {
+        match self {
+            Samples::Decoding(samples) => samples,
+            Samples::Shared(samples) => samples,
+            Samples::Mapped(mapped) => mapped.samples(),
+            Samples::Streaming(_) => &[],
+        }
+    }
+
+    /// The `channels` samples of the frame starting at `index`.
+    fn frame(&self, index: usize, channels: usize) -> &[f32] {
+        match self {
+            Samples::Decoding(samples) => &samples[index..index + channels],
+            Samples::Shared(samples) => &samples[index..index + channels],
+            Samples::Mapped(mapped) => &mapped.samples()[index..index + channels],
+            Samples::Streaming(window) => {
+                let at = index % window.ring.len();
+                &window.ring[at..at + channels]
+            }
+        }
+    }
+
+    /// Move the samples behind an `Arc` so the cache can keep them.  This
+    /// doesn't copy, so it is cheap enough to do under the voice lock.
+    /// Mapped samples are already cached, so they give `None`.
+    fn share(&mut self) -> Option<Arc<Vec<f32>>> {
+        match self {
+            Samples::Decoding(samples) => {
+                let shared = Arc::new(std::mem::take(samples));
+                *self = Samples::Shared(Arc::clone(&shared));
+                Some(shared)
+            }
+            Samples::Shared(shared) => Some(Arc::clone(shared)),
+            Samples::Mapped(_) | Samples::Streaming(_) => None,
+        }
+    }
+}
+
+/// A fixed ring holding the part of a long file around the read position.
+/// Indices are sample positions in the whole decoded file; the ring is a
+/// whole number of frames, so a frame never wraps around it.
+pub struct StreamWindow {
+    ring: Vec<f32>,
+    /// Oldest sample still in the ring.
+    start: usize,
+    /// One past the newest sample.
+    end: usize,
+}
+
+impl StreamWindow {
+    fn new(capacity: usize) -> Self {
+        Self {
+            ring: vec![0.0; capacity.max(1)],
+            start: 0,
+            end: 0,
+        }
+    }
+
+    /// Samples that can be written without overwriting any not yet read
+    /// at `position`.
+    fn free(&self, position: usize) -> usize {
+        self.ring.len() - self.end.saturating_sub(position)
+    }
+
+    fn write(&mut self, samples: &[f32]) {
+        let len = self.ring.len();
+        let at = self.end % len;
+        let first = samples.len().min(len - at);
+        self.ring[at..at + first].copy_from_slice(&samples[..first]);
+        self.ring[..samples.len() - first].copy_from_slice(&samples[first..]);
+        self.end += samples.len();
+        self.start = self.start.max(self.end.saturating_sub(len));
+    }
+
+    /// Whether the read position can move to `index` without decoding.
+    fn contains(&self, index: usize) -> bool {
+        (self.start..=self.end).contains(&index)
+    }
+
+    /// Empty the window, to be refilled from `index`.
+    fn reset(&mut self, index: usize) {
+        self.start = index;
+        self.end = index;
+    }
+}
+
+/// Streaming playback buffer.  A background thread decodes samples and appends
+/// them here while the output callback reads them in real time.  This lets
+/// playback start as soon as the first decoded chunk is ready instead of waiting
+/// for the entire file to be decoded.
+pub struct FilePlayback {
+    /// Identifies the play request, so a decode thread can tell that its
+    /// voice has been replaced by a newer one.
+    pub id: u64,
+    /// Decoded samples are appended here by the decode thread.
+    pub samples: Samples,
+    /// Current read position (advanced by the output callback).
+    pub position: usize,
+    /// Per-file volume multiplier: the play's volume (0.0 .. 1.0) times
+    /// its gain.
+    pub volume: f32,
+    /// Stereo placement of the voice.
+    pub panner: Panner,
+    /// Set to `true` once the decode thread has finished (all samples appended).
+    pub decode_complete: bool,
+    /// Optional per-voice convolution reverb.
+    pub reverb: Option<Convolver>,
+    /// Frames of reverb tail still to render once the source has ended.
+    pub tail_remaining: usize,
+    /// Scratch buffer for the voice's signal when a reverb is attached.
+    pub scratch: Vec<f32>,
+    /// Frames of silence to output before the voice starts, so a
+    /// scheduled voice can start part-way into a block.
+    pub delay_frames: usize,
+    /// Sample index a streaming voice's decode thread should seek to.
+    pub seek_to: Option<usize>,
+}
+
+impl FilePlayback {
+    /// An empty voice waiting for its decode thread.  `capacity` is the
+    /// number of samples to reserve up front.
+    pub fn new(id: u64, volume: f32, panner: Panner, capacity: usize) -> Self {
+        Self {
+            id,
+            samples: Samples::Decoding(Vec::with_capacity(capacity)),
+            position: 0,
+            volume: volume.clamp(0.0, 1.0),
+            panner,
+            decode_complete: false,
+            reverb: None,
+            tail_remaining: 0,
+            scratch: Vec::new(),
+            delay_frames: 0,
+            seek_to: None,
+        }
+    }
+
+    /// An empty voice for a long file, streamed through a window of
+    /// `window` samples.
+    pub fn streaming(id: u64, volume: f32, panner: Panner, window: usize) -> Self {
+        let mut playback = Self::new(id, volume, panner, 0);
+        playback.samples = Samples::Streaming(StreamWindow::new(window));
+        playback
+    }
+
+    /// A voice for a sound from the sample cache, ready to play in full.
+    pub fn cached(id: u64, volume: f32, panner: Panner, sound: CachedSound) -> Self {
+        let mut playback = Self::new(id, volume, panner, 0);
+        playback.samples = Samples::Shared(sound.samples);
+        playback.decode_complete = true;
+        playback
+    }
+
+    /// A voice for a sound mapped from the on-disk cache, ready to play in
+    /// full.
+    pub fn mapped(id: u64, volume: f32, panner: Panner, mapped: Arc<MappedPcm>) -> Self {
+        let mut playback = Self::new(id, volume, panner, 0);
+        playback.samples = Samples::Mapped(mapped);
+        playback.decode_complete = true;
+        playback
+    }
+
+    /// Whether the next `samples` samples can be read without waiting for
+    /// the decode thread.
+    pub fn buffered(&self, samples: usize) -> bool {
+        self.decode_complete || self.samples.len() >= self.position + samples
+    }
+
+    /// Source frames left to play, once the whole file has been decoded.
+    pub fn remaining_frames(&self, channels: usize) -> Option<usize> {
+        self.decode_complete
+            .then(|| self.samples.len().saturating_sub(self.position) / channels.max(1))
+    }
+
+    /// Move the read position to output frame `frame`.  A streaming voice
+    /// moves within its window if it can; otherwise its decode thread
+    /// seeks the file and the voice is silent until it catches up.
+    pub fn seek(&mut self, frame: usize, channels: usize) {
+        let target = frame * channels;
+        self.delay_frames = 0;
+        match &mut self.samples {
+            Samples::Streaming(window) if !window.contains(target) => {
+                window.reset(target);
+                self.seek_to = Some(target);
+                self.decode_complete = false;
+                self.position = target;
+            }
+            _ if self.decode_complete => self.position = target.min(self.samples.len()),
+            // Past what is decoded so far: silent until the decoder gets there.
+            _ => self.position = target,
+        }
+    }
+
+    /// Render the next `out.len()` samples, mixed (added) into `out`.
+    /// Returns `true` while there are (or will be) more samples to play,
+    /// including any reverb tail.
+    pub fn mix_into(&mut self, out: &mut [f32], channels: usize) -> bool {
+        let out = if self.delay_frames > 0 {
+            let frames = out.len() / channels.max(1);
+            let skip = self.delay_frames.min(frames);
+            self.delay_frames -= skip;
+            &mut out[skip * channels..]
+        } else {
+            out
+        };
+        if out.is_empty() {
+            return true;
+        }
+        if self.reverb.is_none() {
+            return self.read_source(out, channels);
+        }
+
+        let mut scratch = std::mem::take(&mut self.scratch);
+        scratch.clear();
+        scratch.resize(out.len(), 0.0);
+        let source_going = self.read_source(&mut scratch, channels);
+        if let Some(reverb) = self.reverb.as_mut() {
+            reverb.process(&mut scratch);
+        }
+        for (o, s) in out.iter_mut().zip(scratch.iter()) {
+            *o += s;
+        }
+        self.scratch = scratch;
+
+        if source_going {
+            return true;
+        }
+        // The source is done; keep going until the tail has rung out.
+        let frames = out.len() / channels.max(1);
+        self.tail_remaining = self.tail_remaining.saturating_sub(frames);
+        self.tail_remaining > 0
+    }
+
+    /// Read up to `out.len()` source samples, mixed (added) into `out`.
+    /// Returns `true` while there are (or will be) more samples to read.
+    fn read_source(&mut self, out: &mut [f32], channels: usize) -> bool {
+        let available = self.samples.len();
+        if !self.panner.is_centered() && channels >= 2 {
+            for frame in out.chunks_mut(channels) {
+                let end = self.position + channels;
+                if end > available {
+                    return !self.decode_complete;
+                }
+                let src = self.samples.frame(self.position, channels);
+                self.panner.mix_frame(src, frame, self.volume);
+                self.position = end;
+            }
+            return self.position < available || !self.decode_complete;
+        }
+        for sample in out.iter_mut() {
+            if self.position >= available {
+                // If decoding is still in progress, we ran out of buffered
+                // samples temporarily — output silence but keep playing.
+                return !self.decode_complete;
+            }
+            *sample += self.samples.get(self.position) * self.volume;
+            self.position += 1;
+        }
+        // Still playing if we haven't reached the end, or decode is ongoing.
+        self.position < available || !self.decode_complete
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Decoding a file into a voice
+// ---------------------------------------------------------------------------
+
+/// An audio file opened for decoding, with the conversion to the output
+/// format worked out from the format at the time it was opened.
+pub struct FileDecode {
+    decoder: AudioDecoder,
+    /// Rate the file was recorded at.
+    file_rate: u32,
+    /// Rate it is resampled from: the file's, adjusted for any pitch shift.
+    src_rate: u32,
+    src_channels: u16,
+    dst_rate: u32,
+    matrix: ChannelMatrix,
+    /// Where to keep the result once fully decoded, and under which path.
+    cache: Option<(SharedSampleCache, String)>,
+    /// Where to persist the result once fully decoded, and under which key.
+    persist: Option<(SharedPcmCache, String)>,
+    /// Long (or of unknown length), so streamed rather than decoded whole.
+    streams: bool,
+}
+
+impl FileDecode {
+    /// Open `path` for playback at `dst_rate` / `dst_channels`.  A user
+    /// matrix in `matrices` for the file's channel count wins if it produces
+    /// the output's channel count.  `set_channel_matrix` only accepts such
+    /// matrices, but one set before the output device changed may not fit;
+    /// the standard matrix is used then.
+    pub fn open(
+        path: &str,
+        dst_rate: u32,
+        dst_channels: u16,
+        matrices: &HashMap<u16, ChannelMatrix>,
+    ) -> Result<Self, String> {
+        let decoder = AudioDecoder::open(path)?;
+        let src_rate = decoder.sample_rate();
+        let src_channels = decoder.channels();
+        let src_mask = decoder.channel_mask();
+        let streams = decoder
+            .duration()
+            .is_none_or(|d| d >= STREAM_MIN_DURATION);
+        let matrix = matrices
+            .get(&src_channels)
+            .filter(|m| m.dst_channels() == dst_channels)
+            .cloned()
+            .unwrap_or_else(|| ChannelMatrix::standard(src_channels, src_mask, dst_channels));
+
+        Ok(Self {
+            decoder,
+            file_rate: src_rate,
+            src_rate,
+            src_channels,
+            dst_rate,
+            matrix,
+            cache: None,
+            persist: None,
+            streams,
+        })
+    }
+
+    /// Keep the decoded samples in `cache` under `path` once the decode
+    /// completes.
+    pub fn cache_into(&mut self, cache: &SharedSampleCache, path: &str) {
+        self.cache = Some((Arc::clone(cache), path.to_string()));
+    }
+
+    /// Write the decoded samples to the on-disk `cache` as entry `key` once
+    /// the decode completes.
+    pub fn persist_into(&mut self, cache: &SharedPcmCache, key: &str) {
+        self.persist = Some((Arc::clone(cache), key.to_string()));
+    }
+
+    /// The up/downmix the file is decoded with.
+    pub fn matrix(&self) -> &ChannelMatrix {
+        &self.matrix
+    }
+
+    pub fn dst_rate(&self) -> u32 {
+        self.dst_rate
+    }
+
+    pub fn src_channels(&self) -> u16 {
+        self.src_channels
+    }
+
+    /// Play the file `semitones` higher (or lower), varispeed style, by
+    /// resampling it as if it had been recorded at a different rate.
+    pub fn set_pitch(&mut self, semitones: f32) {
+        self.src_rate = pitched_rate(self.src_rate, semitones);
+    }
+
+    /// Whether the file should play through a bounded window
+    /// (`FilePlayback::streaming`) instead of being decoded whole.  Such
+    /// files are too long to cache.
+    pub fn streams(&self) -> bool {
+        self.streams
+    }
+
+    /// Samples to reserve for a voice: about 10 seconds of output.  This
+    /// is also the window a streamed file is decoded into.
+    pub fn capacity_hint(&self) -> usize {
+        self.dst_rate as usize * self.matrix.dst_channels() as usize * STREAM_WINDOW_SECONDS
+    }
+
+    /// Decode on a background thread in chunks, resample and
+    /// channel-convert them, and append them to the voice in `slot`.  The
+    /// thread stops as soon as the slot no longer holds voice `id` (it was
+    /// stopped or replaced).
+    pub fn spawn(self, slot: VoiceSlot, id: u64) {
+        if self.streams {
+            return self.spawn_streaming(slot, id);
+        }
+        thread::spawn(move || {
+            let Self {
+                mut decoder,
+                src_rate,
+                src_channels,
+                dst_rate,
+                matrix,
+                cache,
+                persist,
+                ..
+            } = self;
+            let chunk_size = CHUNK_FRAMES * src_channels.max(1) as usize;
+            let mut chunk = Vec::with_capacity(chunk_size);
+
+            loop {
+                chunk.clear();
+                let more = decoder.read(&mut chunk, chunk_size);
+                if !chunk.is_empty() {
+                    let processed =
+                        process_chunk(&chunk, src_rate, dst_rate, src_channels, &matrix);
+                    if let Ok(mut guard) = slot.lock() {
+                        match guard.as_mut() {
+                            Some(fp) if fp.id == id => fp.samples.extend(&processed),
+                            _ => return,
+                        }
+                    }
+                }
+                if !more {
+                    break;
+                }
+            }
+
+            // Mark decode complete.
+            let mut shared = None;
+            if let Ok(mut guard) = slot.lock() {
+                if let Some(fp) = guard.as_mut().filter(|fp| fp.id == id) {
+                    fp.decode_complete = true;
+                    if cache.is_some() || persist.is_some() {
+                        shared = fp.samples.share();
+                    }
+                }
+            }
+
+            let Some(samples) = shared else {
+                return;
+            };
+            if let Some((cache, path)) = cache {
+                let sound = CachedSound {
+                    samples: Arc::clone(&samples),
+                    src_channels,
+                };
+                if let Ok(mut cache) = cache.lock() {
+                    cache.insert(&path, sound, dst_rate, matrix.dst_channels(), false);
+                }
+            }
+            if let Some((disk, key)) = persist {
+                pcm_cache::store(
+                    &disk,
+                    &key,
+                    &samples,
+                    dst_rate,
+                    matrix.dst_channels(),
+                    src_channels,
+                );
+            }
+        });
+    }
+
+    /// Decode a long file into the window of the streaming voice in `slot`,
+    /// waiting whenever the window is full.  Once the file is decoded the
+    /// thread stays around to serve seeks until the voice ends or is
+    /// replaced.
+    fn spawn_streaming(self, slot: VoiceSlot, id: u64) {
+        thread::spawn(move || {
+            let Self {
+                mut decoder,
+                file_rate,
+                src_rate,
+                src_channels,
+                dst_rate,
+                matrix,
+                ..
+            } = self;
+            let channels = matrix.dst_channels().max(1) as usize;
+            let chunk_size = CHUNK_FRAMES * src_channels.max(1) as usize;
+            let mut chunk = Vec::with_capacity(chunk_size);
+            // Converted samples waiting for room in the window.
+            let mut pending = Vec::new();
+            let mut finished = false;
+
+            loop {
+                if pending.is_empty() && !finished {
+                    chunk.clear();
+                    finished = !decoder.read(&mut chunk, chunk_size);
+                    pending = process_chunk(&chunk, src_rate, dst_rate, src_channels, &matrix);
+                }
+
+                let Ok(mut guard) = slot.lock() else {
+                    return;
+                };
+                let Some(fp) = guard.as_mut().filter(|fp| fp.id == id) else {
+                    return;
+                };
+                if let Some(target) = fp.seek_to.take() {
+                    drop(guard);
+                    // Output frames back to time in the file (they differ
+                    // when the voice is pitched).
+                    let frame = (target / channels) as f64 * src_rate as f64 / dst_rate as f64;
+                    let at = Duration::from_secs_f64(frame / file_rate.max(1) as f64);
+                    if let Err(e) = decoder.seek(at) {
+                        eprintln!("[playback] {e}");
+                    }
+                    pending.clear();
+                    finished = false;
+                    continue;
+                }
+                let Samples::Streaming(window) = &mut fp.samples else {
+                    return;
+                };
+                let wrote = !pending.is_empty() && window.free(fp.position) >= pending.len();
+                if wrote {
+                    window.write(&pending);
+                    pending.clear();
+                }
+                if finished && pending.is_empty() {
+                    fp.decode_complete = true;
+                }
+                drop(guard);
+                if !wrote {
+                    thread::sleep(STREAM_POLL);
+                }
+            }
+        });
+    }
+
+    /// Decode the whole file on a background thread and pin it in `cache`
+    /// under `path`.
+    pub fn spawn_preload(mut self, cache: SharedSampleCache, path: String) {
+        thread::spawn(move || {
+            let samples = self.decoder.read_all(usize::MAX);
+            let mut samples = process_chunk(
+                &samples,
+                self.src_rate,
+                self.dst_rate,
+                self.src_channels,
+                &self.matrix,
+            );
+            samples.shrink_to_fit();
+            if let Some((disk, key)) = &self.persist {
+                pcm_cache::store(
+                    disk,
+                    key,
+                    &samples,
+                    self.dst_rate,
+                    self.matrix.dst_channels(),
+                    self.src_channels,
+                );
+            }
+            let sound = CachedSound {
+                samples: Arc::new(samples),
+                src_channels: self.src_channels,
+            };
+            let inserted = cache.lock().is_ok_and(|mut cache| {
+                cache.insert(&path, sound, self.dst_rate, self.matrix.dst_channels(), true)
+            });
+            if !inserted {
+                eprintln!("[preload] {path} was not cached: over budget, or the output format changed");
+            }
+        });
+    }
+}
+
+/// What fills a new voice: a decode of its file, or a pitched copy of a
+/// sound that is already decoded.
+pub enum VoiceFill {
+    Decode(Box<FileDecode>),
+    Repitch(Repitch),
+}
+
+impl VoiceFill {
+    /// Fill the voice in `slot` on a background thread, for as long as it
+    /// holds voice `id`.
+    pub fn spawn(self, slot: VoiceSlot, id: u64) {
+        match self {
+            VoiceFill::Decode(decode) => decode.spawn(slot, id),
+            VoiceFill::Repitch(repitch) => repitch.spawn(slot, id),
+        }
+    }
+}
+
+/// A pitched play of a sound from the sample cache or the on-disk cache.
+/// The cached samples are already in the output format, so they only need
+/// resampling, not decoding again.
+pub struct Repitch {
+    samples: Samples,
+    src_rate: u32,
+    dst_rate: u32,
+    channels: u16,
+}
+
+impl Repitch {
+    /// `samples` (`Shared` or `Mapped`, at `rate` / `channels`) played
+    /// `semitones` higher (or lower).
+    pub fn new(samples: Samples, rate: u32, channels: u16, semitones: f32) -> Self {
+        Self {
+            samples,
+            src_rate: pitched_rate(rate, semitones),
+            dst_rate: rate,
+            channels,
+        }
+    }
+
+    /// Samples the pitched sound comes to.
+    pub fn capacity_hint(&self) -> usize {
+        let ratio = self.dst_rate as f64 / self.src_rate.max(1) as f64;
+        (self.samples.len() as f64 * ratio).ceil() as usize + self.channels as usize
+    }
+
+    /// Resample on a background thread in chunks and append them to the
+    /// voice in `slot`, like `FileDecode::spawn`.
+    pub fn spawn(self, slot: VoiceSlot, id: u64) {
+        thread::spawn(move || {
+            let chunk_size = CHUNK_FRAMES * self.channels.max(1) as usize;
+            for chunk in self.samples.whole().chunks(chunk_size) {
+                let resampled = resample(chunk, self.src_rate, self.dst_rate, self.channels);
+                let Ok(mut guard) = slot.lock() else {
+                    return;
+                };
+                match guard.as_mut() {
+                    Some(fp) if fp.id == id => fp.samples.extend(&resampled),
+                    _ => return,
+                }
+            }
+            if let Ok(mut guard) = slot.lock() {
+                if let Some(fp) = guard.as_mut().filter(|fp| fp.id == id) {
+                    fp.decode_complete = true;
+                }
+            }
+        });
+    }
+}
+
+/// The rate to resample audio recorded at `rate` from so it plays
+/// `semitones` higher (or lower), varispeed style.
+pub fn pitched_rate(rate: u32, semitones: f32) -> u32 {
+    if semitones == 0.0 {
+        return rate;
+    }
+    let ratio = 2f64.powf(semitones as f64 / 12.0);
+    ((rate as f64 * ratio).round() as u32).max(1)
+}
+
+/// Resample and channel-convert a chunk of decoded audio.
+pub fn process_chunk(
+    chunk: &[f32],
+    src_rate: u32,
+    dst_rate: u32,
+    src_channels: u16,
+    matrix: &ChannelMatrix,
+) -> Vec<f32> {
+    match (src_rate != dst_rate, matrix.is_identity()) {
+        (false, true) => chunk.to_vec(),
+        (true, true) => resample(chunk, src_rate, dst_rate, src_channels),
+        (false, false) => matrix.convert(chunk),
+        (true, false) => {
+            // Resample first (at source channel count), then convert channels.
+            let resampled = resample(chunk, src_rate, dst_rate, src_channels);
+            matrix.convert(&resampled)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    /// Checks `out` against `signal` from sample `from` on.
+    fn assert_plays(out: &[f32], signal: &[f32], from: usize, what: &str) {
+        for (i, (&got, &want)) in out.iter().zip(&signal[from..]).enumerate() {
+            assert!((got - want).abs() < 1e-6, "{what}, sample {i}: got {got}, want {want}");
+        }
+    }
+
+    #[test]
+    fn long_files_stream_through_a_window_and_seek() {
+        let frames = STREAM_MIN_DURATION.as_secs() as usize * RATE as usize + RATE as usize;
+        let signal = corpus::float_signal(frames, 2);
+        let path = corpus::wav_float("streamed.wav", RATE, 2, &signal);
+        let path = path.to_str().unwrap();
+        let short = corpus::wav_float("not-streamed.wav", RATE, 2, &signal[..RATE as usize * 2]);
+        let no_matrices = HashMap::new();
+        assert!(FileDecode::open(path, RATE, 2, &no_matrices).unwrap().streams());
+        let short = short.to_str().unwrap();
+        assert!(!FileDecode::open(short, RATE, 2, &no_matrices).unwrap().streams());
+
+        let output = corpus::path("streamed-out.wav");
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+        mixer.play_file(path, "music", corpus::play_params()).unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        {
+            // Only the window is held in memory, not the whole file.
+            let voice = Arc::clone(&mixer.buses.lock().unwrap().get("music").unwrap().voice);
+            let voice = voice.lock().unwrap();
+            let Some(Samples::Streaming(window)) = voice.as_ref().map(|fp| &fp.samples) else {
+                panic!("the file is not streamed");
+            };
+            assert_eq!(window.ring.len(), RATE as usize * 2 * STREAM_WINDOW_SECONDS);
+        }
+        // Far outside the window, so the decoder seeks the file...
+        mixer.seek("music", 30_000.0).unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        // ...and back again, which the window still holds.
+        mixer.seek("music", 30_000.0).unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        drop(mixer);
+
+        let out = corpus::decode(&output).2;
+        let block = RATE as usize / 10 * 2;
+        assert_eq!(out.len(), 3 * block);
+        assert_plays(&out[..block], &signal, 0, "from the start");
+        let at = 30 * RATE as usize * 2;
+        assert_plays(&out[block..2 * block], &signal, at, "after seeking");
+        assert_plays(&out[2 * block..], &signal, at, "after seeking back");
+    }
+}
@@ -1,9 +1,416 @@
+use std::collections::BTreeMap;
+
 use serde::{Deserialize, Serialize};
 
 fn default_volume() -> f32 {
     1.0
 }
 
+fn default_agc_target_db() -> f32 {
+    -18.0
+}
+
+fn default_agc_max_gain_db() -> f32 {
+    24.0
+}
+
+fn default_agc_noise_floor_db() -> f32 {
+    -50.0
+}
+
+fn default_robot_frequency_hz() -> f32 {
+    40.0
+}
+
+fn default_radio_low_hz() -> f32 {
+    300.0
+}
+
+fn default_radio_high_hz() -> f32 {
+    3400.0
+}
+
+fn default_radio_drive() -> f32 {
+    0.5
+}
+
+fn default_eq_q() -> f32 {
+    std::f32::consts::FRAC_1_SQRT_2
+}
+
+fn default_reverb_wet() -> f32 {
+    0.3
+}
+
+fn default_bus() -> String {
+    "sfx".to_string()
+}
+
+fn default_queue_bus() -> String {
+    "music".to_string()
+}
+
+fn default_weight() -> f32 {
+    1.0
+}
+
+fn default_waveform_buckets() -> usize {
+    600
+}
+
+fn default_silence_threshold_db() -> f32 {
+    -50.0
+}
+
+fn default_min_silence_ms() -> f64 {
+    200.0
+}
+
+fn default_bit_depth() -> u16 {
+    16
+}
+
+/// Filter shape of a parametric EQ band.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EqBandType {
+    LowShelf,
+    HighShelf,
+    Peaking,
+    HighPass,
+    LowPass,
+}
+
+/// One band of a parametric EQ.  `gain_db` is ignored by the pass filters
+/// and clamped to ±24 dB by the others.
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct EqBand {
+    #[serde(rename = "type")]
+    pub kind: EqBandType,
+    pub freq_hz: f32,
+    #[serde(default)]
+    pub gain_db: f32,
+    #[serde(default = "default_eq_q")]
+    pub q: f32,
+}
+
+/// Which signal an EQ command applies to.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EqTarget {
+    /// The mic pass-through, before noise suppression and AGC.
+    Mic,
+    /// The final mix, before the master volume.
+    Master,
+}
+
+/// A voice effect for the mic pass-through, selected with
+/// [`Command::SetMicEffect`].
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MicEffect {
+    /// No effect (pass the voice through unchanged).
+    None,
+
+    /// Shift the pitch by `semitones`.  Formants stay put unless
+    /// `formant_semitones` moves them too (set both equal for a chipmunk
+    /// or giant effect).
+    Pitch {
+        #[serde(default)]
+        semitones: f32,
+        #[serde(default)]
+        formant_semitones: f32,
+    },
+
+    /// Ring-modulate the voice with a sine carrier.  `mix` blends the dry
+    /// (0.0) and modulated (1.0) signal.
+    Robot {
+        #[serde(default = "default_robot_frequency_hz")]
+        frequency_hz: f32,
+        #[serde(default = "default_volume")]
+        mix: f32,
+    },
+
+    /// Band-pass the voice like a telephone / radio and add distortion
+    /// (`drive` 0.0 .. 1.0).
+    Radio {
+        #[serde(default = "default_radio_low_hz")]
+        low_hz: f32,
+        #[serde(default = "default_radio_high_hz")]
+        high_hz: f32,
+        #[serde(default = "default_radio_drive")]
+        drive: f32,
+    },
+}
+
+/// Convolution reverb settings, used per voice on [`Command::Play`], on the
+/// master with [`Command::SetMasterReverb`] or as a bus's
+/// [`BusInsert::Reverb`].
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReverbSettings {
+    /// Impulse response file (WAV) on disk.
+    pub ir_path: String,
+    /// Level of the reverberated signal (0.0 .. 2.0).
+    #[serde(default = "default_reverb_wet")]
+    pub wet: f32,
+    /// Level of the original signal (0.0 .. 2.0).
+    #[serde(default = "default_volume")]
+    pub dry: f32,
+}
+
+/// An output device that buses can be routed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BusOutput {
+    /// The main output (typically the virtual cable feeding the mic).
+    Main,
+    /// The local monitor device, if one is open.
+    Monitor,
+}
+
+/// What the local monitor device plays.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MonitorSource {
+    /// The main output as sent to the mic, after the master EQ, reverb,
+    /// volume and balance, plus the buses routed only to the monitor.
+    #[default]
+    Mix,
+    /// The buses routed to [`BusOutput::Monitor`], before the master
+    /// processing (e.g. to hear the sounds without one's own mic).
+    Buses,
+}
+
+/// An entry in a bus's play queue.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QueueItem {
+    pub id: u64,
+    pub file_path: String,
+}
+
+/// What a bus's play queue is doing.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct QueueStatus {
+    /// The entry playing now (the latest to start, if several overlap).
+    pub current: Option<QueueItem>,
+    /// The entry that starts next.
+    pub next: Option<QueueItem>,
+    /// Entries waiting to start, including `next`.
+    pub pending: usize,
+}
+
+/// A candidate file for [`Command::PlayRandom`].
+#[derive(Debug, Clone, Deserialize)]
+pub struct WeightedFile {
+    pub file_path: String,
+    /// Relative chance of being picked.
+    #[serde(default = "default_weight")]
+    pub weight: f32,
+}
+
+/// How [`Command::PlayRandom`] picks from its files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RandomMode {
+    /// An independent weighted pick each time.
+    #[default]
+    Random,
+    /// Every file once per round, with no repeat across rounds.
+    Shuffle,
+    /// The files in order, cycling.
+    RoundRobin,
+}
+
+/// Random per-play variation, as ± ranges around the unchanged sound.
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct Variation {
+    /// Varispeed pitch range in semitones.
+    #[serde(default)]
+    pub pitch_semitones: f32,
+    /// Gain range in dB.
+    #[serde(default)]
+    pub gain_db: f32,
+}
+
+/// Contents of the decoded-sample cache.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct SampleCacheStatus {
+    pub entries: usize,
+    pub used_bytes: usize,
+    pub budget_bytes: usize,
+    /// Files pinned by `Preload`.
+    pub preloaded: Vec<String>,
+}
+
+/// Contents of the on-disk PCM cache.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PcmCacheStatus {
+    /// `None` while the cache is disabled.
+    pub dir: Option<String>,
+    pub entries: usize,
+    pub used_bytes: u64,
+    pub budget_bytes: u64,
+}
+
+/// What `Probe` found out about a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MediaInfo {
+    /// Playing time, without encoder delay and padding.
+    pub duration_ms: f64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Short codec name, e.g. `flac`, `mp3`, `opus` or `pcm_s24le`.
+    pub codec: String,
+    /// Bits per sample, for formats that have one (not the lossy codecs).
+    pub bit_depth: Option<u32>,
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub has_album_art: bool,
+}
+
+/// Peaks of a file for drawing its waveform, one value per bucket.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Waveform {
+    pub duration_ms: f64,
+    /// Lowest and highest sample in each bucket, over all channels.
+    pub min: Vec<f32>,
+    pub max: Vec<f32>,
+    pub rms: Vec<f32>,
+}
+
+/// A quiet stretch of a file.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SilentSpan {
+    pub start_ms: f64,
+    pub end_ms: f64,
+}
+
+/// What `DetectSilence` found.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SilenceReport {
+    pub duration_ms: f64,
+    /// Silence before the sound starts; 0 if it starts straight away.
+    pub leading_ms: f64,
+    /// Silence after the sound ends.
+    pub trailing_ms: f64,
+    /// Silences between the two.
+    pub gaps: Vec<SilentSpan>,
+    /// Where to crop to drop the leading and trailing silence, keeping a
+    /// few milliseconds of it so soft attacks and tails survive.
+    pub suggested_start_ms: f64,
+    pub suggested_end_ms: f64,
+}
+
+/// File format the engine writes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AudioFileFormat {
+    Wav,
+    Flac,
+}
+
+/// Changes `Render` makes to a file, applied in this order.  All optional.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RenderEdits {
+    /// Part of the source to keep, in source time.
+    pub start_ms: f64,
+    /// `None` keeps the rest of the file.
+    pub end_ms: Option<f64>,
+    /// Varispeed pitch shift, as for `Play` (changes the duration too).
+    pub pitch_semitones: f32,
+    /// Output rate; `None` keeps the source's.
+    pub sample_rate: Option<u32>,
+    /// Output channel count, converted with the same matrix as playback;
+    /// `None` keeps the source's.
+    pub channels: Option<u16>,
+    pub gain_db: f32,
+    /// Integrated loudness (LUFS) to normalize to instead of applying
+    /// `gain_db`.  The gain is held back if it would clip.
+    pub loudness_lufs: Option<f32>,
+    /// Linear fades, in output time.
+    pub fade_in_ms: f64,
+    pub fade_out_ms: f64,
+}
+
+/// What `Render` wrote.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RenderInfo {
+    pub duration_ms: f64,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Gain applied, in dB (`gain_db`, or what the loudness target took).
+    pub gain_db: f32,
+    /// Highest sample of the output, in dBFS.
+    pub peak_db: f32,
+    /// Integrated loudness of the output; `None` if it is silent.
+    pub loudness_lufs: Option<f32>,
+}
+
+/// What a recording takes in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RecordingSource {
+    /// The main output as it went out, after the master effects, volume
+    /// and balance.
+    #[default]
+    Master,
+    /// The mic bus, after the mic chain and mic volume.
+    Mic,
+    /// The sound buses, without the mic.
+    Sounds,
+}
+
+/// A recording in progress, or one `StopRecording` finished.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordingInfo {
+    pub path: String,
+    pub source: RecordingSource,
+    pub sample_rate: u32,
+    pub channels: u16,
+    /// Length of what has been recorded.
+    pub duration_ms: f64,
+    /// Audio left out because the writer fell behind or the main output
+    /// was reopened in another format.
+    pub dropped_ms: f64,
+}
+
+/// A play waiting for its start time.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ScheduledPlay {
+    pub id: u64,
+    pub bus: String,
+    pub file_path: String,
+    /// Start time on the engine clock.
+    pub start_ms: f64,
+}
+
+/// An effect inserted on a bus.  Inserts run in list order.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum BusInsert {
+    /// Parametric EQ.
+    Eq { bands: Vec<EqBand> },
+    /// Convolution reverb.
+    Reverb(ReverbSettings),
+}
+
+/// State of one bus, as reported in [`Response::Status`].
+#[derive(Debug, Serialize)]
+pub struct BusStatus {
+    pub name: String,
+    pub gain: f32,
+    pub muted: bool,
+    pub inserts: Vec<BusInsert>,
+    pub outputs: Vec<BusOutput>,
+    /// Whether a sound is playing on the bus.
+    pub playing: bool,
+    /// Pan of the sound playing on the bus, if any.
+    pub pan: Option<f32>,
+    pub queue: QueueStatus,
+}
+
 /// Commands sent from the Node.js server to the audio engine via stdin (JSON, one per line).
 #[derive(Debug, Deserialize)]
 #[serde(tag = "cmd", rename_all = "snake_case")]
@@ -17,28 +424,283 @@ pub enum Command {
     /// Select the WASAPI render (output) device by name (typically VB-Cable Input).
     SetOutputDevice { device_name: String },
 
-    /// Decode and play an audio file, mixed on top of the live microphone capture.
+    /// Select a second render device for local monitoring (e.g. speakers or
+    /// headphones), or close it when `device_name` is omitted.  What it
+    /// plays is chosen by `SetMonitorSource`.
+    SetMonitorDevice {
+        #[serde(default)]
+        device_name: Option<String>,
+    },
+
+    /// Change the monitor device volume (0.0 .. 1.0).
+    SetMonitorVolume { volume: f32 },
+
+    /// Choose what the monitor device plays.
+    SetMonitorSource { source: MonitorSource },
+
+    /// Render `ms` of audio on the null backend's manual clock (the engine
+    /// was started with `--null` but not `--null-realtime`), returning once
+    /// it has been written.
+    AdvanceClock { ms: f64 },
+
+    /// Decode and play an audio file on `bus`, replacing whatever that bus
+    /// was playing.  `pan` places it from -1.0 (left) to 1.0 (right).
+    /// With `reverb`, the file is convolved with an impulse response and
+    /// keeps playing until the reverb tail has rung out.
+    ///
+    /// With `delay_ms`, or `at_ms` on the engine clock (see `clock_ms` in
+    /// the status), the play is scheduled to start on that exact frame and
+    /// the engine answers with `Scheduled`.
     Play {
         file_path: String,
+        #[serde(default = "default_bus")]
+        bus: String,
+        #[serde(default = "default_volume")]
+        volume: f32,
+        #[serde(default)]
+        pan: f32,
+        #[serde(default)]
+        reverb: Option<ReverbSettings>,
+        #[serde(default)]
+        delay_ms: Option<f64>,
+        #[serde(default)]
+        at_ms: Option<f64>,
+    },
+
+    /// Play one file picked from `files` on `bus`.  The picking state
+    /// (shuffle round, round-robin position) is kept per `group` and reset
+    /// when the group's file list or mode changes.  Answers with `Played`.
+    PlayRandom {
+        group: String,
+        files: Vec<WeightedFile>,
+        #[serde(default)]
+        mode: RandomMode,
+        #[serde(default = "default_bus")]
+        bus: String,
+        #[serde(default = "default_volume")]
+        volume: f32,
+        #[serde(default)]
+        variation: Variation,
+    },
+
+    /// Decode a file in the background and keep it in the sample cache, in
+    /// the output format, until it is unloaded.  Plays of it then start
+    /// fully decoded.
+    Preload { file_path: String },
+
+    /// Drop a file from the sample cache.
+    Unload { file_path: String },
+
+    /// Memory budget of the sample cache.  Least recently played sounds
+    /// are evicted to stay within it; preloaded ones are kept.
+    SetSampleCacheBudget { megabytes: f32 },
+
+    /// Keep decoded sounds, in the output format, in `dir` as well, so they
+    /// play without decoding after a restart.  `None` turns the on-disk
+    /// cache off (its files are kept).
+    SetPcmCacheDir { dir: Option<String> },
+
+    /// Disk budget of the on-disk cache.  Least recently played entries
+    /// are deleted to stay within it.
+    SetPcmCacheBudget { megabytes: f32 },
+
+    /// Delete everything in the on-disk cache.
+    ClearPcmCache,
+
+    /// Read a file's format, length and tags without playing it.
+    Probe { file_path: String },
+
+    /// Min/max/RMS peaks of a file in `buckets` evenly spaced slices.
+    /// Peaks are kept in the on-disk cache, when it is enabled, so a file
+    /// is only decoded for its first waveform.
+    GetWaveform {
+        file_path: String,
+        #[serde(default = "default_waveform_buckets")]
+        buckets: usize,
+    },
+
+    /// Find the silence at the start and end of a file and the gaps in
+    /// between: stretches at least `min_silence_ms` long where no sample
+    /// reaches `threshold_db` (dBFS).
+    DetectSilence {
+        file_path: String,
+        #[serde(default = "default_silence_threshold_db")]
+        threshold_db: f32,
+        #[serde(default = "default_min_silence_ms")]
+        min_silence_ms: f64,
+    },
+
+    /// Write `file_path` with `edits` applied to `output_path`, through
+    /// the same resampling and channel conversion as playback.  The format
+    /// follows the output's extension unless given.  The output is written
+    /// under a temporary name and moved into place, so it may be the input.
+    Render {
+        file_path: String,
+        output_path: String,
+        #[serde(default)]
+        edits: RenderEdits,
+        #[serde(default)]
+        format: Option<AudioFileFormat>,
+        /// 16 or 24, or 32 for float WAV.
+        #[serde(default = "default_bit_depth")]
+        bit_depth: u16,
+    },
+
+    /// Record `source` to `path` until `StopRecording`, in the main
+    /// output's rate and channel count.  Only the buses routed to the main
+    /// output are recorded.  The format follows the extension unless given.
+    StartRecording {
+        path: String,
+        #[serde(default)]
+        source: RecordingSource,
+        #[serde(default)]
+        format: Option<AudioFileFormat>,
+        /// 16 or 24, or 32 for float WAV.
+        #[serde(default = "default_bit_depth")]
+        bit_depth: u16,
+    },
+
+    /// Finish the recording; the engine answers with `Recorded`.
+    StopRecording,
+
+    /// Move the sound playing on `bus` (or the current entry of its queue)
+    /// to `position_ms`, in playback time.
+    Seek {
+        #[serde(default = "default_bus")]
+        bus: String,
+        position_ms: f64,
+    },
+
+    /// Cancel a scheduled play that hasn't started yet.
+    CancelScheduled { id: u64 },
+
+    /// Append a file to the play queue of `bus`.  It starts `gap_ms` after
+    /// the previous entry ends, or straight away if the queue is idle; a
+    /// negative gap overlaps the two.
+    Enqueue {
+        file_path: String,
+        #[serde(default = "default_queue_bus")]
+        bus: String,
         #[serde(default = "default_volume")]
         volume: f32,
+        #[serde(default)]
+        gap_ms: f32,
+    },
+
+    /// Stop the current queue entry on `bus` and start the next one.
+    Skip {
+        #[serde(default = "default_queue_bus")]
+        bus: String,
     },
 
-    /// Stop all playback immediately.
+    /// Drop the entries waiting in the queue of `bus`.  The current entry
+    /// keeps playing.
+    ClearQueue {
+        #[serde(default = "default_queue_bus")]
+        bus: String,
+    },
+
+    /// Stop all playback immediately, including every play queue.
     Stop,
 
-    /// Pause both capture pass-through and file playback.
+    /// Pause sound playback.  The mic pass-through keeps running.
     Pause,
 
-    /// Resume after a pause.
+    /// Resume sound playback after a pause.
     Resume,
 
+    /// Mute or unmute the mic pass-through.  Sounds keep playing.
+    SetMicMuted { muted: bool },
+
+    /// Hold (`active: true`) or release the push-to-mute key.  The mic is
+    /// silent while it is held, independently of `SetMicMuted`.
+    SetPushToMute { active: bool },
+
     /// Change the master output volume (0.0 .. 1.0).
     SetVolume { volume: f32 },
 
+    /// Move the sound playing on `bus` (-1.0 left .. 1.0 right).
+    SetPan {
+        pan: f32,
+        #[serde(default = "default_bus")]
+        bus: String,
+    },
+
+    /// Change the master left/right balance (-1.0 left .. 1.0 right).
+    SetBalance { balance: f32 },
+
     /// Change the microphone pass-through volume (0.0 .. 1.0).
     SetMicVolume { volume: f32 },
 
+    /// Enable or disable RNNoise noise suppression on the mic pass-through.
+    SetNoiseSuppression { enabled: bool },
+
+    /// Enable or disable acoustic echo cancellation on the mic pass-through,
+    /// using the engine's own sound output as the reference.  `delay_ms` is
+    /// an optional bulk delay for known output/capture latency, up to
+    /// 1000 ms.
+    SetEchoCancellation {
+        enabled: bool,
+        #[serde(default)]
+        delay_ms: u32,
+    },
+
+    /// Enable or disable automatic gain control on the mic pass-through.
+    /// Levels are in dBFS (RMS); the AGC runs before the mic volume.
+    SetMicAgc {
+        enabled: bool,
+        #[serde(default = "default_agc_target_db")]
+        target_db: f32,
+        #[serde(default = "default_agc_max_gain_db")]
+        max_gain_db: f32,
+        #[serde(default = "default_agc_noise_floor_db")]
+        noise_floor_db: f32,
+    },
+
+    /// Select the voice effect applied to the mic pass-through.
+    SetMicEffect { effect: MicEffect },
+
+    /// Replace the parametric EQ bands on the mic path or the master bus.
+    /// An empty list disables the EQ.
+    SetEq { target: EqTarget, bands: Vec<EqBand> },
+
+    /// Override the up/downmix matrix used for sounds with
+    /// `source_channels` channels: one row per output channel, each with one
+    /// gain per source channel.  A matrix with a different number of rows
+    /// than the current output has channels is refused.  Omitting `matrix`
+    /// restores the standard layout-based matrix.
+    SetChannelMatrix {
+        source_channels: u16,
+        #[serde(default)]
+        matrix: Option<Vec<Vec<f32>>>,
+    },
+
+    /// Set the convolution reverb on the final mix, or remove it when
+    /// `reverb` is omitted.
+    SetMasterReverb {
+        #[serde(default)]
+        reverb: Option<ReverbSettings>,
+    },
+
+    /// Create or reconfigure a bus.  Omitted fields keep their current
+    /// value (new buses start at unity gain, unmuted, with no inserts and
+    /// routed to both outputs).
+    SetBus {
+        name: String,
+        #[serde(default)]
+        gain: Option<f32>,
+        #[serde(default)]
+        muted: Option<bool>,
+        #[serde(default)]
+        inserts: Option<Vec<BusInsert>>,
+        #[serde(default)]
+        outputs: Option<Vec<BusOutput>>,
+    },
+
+    /// Remove a bus, stopping anything playing on it.  The `mic` bus can't
+    /// be removed.
+    RemoveBus { name: String },
+
     /// Query the current mixer state.
     GetStatus,
 
@@ -53,6 +715,9 @@ pub enum Command {
 }
 
 /// Responses sent from the audio engine back to Node.js via stdout (JSON, one per line).
+// Responses are built once and serialized straight away, so the size of
+// `Status` doesn't matter.
+#[allow(clippy::large_enum_variant)]
 #[derive(Debug, Serialize)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum Response {
@@ -65,14 +730,72 @@ pub enum Response {
     /// Generic success acknowledgement.
     Ok,
 
+    /// The file `PlayRandom` picked.
+    Played { file_path: String },
+
+    /// A play was scheduled; `id` cancels it.
+    Scheduled { id: u64 },
+
+    /// A file command was queued on the job worker; its result follows as
+    /// `Finished` with the same `job`.
+    Started { job: u64 },
+
+    /// Result of the file command acknowledged by `Started { job }`.
+    Finished { job: u64, result: Box<Response> },
+
+    /// Result of `Probe`.
+    Probe { media: MediaInfo },
+
+    /// Result of `GetWaveform`.
+    Waveform { waveform: Waveform },
+
+    /// Result of `DetectSilence`.
+    Silence { silence: SilenceReport },
+
+    /// Result of `Render`.
+    Rendered { render: RenderInfo },
+
+    /// Result of `StopRecording`.
+    Recorded { recording: RecordingInfo },
+
     /// Current mixer status.
     Status {
         playing: bool,
+        /// Sound playback is paused.
         paused: bool,
+        /// The mic pass-through is muted.
+        mic_muted: bool,
+        /// The push-to-mute key is held.
+        push_to_mute: bool,
+        /// Engine clock: time rendered by the main output since it was
+        /// opened.
+        clock_ms: f64,
+        /// Plays waiting for their start time, earliest first.
+        scheduled: Vec<ScheduledPlay>,
+        sample_cache: SampleCacheStatus,
+        pcm_cache: PcmCacheStatus,
         volume: f32,
+        balance: f32,
         mic_volume: f32,
+        noise_suppression: bool,
+        echo_cancellation: bool,
+        mic_agc: bool,
+        /// Gain currently applied by the AGC, if enabled.
+        mic_agc_gain_db: Option<f32>,
+        mic_effect: MicEffect,
+        mic_eq: Vec<EqBand>,
+        master_eq: Vec<EqBand>,
+        master_reverb: Option<ReverbSettings>,
+        /// Custom up/downmix matrices, by source channel count.
+        channel_matrices: BTreeMap<u16, Vec<Vec<f32>>>,
         input_device: Option<String>,
         output_device: Option<String>,
+        monitor_device: Option<String>,
+        monitor_volume: f32,
+        monitor_source: MonitorSource,
+        buses: Vec<BusStatus>,
+        /// The recording in progress, if any.
+        recording: Option<RecordingInfo>,
     },
 
     /// An error occurred while processing a command.
@@ -41,7 +41,6 @@ impl PttState {
             let vk = Arc::clone(&self.vk_code);
             let held = Arc::clone(&self.key_held);
             let running = Arc::clone(&self.watcher_running);
-            let playing = playing;
 
             thread::spawn(move || {
                 let mut was_playing = false;
@@ -49,6 +48,16 @@ impl PttState {
                 while running.load(Ordering::Acquire) {
                     let is_playing = playing.load(Ordering::Acquire);
 
+                    if !was_playing && is_playing && !held.load(Ordering::Acquire) {
+                        // Playback started without a Play command pressing
+                        // the key (a scheduled play).
+                        let code = vk.load(Ordering::Acquire);
+                        if code != 0 {
+                            send_key(code, false);
+                            held.store(true, Ordering::Release);
+                        }
+                    }
+
                     if was_playing && !is_playing && held.load(Ordering::Acquire) {
                         // Playback just ended — small delay to allow a rapid
                         // back-to-back play to re-assert the key before we
@@ -148,7 +157,7 @@ fn send_key(vk_code: u16, key_up: bool) {
         },
     };
 
-    let result = unsafe {
-        SendInput(1, &input, std::mem::size_of::<INPUT>() as i32)
-    };
+    unsafe {
+        SendInput(1, &input, std::mem::size_of::<INPUT>() as i32);
+    }
 }
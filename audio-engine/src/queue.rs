@@ -0,0 +1,308 @@
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use crate::bus::{render_slot, VoiceActivity, VoiceSlot};
+use crate::playback::VoiceFill;
+use crate::protocol::{QueueItem, QueueStatus};
+
+/// Upcoming entries that are decoded ahead of their start, so a transition
+/// never waits on the decoder.
+const PRELOAD_ENTRIES: usize = 2;
+
+/// How often the feeder thread looks for entries to start decoding.
+const FEED_INTERVAL: Duration = Duration::from_millis(20);
+
+/// A bus's play queue, shared between the output callback, the command
+/// loop and the feeder thread.
+pub type SharedQueue = Arc<Mutex<PlayQueue>>;
+
+struct Entry {
+    id: u64,
+    file_path: String,
+    /// Frames between the end of the previous entry and the start of this
+    /// one.  Negative values overlap the two.
+    gap_frames: i64,
+    voice: VoiceSlot,
+    /// The opened file, until the feeder starts decoding it.
+    decode: Option<VoiceFill>,
+}
+
+impl Entry {
+    fn item(&self) -> QueueItem {
+        QueueItem {
+            id: self.id,
+            file_path: self.file_path.clone(),
+        }
+    }
+
+    /// Source frames left in the entry, once it has been fully decoded.
+    /// `None` while that isn't known yet (or the voice is busy).
+    fn remaining_frames(&self, channels: usize) -> Option<usize> {
+        let guard = self.voice.try_lock().ok()?;
+        match guard.as_ref() {
+            Some(fp) => fp.remaining_frames(channels),
+            None => Some(0),
+        }
+    }
+
+    fn stop(&self) {
+        if let Ok(mut guard) = self.voice.lock() {
+            *guard = None;
+        }
+    }
+}
+
+/// Files played back to back on one bus.
+///
+/// Transitions are worked out in the output callback: once the current
+/// entry is fully decoded its remaining length is known, and the next
+/// entry is started at the exact frame where that length plus its gap
+/// runs out, even in the middle of a block.
+pub struct PlayQueue {
+    /// Entries waiting to start, in order.
+    pending: VecDeque<Entry>,
+    /// Entries that are sounding.  The last one is the current entry; any
+    /// before it are still overlapping it.
+    active: Vec<Entry>,
+    /// Frames from the start of the next block until the first pending
+    /// entry starts, once that is known.
+    next_start: Option<i64>,
+    /// Whether a feeder thread is running for this queue.
+    feeding: bool,
+}
+
+impl PlayQueue {
+    pub fn new() -> Self {
+        Self {
+            pending: VecDeque::new(),
+            active: Vec::new(),
+            next_start: None,
+            feeding: false,
+        }
+    }
+
+    /// Whether nothing is playing or waiting.
+    pub fn is_idle(&self) -> bool {
+        self.pending.is_empty() && self.active.is_empty()
+    }
+
+    /// Append an entry.  `voice` must already hold a voice with id `id`;
+    /// unless it came from the sample cache, `decode` fills it once the
+    /// entry is close to playing.
+    pub fn push(
+        &mut self,
+        id: u64,
+        file_path: &str,
+        gap_frames: i64,
+        voice: VoiceSlot,
+        decode: Option<VoiceFill>,
+    ) {
+        self.pending.push_back(Entry {
+            id,
+            file_path: file_path.to_string(),
+            gap_frames,
+            voice,
+            decode,
+        });
+    }
+
+    /// The voices of the entries that are sounding or decoding ahead.
+    pub fn voices(&self) -> impl Iterator<Item = &VoiceSlot> {
+        self.active
+            .iter()
+            .chain(self.pending.iter().take(PRELOAD_ENTRIES))
+            .map(|e| &e.voice)
+    }
+
+    /// Stop whatever is sounding and start the next entry straight away.
+    pub fn skip(&mut self) {
+        for entry in self.active.drain(..) {
+            entry.stop();
+        }
+        self.next_start = None;
+    }
+
+    /// Move the current entry to output frame `frame`.  Returns `false` if
+    /// nothing is playing.
+    pub fn seek(&mut self, frame: usize, channels: usize) -> bool {
+        let Some(entry) = self.active.last() else {
+            return false;
+        };
+        let Ok(mut guard) = entry.voice.lock() else {
+            return false;
+        };
+        let Some(fp) = guard.as_mut() else {
+            return false;
+        };
+        fp.seek(frame, channels);
+        // Worked out again from the new position.
+        self.next_start = None;
+        true
+    }
+
+    /// Drop the entries that haven't started.  The current one keeps playing.
+    pub fn clear_pending(&mut self) {
+        for entry in self.pending.drain(..) {
+            entry.stop();
+        }
+        self.next_start = None;
+    }
+
+    /// Stop everything and empty the queue.
+    pub fn clear(&mut self) {
+        self.skip();
+        self.clear_pending();
+    }
+
+    pub fn status(&self) -> QueueStatus {
+        QueueStatus {
+            current: self.active.last().map(Entry::item),
+            next: self.pending.front().map(Entry::item),
+            pending: self.pending.len(),
+        }
+    }
+
+    /// Start a feeder thread for `queue` unless one is running.  The
+    /// feeder starts decoding the sounding and next few entries, and exits
+    /// once there is nothing left to start (or the queue is dropped).
+    pub fn start_feeder(queue: &SharedQueue) {
+        match queue.lock() {
+            Ok(mut q) if !q.feeding => q.feeding = true,
+            _ => return,
+        }
+        let weak = Arc::downgrade(queue);
+        thread::spawn(move || loop {
+            let Some(queue) = weak.upgrade() else {
+                return;
+            };
+            let jobs = match queue.lock() {
+                Ok(mut q) => {
+                    let jobs = q.take_due();
+                    if jobs.is_empty() && q.pending.iter().all(|e| e.decode.is_none()) {
+                        q.feeding = false;
+                        return;
+                    }
+                    jobs
+                }
+                Err(_) => return,
+            };
+            drop(queue);
+            for (decode, voice, id) in jobs {
+                decode.spawn(voice, id);
+            }
+            thread::sleep(FEED_INTERVAL);
+        });
+    }
+
+    /// Decode jobs for the entries that should be decoding by now.
+    fn take_due(&mut self) -> Vec<(VoiceFill, VoiceSlot, u64)> {
+        self.active
+            .iter_mut()
+            .chain(self.pending.iter_mut().take(PRELOAD_ENTRIES))
+            .filter_map(|e| e.decode.take().map(|d| (d, Arc::clone(&e.voice), e.id)))
+            .collect()
+    }
+
+    /// Mix the queue into `out`, starting pending entries when they are due.
+    pub fn render(
+        &mut self,
+        out: &mut [f32],
+        channels: usize,
+        paused: bool,
+        activity: &mut VoiceActivity,
+    ) {
+        if paused {
+            // Nothing moves, so the countdown to the next entry holds too.
+            activity.alive |= !self.is_idle();
+            return;
+        }
+        let frames = (out.len() / channels.max(1)) as i64;
+
+        if self.next_start.is_none() && !self.pending.is_empty() {
+            self.next_start = match self.active.last() {
+                None => Some(0),
+                Some(current) => current
+                    .remaining_frames(channels)
+                    .map(|r| r as i64 + self.pending[0].gap_frames),
+            };
+        }
+
+        self.active
+            .retain(|e| render_slot(&e.voice, out, channels, false, activity));
+
+        while let Some(start) = self.next_start.filter(|&s| s < frames) {
+            let Some(entry) = self.pending.pop_front() else {
+                self.next_start = None;
+                break;
+            };
+            // An overlap longer than what was left of the previous entry
+            // starts the new one immediately.
+            let offset = start.max(0);
+            self.next_start = self.pending.front().and_then(|next| {
+                entry
+                    .remaining_frames(channels)
+                    .map(|r| offset + r as i64 + next.gap_frames)
+            });
+            let from = offset as usize * channels;
+            if render_slot(&entry.voice, &mut out[from..], channels, false, activity) {
+                self.active.push(entry);
+            }
+        }
+
+        if let Some(start) = self.next_start.as_mut() {
+            *start -= frames;
+        }
+        if !self.pending.is_empty() {
+            // Waiting for a gap to run out still counts as playing.
+            activity.alive = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+    const FRAMES: usize = 4800;
+
+    /// Queues `first` then `second` with `gap_ms` between them on the
+    /// null backend and returns what the main output wrote.
+    fn play_queue(name: &str, first: &[f32], second: &[f32], gap_ms: f32) -> Vec<f32> {
+        let a = corpus::wav_float(&format!("{name}-a.wav"), RATE, 2, first);
+        let b = corpus::wav_float(&format!("{name}-b.wav"), RATE, 2, second);
+        let output = corpus::path(&format!("{name}-out.wav"));
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+        mixer.enqueue(a.to_str().unwrap(), "music", 1.0, 0.0).unwrap();
+        mixer.enqueue(b.to_str().unwrap(), "music", 1.0, gap_ms).unwrap();
+        mixer.advance_clock(300.0).unwrap();
+        drop(mixer);
+        corpus::decode(&output).2
+    }
+
+    #[test]
+    fn joins_entries_with_gaps_and_overlaps_to_the_sample() {
+        let first: Vec<f32> = corpus::float_signal(FRAMES, 2).iter().map(|s| s * 0.8).collect();
+        let second: Vec<f32> = corpus::float_signal(FRAMES, 2).iter().map(|s| -s * 0.6).collect();
+        // No gap, 10 ms of silence, 10 ms of overlap.
+        for (gap_ms, offset) in [(0.0, 0), (10.0, 480), (-10.0, -480)] {
+            let out = play_queue(&format!("queue{gap_ms}"), &first, &second, gap_ms);
+            assert_eq!(out.len(), RATE as usize * 3 / 10 * 2);
+            let start = (FRAMES as i64 + offset) as usize * 2;
+            for (i, &got) in out.iter().enumerate() {
+                let want = first.get(i).copied().unwrap_or(0.0)
+                    + i.checked_sub(start)
+                        .and_then(|j| second.get(j))
+                        .copied()
+                        .unwrap_or(0.0);
+                assert!(
+                    (got - want).abs() < 1e-6,
+                    "gap {gap_ms} ms, sample {i}: got {got}, want {want}"
+                );
+            }
+        }
+    }
+}
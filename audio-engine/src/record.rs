@@ -0,0 +1,251 @@
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use crate::encode::AudioWriter;
+use crate::mixer::RingBuffer;
+use crate::protocol::{AudioFileFormat, RecordingInfo, RecordingSource};
+use crate::render::format_for;
+
+/// Seconds of audio the queue to the writer thread holds.
+const QUEUE_SECONDS: usize = 2;
+/// How often the writer thread drains the queue.
+const DRAIN_INTERVAL: Duration = Duration::from_millis(20);
+
+/// The recording tap, shared with the output callback.
+pub type SharedTap = Arc<Mutex<Option<Arc<RecordTap>>>>;
+
+/// The output callback's end of a recording: it pushes each block of the
+/// recorded source into a lock-free queue the writer thread drains.
+pub struct RecordTap {
+    pub source: RecordingSource,
+    sample_rate: u32,
+    channels: usize,
+    queue: RingBuffer,
+    /// Frames queued, and frames left out, since the recording started.
+    frames: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl RecordTap {
+    /// Queue one block of `channels` interleaved channels at `rate`.  A
+    /// block the writer has no room for, or in a format other than the
+    /// recording's, is counted as dropped.  Never blocks.
+    pub fn record(&self, block: &[f32], rate: u32, channels: usize) {
+        let frames = (block.len() / channels.max(1)) as u64;
+        if rate == self.sample_rate && channels == self.channels && self.queue.free() >= block.len()
+        {
+            self.queue.push(block);
+            self.frames.fetch_add(frames, Ordering::Relaxed);
+        } else {
+            self.dropped.fetch_add(frames, Ordering::Relaxed);
+        }
+    }
+}
+
+/// A recording in progress: the tap and the thread writing what it
+/// queues to the file.  Dropping it finishes the file.
+pub struct Recorder {
+    path: String,
+    tap: Arc<RecordTap>,
+    stop: Arc<AtomicBool>,
+    writer: Option<JoinHandle<Result<(), String>>>,
+}
+
+impl Recorder {
+    /// Create `path` and start the writer thread.  The format follows the
+    /// extension unless given.
+    pub fn start(
+        path: &str,
+        source: RecordingSource,
+        format: Option<AudioFileFormat>,
+        bit_depth: u16,
+        sample_rate: u32,
+        channels: u16,
+    ) -> Result<Self, String> {
+        let format = match format {
+            Some(format) => format,
+            None => format_for(Path::new(path))?,
+        };
+        let writer =
+            AudioWriter::create(Path::new(path), format, sample_rate, channels, bit_depth)?;
+        let tap = Arc::new(RecordTap {
+            source,
+            sample_rate,
+            channels: channels as usize,
+            queue: RingBuffer::new(sample_rate as usize * channels as usize * QUEUE_SECONDS),
+            frames: AtomicU64::new(0),
+            dropped: AtomicU64::new(0),
+        });
+        let stop = Arc::new(AtomicBool::new(false));
+        let thread = {
+            let tap = Arc::clone(&tap);
+            let stop = Arc::clone(&stop);
+            thread::spawn(move || write_queue(&tap, writer, &stop))
+        };
+        Ok(Self {
+            path: path.to_string(),
+            tap,
+            stop,
+            writer: Some(thread),
+        })
+    }
+
+    /// The end the output callback feeds.
+    pub fn tap(&self) -> Arc<RecordTap> {
+        Arc::clone(&self.tap)
+    }
+
+    pub fn info(&self) -> RecordingInfo {
+        let ms = |frames: &AtomicU64| {
+            frames.load(Ordering::Relaxed) as f64 * 1000.0 / self.tap.sample_rate as f64
+        };
+        RecordingInfo {
+            path: self.path.clone(),
+            source: self.tap.source,
+            sample_rate: self.tap.sample_rate,
+            channels: self.tap.channels as u16,
+            duration_ms: ms(&self.tap.frames),
+            dropped_ms: ms(&self.tap.dropped),
+        }
+    }
+
+    /// Write out what is still queued and finish the file.  The tap must
+    /// already be out of the output callback's reach.
+    pub fn stop(mut self) -> Result<RecordingInfo, String> {
+        self.join()?;
+        Ok(self.info())
+    }
+
+    fn join(&mut self) -> Result<(), String> {
+        self.stop.store(true, Ordering::Release);
+        match self.writer.take() {
+            Some(thread) => thread
+                .join()
+                .unwrap_or_else(|_| Err("The recording writer panicked".to_string())),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for Recorder {
+    fn drop(&mut self) {
+        if let Err(e) = self.join() {
+            eprintln!("[record] {e}");
+        }
+    }
+}
+
+/// The writer thread: drain the queue into `writer` until told to stop,
+/// then finish the file.
+fn write_queue(tap: &RecordTap, mut writer: AudioWriter, stop: &AtomicBool) -> Result<(), String> {
+    // Whole frames only, so each write ends on a frame boundary.
+    let len = tap.sample_rate as usize / 10 * tap.channels;
+    let mut block = vec![0.0; len];
+    loop {
+        // Checked before draining, so nothing queued before the stop is
+        // left behind.
+        let stopping = stop.load(Ordering::Acquire);
+        let n = tap.queue.pop(&mut block);
+        if n > 0 {
+            writer.write(&block[..n])?;
+        } else if stopping {
+            return writer.finish();
+        } else {
+            thread::sleep(DRAIN_INTERVAL);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mixer::MixerState;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    fn record(
+        mixer: &mut MixerState,
+        name: &str,
+        source: RecordingSource,
+        ms: f64,
+    ) -> (RecordingInfo, Vec<f32>) {
+        let path = corpus::path(name);
+        let bits = if name.ends_with(".flac") { 24 } else { 32 };
+        let file = path.to_str().unwrap();
+        mixer.start_recording(file, source, None, bits).unwrap();
+        assert!(mixer.start_recording(file, source, None, bits).is_err());
+        mixer.advance_clock(ms).unwrap();
+        let info = mixer.stop_recording().unwrap();
+        (info, corpus::decode(&path).2)
+    }
+
+    #[test]
+    fn records_each_source() {
+        let mic_signal: Vec<f32> = (0..RATE as usize)
+            .map(|i| (i as f32 * 0.01).sin() * 0.25)
+            .collect();
+        let mic = corpus::wav_float("record-mic.wav", RATE, 1, &mic_signal);
+        let sound_signal = corpus::float_signal(RATE as usize, 2);
+        let sound = corpus::wav_float("record-sound.wav", RATE, 2, &sound_signal);
+        let output = corpus::path("record-out.wav");
+
+        let mut mixer = corpus::null_mixer(Some(&mic), &output);
+        assert!(mixer
+            .start_recording("record-early.wav", RecordingSource::Master, None, 16)
+            .is_err());
+        mixer.start_output().unwrap();
+        mixer.start_capture().unwrap();
+        mixer
+            .play_file(sound.to_str().unwrap(), "sfx", corpus::play_params())
+            .unwrap();
+        assert!(mixer.stop_recording().is_err());
+
+        // 0-100 ms: the whole mix.
+        let (info, master) = record(
+            &mut mixer,
+            "record-master.wav",
+            RecordingSource::Master,
+            100.0,
+        );
+        assert_eq!((info.sample_rate, info.channels), (RATE, 2));
+        assert_eq!((info.duration_ms, info.dropped_ms), (100.0, 0.0));
+        assert!(mixer.recording().is_none());
+
+        // 100-150 ms: the mic alone, on both channels.
+        let (info, mic) = record(
+            &mut mixer,
+            "record-mic-only.wav",
+            RecordingSource::Mic,
+            50.0,
+        );
+        assert_eq!(info.duration_ms, 50.0);
+        assert_eq!(mic.len(), 2400 * 2);
+        for (i, &got) in mic.iter().enumerate() {
+            assert_eq!(got, mic_signal[4800 + i / 2], "sample {i}");
+        }
+
+        // 150-200 ms: the sound alone.
+        let (_, sounds) = record(
+            &mut mixer,
+            "record-sounds.flac",
+            RecordingSource::Sounds,
+            50.0,
+        );
+        assert_eq!(sounds.len(), 2400 * 2);
+        for (i, &got) in sounds.iter().enumerate() {
+            let want = sound_signal[7200 * 2 + i];
+            assert!(
+                (got - want).abs() < 1e-6,
+                "sample {i}: got {got}, want {want}"
+            );
+        }
+
+        drop(mixer);
+        let out = corpus::decode(&output).2;
+        assert_eq!(master, out[..4800 * 2]);
+    }
+}
@@ -0,0 +1,463 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use crate::channel_map::ChannelMatrix;
+use crate::decode::AudioDecoder;
+use crate::encode::{AudioWriter, MAX_CHANNELS, MAX_SAMPLE_RATE};
+use crate::loudness::LoudnessMeter;
+use crate::playback::{pitched_rate, process_chunk, CHUNK_FRAMES, MAX_PITCH_SEMITONES};
+use crate::protocol::{AudioFileFormat, RenderEdits, RenderInfo};
+
+/// Reported for a silent render rather than -inf, which JSON cannot hold.
+const SILENT_DB: f32 = -120.0;
+
+/// Distinguishes temp files of concurrent renders to the same path.
+static NEXT_TMP: AtomicU64 = AtomicU64::new(0);
+
+/// Apply `edits` to `path` and write the result to `output_path`.
+///
+/// `format` defaults to the one the output's extension names.  The file is
+/// written beside the output and renamed over it when complete, so the
+/// output may be the source itself.  Channel conversion uses the matrix
+/// playback would: the user's for the source channel count if it produces
+/// the requested count, otherwise the standard one.
+///
+/// The source is decoded and converted a chunk at a time, as playback
+/// does, so memory use doesn't grow with its length.  Normalizing to a
+/// loudness takes a second pass to measure it first.
+pub fn render(
+    path: &str,
+    output_path: &str,
+    edits: &RenderEdits,
+    format: Option<AudioFileFormat>,
+    bit_depth: u16,
+    matrices: &HashMap<u16, ChannelMatrix>,
+) -> Result<RenderInfo, String> {
+    validate(edits)?;
+    let output = Path::new(output_path);
+    let format = match format {
+        Some(format) => format,
+        None => format_for(output)?,
+    };
+
+    let mut selection = Selection::open(path, edits, matrices)?;
+    let (sample_rate, channels) = (selection.dst_rate, selection.matrix.dst_channels());
+    let gain_db = match edits.loudness_lufs {
+        // Normalize, but no further than the peak allows.
+        Some(target) => {
+            let mut meter = LoudnessMeter::new(sample_rate, channels);
+            let mut peak = 0.0f32;
+            selection.for_each_chunk(|chunk| {
+                meter.push(chunk);
+                peak = chunk.iter().fold(peak, |peak, s| peak.max(s.abs()));
+                Ok(())
+            })?;
+            match meter.integrated() {
+                Some(measured) if peak > 0.0 => (target - measured).min(-to_db(peak)),
+                _ => 0.0,
+            }
+        }
+        None => edits.gain_db,
+    };
+
+    let tmp = output.with_extension(format!("{}.tmp", NEXT_TMP.fetch_add(1, Ordering::Relaxed)));
+    let writer = AudioWriter::create(&tmp, format, sample_rate, channels, bit_depth)?;
+    let mut sink = Sink::new(writer, sample_rate, channels, gain_db, edits);
+    let written = selection
+        .for_each_chunk(|chunk| sink.push(chunk))
+        .and_then(|()| sink.finish())
+        .and_then(|info| {
+            fs::rename(&tmp, output)
+                .map(|()| info)
+                .map_err(|e| format!("Cannot write {}: {e}", output.display()))
+        });
+    let (frames, peak, loudness_lufs) = match written {
+        Ok(info) => info,
+        Err(e) => {
+            let _ = fs::remove_file(&tmp);
+            return Err(e);
+        }
+    };
+
+    Ok(RenderInfo {
+        duration_ms: frames as f64 * 1000.0 / sample_rate as f64,
+        sample_rate,
+        channels,
+        gain_db,
+        peak_db: to_db(peak).max(SILENT_DB),
+        loudness_lufs,
+    })
+}
+
+/// The part of the source a render keeps, and its conversion to the
+/// output format.
+struct Selection<'a> {
+    path: &'a str,
+    start: Duration,
+    /// Source samples to keep; `usize::MAX` keeps the rest of the file.
+    max_len: usize,
+    /// The decoder opened to read the format, used by the first pass.
+    decoder: Option<AudioDecoder>,
+    /// Rate resampled from: the source's, adjusted for the pitch shift.
+    src_rate: u32,
+    src_channels: u16,
+    dst_rate: u32,
+    matrix: ChannelMatrix,
+}
+
+impl<'a> Selection<'a> {
+    fn open(
+        path: &'a str,
+        edits: &RenderEdits,
+        matrices: &HashMap<u16, ChannelMatrix>,
+    ) -> Result<Self, String> {
+        let decoder = AudioDecoder::open(path)?;
+        let rate = decoder.sample_rate();
+        let src_channels = decoder.channels();
+        let max_len = match edits.end_ms {
+            Some(end_ms) => {
+                let frames = ((end_ms - edits.start_ms) / 1000.0 * rate as f64).round() as usize;
+                frames * src_channels as usize
+            }
+            None => usize::MAX,
+        };
+        let channels = edits.channels.unwrap_or(src_channels);
+        let matrix = matrices
+            .get(&src_channels)
+            .filter(|m| m.dst_channels() == channels)
+            .cloned()
+            .unwrap_or_else(|| {
+                ChannelMatrix::standard(src_channels, decoder.channel_mask(), channels)
+            });
+        Ok(Self {
+            path,
+            start: Duration::from_secs_f64(edits.start_ms / 1000.0),
+            max_len,
+            decoder: Some(decoder),
+            src_rate: pitched_rate(rate, edits.pitch_semitones),
+            src_channels,
+            dst_rate: edits.sample_rate.unwrap_or(rate),
+            matrix,
+        })
+    }
+
+    /// Decode the selection from the start, passing it to `f` a chunk at a
+    /// time in the output format.
+    fn for_each_chunk(
+        &mut self,
+        mut f: impl FnMut(&[f32]) -> Result<(), String>,
+    ) -> Result<(), String> {
+        let mut decoder = match self.decoder.take() {
+            Some(decoder) => decoder,
+            None => AudioDecoder::open(self.path)?,
+        };
+        if !self.start.is_zero() {
+            decoder.seek(self.start)?;
+        }
+        let src_channels = self.src_channels.max(1) as usize;
+        let chunk_size = CHUNK_FRAMES * src_channels;
+        let mut chunk = Vec::with_capacity(chunk_size);
+        let mut remaining = self.max_len - self.max_len % src_channels;
+        while remaining > 0 {
+            chunk.clear();
+            let more = decoder.read(&mut chunk, chunk_size);
+            chunk.truncate(remaining);
+            remaining -= chunk.len();
+            if !chunk.is_empty() {
+                f(&process_chunk(
+                    &chunk,
+                    self.src_rate,
+                    self.dst_rate,
+                    self.src_channels,
+                    &self.matrix,
+                ))?;
+            }
+            if !more {
+                break;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Applies the gain and fades to the converted audio and writes it,
+/// measuring what it writes.
+struct Sink {
+    writer: AudioWriter,
+    channels: usize,
+    gain: f32,
+    /// Fade lengths in frames.
+    fade_in: usize,
+    fade_out: usize,
+    /// The last `fade_out` frames, held back until the end is known.
+    tail: Vec<f32>,
+    /// Frames taken so far.
+    frames: usize,
+    peak: f32,
+    meter: LoudnessMeter,
+}
+
+impl Sink {
+    fn new(writer: AudioWriter, rate: u32, channels: u16, gain_db: f32, edits: &RenderEdits) -> Self {
+        let length = |ms: f64| (ms / 1000.0 * rate as f64).round() as usize;
+        Self {
+            writer,
+            channels: channels as usize,
+            gain: 10f32.powf(gain_db / 20.0),
+            fade_in: length(edits.fade_in_ms),
+            fade_out: length(edits.fade_out_ms),
+            tail: Vec::new(),
+            frames: 0,
+            peak: 0.0,
+            meter: LoudnessMeter::new(rate, channels),
+        }
+    }
+
+    fn push(&mut self, chunk: &[f32]) -> Result<(), String> {
+        let start = self.tail.len();
+        self.tail.extend(chunk.iter().map(|s| s * self.gain));
+        // Linear fade in from silence.
+        let fading = self.fade_in.saturating_sub(self.frames);
+        for (i, frame) in self.tail[start..]
+            .chunks_exact_mut(self.channels)
+            .take(fading)
+            .enumerate()
+        {
+            let gain = (self.frames + i) as f32 / self.fade_in as f32;
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        self.frames += chunk.len() / self.channels;
+
+        let ready = self
+            .tail
+            .len()
+            .saturating_sub(self.fade_out * self.channels);
+        if ready > 0 {
+            self.write(ready)?;
+        }
+        Ok(())
+    }
+
+    /// Fade out what's held back and finish the file: the frames written,
+    /// the peak and the loudness.
+    fn finish(mut self) -> Result<(usize, f32, Option<f32>), String> {
+        if self.frames == 0 {
+            return Err("Nothing to render: the selection is past the end of the file".to_string());
+        }
+        // Everything held back, which is the whole fade unless the file is
+        // shorter.
+        let fade_out = self.tail.len() / self.channels;
+        for (i, frame) in self.tail.chunks_exact_mut(self.channels).enumerate() {
+            let gain = (fade_out - 1 - i) as f32 / fade_out as f32;
+            frame.iter_mut().for_each(|s| *s *= gain);
+        }
+        self.write(self.tail.len())?;
+        self.writer.finish()?;
+        Ok((self.frames, self.peak, self.meter.integrated()))
+    }
+
+    /// Write out the first `len` samples of the tail.
+    fn write(&mut self, len: usize) -> Result<(), String> {
+        let samples = &self.tail[..len];
+        self.peak = samples.iter().fold(self.peak, |peak, s| peak.max(s.abs()));
+        self.meter.push(samples);
+        self.writer.write(samples)?;
+        self.tail.drain(..len);
+        Ok(())
+    }
+}
+
+fn validate(edits: &RenderEdits) -> Result<(), String> {
+    if !(0.0..).contains(&edits.start_ms) {
+        return Err("start_ms must not be negative".to_string());
+    }
+    if edits
+        .end_ms
+        .is_some_and(|end| end.is_nan() || end <= edits.start_ms)
+    {
+        return Err("end_ms must be after start_ms".to_string());
+    }
+    if !(-MAX_PITCH_SEMITONES..=MAX_PITCH_SEMITONES).contains(&edits.pitch_semitones) {
+        return Err(format!(
+            "pitch_semitones must be between -{MAX_PITCH_SEMITONES} and {MAX_PITCH_SEMITONES}"
+        ));
+    }
+    if !edits.gain_db.is_finite() || edits.loudness_lufs.is_some_and(|l| !l.is_finite()) {
+        return Err("gain_db and loudness_lufs must be numbers".to_string());
+    }
+    if !(0.0..).contains(&edits.fade_in_ms) || !(0.0..).contains(&edits.fade_out_ms) {
+        return Err("Fade lengths must not be negative".to_string());
+    }
+    if edits
+        .channels
+        .is_some_and(|ch| !(1..=MAX_CHANNELS).contains(&ch))
+    {
+        return Err(format!("channels must be between 1 and {MAX_CHANNELS}"));
+    }
+    if edits
+        .sample_rate
+        .is_some_and(|rate| !(1..=MAX_SAMPLE_RATE).contains(&rate))
+    {
+        return Err(format!("sample_rate must be between 1 and {MAX_SAMPLE_RATE}"));
+    }
+    Ok(())
+}
+
+/// The format an output path's extension names.
+pub fn format_for(output: &Path) -> Result<AudioFileFormat, String> {
+    let ext = output.extension().and_then(|e| e.to_str()).unwrap_or("");
+    match ext.to_ascii_lowercase().as_str() {
+        "wav" => Ok(AudioFileFormat::Wav),
+        "flac" => Ok(AudioFileFormat::Flac),
+        _ => Err(format!(
+            "Cannot tell the format of {}: use .wav or .flac, or give a format",
+            output.display()
+        )),
+    }
+}
+
+fn to_db(amplitude: f32) -> f32 {
+    20.0 * amplitude.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+    use std::path::PathBuf;
+
+    const RATE: u32 = 48000;
+
+    /// Float WAV, or 24-bit FLAC.
+    fn render_to(source: &Path, name: &str, edits: &RenderEdits) -> (RenderInfo, PathBuf) {
+        let bits = if name.ends_with(".flac") { 24 } else { 32 };
+        let output = corpus::path(name);
+        let info = render(
+            source.to_str().unwrap(),
+            output.to_str().unwrap(),
+            edits,
+            None,
+            bits,
+            &HashMap::new(),
+        )
+        .unwrap();
+        (info, output)
+    }
+
+    #[test]
+    fn crops_gains_and_fades() {
+        let signal = corpus::float_signal(RATE as usize, 2);
+        let source = corpus::wav_float("render-source.wav", RATE, 2, &signal);
+        let edits = RenderEdits {
+            start_ms: 100.0,
+            end_ms: Some(600.0),
+            gain_db: -6.0,
+            fade_in_ms: 10.0,
+            fade_out_ms: 20.0,
+            ..Default::default()
+        };
+        let (info, output) = render_to(&source, "render-crop.wav", &edits);
+        assert_eq!((info.sample_rate, info.channels), (RATE, 2));
+        assert_eq!(info.duration_ms, 500.0);
+
+        let (rate, channels, samples) = corpus::decode(&output);
+        assert_eq!((rate, channels), (RATE, 2));
+        assert_eq!(samples.len(), 24000 * 2);
+        let gain = 10f32.powf(-6.0 / 20.0);
+        let (fade_in, fade_out) = (480, 960);
+        for (i, &got) in samples.iter().enumerate() {
+            let frame = i / 2;
+            let mut want = signal[4800 * 2 + i] * gain;
+            if frame < fade_in {
+                want *= frame as f32 / fade_in as f32;
+            }
+            if frame >= 24000 - fade_out {
+                want *= (23999 - frame) as f32 / fade_out as f32;
+            }
+            assert!(
+                (got - want).abs() < 1e-6,
+                "sample {i}: got {got}, want {want}"
+            );
+        }
+        let peak = samples.iter().fold(0.0f32, |p, s| p.max(s.abs()));
+        assert!((info.peak_db - to_db(peak)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn converts_format_and_normalizes() {
+        let signal = corpus::signal(RATE as usize, 2, 16);
+        let source = corpus::flac("render-source.flac", RATE, 2, 16, &signal);
+        let edits = RenderEdits {
+            sample_rate: Some(24000),
+            channels: Some(1),
+            loudness_lufs: Some(-23.0),
+            ..Default::default()
+        };
+        let (info, output) = render_to(&source, "render-norm.flac", &edits);
+        assert_eq!((info.sample_rate, info.channels), (24000, 1));
+        let lufs = info.loudness_lufs.unwrap();
+        assert!((lufs + 23.0).abs() < 0.05, "{lufs}");
+
+        let (rate, channels, samples) = corpus::decode(&output);
+        assert_eq!((rate, channels, samples.len()), (24000, 1, 24000));
+
+        // A target the peak can't reach stops short of clipping.
+        let edits = RenderEdits {
+            loudness_lufs: Some(0.0),
+            ..Default::default()
+        };
+        let (info, _) = render_to(&source, "render-loud.wav", &edits);
+        assert!(info.peak_db.abs() < 1e-3, "{}", info.peak_db);
+    }
+
+    #[test]
+    fn renders_over_the_source() {
+        let signal = corpus::float_signal(RATE as usize, 1);
+        let source = corpus::wav_float("render-self.wav", RATE, 1, &signal);
+        let edits = RenderEdits {
+            end_ms: Some(250.0),
+            ..Default::default()
+        };
+        let (info, output) = render_to(&source, "render-self.wav", &edits);
+        assert_eq!(output, source);
+        assert_eq!(info.duration_ms, 250.0);
+        assert_eq!(corpus::decode(&source).2, signal[..12000]);
+
+        let past_end = RenderEdits {
+            start_ms: 5000.0,
+            ..Default::default()
+        };
+        let output = corpus::path("render-none.wav");
+        let args = |edits: &RenderEdits| {
+            render(
+                source.to_str().unwrap(),
+                output.to_str().unwrap(),
+                edits,
+                None,
+                16,
+                &HashMap::new(),
+            )
+        };
+        assert!(args(&past_end).is_err());
+        assert!(args(&RenderEdits {
+            end_ms: Some(0.0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(args(&RenderEdits {
+            channels: Some(0),
+            fade_in_ms: 10.0,
+            ..Default::default()
+        })
+        .is_err());
+        assert!(args(&RenderEdits {
+            sample_rate: Some(0),
+            ..Default::default()
+        })
+        .is_err());
+        assert!(!output.exists());
+    }
+}
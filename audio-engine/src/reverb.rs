@@ -0,0 +1,285 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+use crate::channel_map::ChannelMatrix;
+use crate::decode::AudioDecoder;
+use crate::mixer::resample;
+use crate::protocol::ReverbSettings;
+
+/// Partition size of the convolution.  The wet signal lags the dry one by
+/// this many frames (about 10 ms at 48 kHz), which reads as a short
+/// pre-delay.
+const BLOCK_SIZE: usize = 512;
+const FFT_SIZE: usize = BLOCK_SIZE * 2;
+const FREQ_SIZE: usize = BLOCK_SIZE + 1;
+
+/// Longest impulse response accepted.  Cost grows linearly with IR length,
+/// and this keeps a stereo IR well within the output callback's budget.
+const MAX_IR_SECONDS: usize = 6;
+
+/// Highest `wet` / `dry` level accepted (+6 dB).
+pub const MAX_REVERB_LEVEL: f32 = 2.0;
+
+// ---------------------------------------------------------------------------
+// Impulse response
+// ---------------------------------------------------------------------------
+
+/// An impulse response converted to the output format and pre-transformed
+/// into frequency-domain partitions.  Shared (via `Arc`) between every
+/// convolver that uses it.
+pub struct ImpulseResponse {
+    rate: u32,
+    channels: usize,
+    /// Length of the IR in frames.
+    frames: usize,
+    /// `partitions[channel][partition]` is the spectrum of one
+    /// `BLOCK_SIZE` slice of the IR, zero-padded to `FFT_SIZE`.
+    partitions: Vec<Vec<Vec<Complex<f32>>>>,
+}
+
+impl ImpulseResponse {
+    /// Load an impulse response from an audio file (normally a WAV) and
+    /// convert it to `rate` / `channels`.  A mono IR is applied to every
+    /// channel.  Otherwise each output channel is convolved with its own
+    /// IR channel only: a stereo IR gives left and right their own
+    /// response, with no cross-feed between them (not four-path "true
+    /// stereo").
+    pub fn load(path: &str, rate: u32, channels: u16) -> Result<Self, String> {
+        let mut decoder =
+            AudioDecoder::open(path).map_err(|e| format!("Impulse response: {e}"))?;
+        let src_rate = decoder.sample_rate();
+        let src_channels = decoder.channels();
+        let max_samples = MAX_IR_SECONDS * src_rate as usize * src_channels as usize;
+        let raw = decoder.read_all(max_samples);
+        if raw.is_empty() {
+            return Err("Impulse response is empty".to_string());
+        }
+
+        let resampled = resample(&raw, src_rate, rate, src_channels);
+        let converted = ChannelMatrix::standard(src_channels, decoder.channel_mask(), channels)
+            .convert(&resampled);
+        let ch = channels as usize;
+        let frames = converted.len() / ch;
+
+        // Normalise to unit energy (per channel, using the loudest one) so
+        // IRs recorded at different levels give a similar wet level.
+        let energy = (0..ch)
+            .map(|c| converted.iter().skip(c).step_by(ch).map(|s| s * s).sum::<f32>())
+            .fold(0.0, f32::max);
+        let gain = if energy > 0.0 { 1.0 / energy.sqrt() } else { 1.0 };
+
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let mut time_buf = fft.make_input_vec();
+        let mut scratch = fft.make_scratch_vec();
+
+        let count = frames.div_ceil(BLOCK_SIZE);
+        let mut partitions = Vec::with_capacity(ch);
+        for c in 0..ch {
+            let mut parts = Vec::with_capacity(count);
+            for p in 0..count {
+                time_buf.fill(0.0);
+                for (i, t) in time_buf[..BLOCK_SIZE].iter_mut().enumerate() {
+                    let frame = p * BLOCK_SIZE + i;
+                    if frame < frames {
+                        *t = converted[frame * ch + c] * gain;
+                    }
+                }
+                let mut spectrum = fft.make_output_vec();
+                let _ = fft.process_with_scratch(&mut time_buf, &mut spectrum, &mut scratch);
+                parts.push(spectrum);
+            }
+            partitions.push(parts);
+        }
+
+        Ok(Self {
+            rate,
+            channels: ch,
+            frames,
+            partitions,
+        })
+    }
+
+    /// Whether this IR was prepared for the given output format.
+    pub fn matches(&self, rate: u32, channels: usize) -> bool {
+        self.rate == rate && self.channels == channels
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Convolver
+// ---------------------------------------------------------------------------
+
+/// Uniformly partitioned overlap-save convolution reverb for interleaved
+/// audio.
+///
+/// All buffers are allocated up front, so `process` never allocates and
+/// costs the same for every block regardless of IR length.
+pub struct Convolver {
+    settings: ReverbSettings,
+    ir: Arc<ImpulseResponse>,
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    /// Frequency-domain delay line per channel; slot `(head + p) % len`
+    /// holds the input block from `p` blocks ago.
+    fdl: Vec<Vec<Vec<Complex<f32>>>>,
+    head: usize,
+    /// Previous input block per channel (first half of the FFT input).
+    prev: Vec<Vec<f32>>,
+    /// Interleaved input waiting for a full block.
+    pending: Vec<f32>,
+    /// Interleaved wet output, primed with one block of silence.
+    ready: VecDeque<f32>,
+    time_buf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    accum: Vec<Complex<f32>>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+}
+
+impl Convolver {
+    /// `settings.wet` and `dry` are clamped to `0.0 ..= MAX_REVERB_LEVEL`.
+    pub fn new(ir: Arc<ImpulseResponse>, mut settings: ReverbSettings) -> Self {
+        settings.wet = settings.wet.clamp(0.0, MAX_REVERB_LEVEL);
+        settings.dry = settings.dry.clamp(0.0, MAX_REVERB_LEVEL);
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(FFT_SIZE);
+        let ifft = planner.plan_fft_inverse(FFT_SIZE);
+        let ch = ir.channels;
+        let count = ir.partitions.first().map(|p| p.len()).unwrap_or(0).max(1);
+
+        let mut ready = VecDeque::with_capacity(BLOCK_SIZE * ch * 4);
+        ready.extend(std::iter::repeat_n(0.0, BLOCK_SIZE * ch));
+
+        Self {
+            settings,
+            fdl: vec![vec![vec![Complex::default(); FREQ_SIZE]; count]; ch],
+            head: 0,
+            prev: vec![vec![0.0; BLOCK_SIZE]; ch],
+            pending: Vec::with_capacity(BLOCK_SIZE * ch * 4),
+            ready,
+            time_buf: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            accum: fft.make_output_vec(),
+            scratch_fwd: fft.make_scratch_vec(),
+            scratch_inv: ifft.make_scratch_vec(),
+            fft,
+            ifft,
+            ir,
+        }
+    }
+
+    pub fn settings(&self) -> &ReverbSettings {
+        &self.settings
+    }
+
+    /// Whether the convolver can run on a stream with this format.
+    pub fn matches(&self, rate: u32, channels: usize) -> bool {
+        self.ir.matches(rate, channels)
+    }
+
+    /// Frames the reverb keeps ringing after its input goes silent.
+    pub fn tail_frames(&self) -> usize {
+        self.ir.frames + BLOCK_SIZE
+    }
+
+    /// Replace `data` (interleaved, in the IR's format) with
+    /// `dry * data + wet * (data ⊛ IR)`.
+    pub fn process(&mut self, data: &mut [f32]) {
+        let ch = self.ir.channels;
+        let block = BLOCK_SIZE * ch;
+        self.pending.extend_from_slice(data);
+
+        let mut consumed = 0;
+        while self.pending.len() - consumed >= block {
+            self.process_block(consumed);
+            consumed += block;
+        }
+        self.pending.drain(..consumed);
+
+        let wet = self.settings.wet;
+        let dry = self.settings.dry;
+        for s in data.iter_mut() {
+            let w = self.ready.pop_front().unwrap_or(0.0);
+            *s = *s * dry + w * wet;
+        }
+    }
+
+    fn process_block(&mut self, offset: usize) {
+        let ch = self.ir.channels;
+        let count = self.fdl[0].len();
+        let norm = 1.0 / FFT_SIZE as f32;
+        self.head = (self.head + count - 1) % count;
+
+        // Reserve the block's interleaved output; each channel fills its
+        // own lane below.
+        let start = self.ready.len();
+        self.ready.extend(std::iter::repeat_n(0.0, BLOCK_SIZE * ch));
+
+        for c in 0..ch {
+            // Input spectrum of [previous block, current block].
+            self.time_buf[..BLOCK_SIZE].copy_from_slice(&self.prev[c]);
+            for (i, t) in self.time_buf[BLOCK_SIZE..].iter_mut().enumerate() {
+                *t = self.pending[offset + i * ch + c];
+            }
+            self.prev[c].copy_from_slice(&self.time_buf[BLOCK_SIZE..]);
+            let _ = self.fft.process_with_scratch(
+                &mut self.time_buf,
+                &mut self.fdl[c][self.head],
+                &mut self.scratch_fwd,
+            );
+
+            // Multiply-accumulate every IR partition with its delayed input.
+            self.accum.fill(Complex::default());
+            let parts = &self.ir.partitions[c];
+            for (p, h) in parts.iter().enumerate() {
+                let x = &self.fdl[c][(self.head + p) % count];
+                for ((acc, h), x) in self.accum.iter_mut().zip(h).zip(x) {
+                    *acc += h * x;
+                }
+            }
+
+            self.spectrum.copy_from_slice(&self.accum);
+            self.spectrum[0].im = 0.0;
+            self.spectrum[FREQ_SIZE - 1].im = 0.0;
+            let _ = self
+                .ifft
+                .process_with_scratch(&mut self.spectrum, &mut self.time_buf, &mut self.scratch_inv);
+
+            for (i, y) in self.time_buf[BLOCK_SIZE..].iter().enumerate() {
+                self.ready[start + i * ch + c] = y * norm;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    #[test]
+    fn clamps_the_levels() {
+        // A unit impulse: the wet signal is the input, one block late.
+        let mut impulse = vec![0.0; 64];
+        impulse[0] = 1.0;
+        let path = corpus::wav_float("impulse.wav", 48000, 1, &impulse);
+        let ir = Arc::new(ImpulseResponse::load(path.to_str().unwrap(), 48000, 1).unwrap());
+        let settings = ReverbSettings {
+            ir_path: path.to_string_lossy().into_owned(),
+            wet: 5.0,
+            dry: -1.0,
+        };
+        let mut convolver = Convolver::new(ir, settings);
+        assert_eq!((convolver.settings().wet, convolver.settings().dry), (2.0, 0.0));
+
+        let mut data = vec![0.0; BLOCK_SIZE * 2];
+        data[0] = 0.25;
+        convolver.process(&mut data);
+        assert_eq!(data[0], 0.0);
+        assert_eq!(data[BLOCK_SIZE], 0.5);
+    }
+}
@@ -0,0 +1,337 @@
+use std::collections::HashMap;
+use std::fs;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use crate::protocol::SampleCacheStatus;
+
+/// Default memory budget: about 20 minutes of 48 kHz stereo.
+pub const DEFAULT_BUDGET_BYTES: usize = 512 * 1024 * 1024;
+
+/// The cache shared between the command loop and decode threads.
+pub type SharedSampleCache = Arc<Mutex<SampleCache>>;
+
+/// A decoded sound ready to play.
+#[derive(Clone)]
+pub struct CachedSound {
+    pub samples: Arc<Vec<f32>>,
+    /// Channel count of the file (the samples are in the output's).
+    pub src_channels: u16,
+}
+
+struct Entry {
+    sound: CachedSound,
+    /// Modification time of the file when it was decoded; a rewritten
+    /// file is decoded again.
+    modified: Option<SystemTime>,
+    last_used: u64,
+    /// Preloaded entries are only dropped by `Unload`, not evicted.
+    pinned: bool,
+}
+
+impl Entry {
+    fn bytes(&self) -> usize {
+        self.sound.samples.capacity() * std::mem::size_of::<f32>()
+    }
+}
+
+/// Decoded sounds in the output format, by file path, evicted least
+/// recently used first when they outgrow the memory budget.
+pub struct SampleCache {
+    entries: HashMap<String, Entry>,
+    budget: usize,
+    used: usize,
+    /// Use counter for the LRU order.
+    tick: u64,
+    /// Output format the entries are in.
+    rate: u32,
+    channels: u16,
+}
+
+fn modified(path: &str) -> Option<SystemTime> {
+    fs::metadata(path).and_then(|m| m.modified()).ok()
+}
+
+impl SampleCache {
+    pub fn new(budget: usize) -> Self {
+        Self {
+            entries: HashMap::new(),
+            budget,
+            used: 0,
+            tick: 0,
+            rate: 0,
+            channels: 0,
+        }
+    }
+
+    /// Drop everything if the output format changed.
+    pub fn set_format(&mut self, rate: u32, channels: u16) {
+        if (rate, channels) != (self.rate, self.channels) {
+            self.clear();
+            self.rate = rate;
+            self.channels = channels;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.used = 0;
+    }
+
+    pub fn set_budget(&mut self, budget: usize) {
+        self.budget = budget;
+        self.evict(0);
+    }
+
+    /// The decoded sound for `path`, unless the file changed since.
+    pub fn get(&mut self, path: &str) -> Option<CachedSound> {
+        let stale = self.entries.get(path)?.modified != modified(path);
+        if stale {
+            self.remove(path);
+            return None;
+        }
+        self.tick += 1;
+        let entry = self.entries.get_mut(path)?;
+        entry.last_used = self.tick;
+        Some(entry.sound.clone())
+    }
+
+    /// Whether `path` is cached and pinned.
+    pub fn is_pinned(&self, path: &str) -> bool {
+        self.entries.get(path).is_some_and(|e| e.pinned)
+    }
+
+    /// Cache a sound decoded at `rate` / `channels` (ignored if that is no
+    /// longer the output format).  Unpinned entries are evicted to make
+    /// room; a sound that still doesn't fit isn't cached.  Returns whether
+    /// it was.
+    pub fn insert(
+        &mut self,
+        path: &str,
+        sound: CachedSound,
+        rate: u32,
+        channels: u16,
+        pinned: bool,
+    ) -> bool {
+        if (rate, channels) != (self.rate, self.channels) {
+            return false;
+        }
+        let pinned = pinned || self.is_pinned(path);
+        self.remove(path);
+        self.tick += 1;
+        let entry = Entry {
+            sound,
+            modified: modified(path),
+            last_used: self.tick,
+            pinned,
+        };
+        let bytes = entry.bytes();
+        if !self.evict(bytes) {
+            return false;
+        }
+        self.used += bytes;
+        self.entries.insert(path.to_string(), entry);
+        true
+    }
+
+    /// Drop a sound, pinned or not.  Returns whether it was cached.
+    pub fn remove(&mut self, path: &str) -> bool {
+        match self.entries.remove(path) {
+            Some(entry) => {
+                self.used -= entry.bytes();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Drop every sound decoded from a file with `src_channels` channels,
+    /// pinned or not.  Returns the paths of the pinned ones.
+    pub fn remove_source_channels(&mut self, src_channels: u16) -> Vec<String> {
+        let paths: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.sound.src_channels == src_channels)
+            .map(|(path, _)| path.clone())
+            .collect();
+        let mut pinned = Vec::new();
+        for path in paths {
+            if self.is_pinned(&path) {
+                pinned.push(path.clone());
+            }
+            self.remove(&path);
+        }
+        pinned.sort();
+        pinned
+    }
+
+    /// Evict unpinned entries, oldest first, until `extra` more bytes fit
+    /// in the budget.  Returns whether they do.
+    fn evict(&mut self, extra: usize) -> bool {
+        while self.used + extra > self.budget {
+            let oldest = self
+                .entries
+                .iter()
+                .filter(|(_, e)| !e.pinned)
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(path, _)| path.clone());
+            match oldest {
+                Some(path) => {
+                    self.remove(&path);
+                }
+                None => return false,
+            }
+        }
+        true
+    }
+
+    pub fn status(&self) -> SampleCacheStatus {
+        let mut preloaded: Vec<String> = self
+            .entries
+            .iter()
+            .filter(|(_, e)| e.pinned)
+            .map(|(path, _)| path.clone())
+            .collect();
+        preloaded.sort();
+        SampleCacheStatus {
+            entries: self.entries.len(),
+            used_bytes: self.used,
+            budget_bytes: self.budget,
+            preloaded,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::mixer::MixerState;
+    use crate::test_corpus as corpus;
+
+    /// A sound of `frames` stereo frames (`frames * 8` bytes).
+    fn sound(frames: usize) -> CachedSound {
+        CachedSound {
+            samples: Arc::new(vec![0.0; frames * 2]),
+            src_channels: 2,
+        }
+    }
+
+    #[test]
+    fn evicts_least_recently_used_but_not_pinned() {
+        let mut cache = SampleCache::new(3 * 800);
+        cache.set_format(48000, 2);
+        assert!(cache.insert("pinned", sound(100), 48000, 2, true));
+        assert!(cache.insert("a", sound(100), 48000, 2, false));
+        assert!(cache.insert("b", sound(100), 48000, 2, false));
+        // Using `a` leaves `b` the oldest.
+        assert!(cache.get("a").is_some());
+        assert!(cache.insert("c", sound(100), 48000, 2, false));
+        assert!(cache.get("b").is_none());
+        assert!(cache.get("pinned").is_some() && cache.get("a").is_some());
+        // Nothing unpinned can make room for this.
+        assert!(!cache.insert("big", sound(300), 48000, 2, false));
+
+        cache.set_budget(800);
+        assert_eq!(cache.status().preloaded, ["pinned"]);
+        assert_eq!((cache.status().entries, cache.status().used_bytes), (1, 800));
+    }
+
+    #[test]
+    fn only_keeps_sounds_in_the_output_format() {
+        let mut cache = SampleCache::new(DEFAULT_BUDGET_BYTES);
+        cache.set_format(48000, 2);
+        assert!(!cache.insert("other", sound(10), 44100, 2, false));
+        assert!(cache.insert("same", sound(10), 48000, 2, false));
+        cache.set_format(48000, 2);
+        assert_eq!(cache.status().entries, 1);
+        cache.set_format(44100, 2);
+        assert_eq!(cache.status().entries, 0);
+    }
+
+    #[test]
+    fn a_rewritten_file_is_decoded_again() {
+        let path = corpus::wav_float("rewritten.wav", 48000, 2, &[0.0; 20]);
+        let path = path.to_str().unwrap();
+        let mut cache = SampleCache::new(DEFAULT_BUDGET_BYTES);
+        cache.set_format(48000, 2);
+        assert!(cache.insert(path, sound(10), 48000, 2, false));
+        assert!(cache.get(path).is_some());
+        let later = SystemTime::now() + Duration::from_secs(10);
+        fs::File::options()
+            .write(true)
+            .open(path)
+            .and_then(|f| f.set_modified(later))
+            .unwrap();
+        assert!(cache.get(path).is_none());
+    }
+
+    /// Waits until `path` is cached in `mixer`, then returns it.
+    fn wait_cached(mixer: &MixerState, path: &str) -> CachedSound {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            if let Some(sound) = mixer.sample_cache.lock().unwrap().get(path) {
+                return sound;
+            }
+            assert!(Instant::now() < deadline, "preload never finished");
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn preloaded_sounds_play_from_memory() {
+        let signal = corpus::float_signal(4800, 2);
+        let path = corpus::wav_float("preloaded.wav", 48000, 2, &signal);
+        let path = path.to_str().unwrap();
+        let output = corpus::path("preloaded-out.wav");
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+        mixer.preload(path).unwrap();
+        wait_cached(&mixer, path);
+        assert_eq!(mixer.sample_cache_status().preloaded, [path]);
+
+        let cached = mixer.sample_cache.lock().unwrap().get(path).unwrap();
+        assert_eq!(*cached.samples, signal);
+        mixer.play_file(path, "sfx", corpus::play_params()).unwrap();
+        mixer.advance_clock(100.0).unwrap();
+        mixer.unload(path).unwrap();
+        assert!(mixer.sample_cache_status().preloaded.is_empty());
+        assert!(mixer.unload(path).is_err());
+        drop(mixer);
+        assert_eq!(corpus::decode(&output).2[..signal.len()], signal[..]);
+    }
+
+    #[test]
+    fn a_channel_matrix_only_redecodes_sounds_it_applies_to() {
+        let mono = corpus::wav_float("matrix-mono.wav", 48000, 1, &[0.5; 480]);
+        let stereo = corpus::float_signal(480, 2);
+        let stereo = corpus::wav_float("matrix-stereo.wav", 48000, 2, &stereo);
+        let (mono, stereo) = (mono.to_str().unwrap(), stereo.to_str().unwrap());
+        let mut mixer = corpus::null_mixer(None, &corpus::path("matrix-out.wav"));
+        mixer.start_output().unwrap();
+        mixer.preload(mono).unwrap();
+        mixer.preload(stereo).unwrap();
+        let stereo_before = wait_cached(&mixer, stereo);
+        assert_eq!(wait_cached(&mixer, mono).samples[..2], [0.5, 0.5]);
+
+        // One row per output channel, so three rows don't fit stereo.
+        let err = mixer
+            .set_channel_matrix(1, Some(vec![vec![1.0]; 3]))
+            .unwrap_err();
+        assert!(err.contains("2 rows"), "{err}");
+
+        // Left only: the mono sound is decoded again, still preloaded; the
+        // stereo one is left alone.
+        mixer.set_channel_matrix(1, Some(vec![vec![1.0], vec![0.0]])).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while wait_cached(&mixer, mono).samples[..2] != [0.5, 0.0] {
+            assert!(Instant::now() < deadline, "never decoded again");
+            thread::sleep(Duration::from_millis(5));
+        }
+        assert_eq!(mixer.sample_cache_status().preloaded.len(), 2);
+        let stereo_after = mixer.sample_cache.lock().unwrap().get(stereo).unwrap();
+        assert!(Arc::ptr_eq(&stereo_before.samples, &stereo_after.samples));
+    }
+}
@@ -0,0 +1,166 @@
+use std::sync::{Arc, Mutex};
+
+use crate::bus::{BusGraph, VoiceSlot};
+use crate::protocol::ScheduledPlay;
+
+/// The scheduler shared between the command loop and the output callback.
+pub type SharedScheduler = Arc<Mutex<Scheduler>>;
+
+struct Pending {
+    id: u64,
+    bus: String,
+    file_path: String,
+    /// Engine clock frame the voice starts on.
+    start_frame: u64,
+    /// The voice, already decoding.  It is swapped in as the bus voice when
+    /// it starts.
+    voice: VoiceSlot,
+}
+
+/// Plays waiting for their start time on the engine clock (frames rendered
+/// by the main output since it was opened).
+pub struct Scheduler {
+    pending: Vec<Pending>,
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        Self {
+            pending: Vec::with_capacity(16),
+        }
+    }
+
+    pub fn add(&mut self, id: u64, bus: &str, file_path: &str, start_frame: u64, voice: VoiceSlot) {
+        self.pending.push(Pending {
+            id,
+            bus: bus.to_string(),
+            file_path: file_path.to_string(),
+            start_frame,
+            voice,
+        });
+    }
+
+    /// Cancel the play with `id`.  Returns `false` if it isn't pending
+    /// (it already started, or never existed).
+    pub fn cancel(&mut self, id: u64) -> bool {
+        let Some(index) = self.pending.iter().position(|p| p.id == id) else {
+            return false;
+        };
+        let play = self.pending.remove(index);
+        if let Ok(mut guard) = play.voice.lock() {
+            *guard = None;
+        }
+        true
+    }
+
+    /// The voices of the pending plays.
+    pub fn voices(&self) -> impl Iterator<Item = &VoiceSlot> {
+        self.pending.iter().map(|p| &p.voice)
+    }
+
+    /// Cancel everything.
+    pub fn clear(&mut self) {
+        while let Some(play) = self.pending.pop() {
+            if let Ok(mut guard) = play.voice.lock() {
+                *guard = None;
+            }
+        }
+    }
+
+    pub fn status(&self, rate: u32) -> Vec<ScheduledPlay> {
+        let mut plays: Vec<ScheduledPlay> = self
+            .pending
+            .iter()
+            .map(|p| ScheduledPlay {
+                id: p.id,
+                bus: p.bus.clone(),
+                file_path: p.file_path.clone(),
+                start_ms: p.start_frame as f64 * 1000.0 / rate.max(1) as f64,
+            })
+            .collect();
+        plays.sort_by(|a, b| a.start_ms.total_cmp(&b.start_ms));
+        plays
+    }
+
+    /// Start the plays due in the block that begins at `clock` and lasts
+    /// `frames` frames: each one replaces its bus's voice, delayed to its
+    /// exact frame within the block.  Plays whose bus has gone are dropped.
+    /// Returns `true` if any play started.
+    pub fn start_due(&mut self, graph: &mut BusGraph, clock: u64, frames: u64) -> bool {
+        let mut started = false;
+        let mut i = 0;
+        while i < self.pending.len() {
+            let play = &self.pending[i];
+            if play.start_frame >= clock + frames {
+                i += 1;
+                continue;
+            }
+            // The decode thread holds the voice for a moment; try again
+            // next block (the voice then starts at the top of it).
+            let Ok(mut guard) = play.voice.try_lock() else {
+                i += 1;
+                continue;
+            };
+            if let Some(fp) = guard.as_mut() {
+                fp.delay_frames = play.start_frame.saturating_sub(clock) as usize;
+            }
+            drop(guard);
+
+            let play = self.pending.swap_remove(i);
+            if let Some(bus) = graph.get_mut(&play.bus) {
+                let old = std::mem::replace(&mut bus.voice, play.voice);
+                // Stop the replaced voice's decode thread, as Play does.
+                if let Ok(mut guard) = old.try_lock() {
+                    *guard = None;
+                }
+                started = true;
+            }
+        }
+        started
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playback::PlayParams;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    #[test]
+    fn starts_on_its_frame_and_can_be_cancelled() {
+        let signal = corpus::float_signal(2400, 2);
+        let sound = corpus::wav_float("scheduled.wav", RATE, 2, &signal);
+        let other = corpus::wav_float("cancelled.wav", RATE, 2, &signal);
+        let output = corpus::path("scheduled-out.wav");
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+
+        let at = |ms| PlayParams {
+            start_ms: Some(ms),
+            ..corpus::play_params()
+        };
+        // 12.5 ms is frame 600, part-way through a block.
+        let id = mixer.play_file(sound.to_str().unwrap(), "sfx", at(12.5)).unwrap();
+        let cancelled = mixer.play_file(other.to_str().unwrap(), "music", at(25.0)).unwrap();
+        assert!(id.is_some() && cancelled.is_some());
+        let scheduled = mixer.scheduled();
+        assert_eq!(
+            scheduled.iter().map(|p| (p.id, p.start_ms)).collect::<Vec<_>>(),
+            [(id.unwrap(), 12.5), (cancelled.unwrap(), 25.0)]
+        );
+        mixer.cancel_scheduled(cancelled.unwrap()).unwrap();
+        assert!(mixer.cancel_scheduled(cancelled.unwrap()).is_err());
+
+        mixer.advance_clock(100.0).unwrap();
+        assert!(mixer.scheduled().is_empty());
+        drop(mixer);
+
+        let out = corpus::decode(&output).2;
+        assert_eq!(out.len(), 4800 * 2);
+        for (i, &got) in out.iter().enumerate() {
+            let want = i.checked_sub(1200).and_then(|j| signal.get(j)).copied().unwrap_or(0.0);
+            assert!((got - want).abs() < 1e-6, "sample {i}: got {got}, want {want}");
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use crate::protocol::{SilenceReport, SilentSpan};
+use crate::waveform::Peaks;
+
+/// Silence kept either side of a suggested crop.
+const MARGIN_MS: f64 = 10.0;
+
+/// Find the stretches of `peaks` at least `min_silence_ms` long where no
+/// sample reaches `threshold_db`, and suggest a crop without the ones at
+/// either end.
+///
+/// Works from the finest peaks, so edges are placed to within a block: a
+/// few milliseconds for anything shorter than an hour.
+pub fn detect(peaks: &Peaks, threshold_db: f32, min_silence_ms: f64) -> SilenceReport {
+    let threshold = 10f32.powf(threshold_db / 20.0);
+    let frames = peaks.frames();
+    let ms = |frame: u64| frame as f64 * 1000.0 / peaks.rate().max(1) as f64;
+
+    // Quiet stretches, as frame ranges.
+    let mut quiet = Vec::new();
+    let mut from = None;
+    for (start, level) in peaks.blocks() {
+        if level < threshold {
+            from.get_or_insert(start);
+        } else if let Some(from) = from.take() {
+            quiet.push((from, start));
+        }
+    }
+    if let Some(from) = from {
+        quiet.push((from, frames));
+    }
+    quiet.retain(|&(start, end)| ms(end) - ms(start) >= min_silence_ms);
+
+    let duration_ms = ms(frames);
+    let mut report = SilenceReport {
+        duration_ms,
+        leading_ms: 0.0,
+        trailing_ms: 0.0,
+        gaps: Vec::new(),
+        suggested_start_ms: 0.0,
+        suggested_end_ms: duration_ms,
+    };
+    if let [(0, end)] = quiet[..] {
+        if end == frames {
+            // Silent throughout: nothing to crop to.
+            report.leading_ms = duration_ms;
+            return report;
+        }
+    }
+    if let Some(&(0, end)) = quiet.first() {
+        report.leading_ms = ms(end);
+        report.suggested_start_ms = (ms(end) - MARGIN_MS).max(0.0);
+        quiet.remove(0);
+    }
+    if let Some(&(start, end)) = quiet.last() {
+        if end == frames {
+            report.trailing_ms = duration_ms - ms(start);
+            report.suggested_end_ms = (ms(start) + MARGIN_MS).min(duration_ms);
+            quiet.pop();
+        }
+    }
+    report.gaps = quiet
+        .into_iter()
+        .map(|(start, end)| SilentSpan {
+            start_ms: ms(start),
+            end_ms: ms(end),
+        })
+        .collect();
+    report
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48000;
+
+    /// Mono float samples: a tone where `loud`, silence elsewhere.
+    fn sections(parts: &[(f64, bool)]) -> Vec<f32> {
+        let mut out = Vec::new();
+        for &(seconds, loud) in parts {
+            let frames = (seconds * RATE as f64) as usize;
+            let level = if loud { 0.5 } else { 0.0 };
+            out.extend((0..frames).map(|i| level * (i as f32 * 0.1).sin()));
+        }
+        out
+    }
+
+    #[test]
+    fn finds_dead_air_and_gaps() {
+        let signal = sections(&[
+            (0.5, false),
+            (1.0, true),
+            (0.3, false),
+            (1.0, true),
+            // Shorter than the minimum, so not a gap.
+            (0.1, false),
+            (0.5, true),
+            (0.4, false),
+        ]);
+        let path = corpus::wav_float("silence.wav", RATE, 1, &signal);
+        let peaks = Peaks::measure(path.to_str().unwrap()).unwrap();
+        let report = detect(&peaks, -50.0, 200.0);
+
+        let near = |got: f64, want: f64| (got - want).abs() < 1.0;
+        assert!(near(report.duration_ms, 3800.0));
+        assert!(near(report.leading_ms, 500.0), "{}", report.leading_ms);
+        assert!(near(report.trailing_ms, 400.0), "{}", report.trailing_ms);
+        assert_eq!(report.gaps.len(), 1);
+        assert!(near(report.gaps[0].start_ms, 1500.0));
+        assert!(near(report.gaps[0].end_ms, 1800.0));
+        assert!(near(report.suggested_start_ms, 490.0));
+        assert!(near(report.suggested_end_ms, 3410.0));
+
+        // Nothing to cut from a file that is silent throughout.
+        let path = corpus::wav_float("silent.wav", RATE, 1, &sections(&[(1.0, false)]));
+        let peaks = Peaks::measure(path.to_str().unwrap()).unwrap();
+        let report = detect(&peaks, -50.0, 200.0);
+        assert!(near(report.leading_ms, 1000.0));
+        assert_eq!(report.suggested_start_ms, 0.0);
+        assert!(near(report.suggested_end_ms, 1000.0));
+    }
+}
@@ -0,0 +1,306 @@
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::playback::MAX_PITCH_SEMITONES;
+use crate::protocol::{RandomMode, Variation, WeightedFile};
+
+/// Gain variations beyond this many dB either way are refused.
+pub const MAX_GAIN_VARIATION_DB: f32 = 24.0;
+
+/// Check the ranges of a random play's `variation`.
+pub fn validate(variation: &Variation) -> Result<(), String> {
+    if !(0.0..=MAX_PITCH_SEMITONES).contains(&variation.pitch_semitones.abs()) {
+        return Err(format!(
+            "Pitch variation must be between 0 and {MAX_PITCH_SEMITONES} semitones"
+        ));
+    }
+    if !(0.0..=MAX_GAIN_VARIATION_DB).contains(&variation.gain_db.abs()) {
+        return Err(format!(
+            "Gain variation must be between 0 and {MAX_GAIN_VARIATION_DB} dB"
+        ));
+    }
+    Ok(())
+}
+
+/// Small xorshift64* generator.  Picking sounds doesn't need anything
+/// stronger, and it keeps the engine free of an RNG dependency.
+pub struct Rng(u64);
+
+impl Rng {
+    /// Seeded from the system clock.
+    pub fn new() -> Self {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        // The state must never be zero.
+        Self(nanos | 1)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut x = self.0;
+        x ^= x >> 12;
+        x ^= x << 25;
+        x ^= x >> 27;
+        self.0 = x;
+        x.wrapping_mul(0x2545_F491_4F6C_DD1D)
+    }
+
+    /// Uniform in `0.0 .. 1.0`.
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Uniform in `-range .. range`.
+    pub fn spread(&mut self, range: f32) -> f32 {
+        (self.next_f32() * 2.0 - 1.0) * range
+    }
+
+    /// Index into `weights`, picked in proportion to the weights.  Falls
+    /// back to a uniform pick if they are all zero.
+    fn weighted(&mut self, weights: impl Iterator<Item = f32> + Clone) -> usize {
+        let total: f32 = weights.clone().map(|w| w.max(0.0)).sum();
+        let count = weights.clone().count();
+        if total <= 0.0 {
+            return ((self.next_f32() * count as f32) as usize).min(count.saturating_sub(1));
+        }
+        let mut target = self.next_f32() * total;
+        for (i, w) in weights.enumerate() {
+            let w = w.max(0.0);
+            if target < w {
+                return i;
+            }
+            target -= w;
+        }
+        count.saturating_sub(1)
+    }
+}
+
+/// Picking state for one named group of sounds, kept between plays.
+pub struct SoundGroup {
+    /// The file list the state was built for; a different list resets it.
+    files: Vec<String>,
+    mode: RandomMode,
+    /// Shuffle: indices still to play in this round.
+    bag: Vec<usize>,
+    /// Round-robin: the next index.
+    cursor: usize,
+    /// The index picked last, so a new shuffle round doesn't repeat it.
+    last: Option<usize>,
+}
+
+impl SoundGroup {
+    pub fn new(files: &[WeightedFile], mode: RandomMode) -> Self {
+        Self {
+            files: files.iter().map(|f| f.file_path.clone()).collect(),
+            mode,
+            bag: Vec::new(),
+            cursor: 0,
+            last: None,
+        }
+    }
+
+    /// Whether this state was built for `files` and `mode`.
+    pub fn matches(&self, files: &[WeightedFile], mode: RandomMode) -> bool {
+        self.mode == mode
+            && self.files.len() == files.len()
+            && self.files.iter().zip(files).all(|(a, b)| *a == b.file_path)
+    }
+
+    /// Pick the index of the next file to play.  `files` must be the list
+    /// the group was built for and not empty.
+    ///
+    /// * `Random` picks independently each time, by weight.
+    /// * `Shuffle` plays every file once per round, drawn by weight, and
+    ///   never starts a round with the file that ended the last one.
+    /// * `RoundRobin` cycles through the list in order; weights are ignored.
+    pub fn pick(&mut self, files: &[WeightedFile], rng: &mut Rng) -> usize {
+        let index = match self.mode {
+            RandomMode::Random => rng.weighted(files.iter().map(|f| f.weight)),
+            RandomMode::Shuffle => {
+                if self.bag.is_empty() {
+                    self.bag = (0..files.len()).collect();
+                }
+                // Don't start a round with the file that ended the last one.
+                let fresh = self.bag.len() == files.len() && files.len() > 1;
+                let candidates: Vec<usize> = (0..self.bag.len())
+                    .filter(|&slot| !(fresh && Some(self.bag[slot]) == self.last))
+                    .collect();
+                let pick =
+                    rng.weighted(candidates.iter().map(|&slot| files[self.bag[slot]].weight));
+                self.bag.swap_remove(candidates[pick])
+            }
+            RandomMode::RoundRobin => {
+                let index = self.cursor % files.len();
+                self.cursor = index + 1;
+                index
+            }
+        };
+        self.last = Some(index);
+        index
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::playback::PlayParams;
+    use crate::test_corpus as corpus;
+
+    fn files(weights: &[f32]) -> Vec<WeightedFile> {
+        weights
+            .iter()
+            .enumerate()
+            .map(|(i, &weight)| WeightedFile {
+                file_path: format!("{i}.wav"),
+                weight,
+            })
+            .collect()
+    }
+
+    fn picks(mode: RandomMode, files: &[WeightedFile], count: usize) -> Vec<usize> {
+        let mut group = SoundGroup::new(files, mode);
+        let mut rng = Rng(0x1234_5678);
+        (0..count).map(|_| group.pick(files, &mut rng)).collect()
+    }
+
+    #[test]
+    fn round_robin_cycles_in_order() {
+        let files = files(&[1.0, 5.0, 0.0]);
+        assert_eq!(picks(RandomMode::RoundRobin, &files, 7), [0, 1, 2, 0, 1, 2, 0]);
+    }
+
+    #[test]
+    fn shuffle_plays_each_once_per_round_without_repeats() {
+        let files = files(&[1.0, 1.0, 1.0, 4.0]);
+        let picks = picks(RandomMode::Shuffle, &files, 400);
+        for round in picks.chunks(4) {
+            let mut sorted = round.to_vec();
+            sorted.sort();
+            assert_eq!(sorted, [0, 1, 2, 3]);
+        }
+        assert!(picks.windows(2).all(|w| w[0] != w[1]));
+    }
+
+    #[test]
+    fn random_follows_the_weights() {
+        let files = files(&[1.0, 3.0, 0.0]);
+        let picks = picks(RandomMode::Random, &files, 4000);
+        let count = |i| picks.iter().filter(|&&p| p == i).count();
+        assert_eq!(count(2), 0);
+        assert!((2800..3200).contains(&count(1)), "{}", count(1));
+    }
+
+    #[test]
+    fn the_engine_keeps_each_group_between_plays() {
+        let signal = corpus::float_signal(480, 2);
+        let files: Vec<WeightedFile> = (0..3)
+            .map(|i| WeightedFile {
+                file_path: corpus::wav_float(&format!("variant-{i}.wav"), 48000, 2, &signal)
+                    .to_string_lossy()
+                    .into_owned(),
+                weight: 1.0,
+            })
+            .collect();
+        let mut mixer = corpus::null_mixer(None, &corpus::path("variants-out.wav"));
+        mixer.start_output().unwrap();
+        let mut play = |group| {
+            let variation = Variation::default();
+            let file = mixer
+                .play_random(group, &files, RandomMode::RoundRobin, "sfx", 1.0, variation)
+                .unwrap();
+            files.iter().position(|f| f.file_path == file).unwrap()
+        };
+        assert_eq!([play("a"), play("a"), play("b"), play("a"), play("a")], [0, 1, 0, 2, 0]);
+    }
+
+    #[test]
+    fn variation_ranges_are_checked() {
+        let variation = |pitch_semitones, gain_db| Variation {
+            pitch_semitones,
+            gain_db,
+        };
+        assert!(validate(&variation(-24.0, 24.0)).is_ok());
+        assert!(validate(&variation(200.0, 0.0)).is_err());
+        assert!(validate(&variation(0.0, -30.0)).is_err());
+        assert!(validate(&variation(f32::NAN, 0.0)).is_err());
+
+        let path = corpus::wav_float("unchecked.wav", 48000, 2, &[0.0; 96]);
+        let files = [WeightedFile {
+            file_path: path.to_string_lossy().into_owned(),
+            weight: 1.0,
+        }];
+        let mut mixer = corpus::null_mixer(None, &corpus::path("unchecked-out.wav"));
+        let too_far = variation(200.0, 0.0);
+        assert!(mixer
+            .play_random("g", &files, RandomMode::Random, "sfx", 1.0, too_far)
+            .is_err());
+    }
+
+    #[test]
+    fn pitched_variants_play_from_the_cache_and_gain_can_boost() {
+        let signal: Vec<f32> = corpus::float_signal(4800, 2).iter().map(|s| s * 0.8).collect();
+        let path = corpus::wav_float("pitched-variant.wav", 48000, 2, &signal);
+        let output = corpus::path("pitched-variant-out.wav");
+        let mut mixer = corpus::null_mixer(None, &output);
+        mixer.start_output().unwrap();
+        mixer.preload(path.to_str().unwrap()).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(10);
+        while mixer.sample_cache_status().preloaded.is_empty() {
+            assert!(Instant::now() < deadline, "preload never finished");
+            thread::sleep(Duration::from_millis(5));
+        }
+        // Spoil the file behind the cache's back: only the cached samples
+        // can be played now.
+        let modified = fs::metadata(&path).and_then(|m| m.modified()).unwrap();
+        fs::write(&path, [0u8; 64]).unwrap();
+        fs::File::options()
+            .write(true)
+            .open(&path)
+            .and_then(|f| f.set_modified(modified))
+            .unwrap();
+        let files = [WeightedFile {
+            file_path: path.to_string_lossy().into_owned(),
+            weight: 1.0,
+        }];
+        let variation = Variation {
+            pitch_semitones: 12.0,
+            gain_db: 0.0,
+        };
+        mixer
+            .play_random("g", &files, RandomMode::Random, "sfx", 1.0, variation)
+            .unwrap();
+        // Long enough for the variant even an octave down.
+        mixer.advance_clock(250.0).unwrap();
+
+        // +6 dB at full volume isn't capped at unity.
+        let boosted = PlayParams {
+            gain_db: 6.0,
+            ..corpus::play_params()
+        };
+        mixer.play_file(files[0].file_path.as_str(), "music", boosted).unwrap();
+        mixer.advance_clock(50.0).unwrap();
+        drop(mixer);
+
+        let out = corpus::decode(&output).2;
+        let (pitched, boosted) = out.split_at(24000);
+        assert!(pitched.iter().any(|s| s.abs() > 0.1), "the pitched variant is silent");
+        let gain = 10f32.powf(6.0 / 20.0);
+        for (i, (&got, &want)) in boosted.iter().zip(&signal).enumerate() {
+            let want = want * gain;
+            assert!((got - want).abs() < 1e-4, "sample {i}: got {got}, want {want}");
+        }
+    }
+
+    #[test]
+    fn a_new_list_or_mode_starts_over() {
+        let files = files(&[1.0, 1.0]);
+        let group = SoundGroup::new(&files, RandomMode::Shuffle);
+        assert!(group.matches(&files, RandomMode::Shuffle));
+        assert!(!group.matches(&files, RandomMode::RoundRobin));
+        assert!(!group.matches(&files[..1], RandomMode::Shuffle));
+    }
+}
@@ -0,0 +1,870 @@
+//! Writers for the decoder test corpus.
+//!
+//! Lossless formats (WAV, FLAC, ALAC) are written from a known signal, so a
+//! test can check every decoded sample against it.  Opus is encoded with
+//! libopus, which the engine links anyway.  There are no encoders for the
+//! other lossy formats here, so AAC, MP3 and Vorbis files are made of
+//! silent frames: enough to exercise the demuxer and decoder and check the
+//! format and length, but not the codec's output.
+//!
+//! It also holds the few helpers the engine tests share: decoding a file
+//! back and running a mixer on the null backend.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::decode::AudioDecoder;
+use crate::devices::Host;
+use crate::mixer::MixerState;
+use crate::null_audio::{NullConfig, NullHost, MONITOR_DEVICE};
+use crate::playback::PlayParams;
+
+/// A file path for `name` in the corpus directory.
+pub fn path(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("ragepad-corpus-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    dir.join(name)
+}
+
+/// Interleaved integer test signal at `bits` bits: a sine at half scale
+/// with a ramp in the lowest bits, so truncating to a lower bit depth
+/// shows.
+pub fn signal(frames: usize, channels: usize, bits: u32) -> Vec<i32> {
+    let half = (1i64 << (bits - 1)) as f64 / 2.0;
+    (0..frames * channels)
+        .map(|i| {
+            let (frame, ch) = (i / channels, i % channels);
+            let phase = frame as f64 * 0.05 + ch as f64;
+            phase.sin().mul_add(half, (i % 255) as f64) as i32
+        })
+        .collect()
+}
+
+/// The float samples a decoder should produce for `signal`.
+pub fn expected(signal: &[i32], bits: u32) -> Vec<f32> {
+    let scale = (1i64 << (bits - 1)) as f32;
+    signal.iter().map(|&s| s as f32 / scale).collect()
+}
+
+/// Interleaved float test signal, with detail below the 16-bit step.
+pub fn float_signal(frames: usize, channels: usize) -> Vec<f32> {
+    (0..frames * channels)
+        .map(|i| ((i / channels) as f32 * 0.05).sin() * 0.5 + i as f32 * 1e-9)
+        .collect()
+}
+
+/// Mono white noise in `-amplitude .. amplitude`, the same on every run.
+pub fn noise(len: usize, amplitude: f32) -> Vec<f32> {
+    let mut seed = 1u32;
+    (0..len)
+        .map(|_| {
+            seed = seed.wrapping_mul(1_664_525).wrapping_add(1_013_904_223);
+            ((seed >> 8) as f32 / (1 << 24) as f32 * 2.0 - 1.0) * amplitude
+        })
+        .collect()
+}
+
+/// Mono sine of `freq` Hz at `rate`.
+pub fn tone(len: usize, rate: u32, freq: f32, amplitude: f32) -> Vec<f32> {
+    let step = std::f32::consts::TAU * freq / rate as f32;
+    (0..len).map(|i| (i as f32 * step).sin() * amplitude).collect()
+}
+
+/// Mean square of `samples`.
+pub fn power(samples: &[f32]) -> f32 {
+    samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32
+}
+
+// ---------------------------------------------------------------------------
+// Engine helpers
+// ---------------------------------------------------------------------------
+
+/// Decodes all of `path`: its sample rate, channel count and samples.
+pub fn decode(path: &Path) -> (u32, u16, Vec<f32>) {
+    let mut decoder = AudioDecoder::open(path.to_str().unwrap()).unwrap();
+    let samples = decoder.read_all(usize::MAX);
+    (decoder.sample_rate(), decoder.channels(), samples)
+}
+
+/// A mixer on the manual-clock null backend, reading the mic from `input`
+/// and writing the main output to `output`.  No streams are started.
+pub fn null_mixer(input: Option<&Path>, output: &Path) -> MixerState {
+    let config = NullConfig {
+        input: input.map(Path::to_path_buf),
+        output: Some(output.to_path_buf()),
+        ..Default::default()
+    };
+    MixerState::new(Host::Null(NullHost::new(config).unwrap()))
+}
+
+/// `null_mixer` with no mic and a monitor device too, writing to
+/// `monitor`.  No streams are started.
+pub fn monitored_mixer(output: &Path, monitor: &Path) -> MixerState {
+    let config = NullConfig {
+        output: Some(output.to_path_buf()),
+        monitor: Some(monitor.to_path_buf()),
+        ..Default::default()
+    };
+    let mut mixer = MixerState::new(Host::Null(NullHost::new(config).unwrap()));
+    mixer.monitor_device_name = Some(MONITOR_DEVICE.to_string());
+    mixer
+}
+
+/// Play straight away at unity gain, centred and dry.
+pub fn play_params() -> PlayParams {
+    PlayParams {
+        volume: 1.0,
+        pan: 0.0,
+        reverb: None,
+        gain_db: 0.0,
+        pitch_semitones: 0.0,
+        start_ms: None,
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Bit writers
+// ---------------------------------------------------------------------------
+
+/// Packs bits most significant first (FLAC, ALAC, AAC).
+#[derive(Default)]
+struct BitsMsb {
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl BitsMsb {
+    fn put(&mut self, bits: u32, value: u64) {
+        for i in (0..bits).rev() {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << (7 - self.used);
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+/// Packs bits least significant first (Vorbis).
+#[derive(Default)]
+struct BitsLsb {
+    bytes: Vec<u8>,
+    used: u32,
+}
+
+impl BitsLsb {
+    fn put(&mut self, bits: u32, value: u64) {
+        for i in 0..bits {
+            if self.used == 0 {
+                self.bytes.push(0);
+            }
+            let bit = ((value >> i) & 1) as u8;
+            *self.bytes.last_mut().unwrap() |= bit << self.used;
+            self.used = (self.used + 1) % 8;
+        }
+    }
+
+    fn finish(self) -> Vec<u8> {
+        self.bytes
+    }
+}
+
+// ---------------------------------------------------------------------------
+// WAV
+// ---------------------------------------------------------------------------
+
+fn wav(name: &str, format: u16, rate: u32, channels: u16, bits: u16, data: &[u8]) -> PathBuf {
+    riff(name, &fmt_chunk(format, rate, channels, bits), data)
+}
+
+/// The 16 bytes every WAV format chunk starts with.
+fn fmt_chunk(format: u16, rate: u32, channels: u16, bits: u16) -> Vec<u8> {
+    let block = channels as u32 * bits as u32 / 8;
+    let mut fmt = Vec::new();
+    fmt.extend_from_slice(&format.to_le_bytes());
+    fmt.extend_from_slice(&channels.to_le_bytes());
+    fmt.extend_from_slice(&rate.to_le_bytes());
+    fmt.extend_from_slice(&(rate * block).to_le_bytes());
+    fmt.extend_from_slice(&(block as u16).to_le_bytes());
+    fmt.extend_from_slice(&bits.to_le_bytes());
+    fmt
+}
+
+fn riff(name: &str, fmt: &[u8], data: &[u8]) -> PathBuf {
+    let mut out = Vec::new();
+    out.extend_from_slice(b"RIFF");
+    out.extend_from_slice(&(20 + fmt.len() as u32 + data.len() as u32).to_le_bytes());
+    out.extend_from_slice(b"WAVEfmt ");
+    out.extend_from_slice(&(fmt.len() as u32).to_le_bytes());
+    out.extend_from_slice(fmt);
+    out.extend_from_slice(b"data");
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+    let path = path(name);
+    fs::write(&path, out).unwrap();
+    path
+}
+
+/// Integer PCM WAV at 16, 24 or 32 bits.
+pub fn wav_int(name: &str, rate: u32, channels: u16, bits: u16, signal: &[i32]) -> PathBuf {
+    let bytes = bits as usize / 8;
+    let data: Vec<u8> = signal
+        .iter()
+        .flat_map(|s| s.to_le_bytes()[..bytes].to_vec())
+        .collect();
+    wav(name, 1, rate, channels, bits, &data)
+}
+
+/// 32-bit float WAV.
+pub fn wav_float(name: &str, rate: u32, channels: u16, signal: &[f32]) -> PathBuf {
+    let data: Vec<u8> = signal.iter().flat_map(|s| s.to_le_bytes()).collect();
+    wav(name, 3, rate, channels, 32, &data)
+}
+
+/// 32-bit float `WAVE_FORMAT_EXTENSIBLE` WAV with a channel `mask`.
+pub fn wav_float_with_mask(
+    name: &str,
+    rate: u32,
+    channels: u16,
+    mask: u32,
+    signal: &[f32],
+) -> PathBuf {
+    let data: Vec<u8> = signal.iter().flat_map(|s| s.to_le_bytes()).collect();
+    let mut fmt = fmt_chunk(0xFFFE, rate, channels, 32);
+    fmt.extend_from_slice(&22u16.to_le_bytes());
+    fmt.extend_from_slice(&32u16.to_le_bytes());
+    fmt.extend_from_slice(&mask.to_le_bytes());
+    // KSDATAFORMAT_SUBTYPE_IEEE_FLOAT
+    fmt.extend_from_slice(&[
+        0x03, 0, 0, 0, 0, 0, 0x10, 0, 0x80, 0, 0, 0xAA, 0, 0x38, 0x9B, 0x71,
+    ]);
+    riff(name, &fmt, &data)
+}
+
+// ---------------------------------------------------------------------------
+// FLAC (verbatim subframes)
+// ---------------------------------------------------------------------------
+
+fn crc8(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |mut crc, &b| {
+        crc ^= b;
+        for _ in 0..8 {
+            crc = if crc & 0x80 != 0 {
+                (crc << 1) ^ 0x07
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn crc16(data: &[u8]) -> u16 {
+    data.iter().fold(0u16, |mut crc, &b| {
+        crc ^= (b as u16) << 8;
+        for _ in 0..8 {
+            crc = if crc & 0x8000 != 0 {
+                (crc << 1) ^ 0x8005
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+/// FLAC at 16 or 24 bits, stored uncompressed.
+pub fn flac(name: &str, rate: u32, channels: u16, bits: u16, signal: &[i32]) -> PathBuf {
+    const BLOCK: usize = 4096;
+    let ch = channels as usize;
+    let frames = signal.len() / ch;
+
+    let mut info = BitsMsb::default();
+    info.put(16, BLOCK as u64);
+    info.put(16, BLOCK as u64);
+    info.put(24, 0);
+    info.put(24, 0);
+    info.put(20, rate as u64);
+    info.put(3, channels as u64 - 1);
+    info.put(5, bits as u64 - 1);
+    info.put(36, frames as u64);
+    info.put(64, 0);
+    info.put(64, 0);
+    let info = info.finish();
+
+    let mut out = b"fLaC".to_vec();
+    out.push(0x80);
+    out.extend_from_slice(&(info.len() as u32).to_be_bytes()[1..]);
+    out.extend_from_slice(&info);
+
+    let size_code = if bits == 24 { 0b110 } else { 0b100 };
+    for (index, block) in signal.chunks(BLOCK * ch).enumerate() {
+        let mut header = BitsMsb::default();
+        header.put(16, 0xFFF8);
+        // Block size as a 16-bit value after the header; rate from
+        // STREAMINFO.
+        header.put(4, 0b0111);
+        header.put(4, 0);
+        header.put(4, channels as u64 - 1);
+        header.put(3, size_code);
+        header.put(1, 0);
+        // Frame number, UTF-8 coded.
+        if index < 0x80 {
+            header.put(8, index as u64);
+        } else {
+            header.put(8, 0xC0 | (index as u64 >> 6));
+            header.put(8, 0x80 | (index as u64 & 0x3F));
+        }
+        header.put(16, (block.len() / ch) as u64 - 1);
+        let mut frame = header.finish();
+        frame.push(crc8(&frame));
+
+        let mut body = BitsMsb::default();
+        for c in 0..ch {
+            // Verbatim subframe.
+            body.put(8, 0b0000_0010);
+            for s in block.iter().skip(c).step_by(ch) {
+                body.put(bits as u32, *s as u64 & ((1 << bits) - 1));
+            }
+        }
+        frame.extend(body.finish());
+        let crc = crc16(&frame);
+        frame.extend_from_slice(&crc.to_be_bytes());
+        out.extend(frame);
+    }
+    let path = path(name);
+    fs::write(&path, out).unwrap();
+    path
+}
+
+// ---------------------------------------------------------------------------
+// MP4 / M4A
+// ---------------------------------------------------------------------------
+
+fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+    let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+    out.extend_from_slice(kind);
+    out.extend_from_slice(body);
+    out
+}
+
+/// An atom with a version and flags.
+fn full_atom(kind: &[u8; 4], flags: u32, body: &[u8]) -> Vec<u8> {
+    let mut full = flags.to_be_bytes().to_vec();
+    full.extend_from_slice(body);
+    atom(kind, &full)
+}
+
+fn be32(values: &[u32]) -> Vec<u8> {
+    values.iter().flat_map(|v| v.to_be_bytes()).collect()
+}
+
+const MATRIX: [u32; 9] = [0x10000, 0, 0, 0, 0x10000, 0, 0, 0, 0x4000_0000];
+
+/// An M4A with one audio track whose sample entry is `entry` (type and
+/// codec-specific child atom), holding `packets` of `packet_frames`
+/// frames each (the last may be shorter, `last_frames`).
+fn m4a(
+    name: &str,
+    rate: u32,
+    channels: u16,
+    entry: (&[u8; 4], Vec<u8>),
+    packets: &[Vec<u8>],
+    packet_frames: u32,
+    last_frames: u32,
+) -> PathBuf {
+    let count = packets.len() as u32;
+    let duration = packet_frames * (count - 1) + last_frames;
+
+    let mut sample_entry = vec![0u8; 6];
+    sample_entry.extend_from_slice(&1u16.to_be_bytes());
+    sample_entry.extend_from_slice(&[0; 8]);
+    sample_entry.extend_from_slice(&channels.to_be_bytes());
+    sample_entry.extend_from_slice(&16u16.to_be_bytes());
+    sample_entry.extend_from_slice(&[0; 4]);
+    sample_entry.extend_from_slice(&(rate << 16).to_be_bytes());
+    sample_entry.extend(entry.1);
+    let stsd = full_atom(
+        b"stsd",
+        0,
+        &[be32(&[1]), atom(entry.0, &sample_entry)].concat(),
+    );
+
+    let mut stts = vec![count - 1, packet_frames];
+    stts.extend([1, last_frames]);
+    let stts = full_atom(b"stts", 0, &be32(&[&[2], &stts[..]].concat()));
+    let stsc = full_atom(b"stsc", 0, &be32(&[1, 1, count, 1]));
+    let sizes: Vec<u32> = packets.iter().map(|p| p.len() as u32).collect();
+    let stsz = full_atom(b"stsz", 0, &be32(&[&[0, count], &sizes[..]].concat()));
+
+    let ftyp = atom(b"ftyp", b"M4A \0\0\0\0M4A mp42isom");
+    // The chunk offset depends on the size of the moov atom, which doesn't
+    // depend on the offset's value: build it twice.
+    let moov = |offset: u32| {
+        let stco = full_atom(b"stco", 0, &be32(&[1, offset]));
+        let stbl = atom(
+            b"stbl",
+            &[stsd.clone(), stts.clone(), stsc.clone(), stsz.clone(), stco].concat(),
+        );
+        let dref = full_atom(
+            b"dref",
+            0,
+            &[be32(&[1]), full_atom(b"url ", 1, &[])].concat(),
+        );
+        let minf = atom(
+            b"minf",
+            &[full_atom(b"smhd", 0, &[0; 4]), atom(b"dinf", &dref), stbl].concat(),
+        );
+        let mut mdhd = be32(&[0, 0, rate, duration]);
+        mdhd.extend_from_slice(&[0x55, 0xC4, 0, 0]);
+        let mut hdlr = be32(&[0]);
+        hdlr.extend_from_slice(b"soun");
+        hdlr.extend_from_slice(&[0; 13]);
+        let mdia = atom(
+            b"mdia",
+            &[
+                full_atom(b"mdhd", 0, &mdhd),
+                full_atom(b"hdlr", 0, &hdlr),
+                minf,
+            ]
+            .concat(),
+        );
+        let mut tkhd = be32(&[0, 0, 1, 0, duration, 0, 0]);
+        tkhd.extend_from_slice(&[0, 0, 0, 0, 1, 0, 0, 0]);
+        tkhd.extend(be32(&MATRIX));
+        tkhd.extend(be32(&[0, 0]));
+        let trak = atom(b"trak", &[full_atom(b"tkhd", 7, &tkhd), mdia].concat());
+        let mut mvhd = be32(&[0, 0, rate, duration, 0x10000]);
+        mvhd.extend_from_slice(&[1, 0]);
+        mvhd.extend_from_slice(&[0; 10]);
+        mvhd.extend(be32(&MATRIX));
+        mvhd.extend_from_slice(&[0; 24]);
+        mvhd.extend(be32(&[2]));
+        atom(b"moov", &[full_atom(b"mvhd", 0, &mvhd), trak].concat())
+    };
+    let offset = (ftyp.len() + moov(0).len() + 8) as u32;
+    let mdat = atom(b"mdat", &packets.concat());
+
+    let path = path(name);
+    fs::write(&path, [ftyp, moov(offset), mdat].concat()).unwrap();
+    path
+}
+
+/// ALAC in M4A at 16 or 24 bits, mono or stereo, stored uncompressed.
+pub fn alac(name: &str, rate: u32, channels: u16, bits: u16, signal: &[i32]) -> PathBuf {
+    const FRAMES: usize = 4096;
+    let ch = channels as usize;
+    let packets: Vec<Vec<u8>> = signal
+        .chunks(FRAMES * ch)
+        .map(|block| {
+            let frames = block.len() / ch;
+            let mut bits_out = BitsMsb::default();
+            // A single or pair channel element.
+            bits_out.put(3, if ch == 2 { 1 } else { 0 });
+            bits_out.put(4, 0);
+            bits_out.put(12, 0);
+            // Partial frame (the length follows), no shift, uncompressed.
+            let partial = frames != FRAMES;
+            bits_out.put(1, partial as u64);
+            bits_out.put(2, 0);
+            bits_out.put(1, 1);
+            if partial {
+                bits_out.put(32, frames as u64);
+            }
+            for s in block {
+                bits_out.put(bits as u32, *s as u64 & ((1 << bits) - 1));
+            }
+            bits_out.put(3, 7);
+            bits_out.finish()
+        })
+        .collect();
+
+    let mut cookie = (FRAMES as u32).to_be_bytes().to_vec();
+    cookie.extend_from_slice(&[0, bits as u8, 40, 10, 14, channels as u8]);
+    cookie.extend_from_slice(&255u16.to_be_bytes());
+    cookie.extend(be32(&[0, 0, rate]));
+    let last = (signal.len() / ch - FRAMES * (packets.len() - 1)) as u32;
+    m4a(
+        name,
+        rate,
+        channels,
+        (b"alac", full_atom(b"alac", 0, &cookie)),
+        &packets,
+        FRAMES as u32,
+        last,
+    )
+}
+
+/// AAC-LC in M4A, mono: `packets` silent frames of 1024 samples.
+pub fn aac_silence(name: &str, rate: u32, packets: usize) -> PathBuf {
+    let rate_index = match rate {
+        48000 => 3,
+        _ => 4,
+    };
+    let mut config = BitsMsb::default();
+    config.put(5, 2);
+    config.put(4, rate_index);
+    config.put(4, 1);
+    config.put(3, 0);
+    let config = config.finish();
+
+    let mut frame = BitsMsb::default();
+    // A single channel element with no scale factor bands, then the end.
+    frame.put(3, 0);
+    frame.put(4, 0);
+    frame.put(8, 100);
+    frame.put(1, 0);
+    frame.put(2, 0);
+    frame.put(1, 0);
+    frame.put(6, 0);
+    frame.put(1, 0);
+    frame.put(3, 0);
+    frame.put(3, 7);
+    let frame = frame.finish();
+
+    let mut descriptor = vec![0x40, 0x15, 0, 0, 0];
+    descriptor.extend(be32(&[128_000, 128_000]));
+    descriptor.extend_from_slice(&[0x05, config.len() as u8]);
+    descriptor.extend_from_slice(&config);
+    let mut es = vec![0, 0, 0];
+    es.extend_from_slice(&[0x04, descriptor.len() as u8]);
+    es.extend(descriptor);
+    es.extend_from_slice(&[0x06, 1, 0x02]);
+    let mut esds = vec![0x03, es.len() as u8];
+    esds.extend(es);
+
+    m4a(
+        name,
+        rate,
+        1,
+        (b"mp4a", full_atom(b"esds", 0, &esds)),
+        &vec![frame; packets],
+        1024,
+        1024,
+    )
+}
+
+// ---------------------------------------------------------------------------
+// MP3
+// ---------------------------------------------------------------------------
+
+/// MPEG-1 layer III at 44.1 kHz, mono, 128 kbit/s: `frames` silent frames
+/// of 1152 samples.
+pub fn mp3_silence(name: &str, frames: usize) -> PathBuf {
+    let mut frame = vec![0u8; 417];
+    frame[..4].copy_from_slice(&[0xFF, 0xFB, 0x90, 0xC4]);
+    let path = path(name);
+    fs::write(&path, frame.repeat(frames)).unwrap();
+    path
+}
+
+// ---------------------------------------------------------------------------
+// Ogg Vorbis
+// ---------------------------------------------------------------------------
+
+fn ogg_crc(data: &[u8]) -> u32 {
+    data.iter().fold(0u32, |mut crc, &b| {
+        crc ^= (b as u32) << 24;
+        for _ in 0..8 {
+            crc = if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            };
+        }
+        crc
+    })
+}
+
+fn ogg_page(flags: u8, granule: u64, sequence: u32, packets: &[Vec<u8>]) -> Vec<u8> {
+    let mut lacing = Vec::new();
+    for packet in packets {
+        lacing.extend(std::iter::repeat_n(255u8, packet.len() / 255));
+        lacing.push((packet.len() % 255) as u8);
+    }
+    let mut page = b"OggS".to_vec();
+    page.push(0);
+    page.push(flags);
+    page.extend_from_slice(&granule.to_le_bytes());
+    page.extend_from_slice(&1u32.to_le_bytes());
+    page.extend_from_slice(&sequence.to_le_bytes());
+    page.extend_from_slice(&[0; 4]);
+    page.push(lacing.len() as u8);
+    page.extend(lacing);
+    page.extend(packets.concat());
+    let crc = ogg_crc(&page);
+    page[22..26].copy_from_slice(&crc.to_le_bytes());
+    page
+}
+
+/// Vorbis in Ogg: `packets` silent short blocks (256 samples, so 128 new
+/// frames each after the first).
+pub fn vorbis_silence(name: &str, rate: u32, channels: u8, packets: usize) -> PathBuf {
+    let mut ident = vec![1];
+    ident.extend_from_slice(b"vorbis");
+    ident.extend_from_slice(&0u32.to_le_bytes());
+    ident.push(channels);
+    ident.extend_from_slice(&rate.to_le_bytes());
+    ident.extend_from_slice(&[0; 12]);
+    // Block sizes 256 and 2048.
+    ident.push(0xB8);
+    ident.push(1);
+
+    let mut comment = vec![3];
+    comment.extend_from_slice(b"vorbis");
+    comment.extend_from_slice(&4u32.to_le_bytes());
+    comment.extend_from_slice(b"test");
+    comment.extend_from_slice(&0u32.to_le_bytes());
+    comment.push(1);
+
+    // The smallest valid setup: one codebook, floor, residue, mapping and
+    // mode, none of which an all-silent packet uses.
+    let mut setup = BitsLsb::default();
+    setup.put(8, 0);
+    setup.put(24, 0x564342);
+    setup.put(16, 1);
+    setup.put(24, 2);
+    setup.put(1, 0);
+    setup.put(1, 0);
+    setup.put(5, 0);
+    setup.put(5, 0);
+    setup.put(4, 0);
+    setup.put(6, 0);
+    setup.put(16, 0);
+    setup.put(6, 0);
+    setup.put(16, 1);
+    setup.put(5, 0);
+    setup.put(2, 0);
+    setup.put(4, 8);
+    setup.put(6, 0);
+    setup.put(16, 0);
+    setup.put(24, 0);
+    setup.put(24, 0);
+    setup.put(24, 0);
+    setup.put(6, 0);
+    setup.put(8, 0);
+    setup.put(3, 0);
+    setup.put(1, 0);
+    setup.put(6, 0);
+    setup.put(16, 0);
+    setup.put(1, 0);
+    setup.put(1, 0);
+    setup.put(2, 0);
+    setup.put(8, 0);
+    setup.put(8, 0);
+    setup.put(8, 0);
+    setup.put(6, 0);
+    setup.put(1, 0);
+    setup.put(16, 0);
+    setup.put(16, 0);
+    setup.put(8, 0);
+    setup.put(1, 1);
+    let mut setup_packet = vec![5];
+    setup_packet.extend_from_slice(b"vorbis");
+    setup_packet.extend(setup.finish());
+
+    let mut out = ogg_page(0x02, 0, 0, &[ident]);
+    out.extend(ogg_page(0, 0, 1, &[comment, setup_packet]));
+    // An audio packet: audio type bit, no mode bits, floor unused.
+    let audio = vec![0u8];
+    let mut done = 0;
+    let mut sequence = 2;
+    while done < packets {
+        let count = (packets - done).min(50);
+        done += count;
+        let granule = (done as u64 - 1) * 128;
+        let flags = if done == packets { 0x04 } else { 0 };
+        out.extend(ogg_page(
+            flags,
+            granule,
+            sequence,
+            &vec![audio.clone(); count],
+        ));
+        sequence += 1;
+    }
+    let path = path(name);
+    fs::write(&path, out).unwrap();
+    path
+}
+
+// ---------------------------------------------------------------------------
+// Opus (Ogg and WebM)
+// ---------------------------------------------------------------------------
+
+/// 20 ms at 48 kHz.
+const OPUS_FRAME: usize = 960;
+
+/// Encode interleaved 48 kHz `signal` into 20 ms Opus packets, padding the
+/// last one with silence.  Returns the packets and the encoder's pre-skip.
+fn opus_packets(signal: &[f32], channels: usize) -> (Vec<Vec<u8>>, u16) {
+    use audiopus_sys::*;
+
+    let mut error = 0;
+    let encoder =
+        unsafe { opus_encoder_create(48000, channels as i32, OPUS_APPLICATION_AUDIO, &mut error) };
+    assert!(!encoder.is_null() && error == 0);
+    let mut lookahead = 0i32;
+    unsafe {
+        opus_encoder_ctl(
+            encoder,
+            OPUS_GET_LOOKAHEAD_REQUEST,
+            &mut lookahead as *mut i32,
+        )
+    };
+
+    let packets = signal
+        .chunks(OPUS_FRAME * channels)
+        .map(|chunk| {
+            let mut frame = chunk.to_vec();
+            frame.resize(OPUS_FRAME * channels, 0.0);
+            let mut packet = vec![0u8; 4000];
+            let len = unsafe {
+                opus_encode_float(
+                    encoder,
+                    frame.as_ptr(),
+                    OPUS_FRAME as i32,
+                    packet.as_mut_ptr(),
+                    packet.len() as i32,
+                )
+            };
+            assert!(len > 0);
+            packet.truncate(len as usize);
+            packet
+        })
+        .collect();
+    unsafe { opus_encoder_destroy(encoder) };
+    (packets, lookahead as u16)
+}
+
+fn opus_head(channels: u8, pre_skip: u16) -> Vec<u8> {
+    let mut head = b"OpusHead".to_vec();
+    head.push(1);
+    head.push(channels);
+    head.extend_from_slice(&pre_skip.to_le_bytes());
+    head.extend_from_slice(&48000u32.to_le_bytes());
+    head.extend_from_slice(&0i16.to_le_bytes());
+    head.push(0);
+    head
+}
+
+/// Opus in Ogg, with `comments` (`KEY=value`) in its tags.  The last
+/// page's granule position ends the stream at the end of `signal`.
+pub fn ogg_opus(name: &str, channels: u8, signal: &[f32], comments: &[&str]) -> PathBuf {
+    let ch = channels as usize;
+    let (packets, pre_skip) = opus_packets(signal, ch);
+
+    let mut tags = b"OpusTags".to_vec();
+    tags.extend_from_slice(&4u32.to_le_bytes());
+    tags.extend_from_slice(b"test");
+    tags.extend_from_slice(&(comments.len() as u32).to_le_bytes());
+    for comment in comments {
+        tags.extend_from_slice(&(comment.len() as u32).to_le_bytes());
+        tags.extend_from_slice(comment.as_bytes());
+    }
+
+    let mut out = ogg_page(0x02, 0, 0, &[opus_head(channels, pre_skip)]);
+    out.extend(ogg_page(0, 0, 1, &[tags]));
+    let pages: Vec<&[Vec<u8>]> = packets.chunks(25).collect();
+    for (i, page) in pages.iter().enumerate() {
+        let last = i + 1 == pages.len();
+        // Granule positions count decoded frames, pre-skip included.
+        let granule = if last {
+            pre_skip as usize + signal.len() / ch
+        } else {
+            (i + 1) * 25 * OPUS_FRAME
+        };
+        let flags = if last { 0x04 } else { 0 };
+        out.extend(ogg_page(flags, granule as u64, i as u32 + 2, page));
+    }
+    let path = path(name);
+    fs::write(&path, out).unwrap();
+    path
+}
+
+/// An EBML element, with its size coded in eight bytes.
+fn ebml(id: u32, body: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = id
+        .to_be_bytes()
+        .into_iter()
+        .skip_while(|&b| b == 0)
+        .collect();
+    out.push(0x01);
+    out.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+    out.extend_from_slice(body);
+    out
+}
+
+fn ebml_uint(id: u32, value: u64) -> Vec<u8> {
+    ebml(id, &value.to_be_bytes())
+}
+
+/// Opus in WebM, one cluster of simple blocks.  WebM has no end trim, so
+/// the decoded length is whole packets less the pre-skip.
+pub fn webm_opus(name: &str, channels: u8, signal: &[f32]) -> PathBuf {
+    let (packets, pre_skip) = opus_packets(signal, channels as usize);
+
+    let header = [
+        ebml_uint(0x4286, 1),
+        ebml_uint(0x42F7, 1),
+        ebml_uint(0x42F2, 4),
+        ebml_uint(0x42F3, 8),
+        ebml(0x4282, b"webm"),
+        ebml_uint(0x4287, 4),
+        ebml_uint(0x4285, 2),
+    ]
+    .concat();
+
+    let duration_ms = (packets.len() * 20) as f64;
+    let info = [
+        ebml_uint(0x2AD7B1, 1_000_000),
+        ebml(0x4489, &duration_ms.to_be_bytes()),
+        ebml(0x4D80, b"test"),
+        ebml(0x5741, b"test"),
+    ]
+    .concat();
+
+    let audio = [
+        ebml(0xB5, &48000f64.to_be_bytes()),
+        ebml_uint(0x9F, channels as u64),
+    ]
+    .concat();
+    let track = [
+        ebml_uint(0xD7, 1),
+        ebml_uint(0x73C5, 1),
+        ebml_uint(0x83, 2),
+        ebml(0x86, b"A_OPUS"),
+        ebml(0x63A2, &opus_head(channels, pre_skip)),
+        ebml(0xE1, &audio),
+    ]
+    .concat();
+
+    let mut cluster = ebml_uint(0xE7, 0);
+    for (i, packet) in packets.iter().enumerate() {
+        // Track 1, timestamp in milliseconds from the cluster's, keyframe.
+        let mut block = vec![0x81];
+        block.extend_from_slice(&((i * 20) as i16).to_be_bytes());
+        block.push(0x80);
+        block.extend_from_slice(packet);
+        cluster.extend(ebml(0xA3, &block));
+    }
+
+    let segment = [
+        ebml(0x1549A966, &info),
+        ebml(0x1654AE6B, &ebml(0xAE, &track)),
+        ebml(0x1F43B675, &cluster),
+    ]
+    .concat();
+
+    let path = path(name);
+    fs::write(
+        &path,
+        [ebml(0x1A45DFA3, &header), ebml(0x18538067, &segment)].concat(),
+    )
+    .unwrap();
+    path
+}
@@ -0,0 +1,433 @@
+use std::f32::consts::PI;
+use std::sync::Arc;
+
+use realfft::num_complex::Complex;
+use realfft::{ComplexToReal, RealFftPlanner, RealToComplex};
+
+use crate::biquad::{Biquad, BUTTERWORTH_Q};
+use crate::protocol::MicEffect;
+
+// ---------------------------------------------------------------------------
+// Effect dispatcher
+// ---------------------------------------------------------------------------
+
+/// The voice effect currently inserted on the mic path, together with the
+/// settings it was built from (so it can be rebuilt when the device rate
+/// changes, and reported in status).
+pub struct VoiceEffect {
+    config: MicEffect,
+    rate: u32,
+    state: EffectState,
+}
+
+enum EffectState {
+    Pitch(Box<PitchShifter>),
+    Robot(RingModulator),
+    Radio(RadioFilter),
+}
+
+impl VoiceEffect {
+    /// Build the DSP for `config`, or `None` for [`MicEffect::None`].
+    pub fn new(config: MicEffect, rate: u32) -> Option<Self> {
+        let state = match config {
+            MicEffect::None => return None,
+            MicEffect::Pitch {
+                semitones,
+                formant_semitones,
+            } => EffectState::Pitch(Box::new(PitchShifter::new(semitones, formant_semitones))),
+            MicEffect::Robot { frequency_hz, mix } => {
+                EffectState::Robot(RingModulator::new(frequency_hz, mix))
+            }
+            MicEffect::Radio {
+                low_hz,
+                high_hz,
+                drive,
+            } => EffectState::Radio(RadioFilter::new(rate, low_hz, high_hz, drive)),
+        };
+        Some(Self {
+            config,
+            rate,
+            state,
+        })
+    }
+
+    pub fn config(&self) -> MicEffect {
+        self.config
+    }
+
+    /// The device rate the effect was built for.
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    /// Process a block of mono samples at `rate` (the rate it was built
+    /// for) in place.
+    pub fn process(&mut self, samples: &mut [f32], rate: u32) {
+        match &mut self.state {
+            EffectState::Pitch(p) => p.process(samples, rate),
+            EffectState::Robot(r) => r.process(samples, rate),
+            EffectState::Radio(r) => r.process(samples),
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Pitch / formant shifter
+// ---------------------------------------------------------------------------
+
+const PV_FFT_SIZE: usize = 1024;
+const PV_OVERLAP: usize = 4;
+const PV_HOP: usize = PV_FFT_SIZE / PV_OVERLAP;
+const PV_BINS: usize = PV_FFT_SIZE / 2 + 1;
+/// Half-width (in bins) of the moving average used as the spectral
+/// envelope.  About ±230 Hz at 48 kHz: wide enough to smooth over voice
+/// harmonics, narrow enough to keep the formants.
+const PV_ENVELOPE_HALF_WIDTH: usize = 5;
+
+/// Phase-vocoder pitch shifter with an independent formant shift.
+///
+/// Each frame's magnitude spectrum is split into a smoothed envelope (the
+/// formants) and the flattened fine structure (the harmonics).  The fine
+/// structure is moved by the pitch ratio and the envelope by the formant
+/// ratio, so a plain pitch shift keeps the speaker's vocal-tract character
+/// and a formant shift alone changes the apparent size of the speaker.
+/// Latency is `PV_FFT_SIZE - PV_HOP` samples (16 ms at 48 kHz).
+struct PitchShifter {
+    pitch_ratio: f32,
+    formant_ratio: f32,
+    fft: Arc<dyn RealToComplex<f32>>,
+    ifft: Arc<dyn ComplexToReal<f32>>,
+    window: Vec<f32>,
+    in_fifo: Vec<f32>,
+    out_fifo: Vec<f32>,
+    out_accum: Vec<f32>,
+    rover: usize,
+    last_phase: Vec<f32>,
+    sum_phase: Vec<f32>,
+    time_buf: Vec<f32>,
+    spectrum: Vec<Complex<f32>>,
+    scratch_fwd: Vec<Complex<f32>>,
+    scratch_inv: Vec<Complex<f32>>,
+    magnitude: Vec<f32>,
+    frequency: Vec<f32>,
+    envelope: Vec<f32>,
+    synth_magnitude: Vec<f32>,
+    synth_frequency: Vec<f32>,
+}
+
+impl PitchShifter {
+    fn new(semitones: f32, formant_semitones: f32) -> Self {
+        let mut planner = RealFftPlanner::<f32>::new();
+        let fft = planner.plan_fft_forward(PV_FFT_SIZE);
+        let ifft = planner.plan_fft_inverse(PV_FFT_SIZE);
+        let window = (0..PV_FFT_SIZE)
+            .map(|i| 0.5 - 0.5 * (2.0 * PI * i as f32 / PV_FFT_SIZE as f32).cos())
+            .collect();
+
+        Self {
+            pitch_ratio: semitones_to_ratio(semitones.clamp(-24.0, 24.0)),
+            formant_ratio: semitones_to_ratio(formant_semitones.clamp(-12.0, 12.0)),
+            time_buf: fft.make_input_vec(),
+            spectrum: fft.make_output_vec(),
+            scratch_fwd: fft.make_scratch_vec(),
+            scratch_inv: ifft.make_scratch_vec(),
+            fft,
+            ifft,
+            window,
+            in_fifo: vec![0.0; PV_FFT_SIZE],
+            out_fifo: vec![0.0; PV_FFT_SIZE],
+            out_accum: vec![0.0; PV_FFT_SIZE],
+            rover: PV_FFT_SIZE - PV_HOP,
+            last_phase: vec![0.0; PV_BINS],
+            sum_phase: vec![0.0; PV_BINS],
+            magnitude: vec![0.0; PV_BINS],
+            frequency: vec![0.0; PV_BINS],
+            envelope: vec![0.0; PV_BINS],
+            synth_magnitude: vec![0.0; PV_BINS],
+            synth_frequency: vec![0.0; PV_BINS],
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32], _rate: u32) {
+        let latency = PV_FFT_SIZE - PV_HOP;
+        for s in samples.iter_mut() {
+            self.in_fifo[self.rover] = *s;
+            *s = self.out_fifo[self.rover - latency];
+            self.rover += 1;
+            if self.rover >= PV_FFT_SIZE {
+                self.rover = latency;
+                self.process_frame();
+            }
+        }
+    }
+
+    fn process_frame(&mut self) {
+        let expected = 2.0 * PI * PV_HOP as f32 / PV_FFT_SIZE as f32;
+
+        // Analysis.
+        for ((t, x), w) in self.time_buf.iter_mut().zip(&self.in_fifo).zip(&self.window) {
+            *t = x * w;
+        }
+        let _ = self
+            .fft
+            .process_with_scratch(&mut self.time_buf, &mut self.spectrum, &mut self.scratch_fwd);
+
+        for k in 0..PV_BINS {
+            let (mag, phase) = self.spectrum[k].to_polar();
+            let mut delta = phase - self.last_phase[k] - k as f32 * expected;
+            self.last_phase[k] = phase;
+            delta = wrap_phase(delta);
+            self.magnitude[k] = mag;
+            // True frequency in (fractional) bins.
+            self.frequency[k] = k as f32 + delta * PV_OVERLAP as f32 / (2.0 * PI);
+        }
+
+        // Spectral envelope: moving average of the magnitude.  Each window
+        // is summed afresh; a running sum leaves rounding residue from loud
+        // bins in the quiet ones, and dividing by that residue blows them up.
+        for k in 0..PV_BINS {
+            let lo = k.saturating_sub(PV_ENVELOPE_HALF_WIDTH);
+            let hi = (k + PV_ENVELOPE_HALF_WIDTH).min(PV_BINS - 1);
+            let sum: f32 = self.magnitude[lo..=hi].iter().sum();
+            self.envelope[k] = sum / (hi - lo + 1) as f32 + 1e-9;
+        }
+
+        // Move the flattened harmonics by the pitch ratio.  Shifting down
+        // lands several bins on one; the strongest wins, since summing them
+        // would boost the output by up to the inverse ratio...
+        self.synth_magnitude.fill(0.0);
+        self.synth_frequency.fill(0.0);
+        for k in 0..PV_BINS {
+            let j = (k as f32 * self.pitch_ratio).round() as usize;
+            let flat = self.magnitude[k] / self.envelope[k];
+            if j < PV_BINS && flat > self.synth_magnitude[j] {
+                self.synth_magnitude[j] = flat;
+                self.synth_frequency[j] = self.frequency[k] * self.pitch_ratio;
+            }
+        }
+
+        // ...then re-impose the envelope, moved by the formant ratio.
+        for j in 0..PV_BINS {
+            let src = j as f32 / self.formant_ratio;
+            let idx = src as usize;
+            let env = if idx + 1 < PV_BINS {
+                let frac = src - idx as f32;
+                self.envelope[idx] * (1.0 - frac) + self.envelope[idx + 1] * frac
+            } else {
+                0.0
+            };
+            let mag = self.synth_magnitude[j] * env;
+
+            let delta = (self.synth_frequency[j] - j as f32) * 2.0 * PI / PV_OVERLAP as f32;
+            self.sum_phase[j] = wrap_phase(self.sum_phase[j] + j as f32 * expected + delta);
+            self.spectrum[j] = Complex::from_polar(mag, self.sum_phase[j]);
+        }
+        self.spectrum[0].im = 0.0;
+        self.spectrum[PV_BINS - 1].im = 0.0;
+
+        // Synthesis: windowed overlap-add.  Hann² summed over 4x overlap is
+        // 1.5, and the inverse FFT is unnormalised.
+        let _ = self
+            .ifft
+            .process_with_scratch(&mut self.spectrum, &mut self.time_buf, &mut self.scratch_inv);
+        let norm = 1.0 / (PV_FFT_SIZE as f32 * 1.5);
+        for ((acc, t), w) in self.out_accum.iter_mut().zip(&self.time_buf).zip(&self.window) {
+            *acc += t * w * norm;
+        }
+
+        self.out_fifo[..PV_HOP].copy_from_slice(&self.out_accum[..PV_HOP]);
+        self.out_accum.copy_within(PV_HOP.., 0);
+        self.out_accum[PV_FFT_SIZE - PV_HOP..].fill(0.0);
+        self.in_fifo.copy_within(PV_HOP.., 0);
+    }
+}
+
+fn semitones_to_ratio(semitones: f32) -> f32 {
+    2f32.powf(semitones / 12.0)
+}
+
+/// Wrap a phase to `[-PI, PI)`.
+fn wrap_phase(phase: f32) -> f32 {
+    phase - 2.0 * PI * ((phase + PI) / (2.0 * PI)).floor()
+}
+
+// ---------------------------------------------------------------------------
+// Robot (ring modulation)
+// ---------------------------------------------------------------------------
+
+/// Multiplies the voice by a sine carrier, the classic "robot" sound.
+struct RingModulator {
+    frequency_hz: f32,
+    mix: f32,
+    phase: f32,
+}
+
+impl RingModulator {
+    fn new(frequency_hz: f32, mix: f32) -> Self {
+        Self {
+            frequency_hz: frequency_hz.clamp(1.0, 2000.0),
+            mix: mix.clamp(0.0, 1.0),
+            phase: 0.0,
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32], rate: u32) {
+        let step = 2.0 * PI * self.frequency_hz / rate.max(1) as f32;
+        for s in samples.iter_mut() {
+            let wet = *s * self.phase.sin();
+            *s += (wet - *s) * self.mix;
+            self.phase += step;
+            if self.phase >= 2.0 * PI {
+                self.phase -= 2.0 * PI;
+            }
+        }
+    }
+}
+
+// ---------------------------------------------------------------------------
+// Radio / telephone
+// ---------------------------------------------------------------------------
+
+/// Band-limits the voice like a telephone or radio link and drives it into
+/// a soft clipper.  The make-up gain only partially compensates the drive,
+/// so heavier settings come out both dirtier and a little louder.
+struct RadioFilter {
+    high_pass: [Biquad; 2],
+    low_pass: [Biquad; 2],
+    pre_gain: f32,
+    post_gain: f32,
+}
+
+impl RadioFilter {
+    fn new(rate: u32, low_hz: f32, high_hz: f32, drive: f32) -> Self {
+        let low_hz = low_hz.max(20.0);
+        let high_hz = high_hz.max(low_hz + 100.0);
+        let hp = Biquad::high_pass(rate, low_hz, BUTTERWORTH_Q);
+        let lp = Biquad::low_pass(rate, high_hz, BUTTERWORTH_Q);
+        let pre_gain = 1.0 + drive.clamp(0.0, 1.0) * 19.0;
+        Self {
+            high_pass: [hp, hp],
+            low_pass: [lp, lp],
+            pre_gain,
+            post_gain: 1.0 / pre_gain.sqrt(),
+        }
+    }
+
+    fn process(&mut self, samples: &mut [f32]) {
+        for s in samples.iter_mut() {
+            let mut x = *s;
+            for f in self.high_pass.iter_mut().chain(self.low_pass.iter_mut()) {
+                x = f.process(x);
+            }
+            *s = (x * self.pre_gain).tanh() * self.post_gain;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 48_000;
+
+    fn run(config: MicEffect, mut samples: Vec<f32>) -> Vec<f32> {
+        let mut effect = VoiceEffect::new(config, RATE).unwrap();
+        // Mic blocks arrive a device buffer at a time.
+        for block in samples.chunks_mut(480) {
+            effect.process(block, RATE);
+        }
+        samples
+    }
+
+    /// Frequency of the strongest bin in a Hann-windowed FFT of the last
+    /// 8192 samples.
+    fn dominant_hz(samples: &[f32]) -> f32 {
+        const SIZE: usize = 8192;
+        let fft = RealFftPlanner::<f32>::new().plan_fft_forward(SIZE);
+        let tail = &samples[samples.len() - SIZE..];
+        let mut input: Vec<f32> = tail
+            .iter()
+            .enumerate()
+            .map(|(i, s)| s * (0.5 - 0.5 * (2.0 * PI * i as f32 / SIZE as f32).cos()))
+            .collect();
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut input, &mut spectrum).unwrap();
+        let (bin, _) = spectrum
+            .iter()
+            .enumerate()
+            .max_by(|a, b| a.1.norm().total_cmp(&b.1.norm()))
+            .unwrap();
+        bin as f32 * RATE as f32 / SIZE as f32
+    }
+
+    #[test]
+    fn pitch_shift_moves_a_tone_by_the_ratio() {
+        // A lone sine is its own formant envelope, so a plain shift up
+        // leaves the envelope behind and with it the tone; shifted down it
+        // stays inside.  Moving the formants along keeps it in every case.
+        let tone = corpus::tone(RATE as usize, RATE, 440.0, 0.5);
+        let shifts = [(-12.0, 0.0), (-12.0, -12.0), (-5.0, -5.0), (7.0, 7.0), (12.0, 12.0)];
+        for (semitones, formant_semitones) in shifts {
+            let config = MicEffect::Pitch {
+                semitones,
+                formant_semitones,
+            };
+            let got = dominant_hz(&run(config, tone.clone()));
+            let want = 440.0 * semitones_to_ratio(semitones);
+            assert!((got / want - 1.0).abs() < 0.03, "{config:?}: {got} Hz, want {want}");
+        }
+    }
+
+    #[test]
+    fn radio_attenuates_out_of_band_tones() {
+        let radio = MicEffect::Radio {
+            low_hz: 300.0,
+            high_hz: 3000.0,
+            drive: 0.0,
+        };
+        let level = |freq: f32| {
+            let out = run(radio, corpus::tone(RATE as usize / 2, RATE, freq, 0.1));
+            10.0 * corpus::power(&out[RATE as usize / 4..]).log10()
+        };
+        let pass = level(1000.0);
+        assert!((pass - 10.0 * 0.005f32.log10()).abs() < 1.0, "{pass}");
+        for stop in [80.0, 12_000.0] {
+            let db = level(stop);
+            assert!(pass - db > 20.0, "{stop} Hz: {db} dB vs {pass} dB");
+        }
+    }
+
+    #[test]
+    fn every_effect_stays_finite_and_bounded() {
+        let mut input = corpus::noise(RATE as usize / 2, 1.0);
+        input.extend(corpus::tone(RATE as usize / 2, RATE, 220.0, 1.0));
+        // A DC step: the loudest thing a bin can hold.
+        input.extend([1.0; 4800]);
+        let effects = [
+            MicEffect::Pitch {
+                semitones: 24.0,
+                formant_semitones: -12.0,
+            },
+            MicEffect::Pitch {
+                semitones: -24.0,
+                formant_semitones: 12.0,
+            },
+            MicEffect::Robot {
+                frequency_hz: 2000.0,
+                mix: 1.0,
+            },
+            MicEffect::Radio {
+                low_hz: 20.0,
+                high_hz: 20_000.0,
+                drive: 1.0,
+            },
+        ];
+        for config in effects {
+            let out = run(config, input.clone());
+            let peak = out.iter().fold(0f32, |m, s| m.max(s.abs()));
+            assert!(out.iter().all(|s| s.is_finite()), "{config:?}");
+            assert!(peak < 4.0, "{config:?}: peak {peak}");
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use crate::decode::AudioDecoder;
+use crate::protocol::Waveform;
+
+/// The most buckets a `GetWaveform` may ask for.
+pub const MAX_BUCKETS: usize = 65_536;
+
+/// Peaks of the finest level a long file is measured into.  Shorter files
+/// get one peak per `MIN_BLOCK` frames.
+const FINE_PEAKS: u64 = 32_768;
+const MIN_BLOCK: u64 = 16;
+/// Each level merges this many peaks of the one below ...
+const LEVEL_FACTOR: usize = 4;
+/// ... until it has fewer than this many.
+const COARSE_PEAKS: usize = 256;
+
+/// Peaks a bucket is drawn from, at least, when the levels allow.
+const PEAKS_PER_BUCKET: usize = 16;
+
+/// Written in native byte order, like the PCM cache entries.
+const MAGIC: u32 = 0x4b41_4550;
+const VERSION: u32 = 1;
+
+/// Frames decoded at a time while measuring.
+const CHUNK_FRAMES: usize = 16_384;
+
+/// Extremes and energy of one block of frames, over all channels.
+#[derive(Clone, Copy)]
+struct Peak {
+    min: f32,
+    max: f32,
+    /// Sum over the block's frames of the mean square across channels.
+    power: f32,
+}
+
+impl Peak {
+    const EMPTY: Self = Self {
+        min: f32::INFINITY,
+        max: f32::NEG_INFINITY,
+        power: 0.0,
+    };
+
+    fn merge(self, other: Self) -> Self {
+        Self {
+            min: self.min.min(other.min),
+            max: self.max.max(other.max),
+            power: self.power + other.power,
+        }
+    }
+}
+
+/// Peaks of a whole file at several resolutions, so a waveform of any
+/// width is drawn from a few hundred to a few tens of thousands of values
+/// instead of from the samples.
+pub struct Peaks {
+    rate: u32,
+    frames: u64,
+    /// Frames per peak of the finest level.
+    block: u64,
+    /// Finest first; each level's peaks cover `LEVEL_FACTOR` of the last's.
+    levels: Vec<Vec<Peak>>,
+}
+
+impl Peaks {
+    /// Decode the file at `path` and measure its peaks.
+    pub fn measure(path: &str) -> Result<Self, String> {
+        let mut decoder = AudioDecoder::open(path)?;
+        let rate = decoder.sample_rate();
+        let channels = decoder.channels() as usize;
+        let estimate = decoder
+            .duration()
+            .map_or(0, |d| (d.as_secs_f64() * rate as f64) as u64);
+        // A power of two, so long files keep at least `FINE_PEAKS`.
+        let block = 1u64 << (estimate / FINE_PEAKS).max(MIN_BLOCK).ilog2();
+
+        let mut fine = Vec::new();
+        let mut peak = Peak::EMPTY;
+        let mut filled = 0;
+        let mut frames = 0u64;
+        let mut buf = Vec::new();
+        loop {
+            buf.clear();
+            let more = decoder.read(&mut buf, CHUNK_FRAMES * channels);
+            for frame in buf.chunks_exact(channels) {
+                let mut power = 0.0;
+                for &sample in frame {
+                    peak.min = peak.min.min(sample);
+                    peak.max = peak.max.max(sample);
+                    power += sample * sample;
+                }
+                peak.power += power / channels as f32;
+                filled += 1;
+                if filled == block {
+                    fine.push(peak);
+                    peak = Peak::EMPTY;
+                    filled = 0;
+                }
+            }
+            frames += (buf.len() / channels) as u64;
+            if !more {
+                break;
+            }
+        }
+        if filled > 0 {
+            fine.push(peak);
+        }
+
+        let mut levels = vec![fine];
+        while levels.last().is_some_and(|l| l.len() >= COARSE_PEAKS) {
+            let coarser = levels
+                .last()
+                .unwrap()
+                .chunks(LEVEL_FACTOR)
+                .map(|c| c.iter().fold(Peak::EMPTY, |a, &b| a.merge(b)))
+                .collect();
+            levels.push(coarser);
+        }
+        Ok(Self {
+            rate,
+            frames,
+            block,
+            levels,
+        })
+    }
+
+    pub fn rate(&self) -> u32 {
+        self.rate
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    /// The finest blocks in order, as their first frame and the loudest
+    /// sample in them.
+    pub fn blocks(&self) -> impl Iterator<Item = (u64, f32)> + '_ {
+        self.levels[0]
+            .iter()
+            .enumerate()
+            .map(|(i, peak)| (i as u64 * self.block, peak.max.max(-peak.min)))
+    }
+
+    /// `buckets` evenly spaced min/max/RMS values over the whole file.
+    pub fn waveform(&self, buckets: usize) -> Waveform {
+        // The coarsest level that still splits each bucket finely enough
+        // for its edges to fall close to where they should.
+        let level = (0..self.levels.len())
+            .rev()
+            .find(|&l| self.levels[l].len() >= buckets * PEAKS_PER_BUCKET)
+            .unwrap_or(0);
+        let peaks = &self.levels[level];
+        let size = self.block * (LEVEL_FACTOR as u64).pow(level as u32);
+
+        let mut waveform = Waveform {
+            duration_ms: self.frames as f64 * 1000.0 / self.rate.max(1) as f64,
+            min: Vec::with_capacity(buckets),
+            max: Vec::with_capacity(buckets),
+            rms: Vec::with_capacity(buckets),
+        };
+        for i in 0..buckets {
+            // The peaks covering the bucket's frames.
+            let first = self.frames * i as u64 / buckets as u64;
+            let last = self.frames * (i as u64 + 1) / buckets as u64;
+            let start = (first / size) as usize;
+            let end = (last.div_ceil(size) as usize)
+                .max(start + 1)
+                .min(peaks.len());
+            if start >= end {
+                // An empty file.
+                waveform.min.push(0.0);
+                waveform.max.push(0.0);
+                waveform.rms.push(0.0);
+                continue;
+            }
+            let mut peak = Peak::EMPTY;
+            let mut power = 0.0;
+            for (j, &p) in peaks.iter().enumerate().take(end).skip(start) {
+                peak = peak.merge(p);
+                // Edge peaks reach past the bucket; count their energy for
+                // the part inside it.
+                let from = j as u64 * size;
+                let to = (from + size).min(self.frames);
+                let inside = to.min(last).saturating_sub(from.max(first));
+                power += p.power * inside as f32 / (to - from).max(1) as f32;
+            }
+            waveform.min.push(peak.min);
+            waveform.max.push(peak.max);
+            waveform
+                .rms
+                .push((power / (last - first).max(1) as f32).sqrt());
+        }
+        waveform
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::new();
+        out.extend_from_slice(&MAGIC.to_ne_bytes());
+        out.extend_from_slice(&VERSION.to_ne_bytes());
+        out.extend_from_slice(&self.rate.to_ne_bytes());
+        out.extend_from_slice(&(self.levels.len() as u32).to_ne_bytes());
+        out.extend_from_slice(&self.frames.to_ne_bytes());
+        out.extend_from_slice(&self.block.to_ne_bytes());
+        for level in &self.levels {
+            out.extend_from_slice(&(level.len() as u64).to_ne_bytes());
+            for peak in level {
+                out.extend_from_slice(&peak.min.to_ne_bytes());
+                out.extend_from_slice(&peak.max.to_ne_bytes());
+                out.extend_from_slice(&peak.power.to_ne_bytes());
+            }
+        }
+        out
+    }
+
+    /// Read peaks written by `to_bytes`, or `None` if `data` isn't a
+    /// complete peak file of this version.
+    pub fn from_bytes(mut data: &[u8]) -> Option<Self> {
+        let word = |data: &mut &[u8]| Some(u32::from_ne_bytes(take(data, 4)?.try_into().ok()?));
+        let long = |data: &mut &[u8]| Some(u64::from_ne_bytes(take(data, 8)?.try_into().ok()?));
+        if word(&mut data)? != MAGIC || word(&mut data)? != VERSION {
+            return None;
+        }
+        let rate = word(&mut data)?;
+        let count = word(&mut data)?;
+        let frames = long(&mut data)?;
+        let block = long(&mut data)?;
+        let mut levels = Vec::new();
+        for _ in 0..count {
+            let len = long(&mut data)? as usize;
+            let bytes = take(&mut data, len.checked_mul(12)?)?;
+            let float = |i: usize| f32::from_ne_bytes(bytes[i..i + 4].try_into().unwrap());
+            let level = (0..len)
+                .map(|i| Peak {
+                    min: float(i * 12),
+                    max: float(i * 12 + 4),
+                    power: float(i * 12 + 8),
+                })
+                .collect();
+            levels.push(level);
+        }
+        (data.is_empty() && !levels.is_empty() && block > 0).then_some(Self {
+            rate,
+            frames,
+            block,
+            levels,
+        })
+    }
+}
+
+/// Split the first `n` bytes off `data`.
+fn take<'a>(data: &mut &'a [u8], n: usize) -> Option<&'a [u8]> {
+    if data.len() < n {
+        return None;
+    }
+    let (head, rest) = data.split_at(n);
+    *data = rest;
+    Some(head)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_corpus as corpus;
+
+    const RATE: u32 = 44100;
+
+    #[test]
+    fn peaks_match_the_samples() {
+        // Ten seconds of stereo, with the second half at a quarter level.
+        let frames = 10 * RATE as usize;
+        let signal: Vec<f32> = (0..frames * 2)
+            .map(|i| {
+                let level = if i < frames { 1.0 } else { 0.25 };
+                level * ((i / 2) as f32 * 0.05).sin()
+            })
+            .collect();
+        let path = corpus::wav_float("peaks.wav", RATE, 2, &signal);
+        let peaks = Peaks::measure(path.to_str().unwrap()).unwrap();
+        assert!(peaks.levels.len() > 1);
+
+        for buckets in [1, 2, 600, 40_000] {
+            let waveform = peaks.waveform(buckets);
+            assert_eq!(waveform.min.len(), buckets);
+            assert!((waveform.duration_ms - 10_000.0).abs() < 1e-6);
+            for i in 0..buckets {
+                let start = frames * i / buckets * 2;
+                let end = frames * (i + 1) / buckets * 2;
+                let slice = &signal[start..end];
+                let max = slice.iter().copied().fold(f32::MIN, f32::max);
+                let min = slice.iter().copied().fold(f32::MAX, f32::min);
+                // Buckets are drawn from whole peaks, so they can spill a
+                // block into the next one.
+                assert!(waveform.max[i] >= max - 1e-6);
+                assert!(waveform.min[i] <= min + 1e-6);
+            }
+        }
+        let halves = peaks.waveform(2);
+        let rms = 1.0 / 2f32.sqrt();
+        assert!((halves.rms[0] - rms).abs() < 1e-2);
+        assert!((halves.rms[1] - rms / 4.0).abs() < 1e-2);
+
+        let read = Peaks::from_bytes(&peaks.to_bytes()).unwrap();
+        assert_eq!(read.to_bytes(), peaks.to_bytes());
+        assert!(Peaks::from_bytes(&peaks.to_bytes()[..100]).is_none());
+    }
+}
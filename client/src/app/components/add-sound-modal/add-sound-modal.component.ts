@@ -3,7 +3,7 @@ import { CommonModule } from '@angular/common';
 import { FormsModule } from '@angular/forms';
 import { take, Subscription } from 'rxjs';
 import { SoundService, YoutubeFetchProgress, YoutubeCacheEntry } from '../../services/sound.service';
-import { Sound, CategoryIcon } from '../../models/sound.model';
+import { Sound, CategoryIcon, SilenceReport } from '../../models/sound.model';
 import { CategorySelectComponent } from '../category-select/category-select.component';
 import { WaveformPreviewComponent } from '../waveform-preview/waveform-preview.component';
 
@@ -27,6 +27,8 @@ export class AddSoundModalComponent implements OnChanges {
 
   isAddingSound = false;
   addSoundFile: File | null = null;
+  /** The engine's silence analysis of `addSoundFile`, for the trim suggestion. */
+  silenceReport: SilenceReport | null = null;
   addSoundName = '';
   addSoundArtist = '';
   addSoundCategory = '';
@@ -98,6 +100,7 @@ export class AddSoundModalComponent implements OnChanges {
             // If a file was pre-selected (from drag-and-drop), skip to preview
             if (preFile) {
               this.addSoundFile = preFile;
+              this.analyzeSilence(preFile);
               this.addSoundName = preFile.name.replace(/\.[^/.]+$/, '');
               this.step = 'preview';
               this.cdr.markForCheck();
@@ -113,6 +116,7 @@ export class AddSoundModalComponent implements OnChanges {
   private resetState(): void {
     this.step = 'select';
     this.addSoundFile = null;
+    this.silenceReport = null;
     this.addSoundName = '';
     this.addSoundArtist = '';
     this.addSoundCategory = '';
@@ -349,6 +353,7 @@ export class AddSoundModalComponent implements OnChanges {
       return;
     }
     this.addSoundFile = file;
+    this.analyzeSilence(file);
     this.addSoundName = this.fileNameWithoutExtension(file.name);
     this.step = 'preview';
   }
@@ -365,6 +370,7 @@ export class AddSoundModalComponent implements OnChanges {
       return;
     }
     this.addSoundFile = file;
+    this.analyzeSilence(file);
     this.addSoundName = this.fileNameWithoutExtension(file.name);
     this.step = 'preview';
   }
@@ -377,6 +383,7 @@ export class AddSoundModalComponent implements OnChanges {
   removeFile(event?: Event): void {
     event?.stopPropagation();
     this.addSoundFile = null;
+    this.silenceReport = null;
     this.addSoundName = '';
     this.addSoundArtist = '';
     this.addSoundIcon = '';
@@ -435,6 +442,7 @@ export class AddSoundModalComponent implements OnChanges {
                   this.youtubePhase = '';
                   this.youtubeFetchAttempts = 0;
                   this.addSoundFile = file;
+                  this.analyzeSilence(file);
                   this.youtubeDurationSeconds = durationSeconds;
                   this.addSoundName = title;
                   this.step = 'preview';
@@ -479,6 +487,19 @@ export class AddSoundModalComponent implements OnChanges {
     return `${(bytes / (1024 * 1024)).toFixed(1)} MB`;
   }
 
+  /** Ask the engine where the dead air at either end of `file` is. */
+  private analyzeSilence(file: File): void {
+    this.silenceReport = null;
+    this.soundService.detectSilence(file)
+      .pipe(take(1))
+      .subscribe({
+        next: (report) => {
+          if (this.addSoundFile === file) this.silenceReport = report;
+        },
+        error: (err) => console.warn('Silence detection failed:', err)
+      });
+  }
+
   // Waveform event handlers
   onWaveformFileChanged(newFile: File): void {
     this.addSoundFile = newFile;
@@ -3,7 +3,7 @@ import { CommonModule } from '@angular/common';
 import { FormsModule } from '@angular/forms';
 import { take } from 'rxjs';
 import { SoundService } from '../../services/sound.service';
-import { Sound } from '../../models/sound.model';
+import { Sound, SoundEdits, SoundWaveform } from '../../models/sound.model';
 import { WaveformPreviewComponent } from '../waveform-preview/waveform-preview.component';
 
 @Component({
@@ -23,6 +23,9 @@ export class EditSoundModalComponent implements OnChanges {
   isSaving = false;
   audioFile: File | null = null;
   originalUncroppedFile: File | null = null;
+  serverWaveform: SoundWaveform | null = null;
+  serverWaveformFailed = false;
+  edits: SoundEdits | null = null;
   errorMessage = '';
 
   // Waveform state
@@ -65,6 +68,9 @@ export class EditSoundModalComponent implements OnChanges {
     this.isSaving = false;
     this.audioFile = null;
     this.originalUncroppedFile = null;
+    this.serverWaveform = null;
+    this.serverWaveformFailed = false;
+    this.edits = null;
     this.errorMessage = '';
     this.previewDuration = 0;
     this.cropStart = 0;
@@ -80,6 +86,21 @@ export class EditSoundModalComponent implements OnChanges {
     this.isLoading = true;
     this.errorMessage = '';
 
+    // The engine's peaks let the waveform draw without the browser
+    // decoding the file, which takes a while on phones; the preview only
+    // decodes it to play or edit it.  Without them it decodes straight away.
+    const id = this.sound.id;
+    this.soundService.getSoundWaveform(id, 600)
+      .pipe(take(1))
+      .subscribe({
+        next: (waveform) => {
+          if (this.sound?.id === id) this.serverWaveform = waveform;
+        },
+        error: () => {
+          if (this.sound?.id === id) this.serverWaveformFailed = true;
+        }
+      });
+
     this.soundService.getSoundAudio(this.sound.id)
       .pipe(take(1))
       .subscribe({
@@ -119,6 +140,10 @@ export class EditSoundModalComponent implements OnChanges {
     this.previewDuration = state.duration;
   }
 
+  onEditsChanged(edits: SoundEdits): void {
+    this.edits = edits;
+  }
+
   get hasPendingCrop(): boolean {
     return this.cropStart !== 0 || this.cropEnd !== 1;
   }
@@ -138,7 +163,15 @@ export class EditSoundModalComponent implements OnChanges {
     this.isSaving = true;
     this.errorMessage = '';
 
-    this.soundService.updateSoundFile(this.sound.id, this.audioFile)
+    // Crops and volume changes are rendered from the original file by the
+    // engine; the re-encoded WAV is only uploaded when nothing was edited.
+    const edits = this.edits;
+    const hasEdits = edits !== null && ((edits.startMs ?? 0) > 0 || edits.endMs !== undefined || (edits.gainDb ?? 0) !== 0);
+    const save$ = hasEdits
+      ? this.soundService.renderSound(this.sound.id, edits)
+      : this.soundService.updateSoundFile(this.sound.id, this.audioFile);
+
+    save$
       .pipe(take(1))
       .subscribe({
         next: () => {
@@ -3,6 +3,7 @@ import {
   OnChanges, SimpleChanges, OnDestroy, NgZone, ChangeDetectorRef
 } from '@angular/core';
 import { CommonModule } from '@angular/common';
+import { SilenceReport, SoundEdits, SoundWaveform } from '../../models/sound.model';
 
 @Component({
   selector: 'app-waveform-preview',
@@ -13,17 +14,28 @@ import { CommonModule } from '@angular/common';
 })
 export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   @Input() file: File | null = null;
+  /** Peaks of `file` from the engine, drawn while the file is still decoding. */
+  @Input() serverWaveform: SoundWaveform | null = null;
+  /** The engine's silence analysis of `file`, offered as a crop. */
+  @Input() silenceReport: SilenceReport | null = null;
+  /**
+   * Draw `serverWaveform` and decode `file` only once playback or an edit
+   * needs it, instead of as soon as it is set.
+   */
+  @Input() deferDecode = false;
   @Output() fileChanged = new EventEmitter<File>();
   @Output() originalFileChanged = new EventEmitter<File | null>();
   @Output() metadataParsed = new EventEmitter<{ artist: string; title: string }>();
   @Output() durationChanged = new EventEmitter<number>();
   @Output() cropStateChanged = new EventEmitter<{ start: number; end: number; duration: number }>();
+  @Output() editsChanged = new EventEmitter<SoundEdits>();
 
   @ViewChild('waveformCanvas') waveformCanvas!: ElementRef<HTMLCanvasElement>;
   @ViewChild('frequencyCanvas') frequencyCanvas!: ElementRef<HTMLCanvasElement>;
   @ViewChild('waveformSection') waveformSection!: ElementRef<HTMLDivElement>;
 
   previewLoading = false;
+  previewDecoding = false;
   previewDuration = 0;
   previewCurrentTime = 0;
   previewIsPlaying = false;
@@ -43,8 +55,12 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   clippingWarningFading = false;
 
   private originalAudioBuffer: AudioBuffer | null = null;
+  // Applied crop, in seconds of the file that was loaded.
+  private appliedStartSec = 0;
+  private appliedEndSec: number | null = null;
   private audioCtx: AudioContext | null = null;
   private audioBuffer: AudioBuffer | null = null;
+  private decoding: Promise<AudioBuffer | null> | null = null;
   private previewSourceNode: AudioBufferSourceNode | null = null;
   private previewStartedAt = 0;
   private previewOffsetSec = 0;
@@ -68,6 +84,12 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   constructor(private ngZone: NgZone, private cdr: ChangeDetectorRef) {}
 
   ngOnChanges(changes: SimpleChanges): void {
+    if (changes['serverWaveform']) {
+      this.applyServerWaveform();
+    }
+    if (changes['deferDecode'] && !this.deferDecode && !changes['file']) {
+      this.decodePreview();
+    }
     if (changes['file']) {
       if (this.skipNextFileChange) {
         this.skipNextFileChange = false;
@@ -98,11 +120,15 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     this.focusedCropHandle = null;
     this.isCropping = false;
     this.audioBuffer = null;
+    this.decoding = null;
+    this.previewDecoding = false;
     this.waveformPeaks = null;
     this.previewOffsetSec = 0;
     this.previewStartedAt = 0;
     this.originalFile = null;
     this.originalAudioBuffer = null;
+    this.appliedStartSec = 0;
+    this.appliedEndSec = null;
     this.volumeGain = 1.0;
     this.volumeSliderMax = 300;
     this.scopeMaxFreq = 20000;
@@ -145,48 +171,113 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     this.resetPreviewState();
     this.previewLoading = true;
 
-    const reader = new FileReader();
-    reader.onload = (e) => {
-      const arrayBuffer = e.target?.result as ArrayBuffer;
-      if (!arrayBuffer) {
+    this.audioCtx = new AudioContext();
+    this.analyserNode = this.audioCtx.createAnalyser();
+    this.analyserNode.fftSize = 256;
+    this.analyserNode.smoothingTimeConstant = 0.8;
+    this.frequencyData = new Uint8Array(this.analyserNode.frequencyBinCount);
+    this.frequencyPeaks = new Float32Array(this.analyserNode.frequencyBinCount);
+    this.frequencyPeakClipped = new Uint8Array(this.analyserNode.frequencyBinCount);
+    this.frequencyPeakClipTime = new Float64Array(this.analyserNode.frequencyBinCount);
+    this.timeDomainData = new Float32Array(this.analyserNode.fftSize);
+
+    this.readId3Tags(file);
+    this.applyServerWaveform();
+    if (!this.deferDecode) {
+      this.decodePreview();
+    }
+  }
+
+  /** Emit the artist and title from `file`'s ID3v2 tag, reading only the tag. */
+  private readId3Tags(file: File): void {
+    file.slice(0, 10).arrayBuffer()
+      .then((header) => {
+        const bytes = new Uint8Array(header);
+        if (bytes.length < 10 || bytes[0] !== 0x49 || bytes[1] !== 0x44 || bytes[2] !== 0x33) {
+          return null;
+        }
+        const tagSize =
+          ((bytes[6] & 0x7f) << 21) |
+          ((bytes[7] & 0x7f) << 14) |
+          ((bytes[8] & 0x7f) << 7) |
+          (bytes[9] & 0x7f);
+        return file.slice(0, 10 + tagSize).arrayBuffer();
+      })
+      .then((tag) => {
+        if (!tag || this.file !== file) return;
+        const tags = this.parseId3Tags(tag);
+        if (tags.artist || tags.title) {
+          this.metadataParsed.emit(tags);
+        }
+      })
+      .catch(() => {});
+  }
+
+  /**
+   * Decode the loaded file for playback and edits.  Only the first call
+   * reads the file; later ones share its result, which is `null` if the
+   * file can't be decoded or another one has been loaded since.
+   */
+  private decodePreview(): Promise<AudioBuffer | null> {
+    if (this.audioBuffer) return Promise.resolve(this.audioBuffer);
+    if (this.decoding) return this.decoding;
+    const file = this.file;
+    const ctx = this.audioCtx;
+    if (!file || !ctx) return Promise.resolve(null);
+
+    this.previewDecoding = true;
+    this.decoding = file.arrayBuffer()
+      .then((arrayBuffer) => ctx.decodeAudioData(arrayBuffer))
+      .then((buffer) => this.ngZone.run(() => {
+        if (this.audioCtx !== ctx) return null;
+        this.audioBuffer = buffer;
+        this.previewDuration = buffer.duration;
         this.previewLoading = false;
-        return;
-      }
+        this.previewDecoding = false;
+        this.durationChanged.emit(buffer.duration);
+        this.waveformPeaks = this.computePeaks(buffer, 600);
+        this.scopeMaxFreq = 20000;
+        this.updateCroppedPeakAmplitude();
+        setTimeout(() => this.drawWaveform(), 50);
+        return buffer;
+      }))
+      .catch(() => this.ngZone.run(() => {
+        if (this.audioCtx === ctx) {
+          this.previewLoading = false;
+          this.previewDecoding = false;
+          this.decoding = null;
+        }
+        return null;
+      }));
+    return this.decoding;
+  }
 
-      const tags = this.parseId3Tags(arrayBuffer);
-      if (tags.artist || tags.title) {
-        this.metadataParsed.emit(tags);
-      }
+  /** Run `action` on the decoded file, decoding it first if need be. */
+  private whenDecoded(action: () => void): void {
+    if (this.audioBuffer) {
+      action();
+      return;
+    }
+    this.decodePreview().then((buffer) => {
+      if (buffer) action();
+    });
+  }
 
-      this.audioCtx = new AudioContext();
-      this.analyserNode = this.audioCtx.createAnalyser();
-      this.analyserNode.fftSize = 256;
-      this.analyserNode.smoothingTimeConstant = 0.8;
-      this.frequencyData = new Uint8Array(this.analyserNode.frequencyBinCount);
-      this.frequencyPeaks = new Float32Array(this.analyserNode.frequencyBinCount);
-      this.frequencyPeakClipped = new Uint8Array(this.analyserNode.frequencyBinCount);
-      this.frequencyPeakClipTime = new Float64Array(this.analyserNode.frequencyBinCount);
-      this.timeDomainData = new Float32Array(this.analyserNode.fftSize);
-      this.audioCtx.decodeAudioData(arrayBuffer.slice(0))
-        .then((buffer) => {
-          this.ngZone.run(() => {
-            this.audioBuffer = buffer;
-            this.previewDuration = buffer.duration;
-            this.previewLoading = false;
-            this.durationChanged.emit(buffer.duration);
-            this.waveformPeaks = this.computePeaks(buffer, 600);
-            this.scopeMaxFreq = 20000;
-            this.updateCroppedPeakAmplitude();
-            setTimeout(() => this.drawWaveform(), 50);
-          });
-        })
-        .catch(() => {
-          this.ngZone.run(() => {
-            this.previewLoading = false;
-          });
-        });
-    };
-    reader.readAsArrayBuffer(file);
+  /** Draw the engine's peaks until (or instead of) decoding the file. */
+  private applyServerWaveform(): void {
+    const waveform = this.serverWaveform;
+    if (!waveform || !this.file || this.audioBuffer) return;
+    const peaks = new Float32Array(waveform.max.length);
+    for (let i = 0; i < peaks.length; i++) {
+      peaks[i] = Math.max(Math.abs(waveform.min[i]), Math.abs(waveform.max[i]));
+    }
+    this.waveformPeaks = peaks;
+    this.previewDuration = waveform.duration_ms / 1000;
+    this.durationChanged.emit(this.previewDuration);
+    // The crop UI works on the peaks; playback waits for the decode.
+    this.previewLoading = false;
+    this.updateCroppedPeakAmplitude();
+    setTimeout(() => this.drawWaveform(), 50);
   }
 
   private parseId3Tags(buffer: ArrayBuffer): { artist: string; title: string } {
@@ -338,7 +429,7 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
 
   onWaveformMousedown(event: MouseEvent): void {
     const canvasEl = this.waveformCanvas?.nativeElement;
-    if (!canvasEl || !this.audioBuffer) return;
+    if (!canvasEl || !this.previewDuration) return;
     const rect = canvasEl.getBoundingClientRect();
     const frac = Math.max(0, Math.min(1, (event.clientX - rect.left) / rect.width));
     this.seekPreviewTo(frac * this.previewDuration);
@@ -375,7 +466,13 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   }
 
   private startPreviewPlayback(): void {
-    if (!this.audioCtx || !this.audioBuffer) return;
+    if (!this.audioBuffer) {
+      this.whenDecoded(() => {
+        if (!this.previewIsPlaying) this.startPreviewPlayback();
+      });
+      return;
+    }
+    if (!this.audioCtx) return;
     if (this.audioCtx.state === 'suspended') {
       this.audioCtx.resume();
     }
@@ -526,6 +623,25 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     }
   }
 
+  /** Whether there is dead air to offer trimming: only before any crop. */
+  get canTrimSilence(): boolean {
+    const report = this.silenceReport;
+    if (!report || report.duration_ms <= 0 || this.originalFile !== null) return false;
+    if (this.cropStart !== 0 || this.cropEnd !== 1) return false;
+    return report.suggested_start_ms > 0 || report.suggested_end_ms < report.duration_ms;
+  }
+
+  /** Set the crop handles to the engine's suggestion. */
+  trimSilence(): void {
+    const report = this.silenceReport;
+    if (!report || report.duration_ms <= 0) return;
+    this.cropStart = Math.max(0, Math.min(1, report.suggested_start_ms / report.duration_ms));
+    this.cropEnd = Math.max(this.cropStart, Math.min(1, report.suggested_end_ms / report.duration_ms));
+    this.cropStateChanged.emit({ start: this.cropStart, end: this.cropEnd, duration: this.previewDuration });
+    this.updateCroppedPeakAmplitude();
+    this.drawWaveform();
+  }
+
   resetCrop(): void {
     this.cropStart = 0;
     this.cropEnd = 1;
@@ -541,8 +657,12 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   }
 
   applyCrop(): void {
-    if (!this.audioBuffer || !this.audioCtx || this.isCropping) return;
+    if (!this.audioCtx || this.isCropping) return;
     if (this.cropStart === 0 && this.cropEnd === 1) return;
+    if (!this.audioBuffer) {
+      this.whenDecoded(() => this.applyCrop());
+      return;
+    }
 
     this.isCropping = true;
     this.stopPreviewPlayback();
@@ -552,6 +672,8 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     const startSample = Math.floor(this.cropStart * this.audioBuffer.length);
     const endSample   = Math.ceil(this.cropEnd   * this.audioBuffer.length);
     const newLength   = endSample - startSample;
+    this.appliedEndSec = this.appliedStartSec + endSample / sampleRate;
+    this.appliedStartSec += startSample / sampleRate;
 
     if (!this.originalFile) {
       this.originalFile = this.file;
@@ -602,6 +724,7 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     this.cropStateChanged.emit({ start: 0, end: 1, duration: croppedBuffer.duration });
     this.skipNextFileChange = true;
     this.fileChanged.emit(newFile);
+    this.emitEdits();
     this.startPeakDecay();
     this.cdr.detectChanges();
     setTimeout(() => this.drawWaveform(), 0);
@@ -618,6 +741,8 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
 
     this.originalFile = null;
     this.originalAudioBuffer = null;
+    this.appliedStartSec = 0;
+    this.appliedEndSec = null;
 
     this.cropStart = 0;
     this.cropEnd = 1;
@@ -648,6 +773,7 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
     this.skipNextFileChange = true;
     this.fileChanged.emit(restoredFile);
     this.originalFileChanged.emit(null);
+    this.emitEdits();
     this.startPeakDecay();
     this.cdr.detectChanges();
     setTimeout(() => this.drawWaveform(), 0);
@@ -1080,7 +1206,10 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   }
 
   normalizeVolume(): void {
-    if (!this.audioBuffer) return;
+    if (!this.audioBuffer) {
+      this.whenDecoded(() => this.normalizeVolume());
+      return;
+    }
     // Always use the true peak from the raw audio buffer (all channels, full resolution)
     // to avoid under-estimating the peak from the downsampled waveformPeaks
     const truePeak = this.findPeakAmplitude(this.audioBuffer);
@@ -1123,13 +1252,29 @@ export class WaveformPreviewComponent implements OnChanges, OnDestroy {
   }
 
   private emitCurrentFile(): void {
-    if (!this.audioBuffer) return;
+    if (!this.audioBuffer) {
+      this.whenDecoded(() => this.emitCurrentFile());
+      return;
+    }
     const wavBlob = this.audioBufferToWav(this.audioBuffer, this.volumeGain);
     const originalName = this.file?.name ?? 'sound.wav';
     const baseName = this.fileNameWithoutExtension(originalName);
     const newFile = new File([wavBlob], `${baseName}.wav`, { type: 'audio/wav' });
     this.skipNextFileChange = true;
     this.fileChanged.emit(newFile);
+    this.emitEdits();
+  }
+
+  /**
+   * The applied crop and volume as edits to the loaded file, so the parent
+   * can have the engine render them instead of uploading the re-encoded WAV.
+   */
+  private emitEdits(): void {
+    this.editsChanged.emit({
+      startMs: this.appliedStartSec * 1000,
+      endMs: this.appliedEndSec !== null ? this.appliedEndSec * 1000 : undefined,
+      gainDb: this.volumeGain > 0 ? 20 * Math.log10(this.volumeGain) : -120,
+    });
   }
 
   formatTime(seconds: number): string {
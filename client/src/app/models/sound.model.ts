@@ -75,3 +75,35 @@ export interface AudioDevices {
   input: string[];
   output: string[];
 }
+
+/** Silence the audio engine found in a file, and where it suggests cropping. */
+export interface SilenceReport {
+  duration_ms: number;
+  leading_ms: number;
+  trailing_ms: number;
+  gaps: { start_ms: number; end_ms: number }[];
+  suggested_start_ms: number;
+  suggested_end_ms: number;
+}
+
+/** Edits for the server to render into a sound's file; times are in the
+ *  file as it is now. */
+export interface SoundEdits {
+  startMs?: number;
+  endMs?: number;
+  gainDb?: number;
+  loudnessLufs?: number;
+  fadeInMs?: number;
+  fadeOutMs?: number;
+  pitchSemitones?: number;
+  sampleRate?: number;
+  channels?: number;
+}
+
+/** Peaks of a sound file, one value per bucket, from the audio engine. */
+export interface SoundWaveform {
+  duration_ms: number;
+  min: number[];
+  max: number[];
+  rms: number[];
+}
@@ -11,7 +11,7 @@ export interface UpdateInfo {
   latestVersion: string;
   downloadUrl: string;
 }
-import { Sound, ConnectionStatus, CategoryIcon, AudioDevices, StoreCategory, StoreCategoryDetail } from '../models/sound.model';
+import { Sound, ConnectionStatus, CategoryIcon, AudioDevices, StoreCategory, StoreCategoryDetail, SoundWaveform, SilenceReport, SoundEdits } from '../models/sound.model';
 import { environment } from '../../environments/environment';
 
 export interface YoutubeFetchProgress {
@@ -597,12 +597,28 @@ export class SoundService implements OnDestroy {
     );
   }
 
+  getSoundWaveform(id: number, buckets: number): Observable<SoundWaveform> {
+    return this.http.get<SoundWaveform>(`${this.apiUrl}/sounds/${id}/waveform`, {
+      params: { buckets },
+    });
+  }
+
+  detectSilence(file: File): Observable<SilenceReport> {
+    const formData = new FormData();
+    formData.append('soundFile', file);
+    return this.http.post<SilenceReport>(`${this.apiUrl}/audio/detect-silence`, formData);
+  }
+
   updateSoundFile(id: number, file: File): Observable<any> {
     const formData = new FormData();
     formData.append('soundFile', file);
     return this.http.post(`${this.apiUrl}/sounds/${id}/update-file`, formData);
   }
 
+  renderSound(id: number, edits: SoundEdits): Observable<any> {
+    return this.http.post(`${this.apiUrl}/sounds/${id}/render`, edits);
+  }
+
   fetchYoutubeAudio(url: string): Observable<{ file: File; title: string; durationSeconds: number }> {
     return this.http.post(`${this.apiUrl}/youtube/fetch`, { url }, {
       responseType: 'blob',
@@ -10,17 +10,227 @@ interface Command {
   [key: string]: any;
 }
 
+export type MicEffect =
+  | { type: 'none' }
+  | { type: 'pitch'; semitones?: number; formant_semitones?: number }
+  | { type: 'robot'; frequency_hz?: number; mix?: number }
+  | { type: 'radio'; low_hz?: number; high_hz?: number; drive?: number };
+
+export interface EqBand {
+  type: 'low_shelf' | 'high_shelf' | 'peaking' | 'high_pass' | 'low_pass';
+  freq_hz: number;
+  gain_db?: number;
+  q?: number;
+}
+
+export interface ReverbSettings {
+  ir_path: string;
+  wet?: number;
+  dry?: number;
+}
+
+export type BusOutput = 'main' | 'monitor';
+
+/**
+ * What the monitor device plays: `mix` is the main output as the mic gets
+ * it (plus buses routed only to the monitor), `buses` the buses routed to
+ * the monitor before the master stage.
+ */
+export type MonitorSource = 'mix' | 'buses';
+
+export type BusInsert =
+  | { type: 'eq'; bands: EqBand[] }
+  | ({ type: 'reverb' } & ReverbSettings);
+
+export interface QueueItem {
+  id: number;
+  file_path: string;
+}
+
+export interface QueueStatus {
+  current: QueueItem | null;
+  next: QueueItem | null;
+  pending: number;
+}
+
+export interface BusStatus {
+  name: string;
+  gain: number;
+  muted: boolean;
+  inserts: BusInsert[];
+  outputs: BusOutput[];
+  playing: boolean;
+  pan: number | null;
+  queue: QueueStatus;
+}
+
+export interface BusOptions {
+  gain?: number;
+  muted?: boolean;
+  inserts?: BusInsert[];
+  outputs?: BusOutput[];
+}
+
+export interface PlayOptions {
+  /** Bus to play on (default `sfx`). */
+  bus?: string;
+  /** -1 (left) .. 1 (right). */
+  pan?: number;
+  reverb?: ReverbSettings;
+  /** Start this many milliseconds from now. */
+  delayMs?: number;
+  /** Start at this time on the engine clock (`clockMs` in the status). */
+  atMs?: number;
+}
+
+export interface WeightedFile {
+  file_path: string;
+  weight?: number;
+}
+
+export type RandomMode = 'random' | 'shuffle' | 'round_robin';
+
+export interface PlayRandomOptions {
+  mode?: RandomMode;
+  bus?: string;
+  /** 0 - 100, like `play`. */
+  volume?: number;
+  /** Random pitch range, ± semitones. */
+  pitchVariation?: number;
+  /** Random gain range, ± dB. */
+  gainVariationDb?: number;
+}
+
+export interface SampleCacheStatus {
+  entries: number;
+  used_bytes: number;
+  budget_bytes: number;
+  preloaded: string[];
+}
+
+export interface PcmCacheStatus {
+  dir: string | null;
+  entries: number;
+  used_bytes: number;
+  budget_bytes: number;
+}
+
+export interface MediaInfo {
+  duration_ms: number;
+  sample_rate: number;
+  channels: number;
+  codec: string;
+  bit_depth: number | null;
+  title: string | null;
+  artist: string | null;
+  album: string | null;
+  has_album_art: boolean;
+}
+
+export interface Waveform {
+  duration_ms: number;
+  min: number[];
+  max: number[];
+  rms: number[];
+}
+
+export interface SilentSpan {
+  start_ms: number;
+  end_ms: number;
+}
+
+export interface SilenceReport {
+  duration_ms: number;
+  leading_ms: number;
+  trailing_ms: number;
+  gaps: SilentSpan[];
+  suggested_start_ms: number;
+  suggested_end_ms: number;
+}
+
+/** Changes `render()` makes to a file; all optional. */
+export interface RenderEdits {
+  start_ms?: number;
+  end_ms?: number;
+  pitch_semitones?: number;
+  sample_rate?: number;
+  channels?: number;
+  gain_db?: number;
+  /** Integrated loudness to normalize to, instead of `gain_db`. */
+  loudness_lufs?: number;
+  fade_in_ms?: number;
+  fade_out_ms?: number;
+}
+
+export interface RenderInfo {
+  duration_ms: number;
+  sample_rate: number;
+  channels: number;
+  gain_db: number;
+  peak_db: number;
+  loudness_lufs: number | null;
+}
+
+/** What a recording takes in: the whole mix, or the mic or sounds alone. */
+export type RecordingSource = 'master' | 'mic' | 'sounds';
+
+export interface RecordingInfo {
+  path: string;
+  source: RecordingSource;
+  sample_rate: number;
+  channels: number;
+  duration_ms: number;
+  /** Audio left out because the writer fell behind. */
+  dropped_ms: number;
+}
+
+export interface ScheduledPlay {
+  id: number;
+  bus: string;
+  file_path: string;
+  start_ms: number;
+}
+
 interface EngineResponse {
-  type: 'ok' | 'error' | 'devices' | 'status';
+  type: 'ok' | 'error' | 'devices' | 'status' | 'scheduled' | 'started' | 'finished' | 'played' | 'probe' | 'waveform' | 'silence' | 'rendered' | 'recorded';
   message?: string;
+  id?: number;
+  job?: number;
+  result?: EngineResponse;
+  file_path?: string;
+  media?: MediaInfo;
+  waveform?: Waveform;
+  silence?: SilenceReport;
+  render?: RenderInfo;
+  recording?: RecordingInfo | null;
   input?: string[];
   output?: string[];
   playing?: boolean;
   paused?: boolean;
+  mic_muted?: boolean;
+  push_to_mute?: boolean;
+  clock_ms?: number;
+  scheduled?: ScheduledPlay[];
+  sample_cache?: SampleCacheStatus;
+  pcm_cache?: PcmCacheStatus;
   volume?: number;
+  balance?: number;
   mic_volume?: number;
+  noise_suppression?: boolean;
+  echo_cancellation?: boolean;
+  mic_agc?: boolean;
+  mic_agc_gain_db?: number | null;
+  mic_effect?: MicEffect;
+  mic_eq?: EqBand[];
+  master_eq?: EqBand[];
+  master_reverb?: ReverbSettings | null;
+  channel_matrices?: Record<string, number[][]>;
   input_device?: string | null;
   output_device?: string | null;
+  monitor_device?: string | null;
+  monitor_volume?: number;
+  monitor_source?: MonitorSource;
+  buses?: BusStatus[];
 }
 
 export interface AudioDevices {
@@ -31,10 +241,31 @@ export interface AudioDevices {
 export interface AudioStatus {
   playing: boolean;
   paused: boolean;
+  micMuted: boolean;
+  pushToMute: boolean;
+  clockMs: number;
+  scheduled: ScheduledPlay[];
+  sampleCache: SampleCacheStatus | null;
+  pcmCache: PcmCacheStatus | null;
   volume: number;
+  balance: number;
   micVolume: number;
+  noiseSuppression: boolean;
+  echoCancellation: boolean;
+  micAgc: boolean;
+  micAgcGainDb: number | null;
+  micEffect: MicEffect;
+  micEq: EqBand[];
+  masterEq: EqBand[];
+  masterReverb: ReverbSettings | null;
+  channelMatrices: Record<string, number[][]>;
   inputDevice: string | null;
   outputDevice: string | null;
+  monitorDevice: string | null;
+  monitorVolume: number;
+  monitorSource: MonitorSource;
+  buses: BusStatus[];
+  recording: RecordingInfo | null;
 }
 
 // ── AudioEngine ────────────────────────────────────────────────────────────
@@ -46,8 +277,20 @@ export class AudioEngine extends EventEmitter {
     resolve: (value: EngineResponse) => void;
     reject: (reason: Error) => void;
   }> = [];
+  /**
+   * File commands (probe, waveform, silence, render) the engine runs on
+   * its job worker, by job id.  They answer `started` at once and
+   * `finished` when done, possibly after later commands.
+   */
+  private pendingJobs = new Map<number, {
+    resolve: (value: EngineResponse) => void;
+    reject: (reason: Error) => void;
+  }>();
   private lineBuffer = '';
   private _running = false;
+  private _monitorDevice: string | null = null;
+  private _monitorSource: MonitorSource = 'mix';
+  private _pcmCacheDir: string | null = null;
 
   constructor() {
     super();
@@ -106,10 +349,11 @@ export class AudioEngine extends EventEmitter {
       this.process = null;
 
       // Reject any pending requests
-      for (const req of this.pendingRequests) {
+      for (const req of [...this.pendingRequests, ...this.pendingJobs.values()]) {
         req.reject(new Error('Audio engine process exited'));
       }
       this.pendingRequests = [];
+      this.pendingJobs.clear();
 
       this.emit('exit', code, signal);
     });
@@ -119,11 +363,18 @@ export class AudioEngine extends EventEmitter {
       this._running = false;
       this.process = null;
 
-      for (const req of this.pendingRequests) {
+      for (const req of [...this.pendingRequests, ...this.pendingJobs.values()]) {
         req.reject(err);
       }
       this.pendingRequests = [];
+      this.pendingJobs.clear();
     });
+
+    if (this._pcmCacheDir) {
+      this.setPcmCacheDir(this._pcmCacheDir).catch(err => {
+        console.warn(`[audio-engine] Could not set PCM cache directory:`, err.message);
+      });
+    }
   }
 
   async stop(): Promise<void> {
@@ -176,22 +427,210 @@ export class AudioEngine extends EventEmitter {
     if (resp.type === 'error') throw new Error(resp.message);
   }
 
-  async play(filePath: string, volume?: number): Promise<void> {
+  /**
+   * Open a second output device for local monitoring (`null` closes it).
+   * What it plays is chosen with `setMonitorSource`.
+   */
+  async setMonitorDevice(deviceName: string | null): Promise<void> {
+    const resp = await this.send({
+      cmd: 'set_monitor_device',
+      device_name: deviceName ?? undefined,
+    });
+    if (resp.type === 'error') throw new Error(resp.message);
+    this._monitorDevice = deviceName;
+  }
+
+  async setMonitorVolume(volume: number): Promise<void> {
+    const resp = await this.send({ cmd: 'set_monitor_volume', volume: volume / 100 });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setMonitorSource(source: MonitorSource): Promise<void> {
+    const resp = await this.send({ cmd: 'set_monitor_source', source });
+    if (resp.type === 'error') throw new Error(resp.message);
+    this._monitorSource = source;
+  }
+
+  /** Monitor device currently open in the engine, if any. */
+  get monitorDevice(): string | null {
+    return this._monitorDevice;
+  }
+
+  /** What the monitor device plays: the finished mix or the sound buses. */
+  get monitorSource(): MonitorSource {
+    return this._monitorSource;
+  }
+
+  /**
+   * Play a file.  With `delayMs` or `atMs` the play is scheduled and its id
+   * (for `cancelScheduled`) is returned; otherwise `null`.
+   */
+  async play(filePath: string, volume?: number, options: PlayOptions = {}): Promise<number | null> {
     const resp = await this.send({
       cmd: 'play',
       file_path: filePath,
       volume: volume !== undefined ? volume / 100 : undefined,
+      bus: options.bus,
+      pan: options.pan,
+      reverb: options.reverb,
+      delay_ms: options.delayMs,
+      at_ms: options.atMs,
     });
     if (resp.type === 'error') throw new Error(resp.message);
+    return resp.type === 'scheduled' ? resp.id ?? null : null;
+  }
+
+  /**
+   * Play one file picked from `files`.  Picking state is kept per `group`
+   * in the engine.  Returns the file that was played.
+   */
+  async playRandom(group: string, files: WeightedFile[], options: PlayRandomOptions = {}): Promise<string> {
+    const resp = await this.send({
+      cmd: 'play_random',
+      group,
+      files,
+      mode: options.mode,
+      bus: options.bus,
+      volume: options.volume !== undefined ? options.volume / 100 : undefined,
+      variation: {
+        pitch_semitones: options.pitchVariation ?? 0,
+        gain_db: options.gainVariationDb ?? 0,
+      },
+    });
+    if (resp.type === 'error') throw new Error(resp.message);
+    return resp.file_path ?? '';
+  }
+
+  /** Decode a file into the engine's sample cache so it plays instantly. */
+  async preload(filePath: string): Promise<void> {
+    const resp = await this.send({ cmd: 'preload', file_path: filePath });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async unload(filePath: string): Promise<void> {
+    const resp = await this.send({ cmd: 'unload', file_path: filePath });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setSampleCacheBudget(megabytes: number): Promise<void> {
+    const resp = await this.send({ cmd: 'set_sample_cache_budget', megabytes });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /**
+   * Persist decoded sounds in `dir` so they play without decoding after a
+   * restart, or turn that off with `null`.  Re-applied whenever the engine
+   * is (re)started.
+   */
+  async setPcmCacheDir(dir: string | null): Promise<void> {
+    this._pcmCacheDir = dir;
+    const resp = await this.send({ cmd: 'set_pcm_cache_dir', dir });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setPcmCacheBudget(megabytes: number): Promise<void> {
+    const resp = await this.send({ cmd: 'set_pcm_cache_budget', megabytes });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async clearPcmCache(): Promise<void> {
+    const resp = await this.send({ cmd: 'clear_pcm_cache' });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Format, length and tags of a file, read without playing it. */
+  async probe(filePath: string): Promise<MediaInfo> {
+    const resp = await this.send({ cmd: 'probe', file_path: filePath });
+    if (resp.type === 'error' || !resp.media) throw new Error(resp.message ?? 'Probe failed');
+    return resp.media;
+  }
+
+  /** Min/max/RMS peaks of a file in `buckets` slices, for drawing it. */
+  async getWaveform(filePath: string, buckets: number): Promise<Waveform> {
+    const resp = await this.send({ cmd: 'get_waveform', file_path: filePath, buckets });
+    if (resp.type === 'error' || !resp.waveform) throw new Error(resp.message ?? 'Waveform failed');
+    return resp.waveform;
+  }
+
+  /**
+   * Leading, trailing and internal silence of a file, and a suggested crop.
+   * Silence is anything quieter than `thresholdDb` (dBFS) for at least
+   * `minSilenceMs`.
+   */
+  async detectSilence(filePath: string, thresholdDb?: number, minSilenceMs?: number): Promise<SilenceReport> {
+    const resp = await this.send({
+      cmd: 'detect_silence',
+      file_path: filePath,
+      threshold_db: thresholdDb,
+      min_silence_ms: minSilenceMs,
+    });
+    if (resp.type === 'error' || !resp.silence) throw new Error(resp.message ?? 'Silence detection failed');
+    return resp.silence;
+  }
+
+  /**
+   * Write `filePath` with `edits` applied to `outputPath` as WAV or FLAC
+   * (from the extension unless `format` is given), through the same
+   * resampling and channel mapping as playback.  The output may be the
+   * source.
+   */
+  async render(
+    filePath: string,
+    outputPath: string,
+    edits: RenderEdits,
+    format?: 'wav' | 'flac',
+    bitDepth?: 16 | 24 | 32,
+  ): Promise<RenderInfo> {
+    const resp = await this.send({
+      cmd: 'render',
+      file_path: filePath,
+      output_path: outputPath,
+      edits,
+      format,
+      bit_depth: bitDepth,
+    });
+    if (resp.type === 'error' || !resp.render) throw new Error(resp.message ?? 'Render failed');
+    return resp.render;
+  }
+
+  /**
+   * Record `source` to `filePath` as WAV or FLAC (from the extension unless
+   * `format` is given), in the main output's format, until
+   * `stopRecording()`.
+   */
+  async startRecording(
+    filePath: string,
+    source: RecordingSource = 'master',
+    format?: 'wav' | 'flac',
+    bitDepth?: 16 | 24 | 32,
+  ): Promise<void> {
+    const resp = await this.send({ cmd: 'start_recording', path: filePath, source, format, bit_depth: bitDepth });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async stopRecording(): Promise<RecordingInfo> {
+    const resp = await this.send({ cmd: 'stop_recording' });
+    if (resp.type === 'error' || !resp.recording) throw new Error(resp.message ?? 'Stopping the recording failed');
+    return resp.recording;
+  }
+
+  async cancelScheduled(id: number): Promise<void> {
+    const resp = await this.send({ cmd: 'cancel_scheduled', id });
+    if (resp.type === 'error') throw new Error(resp.message);
   }
 
   /** Fire-and-forget play — sends the command without waiting for a response. */
-  playFireAndForget(filePath: string, volume?: number): void {
+  playFireAndForget(filePath: string, volume?: number, options: PlayOptions = {}): void {
     if (!this.process || !this.process.stdin) return;
     const command = {
       cmd: 'play',
       file_path: filePath,
       volume: volume !== undefined ? volume / 100 : undefined,
+      bus: options.bus,
+      pan: options.pan,
+      reverb: options.reverb,
+      delay_ms: options.delayMs,
+      at_ms: options.atMs,
     };
     const json = JSON.stringify(command) + '\n';
     this.pendingRequests.push({
@@ -207,11 +646,52 @@ export class AudioEngine extends EventEmitter {
     this.process.stdin.write(json);
   }
 
+  /**
+   * Append a file to a bus's play queue (default `music`).  It starts
+   * `gapMs` after the previous entry ends; a negative gap overlaps them.
+   */
+  async enqueue(
+    filePath: string,
+    options: { bus?: string; volume?: number; gapMs?: number } = {},
+  ): Promise<void> {
+    const resp = await this.send({
+      cmd: 'enqueue',
+      file_path: filePath,
+      bus: options.bus,
+      volume: options.volume !== undefined ? options.volume / 100 : undefined,
+      gap_ms: options.gapMs,
+    });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Stop the current queue entry and start the next one. */
+  async skip(bus?: string): Promise<void> {
+    const resp = await this.send({ cmd: 'skip', bus });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /**
+   * Move the sound playing on a bus (or the current entry of its queue) to
+   * `positionMs`.  Long files are streamed, so a far seek may be silent for
+   * a moment while the decoder catches up.
+   */
+  async seek(positionMs: number, bus?: string): Promise<void> {
+    const resp = await this.send({ cmd: 'seek', bus, position_ms: positionMs });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Drop the entries waiting in a queue; the current one keeps playing. */
+  async clearQueue(bus?: string): Promise<void> {
+    const resp = await this.send({ cmd: 'clear_queue', bus });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
   async stopPlayback(): Promise<void> {
     const resp = await this.send({ cmd: 'stop' });
     if (resp.type === 'error') throw new Error(resp.message);
   }
 
+  /** Pause sound playback; the mic pass-through keeps running. */
   async pause(): Promise<void> {
     const resp = await this.send({ cmd: 'pause' });
     if (resp.type === 'error') throw new Error(resp.message);
@@ -222,6 +702,17 @@ export class AudioEngine extends EventEmitter {
     if (resp.type === 'error') throw new Error(resp.message);
   }
 
+  async setMicMuted(muted: boolean): Promise<void> {
+    const resp = await this.send({ cmd: 'set_mic_muted', muted });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Hold (`true`) or release (`false`) push-to-mute. */
+  async setPushToMute(active: boolean): Promise<void> {
+    const resp = await this.send({ cmd: 'set_push_to_mute', active });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
   async setVolume(volume: number): Promise<void> {
     const resp = await this.send({ cmd: 'set_volume', volume: volume / 100 });
     if (resp.type === 'error') throw new Error(resp.message);
@@ -232,15 +723,112 @@ export class AudioEngine extends EventEmitter {
     if (resp.type === 'error') throw new Error(resp.message);
   }
 
+  async setNoiseSuppression(enabled: boolean): Promise<void> {
+    const resp = await this.send({ cmd: 'set_noise_suppression', enabled });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setEchoCancellation(enabled: boolean, delayMs?: number): Promise<void> {
+    const resp = await this.send({ cmd: 'set_echo_cancellation', enabled, delay_ms: delayMs });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setMicAgc(
+    enabled: boolean,
+    options: { targetDb?: number; maxGainDb?: number; noiseFloorDb?: number } = {},
+  ): Promise<void> {
+    const resp = await this.send({
+      cmd: 'set_mic_agc',
+      enabled,
+      target_db: options.targetDb,
+      max_gain_db: options.maxGainDb,
+      noise_floor_db: options.noiseFloorDb,
+    });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setMicEffect(effect: MicEffect): Promise<void> {
+    const resp = await this.send({ cmd: 'set_mic_effect', effect });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async setEq(target: 'mic' | 'master', bands: EqBand[]): Promise<void> {
+    const resp = await this.send({ cmd: 'set_eq', target, bands });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Move the sound playing on a bus (-1 left .. 1 right). */
+  async setPan(pan: number, bus?: string): Promise<void> {
+    const resp = await this.send({ cmd: 'set_pan', pan, bus });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Master left/right balance (-1 left .. 1 right). */
+  async setBalance(balance: number): Promise<void> {
+    const resp = await this.send({ cmd: 'set_balance', balance });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /**
+   * Override the up/downmix matrix for sources with `sourceChannels`
+   * channels (one row per output channel); `null` restores the standard one.
+   */
+  async setChannelMatrix(sourceChannels: number, matrix: number[][] | null): Promise<void> {
+    const resp = await this.send({
+      cmd: 'set_channel_matrix',
+      source_channels: sourceChannels,
+      matrix: matrix ?? undefined,
+    });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Set the convolution reverb on the master output; `null` removes it. */
+  async setMasterReverb(reverb: ReverbSettings | null): Promise<void> {
+    const resp = await this.send({ cmd: 'set_master_reverb', reverb: reverb ?? undefined });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  /** Create or update a bus; omitted options are left unchanged. */
+  async setBus(name: string, options: BusOptions = {}): Promise<void> {
+    const resp = await this.send({ cmd: 'set_bus', name, ...options });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
+  async removeBus(name: string): Promise<void> {
+    const resp = await this.send({ cmd: 'remove_bus', name });
+    if (resp.type === 'error') throw new Error(resp.message);
+  }
+
   async getStatus(): Promise<AudioStatus> {
     const resp = await this.send({ cmd: 'get_status' });
     return {
       playing: resp.playing || false,
       paused: resp.paused || false,
+      micMuted: resp.mic_muted || false,
+      pushToMute: resp.push_to_mute || false,
+      clockMs: resp.clock_ms ?? 0,
+      scheduled: resp.scheduled || [],
+      sampleCache: resp.sample_cache ?? null,
+      pcmCache: resp.pcm_cache ?? null,
       volume: Math.round((resp.volume || 0) * 100),
+      balance: resp.balance ?? 0,
       micVolume: Math.round((resp.mic_volume ?? 1) * 100),
+      noiseSuppression: resp.noise_suppression || false,
+      echoCancellation: resp.echo_cancellation || false,
+      micAgc: resp.mic_agc || false,
+      micAgcGainDb: resp.mic_agc_gain_db ?? null,
+      micEffect: resp.mic_effect || { type: 'none' },
+      micEq: resp.mic_eq || [],
+      masterEq: resp.master_eq || [],
+      masterReverb: resp.master_reverb ?? null,
+      channelMatrices: resp.channel_matrices || {},
       inputDevice: resp.input_device || null,
       outputDevice: resp.output_device || null,
+      monitorDevice: resp.monitor_device || null,
+      monitorVolume: Math.round((resp.monitor_volume ?? 1) * 100),
+      monitorSource: resp.monitor_source || 'mix',
+      buses: resp.buses || [],
+      recording: resp.recording ?? null,
     };
   }
 
@@ -279,10 +867,20 @@ export class AudioEngine extends EventEmitter {
   private handleLine(line: string): void {
     try {
       const response: EngineResponse = JSON.parse(line);
+      if (response.type === 'finished' && response.job !== undefined && response.result) {
+        const job = this.pendingJobs.get(response.job);
+        this.pendingJobs.delete(response.job);
+        job?.resolve(response.result);
+        return;
+      }
       const pending = this.pendingRequests.shift();
-      if (pending) {
-        pending.resolve(response);
+      if (!pending) return;
+      if (response.type === 'started' && response.job !== undefined) {
+        // The result follows as `finished`.
+        this.pendingJobs.set(response.job, pending);
+        return;
       }
+      pending.resolve(response);
     } catch {
       console.warn(`[audio-engine] Non-JSON output: ${line}`);
     }
@@ -75,6 +75,9 @@ const soundDb = new SoundDb(dbPath, soundsDir);
 
 const audioEngine = new AudioEngine();
 audioEngine.start();
+audioEngine.setPcmCacheDir(path.join(dataDir, 'pcm-cache')).catch(err => {
+  console.warn(`[audio-engine] Could not set PCM cache directory:`, err.message);
+});
 
 // Apply saved audio device settings
 const savedInputDevice = getSetting('audioInputDevice');
@@ -6,8 +6,8 @@ import * as os from 'os';
 import { execFile, spawn } from 'child_process';
 import crypto from 'crypto';
 import { SoundDb } from './sound-db';
-import { AudioEngine } from './audio-engine';
-import { getSetting } from './database';
+import { AudioEngine, RecordingSource, RenderEdits } from './audio-engine';
+import { dataDir, getSetting } from './database';
 import { trySyncIfPublic, syncCategoryToStore, removeCategoryFromStore } from './store-sync';
 
 // ── Module-level state (set by initRoutes) ─────────────────────────────────
@@ -26,7 +26,7 @@ const router: Router = express.Router();
 
 // --- Multer setup for file uploads ---
 const ALLOWED_AUDIO_EXTENSIONS = [
-  '.mp3', '.wav', '.ogg', '.flac', '.aac', '.wma', '.m4a', '.opus', '.aiff', '.ape'
+  '.mp3', '.wav', '.ogg', '.flac', '.aac', '.wma', '.m4a', '.opus', '.webm', '.mka', '.aiff', '.ape'
 ];
 
 const upload = multer({
@@ -74,9 +74,16 @@ router.get('/status', async (_req: Request, res: Response) => {
 router.get('/audio/engine-status', async (_req: Request, res: Response) => {
   try {
     const status = await audioEngine.getStatus();
-    res.json({ playing: status.playing, paused: status.paused, volume: status.volume, micVolume: status.micVolume });
+    res.json({
+      playing: status.playing,
+      paused: status.paused,
+      micMuted: status.micMuted,
+      pushToMute: status.pushToMute,
+      volume: status.volume,
+      micVolume: status.micVolume,
+    });
   } catch {
-    res.json({ playing: false, paused: false, volume: 0, micVolume: 100 });
+    res.json({ playing: false, paused: false, micMuted: false, pushToMute: false, volume: 0, micVolume: 100 });
   }
 });
 
@@ -121,6 +128,101 @@ router.post('/audio/output-device', async (req: Request, res: Response) => {
   }
 });
 
+// Local monitor device: a second engine output (e.g. speakers) that plays
+// the mix sent to the mic, or the buses routed to it (see monitor-source).
+// `deviceName: null` closes it.
+router.post('/audio/monitor-device', async (req: Request, res: Response) => {
+  try {
+    const { deviceName } = req.body;
+    if (deviceName !== null && (typeof deviceName !== 'string' || !deviceName)) {
+      res.status(400).json({ error: 'deviceName must be a device name or null' });
+      return;
+    }
+    await audioEngine.setMonitorDevice(deviceName);
+    res.json({ message: deviceName ? `Monitor device set to: ${deviceName}` : 'Monitor device closed' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to set monitor device';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.post('/audio/monitor-volume', async (req: Request, res: Response) => {
+  try {
+    const { volume } = req.body;
+    if (typeof volume !== 'number' || volume < 0 || volume > 100) {
+      res.status(400).json({ error: 'Volume must be a number between 0 and 100' });
+      return;
+    }
+    await audioEngine.setMonitorVolume(volume);
+    res.json({ message: 'Monitor volume set', volume });
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to set monitor volume' });
+  }
+});
+
+router.post('/audio/monitor-source', async (req: Request, res: Response) => {
+  try {
+    const { source } = req.body;
+    if (source !== 'mix' && source !== 'buses') {
+      res.status(400).json({ error: "source must be 'mix' or 'buses'" });
+      return;
+    }
+    await audioEngine.setMonitorSource(source);
+    res.json({ message: 'Monitor source set', source });
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to set monitor source' });
+  }
+});
+
+// ── Recording ──────────────────────────────────────────────────────────────
+
+// Recordings of what went out on the virtual mic, kept in the data
+// directory.  `source` picks the whole mix (default), the mic or the sounds.
+const RECORDING_SOURCES: RecordingSource[] = ['master', 'mic', 'sounds'];
+const recordingsDir = path.join(dataDir, 'recordings');
+
+router.get('/audio/recording', async (_req: Request, res: Response) => {
+  try {
+    const status = await audioEngine.getStatus();
+    res.json({ recording: status.recording });
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to get recording status' });
+  }
+});
+
+router.post('/audio/recording/start', async (req: Request, res: Response) => {
+  try {
+    const source: RecordingSource = req.body?.source ?? 'master';
+    const format: 'wav' | 'flac' = req.body?.format ?? 'flac';
+    if (!RECORDING_SOURCES.includes(source)) {
+      res.status(400).json({ error: `source must be one of: ${RECORDING_SOURCES.join(', ')}` });
+      return;
+    }
+    if (format !== 'wav' && format !== 'flac') {
+      res.status(400).json({ error: 'format must be wav or flac' });
+      return;
+    }
+    fs.mkdirSync(recordingsDir, { recursive: true });
+    const stamp = new Date().toISOString().replace(/[:.]/g, '-');
+    const filePath = path.join(recordingsDir, `recording-${stamp}.${format}`);
+    await audioEngine.startRecording(filePath, source, format, format === 'flac' ? 24 : 16);
+    res.json({ message: 'Recording started', path: filePath });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to start recording';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.post('/audio/recording/stop', async (_req: Request, res: Response) => {
+  try {
+    const recording = await audioEngine.stopRecording();
+    res.json({ message: 'Recording saved', recording });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to stop recording';
+    res.status(500).json({ error: msg });
+  }
+});
+
 // ── Sounds ─────────────────────────────────────────────────────────────────
 
 router.get('/sounds', (_req: Request, res: Response) => {
@@ -162,16 +264,24 @@ router.post('/sounds/:id/play', async (req: Request, res: Response) => {
 
     console.log(`[play] id=${id} speakersOnly=${speakersOnly} micOnly=${micOnly} speakerPlayback=${speakerPlayback} engineRunning=${audioEngine.running} file=${filePath}`);
 
-    // Play through audio engine (mic/VB-Cable output) unless speakers-only.
+    // Pick the engine bus: `mic` feeds the mic output, `monitor` only the
+    // local monitor device, and `sfx` both.  The monitor plays the whole
+    // mix by default, `mic` bus included; only when it plays the sound
+    // buses does it leave `mic` out.  Speakers-only sounds skip the engine
+    // when there is no monitor device to play them on.
     // Fire-and-forget: don't await the decode — respond immediately so the
     // client can start speaker playback with minimal latency.
-    if (!speakersOnly) {
-      audioEngine.playFireAndForget(filePath);
+    const monitorOpen = !!audioEngine.monitorDevice;
+    const monitorHears = monitorOpen && (!micOnly || audioEngine.monitorSource === 'mix');
+    const bus = micOnly ? 'mic' : speakersOnly ? 'monitor' : 'sfx';
+    if (!speakersOnly || monitorOpen) {
+      audioEngine.playFireAndForget(filePath, undefined, { bus });
     }
 
     // Mobile clients can't use Web Audio — tell connected desktop clients
-    // to play the sound through their speakers via Web Audio.
-    if (speakerPlayback) {
+    // to play the sound through their speakers via Web Audio.  Not needed
+    // when the engine's monitor device already plays it.
+    if (speakerPlayback && !monitorHears) {
       broadcastSseEvent('play-sound', { id });
     }
 
@@ -196,6 +306,179 @@ router.post('/stop', async (_req: Request, res: Response) => {
   }
 });
 
+// Play one of several sounds, picked by the engine.  `sounds` is a list of
+// `{ id, weight? }`; `group` names the picking state (shuffle round,
+// round-robin position) kept between calls.
+router.post('/sounds/play-random', async (req: Request, res: Response) => {
+  try {
+    const { group, sounds, mode, bus, pitchVariation, gainVariationDb } = req.body;
+    if (typeof group !== 'string' || !group || !Array.isArray(sounds) || sounds.length === 0) {
+      res.status(400).json({ error: 'group and a non-empty sounds list are required' });
+      return;
+    }
+    const files: { file_path: string; weight?: number }[] = [];
+    const idsByPath = new Map<string, number>();
+    for (const sound of sounds) {
+      const id = parseInt(sound?.id, 10);
+      const filePath = isNaN(id) ? null : soundDb.getSoundFilePath(id);
+      if (!filePath || !fs.existsSync(filePath)) {
+        res.status(404).json({ error: `Sound file not found: ${sound?.id}` });
+        return;
+      }
+      files.push({ file_path: filePath, weight: typeof sound.weight === 'number' ? sound.weight : undefined });
+      idsByPath.set(filePath, id);
+    }
+    const played = await audioEngine.playRandom(group, files, { mode, bus, pitchVariation, gainVariationDb });
+    const id = idsByPath.get(played);
+    if (id !== undefined) {
+      soundDb.recordPlay(id);
+    }
+    res.json({ message: 'Sound playing', id });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to play sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+// Keep a sound decoded in the engine so it starts instantly (e.g. for
+// favourites); DELETE releases it.
+router.post('/sounds/:id/preload', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    const filePath = isNaN(id) ? null : soundDb.getSoundFilePath(id);
+    if (!filePath || !fs.existsSync(filePath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+    await audioEngine.preload(filePath);
+    res.json({ message: 'Sound preloading' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to preload sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.delete('/sounds/:id/preload', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    const filePath = isNaN(id) ? null : soundDb.getSoundFilePath(id);
+    if (!filePath) {
+      res.status(404).json({ error: 'Sound not found' });
+      return;
+    }
+    await audioEngine.unload(filePath);
+    res.json({ message: 'Sound unloaded' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to unload sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+// Schedule a sound to play after `delayMs`, or at `atMs` on the engine
+// clock, so several plays can be lined up sample-accurately.
+router.post('/sounds/:id/schedule', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid sound id' });
+      return;
+    }
+    const { delayMs, atMs, bus } = req.body;
+    if (typeof delayMs !== 'number' && typeof atMs !== 'number') {
+      res.status(400).json({ error: 'delayMs or atMs is required' });
+      return;
+    }
+    const filePath = soundDb.getSoundFilePath(id);
+    if (!filePath || !fs.existsSync(filePath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+    const scheduleId = await audioEngine.play(filePath, undefined, { bus, delayMs, atMs });
+    soundDb.recordPlay(id);
+    res.json({ message: 'Sound scheduled', scheduleId });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to schedule sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.delete('/scheduled/:id', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid schedule id' });
+      return;
+    }
+    await audioEngine.cancelScheduled(id);
+    res.json({ message: 'Scheduled play cancelled' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to cancel scheduled play';
+    res.status(404).json({ error: msg });
+  }
+});
+
+// ── Play queue ─────────────────────────────────────────────────────────────
+
+router.post('/queue', async (req: Request, res: Response) => {
+  try {
+    const { soundId, bus, gapMs = 0 } = req.body;
+    const id = parseInt(soundId, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid sound id' });
+      return;
+    }
+    if (typeof gapMs !== 'number') {
+      res.status(400).json({ error: 'gapMs must be a number' });
+      return;
+    }
+    const filePath = soundDb.getSoundFilePath(id);
+    if (!filePath || !fs.existsSync(filePath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+    await audioEngine.enqueue(filePath, { bus, gapMs });
+    res.json({ message: 'Sound queued' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to queue sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.post('/queue/skip', async (req: Request, res: Response) => {
+  try {
+    await audioEngine.skip(req.body?.bus);
+    res.json({ message: 'Skipped' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to skip';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.post('/audio/seek', async (req: Request, res: Response) => {
+  try {
+    const { positionMs, bus } = req.body;
+    if (typeof positionMs !== 'number' || positionMs < 0) {
+      res.status(400).json({ error: 'positionMs must be a non-negative number' });
+      return;
+    }
+    await audioEngine.seek(positionMs, bus);
+    res.json({ message: 'Seeked', positionMs });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to seek';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.post('/queue/clear', async (req: Request, res: Response) => {
+  try {
+    await audioEngine.clearQueue(req.body?.bus);
+    res.json({ message: 'Queue cleared' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to clear queue';
+    res.status(500).json({ error: msg });
+  }
+});
+
 router.post('/pause', async (_req: Request, res: Response) => {
   try {
     // Get current status to determine if we should pause or resume
@@ -211,6 +494,56 @@ router.post('/pause', async (_req: Request, res: Response) => {
   }
 });
 
+router.post('/audio/mic-mute', async (req: Request, res: Response) => {
+  try {
+    const { muted } = req.body;
+    if (typeof muted !== 'boolean') {
+      res.status(400).json({ error: 'muted must be a boolean' });
+      return;
+    }
+    await audioEngine.setMicMuted(muted);
+    res.json({ message: muted ? 'Mic muted' : 'Mic unmuted', muted });
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to set mic mute' });
+  }
+});
+
+// Push-to-mute: the client sends `active: true` on key down and `false`
+// on key up.  Independent of the sticky mic mute above.
+router.post('/audio/push-to-mute', async (req: Request, res: Response) => {
+  try {
+    const { active } = req.body;
+    if (typeof active !== 'boolean') {
+      res.status(400).json({ error: 'active must be a boolean' });
+      return;
+    }
+    await audioEngine.setPushToMute(active);
+    res.json({ message: 'Push-to-mute updated', active });
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to set push-to-mute' });
+  }
+});
+
+// On-disk cache of decoded sounds: report its size, or clear it.
+router.get('/audio/pcm-cache', async (_req: Request, res: Response) => {
+  try {
+    const status = await audioEngine.getStatus();
+    res.json(status.pcmCache);
+  } catch (error) {
+    res.status(500).json({ error: 'Failed to get PCM cache status' });
+  }
+});
+
+router.delete('/audio/pcm-cache', async (_req: Request, res: Response) => {
+  try {
+    await audioEngine.clearPcmCache();
+    res.json({ message: 'PCM cache cleared' });
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to clear PCM cache';
+    res.status(500).json({ error: msg });
+  }
+});
+
 router.post('/volume', async (req: Request, res: Response) => {
   try {
     const { volume } = req.body;
@@ -807,7 +1140,7 @@ router.post('/store/download/:categoryId', async (req: Request, res: Response) =
           title: sound.title,
           fileName,
           artist: sound.artist || '',
-          durationMs: sound.duration_ms || 0,
+          durationMs: (await probeDurationMs(destPath)) ?? (sound.duration_ms || 0),
           categoryId: localCatId,
           icon: sound.icon || '',
           iconIsBase64: sound.icon_is_base64 || false,
@@ -837,6 +1170,17 @@ router.post('/store/download/:categoryId', async (req: Request, res: Response) =
 
 // ── Add sound ──────────────────────────────────────────────────────────────
 
+/** Length of a sound file as the engine decodes it, or null if it can't read it. */
+async function probeDurationMs(filePath: string): Promise<number | null> {
+  try {
+    const media = await audioEngine.probe(filePath);
+    return Math.round(media.duration_ms);
+  } catch (err) {
+    console.warn(`[probe] Could not read ${filePath}:`, err);
+    return null;
+  }
+}
+
 const addSoundUpload = upload.fields([
   { name: 'soundFile', maxCount: 1 },
   { name: 'originalFile', maxCount: 1 },
@@ -862,6 +1206,7 @@ router.post('/sounds/add', addSoundUpload, async (req: Request, res: Response) =
 
     const displayName = (req.body.displayName as string | undefined)?.trim() || undefined;
     const artist = typeof req.body.artist === 'string' ? req.body.artist : '';
+    // Only used if the engine can't read the file.
     const durationSeconds = parseInt(req.body.durationSeconds, 10) || 0;
     const icon = typeof req.body.icon === 'string' ? req.body.icon : '';
     const hideTitle = req.body.hideTitle === 'true' || req.body.hideTitle === '1';
@@ -911,7 +1256,7 @@ router.post('/sounds/add', addSoundUpload, async (req: Request, res: Response) =
       title: soundTitle,
       fileName,
       artist,
-      durationMs: durationSeconds * 1000,
+      durationMs: (await probeDurationMs(destPath)) ?? durationSeconds * 1000,
       categoryId,
       hasUncropped,
       icon,
@@ -976,6 +1321,8 @@ router.get('/sounds/:id/audio', (req: Request, res: Response) => {
       '.wma': 'audio/x-ms-wma',
       '.m4a': 'audio/mp4',
       '.opus': 'audio/opus',
+      '.webm': 'audio/webm',
+      '.mka': 'audio/x-matroska',
       '.aiff': 'audio/aiff',
       '.ape': 'audio/x-ape',
     };
@@ -993,9 +1340,85 @@ router.get('/sounds/:id/audio', (req: Request, res: Response) => {
   }
 });
 
+// Format, length and tags of a sound, as the engine reads them.
+router.get('/sounds/:id/probe', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid sound id' });
+      return;
+    }
+
+    const filePath = soundDb.getSoundFilePath(id);
+    if (!filePath || !fs.existsSync(filePath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+
+    res.json(await audioEngine.probe(filePath));
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to probe sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
+router.get('/sounds/:id/waveform', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid sound id' });
+      return;
+    }
+    const buckets = req.query.buckets === undefined ? 600 : parseInt(String(req.query.buckets), 10);
+    if (isNaN(buckets) || buckets < 1 || buckets > 65536) {
+      res.status(400).json({ error: 'buckets must be between 1 and 65536' });
+      return;
+    }
+
+    const filePath = soundDb.getSoundFilePath(id);
+    if (!filePath || !fs.existsSync(filePath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+
+    res.json(await audioEngine.getWaveform(filePath, buckets));
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to read waveform';
+    res.status(500).json({ error: msg });
+  }
+});
+
+// Silence analysis of a file that isn't in the library yet (the add-sound
+// modal's clip), for suggesting where to crop it.
+router.post('/audio/detect-silence', upload.single('soundFile'), async (req: Request, res: Response) => {
+  const soundFile = req.file;
+  if (!soundFile) {
+    res.status(400).json({ error: 'No sound file uploaded' });
+    return;
+  }
+  // Keep the extension so the engine knows what to expect.
+  const filePath = soundFile.path + path.extname(soundFile.originalname).toLowerCase();
+  try {
+    fs.renameSync(soundFile.path, filePath);
+    const thresholdDb = req.body.thresholdDb !== undefined ? parseFloat(req.body.thresholdDb) : undefined;
+    const minSilenceMs = req.body.minSilenceMs !== undefined ? parseFloat(req.body.minSilenceMs) : undefined;
+    if ((thresholdDb !== undefined && isNaN(thresholdDb)) || (minSilenceMs !== undefined && isNaN(minSilenceMs))) {
+      res.status(400).json({ error: 'thresholdDb and minSilenceMs must be numbers' });
+      return;
+    }
+    res.json(await audioEngine.detectSilence(filePath, thresholdDb, minSilenceMs));
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to detect silence';
+    res.status(500).json({ error: msg });
+  } finally {
+    try { fs.unlinkSync(filePath); } catch { /* ignore */ }
+    try { fs.unlinkSync(soundFile.path); } catch { /* ignore */ }
+  }
+});
+
 // ── Update sound file ──────────────────────────────────────────────────────
 
-router.post('/sounds/:id/update-file', upload.single('soundFile'), (req: Request, res: Response) => {
+router.post('/sounds/:id/update-file', upload.single('soundFile'), async (req: Request, res: Response) => {
   const soundFile = req.file;
   try {
     const id = parseInt(req.params.id, 10);
@@ -1021,6 +1444,9 @@ router.post('/sounds/:id/update-file', upload.single('soundFile'), (req: Request
     fs.copyFileSync(soundFile.path, currentPath);
     try { fs.unlinkSync(soundFile.path); } catch { /* ignore */ }
 
+    const durationMs = await probeDurationMs(currentPath);
+    if (durationMs !== null) soundDb.setDuration(id, durationMs);
+
     notifySseClients();
     res.json({ message: 'Sound file updated' });
   } catch (error) {
@@ -1046,6 +1472,99 @@ router.post('/sounds/:id/update-file', upload.single('soundFile'), (req: Request
   }
 });
 
+// ── Render edits ───────────────────────────────────────────────────────────
+
+const RENDER_EDIT_FIELDS: [string, keyof RenderEdits][] = [
+  ['startMs', 'start_ms'],
+  ['endMs', 'end_ms'],
+  ['pitchSemitones', 'pitch_semitones'],
+  ['sampleRate', 'sample_rate'],
+  ['channels', 'channels'],
+  ['gainDb', 'gain_db'],
+  ['loudnessLufs', 'loudness_lufs'],
+  ['fadeInMs', 'fade_in_ms'],
+  ['fadeOutMs', 'fade_out_ms'],
+];
+
+/** A file name next to `fileName` with extension `ext` that isn't taken. */
+function freeFileName(fileName: string, ext: string): string {
+  const dir = path.dirname(fileName);
+  const base = path.basename(fileName, path.extname(fileName));
+  let candidate = path.join(dir, `${base}${ext}`);
+  for (let n = 2; fs.existsSync(path.join(soundDb.getSoundsDir(), candidate)); n++) {
+    candidate = path.join(dir, `${base} (${n})${ext}`);
+  }
+  return candidate;
+}
+
+// Crop, fade, gain / normalize, resample or remix a sound in the engine, with
+// the same DSP as playback, and replace its file.  WAV and FLAC sounds keep
+// their format; others become FLAC.  The first edit keeps the original as
+// the sound's uncropped backup, so reset-crop can restore it.
+router.post('/sounds/:id/render', async (req: Request, res: Response) => {
+  try {
+    const id = parseInt(req.params.id, 10);
+    if (isNaN(id)) {
+      res.status(400).json({ error: 'Invalid sound id' });
+      return;
+    }
+
+    const edits: RenderEdits = {};
+    for (const [field, key] of RENDER_EDIT_FIELDS) {
+      const value = req.body?.[field];
+      if (value === undefined || value === null) continue;
+      if (typeof value !== 'number' || !isFinite(value)) {
+        res.status(400).json({ error: `${field} must be a number` });
+        return;
+      }
+      edits[key] = value;
+    }
+
+    const currentPath = soundDb.getSoundFilePath(id);
+    if (!currentPath || !fs.existsSync(currentPath)) {
+      res.status(404).json({ error: 'Sound file not found' });
+      return;
+    }
+
+    const soundsDir = soundDb.getSoundsDir();
+    const fileName = path.relative(soundsDir, currentPath);
+    const ext = path.extname(fileName).toLowerCase();
+    const keepsFormat = ext === '.wav' || ext === '.flac';
+    const newFileName = keepsFormat ? fileName : freeFileName(fileName, '.flac');
+    const newPath = path.join(soundsDir, newFileName);
+
+    // Keep the source's resolution; decoded lossy audio goes to 16 bits.
+    const media = await audioEngine.probe(currentPath);
+    const bitDepth: 16 | 24 | 32 = media.codec.startsWith('pcm_f') && ext === '.wav'
+      ? 32
+      : media.bit_depth !== null && media.bit_depth > 16 ? 24 : 16;
+
+    const backupPath = soundDb.getUncroppedPath(fileName);
+    if (keepsFormat) {
+      if (!fs.existsSync(backupPath)) fs.copyFileSync(currentPath, backupPath);
+    } else {
+      // The backup has to share the sound's new extension.
+      const source = fs.existsSync(backupPath) ? backupPath : currentPath;
+      await audioEngine.render(source, soundDb.getUncroppedPath(newFileName), {}, 'flac', bitDepth);
+      try { fs.unlinkSync(backupPath); } catch { /* ignore */ }
+    }
+
+    const info = await audioEngine.render(currentPath, newPath, edits, undefined, bitDepth);
+    if (newFileName !== fileName) {
+      soundDb.updateSoundFile(id, newFileName);
+      try { fs.unlinkSync(currentPath); } catch { /* ignore */ }
+    }
+    soundDb.setHasUncropped(id, true);
+    soundDb.setDuration(id, Math.round(info.duration_ms));
+
+    notifySseClients();
+    res.json(info);
+  } catch (error) {
+    const msg = error instanceof Error ? error.message : 'Failed to render sound';
+    res.status(500).json({ error: msg });
+  }
+});
+
 // ── Uncropped backups ──────────────────────────────────────────────────────
 
 router.get('/sounds/uncropped-list', (_req: Request, res: Response) => {
@@ -1057,7 +1576,7 @@ router.get('/sounds/uncropped-list', (_req: Request, res: Response) => {
   }
 });
 
-router.post('/sounds/reset-crop', (req: Request, res: Response) => {
+router.post('/sounds/reset-crop', async (req: Request, res: Response) => {
   try {
     const { url: soundUrl } = req.body;
     if (!soundUrl || typeof soundUrl !== 'string') {
@@ -1085,6 +1604,8 @@ router.post('/sounds/reset-crop', (req: Request, res: Response) => {
     const sound = sounds.find(s => s.url === soundUrl);
     if (sound) {
       soundDb.setHasUncropped(sound.id, false);
+      const durationMs = await probeDurationMs(soundUrl);
+      if (durationMs !== null) soundDb.setDuration(sound.id, durationMs);
     }
 
     notifySseClients();
@@ -311,6 +311,10 @@ export class SoundDb {
     return result.changes > 0;
   }
 
+  setDuration(id: number, durationMs: number): void {
+    this.db.prepare('UPDATE sounds SET duration_ms = ? WHERE id = ?').run(durationMs, id);
+  }
+
   setHasUncropped(id: number, hasUncropped: boolean): void {
     this.db.prepare('UPDATE sounds SET has_uncropped = ? WHERE id = ?').run(hasUncropped ? 1 : 0, id);
   }